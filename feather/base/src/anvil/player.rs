@@ -3,6 +3,7 @@ use std::{
     collections::HashMap,
     fs,
     fs::File,
+    io::{ErrorKind, Read, Write},
     path::{Path, PathBuf},
 };
 
@@ -35,6 +36,27 @@ pub struct PlayerData {
     #[serde(rename = "SelectedItemSlot")]
     pub held_item: i32,
     pub abilities: PlayerAbilities,
+    #[serde(rename = "foodLevel", default = "default_food_level")]
+    pub food_level: i32,
+    #[serde(rename = "foodSaturationLevel", default = "default_food_saturation")]
+    pub food_saturation: f32,
+    #[serde(rename = "foodExhaustionLevel", default)]
+    pub food_exhaustion: f32,
+    #[serde(rename = "XpTotal", default)]
+    pub xp_total: i32,
+    #[serde(rename = "XpLevel", default)]
+    pub xp_level: i32,
+    /// Progress towards the next level, from 0 to 1.
+    #[serde(rename = "XpP", default)]
+    pub xp_progress: f32,
+}
+
+fn default_food_level() -> i32 {
+    20
+}
+
+fn default_food_saturation() -> f32 {
+    5.0
 }
 
 /// Represents player's abilities (flying, invulnerability, speed, etc.)
@@ -162,26 +184,100 @@ impl From<&InventorySlot> for ItemStack {
     }
 }
 
-pub fn load_player_data(world_dir: &Path, uuid: Uuid) -> Result<PlayerData, nbt::Error> {
-    let file_path = file_path(world_dir, uuid);
-    let mut file = File::open(file_path)?;
-    let data = nbt::from_gzip_reader(&mut file)?;
-    Ok(data)
+/// The file format player data is saved in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayerDataFormat {
+    /// Gzipped NBT in `playerdata/<uuid>.dat`, as written by vanilla.
+    Nbt,
+    /// Human-readable JSON in `playerdata/<uuid>.json`.
+    Json,
+}
+
+impl Default for PlayerDataFormat {
+    fn default() -> Self {
+        PlayerDataFormat::Nbt
+    }
+}
+
+impl PlayerDataFormat {
+    /// The extension of player data files in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            PlayerDataFormat::Nbt => "dat",
+            PlayerDataFormat::Json => "json",
+        }
+    }
+
+    /// The other supported format.
+    fn other(self) -> Self {
+        match self {
+            PlayerDataFormat::Nbt => PlayerDataFormat::Json,
+            PlayerDataFormat::Json => PlayerDataFormat::Nbt,
+        }
+    }
+
+    /// Reads player data in this format.
+    pub fn read(self, reader: &mut impl Read) -> anyhow::Result<PlayerData> {
+        Ok(match self {
+            PlayerDataFormat::Nbt => nbt::from_gzip_reader(reader)?,
+            PlayerDataFormat::Json => serde_json::from_reader(reader)?,
+        })
+    }
+
+    /// Writes player data in this format.
+    pub fn write(self, writer: &mut impl Write, data: &PlayerData) -> anyhow::Result<()> {
+        match self {
+            PlayerDataFormat::Nbt => nbt::to_gzip_writer(writer, data, None)?,
+            PlayerDataFormat::Json => serde_json::to_writer_pretty(writer, data)?,
+        }
+        Ok(())
+    }
 }
 
+/// Loads a player's data, preferring the file in `format`.
+///
+/// Falls back to the other format if no such file exists,
+/// so switching formats does not lose existing saves.
+pub fn load_player_data(
+    world_dir: &Path,
+    uuid: Uuid,
+    format: PlayerDataFormat,
+) -> anyhow::Result<PlayerData> {
+    let format = if file_path(world_dir, uuid, format).exists() {
+        format
+    } else {
+        format.other()
+    };
+    let mut file = File::open(file_path(world_dir, uuid, format))?;
+    format.read(&mut file)
+}
+
+/// Saves a player's data in `format`.
+///
+/// Any save in the other format is deleted afterwards, so that
+/// switching formats back never loads the outdated file.
 pub fn save_player_data(
     world_dir: &Path,
     uuid: Uuid,
     data: &PlayerData,
-) -> Result<(), anyhow::Error> {
+    format: PlayerDataFormat,
+) -> anyhow::Result<()> {
     fs::create_dir_all(world_dir.join("playerdata"))?;
-    let file_path = file_path(world_dir, uuid);
+    let file_path = file_path(world_dir, uuid, format);
     let mut file = File::create(file_path)?;
-    nbt::to_gzip_writer(&mut file, data, None).map_err(anyhow::Error::from)
+    format.write(&mut file, data)?;
+
+    match fs::remove_file(self::file_path(world_dir, uuid, format.other())) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
 }
 
-fn file_path(world_dir: &Path, uuid: Uuid) -> PathBuf {
-    world_dir.join("playerdata").join(format!("{}.dat", uuid))
+fn file_path(world_dir: &Path, uuid: Uuid, format: PlayerDataFormat) -> PathBuf {
+    world_dir
+        .join("playerdata")
+        .join(format!("{}.{}", uuid, format.extension()))
 }
 
 #[cfg(test)]
@@ -210,6 +306,51 @@ mod tests {
         );
         assert_eq!(player.inventory[0].item, "minecraft:diamond_shovel");
         assert_eq!(player.inventory[0].nbt, Some(ItemNbt { damage: Some(3) }));
+        assert_eq!(player.food_level, 20);
+        assert_eq!(player.food_saturation, 5.0);
+    }
+
+    #[test]
+    fn player_data_formats_round_trip() {
+        let mut cursor = Cursor::new(include_bytes!("player.dat").to_vec());
+        let player = PlayerDataFormat::Nbt.read(&mut cursor).unwrap();
+
+        for &format in &[PlayerDataFormat::Nbt, PlayerDataFormat::Json] {
+            let mut buffer = Vec::new();
+            format.write(&mut buffer, &player).unwrap();
+            let read = format.read(&mut Cursor::new(buffer)).unwrap();
+            assert_eq!(format!("{:?}", read), format!("{:?}", player));
+        }
+    }
+
+    #[test]
+    fn load_falls_back_to_existing_nbt_save() {
+        let world_dir =
+            std::env::temp_dir().join(format!("feather-player-data-{}", std::process::id()));
+        let uuid = Uuid::from_u128(0x1234);
+        let mut cursor = Cursor::new(include_bytes!("player.dat").to_vec());
+        let player = PlayerDataFormat::Nbt.read(&mut cursor).unwrap();
+
+        save_player_data(&world_dir, uuid, &player, PlayerDataFormat::Nbt).unwrap();
+        let loaded = load_player_data(&world_dir, uuid, PlayerDataFormat::Json).unwrap();
+        assert_eq!(loaded.inventory, player.inventory);
+
+        let mut player = player;
+        player.food_level = 3;
+        save_player_data(&world_dir, uuid, &player, PlayerDataFormat::Json).unwrap();
+        let path = |extension| {
+            world_dir
+                .join("playerdata")
+                .join(format!("{}.{}", uuid, extension))
+        };
+        assert!(path("json").exists());
+        assert!(!path("dat").exists());
+
+        // Switching back loads the newer save
+        let loaded = load_player_data(&world_dir, uuid, PlayerDataFormat::Nbt).unwrap();
+        assert_eq!(loaded.food_level, 3);
+
+        fs::remove_dir_all(world_dir).unwrap();
     }
 
     #[test]
@@ -15,6 +15,7 @@ pub mod chunk;
 pub mod chunk_lock;
 pub mod inventory;
 pub mod metadata;
+pub mod sound;
 
 pub use block::{BlockPositionValidationError, ValidBlockPosition};
 pub use blocks::*;
@@ -28,9 +29,10 @@ pub use libcraft_core::{
 pub use libcraft_inventory::{Area, Inventory};
 pub use libcraft_items::{Item, ItemStack, ItemStackBuilder, ItemStackError};
 pub use libcraft_particles::{Particle, ParticleKind};
-pub use libcraft_text::{deserialize_text, Text, Title};
+pub use libcraft_text::{deserialize_text, Text, TextComponentBuilder, Title};
 #[doc(inline)]
 pub use metadata::EntityMetadata;
+pub use sound::{SoundCategory, SoundId};
 
 /// Number of updates (ticks) to do per second.
 pub const TPS: u32 = 20;
@@ -50,6 +52,24 @@ pub enum Direction {
     West,
 }
 
+/// The difficulty of the game.
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, FromPrimitive, ToPrimitive,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Difficulty {
+    Peaceful,
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Difficulty::Normal
+    }
+}
+
 /// A profile property, which stores metadata
 /// for some player's account. This is usually
 /// used to store skin data.
@@ -0,0 +1,49 @@
+//! Sound identifiers and categories.
+
+use num_derive::{FromPrimitive, ToPrimitive};
+
+/// Identifies a sound to play on the client.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SoundId {
+    /// A sound from the vanilla sound registry, sent
+    /// via its numeric protocol ID.
+    Id(u32),
+    /// A sound identified by its resource location,
+    /// e.g. `minecraft:entity.generic.explode`.
+    /// This also allows playing sounds from resource packs.
+    Named(String),
+}
+
+impl From<u32> for SoundId {
+    fn from(id: u32) -> Self {
+        SoundId::Id(id)
+    }
+}
+
+impl From<String> for SoundId {
+    fn from(name: String) -> Self {
+        SoundId::Named(name)
+    }
+}
+
+impl From<&str> for SoundId {
+    fn from(name: &str) -> Self {
+        SoundId::Named(name.to_owned())
+    }
+}
+
+/// The category of a sound, which determines
+/// which volume slider on the client applies to it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, FromPrimitive, ToPrimitive)]
+pub enum SoundCategory {
+    Master,
+    Music,
+    Records,
+    Weather,
+    Blocks,
+    Hostile,
+    Neutral,
+    Players,
+    Ambient,
+    Voice,
+}
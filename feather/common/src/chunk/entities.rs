@@ -2,7 +2,6 @@ use ahash::AHashMap;
 use base::{ChunkPosition, Position};
 use ecs::{Entity, SysResult, SystemExecutor};
 use quill_common::events::{EntityCreateEvent, EntityRemoveEvent};
-use utils::vec_remove_item;
 
 use crate::{events::ChunkCrossEvent, Game};
 
@@ -11,13 +10,16 @@ pub fn register(systems: &mut SystemExecutor<Game>) {
 }
 
 /// A spatial index to look up entities within a given chunk.
+///
+/// The entities of each chunk are kept sorted, so the order in
+/// which they are iterated does not depend on the order of ECS queries.
 #[derive(Default)]
 pub struct ChunkEntities {
     entities: AHashMap<ChunkPosition, Vec<Entity>>,
 }
 
 impl ChunkEntities {
-    /// Returns the entities in the given chunk.
+    /// Returns the entities in the given chunk, sorted by entity ID.
     pub fn entities_in_chunk(&self, chunk: ChunkPosition) -> &[Entity] {
         self.entities
             .get(&chunk)
@@ -25,6 +27,47 @@ impl ChunkEntities {
             .unwrap_or_default()
     }
 
+    /// Counts the entities in the given chunk for which `predicate` returns `true`.
+    pub fn count_where(
+        &self,
+        chunk: ChunkPosition,
+        mut predicate: impl FnMut(Entity) -> bool,
+    ) -> usize {
+        self.entities_in_chunk(chunk)
+            .iter()
+            .filter(|&&entity| predicate(entity))
+            .count()
+    }
+
+    /// Iterates over all chunks containing entities,
+    /// yielding each chunk with its entities.
+    ///
+    /// Chunks without entities are skipped.
+    /// The order of iteration is unspecified.
+    pub fn iter(&self) -> impl Iterator<Item = (ChunkPosition, &[Entity])> + '_ {
+        self.entities
+            .iter()
+            .filter(|(_, entities)| !entities.is_empty())
+            .map(|(&chunk, entities)| (chunk, entities.as_slice()))
+    }
+
+    /// Removes all entities from the given chunk and returns them.
+    ///
+    /// The entities' `ChunkPosition` components are left unchanged,
+    /// but they are no longer indexed: use [`add_entity`](Self::add_entity)
+    /// to re-add them if they should stay in the world. Draining a chunk
+    /// without entities returns an empty vector.
+    pub fn drain_chunk(&mut self, chunk: ChunkPosition) -> Vec<Entity> {
+        self.entities.remove(&chunk).unwrap_or_default()
+    }
+
+    /// Adds an entity to the given chunk.
+    ///
+    /// The chunk should match the entity's `ChunkPosition` component.
+    pub fn add_entity(&mut self, entity: Entity, chunk: ChunkPosition) {
+        self.update(entity, None, chunk);
+    }
+
     fn update(
         &mut self,
         entity: Entity,
@@ -32,17 +75,20 @@ impl ChunkEntities {
         new_chunk: ChunkPosition,
     ) {
         if let Some(old_chunk) = old_chunk {
-            if let Some(vec) = self.entities.get_mut(&old_chunk) {
-                vec_remove_item(vec, &entity);
-            }
+            self.remove_entity(entity, old_chunk);
         }
 
-        self.entities.entry(new_chunk).or_default().push(entity);
+        let entities = self.entities.entry(new_chunk).or_default();
+        if let Err(index) = entities.binary_search(&entity) {
+            entities.insert(index, entity);
+        }
     }
 
     fn remove_entity(&mut self, entity: Entity, chunk: ChunkPosition) {
-        if let Some(vec) = self.entities.get_mut(&chunk) {
-            vec_remove_item(vec, &entity);
+        if let Some(entities) = self.entities.get_mut(&chunk) {
+            if let Ok(index) = entities.binary_search(&entity) {
+                entities.remove(index);
+            }
         }
     }
 }
@@ -96,3 +142,114 @@ fn update_chunk_entities(game: &mut Game) -> SysResult {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use ecs::Ecs;
+
+    use super::*;
+
+    #[test]
+    fn drain_chunk() {
+        let mut ecs = Ecs::new();
+        let a = ecs.spawn(());
+        let b = ecs.spawn(());
+        let chunk = ChunkPosition::new(1, -2);
+
+        let mut chunk_entities = ChunkEntities::default();
+        chunk_entities.add_entity(a, chunk);
+        chunk_entities.add_entity(b, chunk);
+
+        assert_eq!(chunk_entities.drain_chunk(chunk), vec![a, b]);
+        assert!(chunk_entities.entities_in_chunk(chunk).is_empty());
+    }
+
+    #[test]
+    fn iter_skips_empty_chunks() {
+        let mut ecs = Ecs::new();
+        let a = ecs.spawn(());
+        let b = ecs.spawn(());
+        let old_chunk = ChunkPosition::new(0, 0);
+        let new_chunk = ChunkPosition::new(3, 4);
+
+        let mut chunk_entities = ChunkEntities::default();
+        chunk_entities.add_entity(a, old_chunk);
+        chunk_entities.add_entity(b, new_chunk);
+        chunk_entities.update(a, Some(old_chunk), new_chunk);
+
+        let chunks: Vec<_> = chunk_entities.iter().collect();
+        assert_eq!(chunks, vec![(new_chunk, &[a, b][..])]);
+    }
+
+    #[test]
+    fn order_does_not_depend_on_insertion_order() {
+        let mut ecs = Ecs::new();
+        let entities: Vec<_> = (0..5).map(|_| ecs.spawn(())).collect();
+        let old_chunk = ChunkPosition::new(0, 0);
+        let new_chunk = ChunkPosition::new(1, 0);
+
+        let mut forward = ChunkEntities::default();
+        let mut backward = ChunkEntities::default();
+        for &entity in &entities {
+            forward.add_entity(entity, old_chunk);
+            forward.update(entity, Some(old_chunk), new_chunk);
+        }
+        for &entity in entities.iter().rev() {
+            backward.add_entity(entity, old_chunk);
+            backward.update(entity, Some(old_chunk), new_chunk);
+        }
+
+        assert_eq!(forward.entities_in_chunk(new_chunk), &entities[..]);
+        assert_eq!(
+            forward.entities_in_chunk(new_chunk),
+            backward.entities_in_chunk(new_chunk)
+        );
+        assert!(backward.entities_in_chunk(old_chunk).is_empty());
+    }
+
+    #[test]
+    fn count_where() {
+        let mut ecs = Ecs::new();
+        let a = ecs.spawn(());
+        let b = ecs.spawn(());
+        let chunk = ChunkPosition::new(0, 0);
+
+        let mut chunk_entities = ChunkEntities::default();
+        chunk_entities.add_entity(a, chunk);
+        chunk_entities.add_entity(b, chunk);
+
+        assert_eq!(chunk_entities.count_where(chunk, |entity| entity == b), 1);
+        assert_eq!(chunk_entities.count_where(chunk, |_| true), 2);
+        assert_eq!(
+            chunk_entities.count_where(ChunkPosition::new(1, 0), |_| true),
+            0
+        );
+    }
+
+    #[test]
+    fn cleared_entities_leave_the_index() {
+        let mut game = Game::new();
+        let chunk = ChunkPosition::new(0, 0);
+        let player = game
+            .ecs
+            .spawn((Position::default(), chunk, quill_common::entities::Player));
+        let mob = game.ecs.spawn((Position::default(), chunk));
+        game.chunk_entities.add_entity(player, chunk);
+        game.chunk_entities.add_entity(mob, chunk);
+
+        assert_eq!(game.clear_entities(true), 1);
+        assert!(game.ecs.get::<EntityRemoveEvent>(mob).is_ok());
+        assert!(game.ecs.get::<EntityRemoveEvent>(player).is_err());
+
+        update_chunk_entities(&mut game).unwrap();
+        assert_eq!(game.chunk_entities.entities_in_chunk(chunk), &[player]);
+    }
+
+    #[test]
+    fn drain_nonexistent_chunk() {
+        let mut chunk_entities = ChunkEntities::default();
+        assert!(chunk_entities
+            .drain_chunk(ChunkPosition::new(0, 0))
+            .is_empty());
+    }
+}
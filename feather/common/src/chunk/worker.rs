@@ -2,10 +2,15 @@ use std::{path::PathBuf, sync::Arc};
 
 use anyhow::bail;
 use base::{
-    anvil::{block_entity::BlockEntityData, entity::EntityData},
+    anvil::{
+        block_entity::BlockEntityData,
+        entity::EntityData,
+        player::{PlayerData, PlayerDataFormat},
+    },
     Chunk, ChunkHandle, ChunkPosition,
 };
 use flume::{Receiver, Sender};
+use uuid::Uuid;
 use worldgen::WorldGenerator;
 
 use crate::region_worker::RegionWorker;
@@ -39,11 +44,21 @@ pub struct SaveRequest {
     pub block_entities: Vec<BlockEntityData>,
 }
 
+#[derive(Debug)]
+pub struct PlayerSaveRequest {
+    pub uuid: Uuid,
+    pub data: PlayerData,
+    pub format: PlayerDataFormat,
+}
+
 #[derive(Debug)]
 #[allow(clippy::large_enum_variant)]
 pub enum WorkerRequest {
     Load(LoadRequest),
     Save(SaveRequest),
+    SavePlayer(PlayerSaveRequest),
+    /// Replies once all previously queued requests have been handled.
+    Flush(Sender<()>),
 }
 pub struct ChunkWorker {
     generator: Arc<dyn WorldGenerator>,
@@ -112,4 +127,17 @@ impl ChunkWorker {
     pub fn queue_chunk_save(&mut self, req: SaveRequest) {
         self.send_req.send(WorkerRequest::Save(req)).unwrap()
     }
+
+    pub fn queue_player_save(&mut self, req: PlayerSaveRequest) {
+        self.send_req.send(WorkerRequest::SavePlayer(req)).unwrap()
+    }
+
+    /// Blocks until all previously queued saves have been written.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        let (done_tx, done_rx) = flume::bounded(1);
+        if self.send_req.send(WorkerRequest::Flush(done_tx)).is_err() || done_rx.recv().is_err() {
+            bail!("RegionWorker died");
+        }
+        Ok(())
+    }
 }
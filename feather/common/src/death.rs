@@ -0,0 +1,115 @@
+//! Dropping the inventory of players who die.
+
+use std::mem;
+
+use base::{inventory::SLOT_OFFHAND, ItemStack, Position};
+use ecs::{SysResult, SystemExecutor};
+use libcraft_items::InventorySlot;
+use quill_common::{components::Health, entities::Player, entity_init::EntityInit};
+
+use crate::{entities::item::PickupDelay, events::DamageEvent, game_rules, Game, Window};
+
+/// First slot of the player window dropped on death.
+/// Slot 0 is the crafting output, which holds no item of its own.
+const FIRST_DROPPED_SLOT: usize = 1;
+
+pub fn register(systems: &mut SystemExecutor<Game>) {
+    systems.add_system(drop_inventories_on_death);
+}
+
+/// Drops the items of players killed this tick,
+/// unless the `keepInventory` game rule is set.
+fn drop_inventories_on_death(game: &mut Game) -> SysResult {
+    if game_rules::game_rules(game).keep_inventory {
+        return Ok(());
+    }
+
+    let mut drops: Vec<(Position, ItemStack)> = Vec::new();
+    for (_, (_event, health, &position, window, _)) in game
+        .ecs
+        .query::<(&DamageEvent, &Health, &Position, &Window, &Player)>()
+        .iter()
+    {
+        if health.0 > 0.0 {
+            continue;
+        }
+        for slot in FIRST_DROPPED_SLOT..=SLOT_OFFHAND {
+            let mut item = window.item(slot)?;
+            if let InventorySlot::Filled(stack) = mem::replace(&mut *item, InventorySlot::Empty) {
+                drops.push((position, stack));
+            }
+        }
+    }
+
+    for (position, stack) in drops {
+        let mut builder = game.create_entity_builder(position, EntityInit::Item);
+        builder.add(stack).add(PickupDelay::DROPPED);
+        game.spawn_entity(builder);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use base::{Inventory, Item};
+
+    use super::*;
+    use crate::{game_rules::GameRules, window::BackingWindow};
+
+    fn dying_player(game: &mut Game) -> ecs::Entity {
+        let window = Window::new(BackingWindow::Player {
+            player: Inventory::player().new_handle(),
+        });
+        window
+            .set_item(
+                36,
+                InventorySlot::Filled(ItemStack::new(Item::Diamond, 3).unwrap()),
+            )
+            .unwrap();
+        let player = game
+            .ecs
+            .spawn((Player, Health(0.0), Position::default(), window));
+        game.ecs
+            .insert_entity_event(
+                player,
+                DamageEvent {
+                    attacker: None,
+                    amount: 20.0,
+                },
+            )
+            .unwrap();
+        player
+    }
+
+    fn dropped_items(game: &Game) -> usize {
+        game.ecs.query::<&ItemStack>().iter().count()
+    }
+
+    #[test]
+    fn dead_players_drop_their_items() {
+        let mut game = Game::new();
+        game.insert_resource(GameRules::default());
+        let player = dying_player(&mut game);
+
+        drop_inventories_on_death(&mut game).unwrap();
+
+        assert_eq!(dropped_items(&game), 1);
+        let window = game.ecs.get::<Window>(player).unwrap();
+        assert!(window.item(36).unwrap().is_empty());
+    }
+
+    #[test]
+    fn keep_inventory_prevents_drops() {
+        let mut game = Game::new();
+        let mut rules = GameRules::default();
+        rules.set("keepInventory", "true").unwrap();
+        game.insert_resource(rules);
+        let player = dying_player(&mut game);
+
+        drop_inventories_on_death(&mut game).unwrap();
+
+        assert_eq!(dropped_items(&game), 0);
+        let window = game.ecs.get::<Window>(player).unwrap();
+        assert!(!window.item(36).unwrap().is_empty());
+    }
+}
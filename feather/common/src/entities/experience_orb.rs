@@ -4,5 +4,8 @@ use quill_common::entities::ExperienceOrb;
 
 pub fn build_default(builder: &mut EntityBuilder) {
     super::build_default(builder);
-    builder.add(ExperienceOrb).add(EntityKind::ExperienceOrb);
+    builder
+        .add(ExperienceOrb)
+        .add(crate::experience::ExperienceOrb { amount: 1 })
+        .add(EntityKind::ExperienceOrb);
 }
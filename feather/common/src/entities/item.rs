@@ -1,8 +1,75 @@
-use base::EntityKind;
-use ecs::EntityBuilder;
+use base::{EntityKind, Position};
+use ecs::{EntityBuilder, SysResult, SystemExecutor};
 use quill_common::entities::Item;
 
+use crate::{simulation::SimulatedChunks, Game};
+
 pub fn build_default(builder: &mut EntityBuilder) {
     super::build_default(builder);
     builder.add(Item).add(EntityKind::Item);
 }
+
+pub fn register(systems: &mut SystemExecutor<Game>) {
+    systems.add_system(count_down_pickup_delays);
+}
+
+/// Ticks left before a dropped item can be picked up.
+/// Items without this component can be picked up at once.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PickupDelay(pub u32);
+
+impl PickupDelay {
+    /// Delay of items dropped by players, e.g. when they die.
+    pub const DROPPED: Self = Self(10);
+    /// Delay of items thrown by players.
+    pub const THROWN: Self = Self(40);
+
+    pub fn is_over(self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// Counts down the pickup delays of items in simulated chunks.
+fn count_down_pickup_delays(game: &mut Game) -> SysResult {
+    let simulated = game.resources.get::<SimulatedChunks>().ok();
+    for (_, (delay, &position)) in game.ecs.query::<(&mut PickupDelay, &Position)>().iter() {
+        if let Some(simulated) = &simulated {
+            if !simulated.contains(position.chunk()) {
+                continue;
+            }
+        }
+        delay.0 = delay.0.saturating_sub(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use base::position;
+    use quill_common::entities::Player;
+
+    use super::*;
+    use crate::simulation::update_simulated_chunks;
+
+    #[test]
+    fn pickup_delays_only_count_down_in_simulated_chunks() {
+        let mut game = Game::new();
+        game.insert_resource(SimulatedChunks::new(2));
+        game.ecs.spawn((Position::default(), Player));
+        let near = game
+            .ecs
+            .spawn((PickupDelay::DROPPED, position!(8.0, 64.0, 8.0)));
+        let far = game
+            .ecs
+            .spawn((PickupDelay::DROPPED, position!(100.0, 64.0, 8.0)));
+
+        update_simulated_chunks(&mut game).unwrap();
+        count_down_pickup_delays(&mut game).unwrap();
+
+        assert_eq!(*game.ecs.get::<PickupDelay>(near).unwrap(), PickupDelay(9));
+        assert_eq!(
+            *game.ecs.get::<PickupDelay>(far).unwrap(),
+            PickupDelay::DROPPED
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! Per-tick entity AI.
+//!
+//! Systems registered in [`register`] run after
+//! `update_chunk_entities`, so [`Game::nearest_entity`] and
+//! [`Game::chunk_entities`] reflect entity positions as of the start
+//! of this tick. They run before the server broadcasts entity movement,
+//! which picks up any `Position` changes made here.
+//!
+//! New AI should be added as a system in [`register`],
+//! keeping this ordering. Entities outside the
+//! [`SimulatedChunks`](crate::simulation::SimulatedChunks) are not ticked.
+
+use base::{Position, Vec3d};
+use ecs::{Entity, SysResult, SystemExecutor};
+use quill_common::entities::Player;
+use rand::Rng;
+
+use crate::{simulation::SimulatedChunks, Game};
+
+/// Distance in blocks within which a wandering entity follows a player.
+const FOLLOW_DISTANCE: f64 = 8.0;
+/// Maximum distance in blocks of a random wander target.
+const WANDER_DISTANCE: f64 = 6.0;
+/// Distance moved each tick by a wandering entity, in blocks.
+const WANDER_SPEED: f64 = 0.1;
+/// Number of ticks before a wandering entity picks a new target.
+const WANDER_INTERVAL: u32 = 100;
+
+pub fn register(systems: &mut SystemExecutor<Game>) {
+    systems.add_system(wander);
+}
+
+/// Example AI: the entity wanders around randomly and
+/// walks toward the nearest player within [`FOLLOW_DISTANCE`].
+///
+/// Add this component to an entity to enable the behavior.
+/// Movement is horizontal only; there is no collision with blocks.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Wander {
+    target: Option<Position>,
+    ticks_until_retarget: u32,
+}
+
+/// System to move wandering entities.
+fn wander(game: &mut Game) -> SysResult {
+    let mut rng = rand::thread_rng();
+    let simulated = game.resources.get::<SimulatedChunks>().ok();
+    let mut moves: Vec<(Entity, Position)> = Vec::new();
+    for (entity, (wander, &position)) in game.ecs.query::<(&mut Wander, &Position)>().iter() {
+        if let Some(simulated) = &simulated {
+            if !simulated.contains(position.chunk()) {
+                continue;
+            }
+        }
+
+        let nearest_player = game
+            .nearest_entity(position, FOLLOW_DISTANCE, |other| {
+                other != entity && game.ecs.get::<Player>(other).is_ok()
+            })
+            .and_then(|player| game.ecs.get::<Position>(player).ok().map(|pos| *pos));
+
+        let target = match nearest_player {
+            Some(player_position) => player_position,
+            None => {
+                if wander.ticks_until_retarget == 0 || wander.target.is_none() {
+                    wander.ticks_until_retarget = WANDER_INTERVAL;
+                    wander.target = Some(
+                        position
+                            + Vec3d::new(
+                                rng.gen_range(-WANDER_DISTANCE..=WANDER_DISTANCE),
+                                0.0,
+                                rng.gen_range(-WANDER_DISTANCE..=WANDER_DISTANCE),
+                            ),
+                    );
+                }
+                wander.ticks_until_retarget -= 1;
+                match wander.target {
+                    Some(target) => target,
+                    None => continue,
+                }
+            }
+        };
+
+        if let Some(new_position) = step_toward(position, target) {
+            moves.push((entity, new_position));
+        }
+    }
+
+    for (entity, new_position) in moves {
+        *game.ecs.get_mut::<Position>(entity)? = new_position;
+    }
+    Ok(())
+}
+
+/// Returns the position after moving one step horizontally
+/// from `position` toward `target`, facing the direction of movement,
+/// or `None` if `position` has already reached `target`.
+fn step_toward(position: Position, target: Position) -> Option<Position> {
+    let mut direction = Vec3d::new(target.x - position.x, 0.0, target.z - position.z);
+    let distance = direction.magnitude();
+    if distance < WANDER_SPEED {
+        return None;
+    }
+    direction /= distance;
+
+    let mut new_position = position + direction * WANDER_SPEED;
+    new_position.yaw = (-direction.x).atan2(direction.z).to_degrees() as f32;
+    Some(new_position)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::simulation::update_simulated_chunks;
+
+    use super::*;
+
+    #[test]
+    fn wanderer_walks_toward_player() {
+        let mut game = Game::new();
+        let player_position = Position {
+            x: 5.0,
+            ..Default::default()
+        };
+        let player = game.ecs.spawn((player_position, Player));
+        game.chunk_entities
+            .add_entity(player, player_position.chunk());
+        let mob = game.ecs.spawn((Position::default(), Wander::default()));
+
+        wander(&mut game).unwrap();
+
+        let mob_position = *game.ecs.get::<Position>(mob).unwrap();
+        assert!((mob_position.x - WANDER_SPEED).abs() < 1e-9);
+        assert_eq!(mob_position.z, 0.0);
+        assert_eq!(
+            game.nearest_entity(mob_position, FOLLOW_DISTANCE, |e| e != mob),
+            Some(player)
+        );
+    }
+
+    #[test]
+    fn distant_wanderers_are_not_ticked() {
+        let mut game = Game::new();
+        game.insert_resource(SimulatedChunks::new(2));
+        game.ecs.spawn((Position::default(), Player));
+        let near = game.ecs.spawn((Position::default(), Wander::default()));
+        let far_position = Position {
+            x: 1000.0,
+            ..Default::default()
+        };
+        let far: Vec<Entity> = (0..100)
+            .map(|_| game.ecs.spawn((far_position, Wander::default())))
+            .collect();
+
+        update_simulated_chunks(&mut game).unwrap();
+        wander(&mut game).unwrap();
+
+        assert_ne!(
+            *game.ecs.get::<Position>(near).unwrap(),
+            Position::default()
+        );
+        for entity in far {
+            assert_eq!(*game.ecs.get::<Position>(entity).unwrap(), far_position);
+        }
+    }
+
+    #[test]
+    fn nearest_entity_respects_distance() {
+        let mut game = Game::new();
+        let far = Position {
+            x: 20.0,
+            ..Default::default()
+        };
+        let player = game.ecs.spawn((far, Player));
+        game.chunk_entities.add_entity(player, far.chunk());
+        assert_eq!(
+            game.nearest_entity(Position::default(), FOLLOW_DISTANCE, |_| true),
+            None
+        );
+    }
+}
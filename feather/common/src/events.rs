@@ -1,11 +1,13 @@
-use base::{ChunkHandle, ChunkPosition};
+use base::{ChunkHandle, ChunkPosition, Item, ValidBlockPosition};
+use ecs::Entity;
+use libcraft_core::Hand;
 
 use crate::view::View;
 
 mod block_change;
 mod plugin_message;
 
-pub use block_change::BlockChangeEvent;
+pub use block_change::{BlockChange, BlockChangeEvent};
 pub use plugin_message::PluginMessageEvent;
 
 /// Event triggered when a player changes their `View`,
@@ -37,6 +39,11 @@ impl ViewUpdateEvent {
     }
 }
 
+/// Event triggered on a vehicle when entities
+/// start or stop riding it.
+#[derive(Debug)]
+pub struct PassengersChangeEvent;
+
 /// Event triggered when an entity crosses into a new chunk.
 ///
 /// Unlike [`ViewUpdateEvent`], this event triggers for all entities,
@@ -58,3 +65,74 @@ pub struct ChunkLoadEvent {
 pub struct ChunkLoadFailEvent {
     pub position: ChunkPosition,
 }
+
+/// Triggered when an entity takes damage.
+#[derive(Debug)]
+pub struct DamageEvent {
+    /// The entity that caused the damage, if any.
+    /// Damaged entities are knocked back away from the attacker.
+    pub attacker: Option<Entity>,
+    pub amount: f32,
+}
+
+/// Triggered when a player's health, food level or saturation
+/// changes, so that the player can be sent its new values.
+#[derive(Debug)]
+pub struct HealthUpdateEvent;
+
+/// Triggered when a player's experience changes.
+#[derive(Debug)]
+pub struct ExperienceChangeEvent;
+
+/// Triggered when a player uses the item held in a hand,
+/// e.g. to throw an ender pearl or eat food.
+///
+/// Not triggered for items on cooldown.
+#[derive(Debug)]
+pub struct ItemUseEvent {
+    pub item: Item,
+    pub hand: Hand,
+}
+
+/// A player interaction about to be applied, given to the interaction
+/// handlers of the server, e.g. to protect regions of the world.
+///
+/// A handler setting `cancelled` prevents the interaction, and the
+/// server reverts what the player's client predicted, such as a
+/// placed block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerInteractEvent {
+    pub player: Entity,
+    pub kind: InteractionKind,
+    pub target: InteractionTarget,
+    pub cancelled: bool,
+}
+
+impl PlayerInteractEvent {
+    pub fn new(player: Entity, kind: InteractionKind, target: InteractionTarget) -> Self {
+        Self {
+            player,
+            kind,
+            target,
+            cancelled: false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InteractionKind {
+    /// Breaking the target block.
+    BreakBlock,
+    /// Placing a block at the target position.
+    PlaceBlock,
+    /// Left-clicking the target entity.
+    AttackEntity,
+    /// Right-clicking the target entity.
+    InteractEntity,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InteractionTarget {
+    Block(ValidBlockPosition),
+    Entity(Entity),
+}
@@ -1,12 +1,21 @@
-use std::{convert::TryInto, iter};
+use std::{convert::TryInto, iter, sync::Arc};
 
+use ahash::AHashMap;
 use base::{
     chunk::{SECTION_HEIGHT, SECTION_VOLUME},
-    BlockPosition, ChunkPosition, ValidBlockPosition,
+    BlockId, BlockPosition, ChunkPosition, ChunkSection, ValidBlockPosition,
 };
 use itertools::Either;
 
-/// Event triggered when one or more blocks are changed.
+/// Event triggered when one or more blocks are changed,
+/// whatever the source of the change.
+///
+/// [`Game::set_block`](crate::Game::set_block) triggers one event per block,
+/// while [`Game::set_blocks`](crate::Game::set_blocks) and
+/// [`Game::fill_chunk_section`](crate::Game::fill_chunk_section)
+/// trigger a single event for the whole batch. Batch changes such as
+/// explosions should use the latter so that systems reacting to
+/// block changes can process them at once.
 ///
 /// This event can efficiently store bulk block updates
 /// using a variety of different representations. Cloning
@@ -16,19 +25,53 @@ pub struct BlockChangeEvent {
     changes: BlockChanges,
 }
 
+/// A change of a single block.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BlockChange {
+    pub position: ValidBlockPosition,
+    /// The block before the change.
+    pub old: BlockId,
+    /// The block after the change.
+    pub new: BlockId,
+}
+
 impl BlockChangeEvent {
     /// Creates an event affecting a single block.
-    pub fn single(pos: ValidBlockPosition) -> Self {
+    pub fn single(pos: ValidBlockPosition, old: BlockId, new: BlockId) -> Self {
         Self {
-            changes: BlockChanges::Single { pos },
+            changes: BlockChanges::Single(BlockChange {
+                position: pos,
+                old,
+                new,
+            }),
+        }
+    }
+
+    /// Creates an event affecting any number of blocks.
+    pub fn multiple(changes: Vec<BlockChange>) -> Self {
+        Self {
+            changes: BlockChanges::Multiple(changes.into()),
         }
     }
 
     /// Creates an event corresponding to a block update
     /// that fills an entire chunk section with the same block.
-    pub fn fill_chunk_section(chunk: ChunkPosition, section: u32) -> Self {
+    ///
+    /// `old` is the section before the update, or `None`
+    /// if it was empty.
+    pub fn fill_chunk_section(
+        chunk: ChunkPosition,
+        section: u32,
+        old: Option<ChunkSection>,
+        new: BlockId,
+    ) -> Self {
         Self {
-            changes: BlockChanges::FillChunkSection { chunk, section },
+            changes: BlockChanges::FillChunkSection {
+                chunk,
+                section,
+                old: old.map(Arc::new),
+                new,
+            },
         }
     }
 
@@ -36,18 +79,45 @@ impl BlockChangeEvent {
     /// changed in this block change event.
     pub fn count(&self) -> usize {
         match &self.changes {
-            BlockChanges::Single { .. } => 1,
+            BlockChanges::Single(_) => 1,
+            BlockChanges::Multiple(changes) => changes.len(),
             BlockChanges::FillChunkSection { .. } => SECTION_VOLUME,
         }
     }
 
     /// Returns an iterator over block positions affected by this block change.
     pub fn iter_changed_blocks(&self) -> impl Iterator<Item = ValidBlockPosition> + '_ {
+        self.iter_changes().map(|change| change.position)
+    }
+
+    /// Returns an iterator over the changes in this event,
+    /// including the old and new block of each position.
+    pub fn iter_changes(&self) -> impl Iterator<Item = BlockChange> + '_ {
         match &self.changes {
-            BlockChanges::Single { pos } => Either::Left(iter::once(*pos)),
-            BlockChanges::FillChunkSection { chunk, section } => {
-                Either::Right(iter_section_blocks(*chunk, *section))
-            }
+            BlockChanges::Single(change) => Either::Left(Either::Left(iter::once(*change))),
+            BlockChanges::Multiple(changes) => Either::Left(Either::Right(changes.iter().copied())),
+            BlockChanges::FillChunkSection {
+                chunk,
+                section,
+                old,
+                new,
+            } => Either::Right(iter_section_blocks(*chunk, *section).map(move |position| {
+                let old = old
+                    .as_ref()
+                    .and_then(|old| {
+                        old.block_at(
+                            position.x().rem_euclid(16) as usize,
+                            position.y().rem_euclid(16) as usize,
+                            position.z().rem_euclid(16) as usize,
+                        )
+                    })
+                    .unwrap_or_else(BlockId::air);
+                BlockChange {
+                    position,
+                    old,
+                    new: *new,
+                }
+            })),
         }
     }
 
@@ -59,12 +129,28 @@ impl BlockChangeEvent {
         &self,
     ) -> impl Iterator<Item = (ChunkPosition, usize, usize)> + '_ {
         match &self.changes {
-            BlockChanges::Single { pos } => {
-                iter::once((pos.chunk(), pos.y() as usize / SECTION_HEIGHT, 1))
-            }
-            BlockChanges::FillChunkSection { chunk, section } => {
-                iter::once((*chunk, *section as usize, SECTION_VOLUME))
+            BlockChanges::Single(change) => Either::Left(iter::once((
+                change.position.chunk(),
+                change.position.y() as usize / SECTION_HEIGHT,
+                1,
+            ))),
+            BlockChanges::Multiple(changes) => {
+                let mut sections = AHashMap::new();
+                for change in changes.iter() {
+                    let section = change.position.y() as usize / SECTION_HEIGHT;
+                    *sections
+                        .entry((change.position.chunk(), section))
+                        .or_insert(0) += 1;
+                }
+                Either::Right(Either::Left(
+                    sections
+                        .into_iter()
+                        .map(|((chunk, section), count)| (chunk, section, count)),
+                ))
             }
+            BlockChanges::FillChunkSection { chunk, section, .. } => Either::Right(Either::Right(
+                iter::once((*chunk, *section as usize, SECTION_VOLUME)),
+            )),
         }
     }
 }
@@ -89,9 +175,16 @@ fn iter_section_blocks(
 #[derive(Debug, Clone)]
 enum BlockChanges {
     /// A single block change.
-    Single { pos: ValidBlockPosition },
+    Single(BlockChange),
+    /// Any number of block changes, e.g. from an explosion.
+    Multiple(Arc<[BlockChange]>),
     /// A whole chunk section was filled with the same block.
-    FillChunkSection { chunk: ChunkPosition, section: u32 },
+    FillChunkSection {
+        chunk: ChunkPosition,
+        section: u32,
+        old: Option<Arc<ChunkSection>>,
+        new: BlockId,
+    },
 }
 
 #[cfg(test)]
@@ -104,26 +197,66 @@ mod tests {
     #[test]
     fn create_single() {
         let pos = BlockPosition::new(5, 64, 9).try_into().unwrap();
-        let event = BlockChangeEvent::single(pos);
+        let event = BlockChangeEvent::single(pos, BlockId::air(), BlockId::stone());
         assert_eq!(event.count(), 1);
         assert_eq!(event.iter_changed_blocks().collect::<Vec<_>>(), vec![pos]);
+        assert_eq!(
+            event.iter_changes().collect::<Vec<_>>(),
+            vec![BlockChange {
+                position: pos,
+                old: BlockId::air(),
+                new: BlockId::stone(),
+            }]
+        );
         assert_eq!(
             event.iter_affected_chunk_sections().collect::<Vec<_>>(),
             vec![(pos.chunk(), 4, 1)]
         );
     }
 
+    #[test]
+    fn create_multiple() {
+        let change = |x, y, z| BlockChange {
+            position: BlockPosition::new(x, y, z).try_into().unwrap(),
+            old: BlockId::stone(),
+            new: BlockId::air(),
+        };
+        let changes = vec![change(0, 64, 0), change(1, 64, 0), change(0, 80, 0)];
+        let event = BlockChangeEvent::multiple(changes.clone());
+        assert_eq!(event.count(), 3);
+        assert_eq!(event.iter_changes().collect::<Vec<_>>(), changes);
+
+        let mut sections: Vec<_> = event.iter_affected_chunk_sections().collect();
+        sections.sort_unstable_by_key(|&(_, section, _)| section);
+        let chunk = ChunkPosition::new(0, 0);
+        assert_eq!(sections, vec![(chunk, 4, 2), (chunk, 5, 1)]);
+    }
+
     #[test]
     fn create_chunk_section_fill() {
         let chunk = ChunkPosition::new(10, 15);
         let section_y = 5;
-        let event = BlockChangeEvent::fill_chunk_section(chunk, section_y);
+        let mut old = ChunkSection::default();
+        old.set_block_at(1, 2, 3, BlockId::stone());
+        let event =
+            BlockChangeEvent::fill_chunk_section(chunk, section_y, Some(old), BlockId::dirt());
         assert_eq!(event.count(), SECTION_VOLUME);
         assert_eq!(event.iter_changed_blocks().count(), SECTION_VOLUME);
         assert_eq!(
             event.iter_affected_chunk_sections().collect::<Vec<_>>(),
             vec![(chunk, section_y as usize, SECTION_VOLUME)]
         );
+
+        let stone: ValidBlockPosition = BlockPosition::new(161, 82, 243).try_into().unwrap();
+        for change in event.iter_changes() {
+            let expected_old = if change.position == stone {
+                BlockId::stone()
+            } else {
+                BlockId::air()
+            };
+            assert_eq!(change.old, expected_old);
+            assert_eq!(change.new, BlockId::dirt());
+        }
     }
 
     #[test]
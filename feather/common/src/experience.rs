@@ -0,0 +1,172 @@
+//! Experience points carried by orbs and collected by players.
+
+use ahash::AHashSet;
+use base::{ChunkPosition, Position};
+use ecs::{Entity, SysResult, SystemExecutor};
+use quill_common::events::EntityRemoveEvent;
+
+use crate::{simulation::SimulatedChunks, Game};
+
+/// Distance along each axis within which orbs merge.
+const MERGE_RADIUS: f64 = 0.5;
+
+pub fn register(systems: &mut SystemExecutor<Game>) {
+    systems.add_system(merge_experience_orbs);
+}
+
+/// The experience carried by an experience orb entity.
+///
+/// Not to be confused with the
+/// [`ExperienceOrb`](quill_common::entities::ExperienceOrb)
+/// marker component.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ExperienceOrb {
+    pub amount: u32,
+}
+
+/// The experience collected by a player.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Experience {
+    /// Total experience points collected.
+    pub total: u32,
+}
+
+impl Experience {
+    pub fn add(&mut self, amount: u32) {
+        self.total = self.total.saturating_add(amount);
+    }
+
+    /// Returns the current level.
+    pub fn level(self) -> u32 {
+        self.level_and_points().0
+    }
+
+    /// Returns the progress towards the next level, from 0 to 1.
+    pub fn progress(self) -> f32 {
+        let (level, points) = self.level_and_points();
+        points as f32 / points_to_next_level(level) as f32
+    }
+
+    /// Returns the level and the points collected towards the next level.
+    fn level_and_points(self) -> (u32, u32) {
+        let mut level = 0;
+        let mut points = self.total;
+        while points >= points_to_next_level(level) {
+            points -= points_to_next_level(level);
+            level += 1;
+        }
+        (level, points)
+    }
+}
+
+/// The experience needed to advance from `level` to the next level.
+fn points_to_next_level(level: u32) -> u32 {
+    match level {
+        0..=15 => 2 * level + 7,
+        16..=30 => 5 * level - 38,
+        _ => 9 * level - 158,
+    }
+}
+
+/// Merges experience orbs close to each other into a single orb.
+///
+/// Only orbs in simulated chunks absorb the orbs around them.
+fn merge_experience_orbs(game: &mut Game) -> SysResult {
+    let simulated = game.resources.get::<SimulatedChunks>().ok();
+    let mut merged = AHashSet::new();
+    let mut merges: Vec<(Entity, Entity)> = Vec::new();
+    for (orb, (_, &position)) in game.ecs.query::<(&ExperienceOrb, &Position)>().iter() {
+        if merged.contains(&orb) || game.ecs.get::<EntityRemoveEvent>(orb).is_ok() {
+            continue;
+        }
+        let center = position.chunk();
+        if let Some(simulated) = &simulated {
+            if !simulated.contains(center) {
+                continue;
+            }
+        }
+        for x in center.x - 1..=center.x + 1 {
+            for z in center.z - 1..=center.z + 1 {
+                for &other in game
+                    .chunk_entities
+                    .entities_in_chunk(ChunkPosition::new(x, z))
+                {
+                    if other == orb
+                        || merged.contains(&other)
+                        || game.ecs.get::<ExperienceOrb>(other).is_err()
+                        || game.ecs.get::<EntityRemoveEvent>(other).is_ok()
+                    {
+                        continue;
+                    }
+                    let other_position = match game.ecs.get::<Position>(other) {
+                        Ok(other_position) => *other_position,
+                        Err(_) => continue,
+                    };
+                    if (other_position.x - position.x).abs() <= MERGE_RADIUS
+                        && (other_position.y - position.y).abs() <= MERGE_RADIUS
+                        && (other_position.z - position.z).abs() <= MERGE_RADIUS
+                    {
+                        merged.insert(other);
+                        merges.push((orb, other));
+                    }
+                }
+            }
+        }
+    }
+
+    for (orb, other) in merges {
+        let amount = game.ecs.get::<ExperienceOrb>(other)?.amount;
+        let mut target = game.ecs.get_mut::<ExperienceOrb>(orb)?;
+        target.amount = target.amount.saturating_add(amount);
+        drop(target);
+        game.remove_entity(other)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use base::position;
+
+    use super::*;
+
+    #[test]
+    fn levels_follow_vanilla_thresholds() {
+        assert_eq!(Experience { total: 0 }.level(), 0);
+        assert_eq!(Experience { total: 7 }.level(), 1);
+        // Level 16 takes 352 points, level 31 takes 1507
+        assert_eq!(Experience { total: 351 }.level(), 15);
+        assert_eq!(Experience { total: 352 }.level(), 16);
+        assert_eq!(Experience { total: 1507 }.level(), 31);
+        assert!((Experience { total: 10 }.progress() - 3.0 / 9.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn close_orbs_merge() {
+        let mut game = Game::new();
+        let orb = game
+            .ecs
+            .spawn((ExperienceOrb { amount: 3 }, position!(0.5, 64.0, 0.5)));
+        let close = game
+            .ecs
+            .spawn((ExperienceOrb { amount: 7 }, position!(0.8, 64.2, 0.5)));
+        let far = game
+            .ecs
+            .spawn((ExperienceOrb { amount: 1 }, position!(5.0, 64.0, 0.5)));
+        for &entity in &[orb, close, far] {
+            let chunk = game.ecs.get::<Position>(entity).unwrap().chunk();
+            game.chunk_entities.add_entity(entity, chunk);
+        }
+
+        merge_experience_orbs(&mut game).unwrap();
+        let (kept, removed) = if game.ecs.get::<EntityRemoveEvent>(close).is_ok() {
+            (orb, close)
+        } else {
+            (close, orb)
+        };
+        assert!(game.ecs.get::<EntityRemoveEvent>(removed).is_ok());
+        assert!(game.ecs.get::<EntityRemoveEvent>(kept).is_err());
+        assert_eq!(game.ecs.get::<ExperienceOrb>(kept).unwrap().amount, 10);
+        assert!(game.ecs.get::<EntityRemoveEvent>(far).is_err());
+    }
+}
@@ -1,17 +1,21 @@
 use std::{cell::RefCell, mem, rc::Rc, sync::Arc};
 
-use base::{BlockId, ChunkPosition, Position, Text, Title, ValidBlockPosition};
+use ahash::AHashSet;
+use base::{
+    BlockId, ChunkPosition, Difficulty, EntityKind, Gamemode, Position, Text, Title,
+    ValidBlockPosition, Vec3d, CHUNK_WIDTH,
+};
 use ecs::{
     Ecs, Entity, EntityBuilder, HasEcs, HasResources, NoSuchEntity, Resources, SysResult,
     SystemExecutor,
 };
-use quill_common::events::{EntityCreateEvent, EntityRemoveEvent, PlayerJoinEvent};
+use quill_common::events::{EntityCreateEvent, EntityRemoveEvent, GamemodeEvent, PlayerJoinEvent};
 use quill_common::{entities::Player, entity_init::EntityInit};
 
 use crate::{
     chat::{ChatKind, ChatMessage},
     chunk::entities::ChunkEntities,
-    events::BlockChangeEvent,
+    events::{BlockChange, BlockChangeEvent},
     ChatBox, World,
 };
 
@@ -52,6 +56,9 @@ pub struct Game {
     /// Total ticks elapsed since the server started.
     pub tick_count: u64,
 
+    /// The difficulty of the game.
+    pub difficulty: Difficulty,
+
     entity_spawn_callbacks: Vec<EntitySpawnCallback>,
 
     entity_builder: EntityBuilder,
@@ -73,11 +80,22 @@ impl Game {
             resources: Arc::new(Resources::new()),
             chunk_entities: ChunkEntities::default(),
             tick_count: 0,
+            difficulty: Difficulty::default(),
             entity_spawn_callbacks: Vec::new(),
             entity_builder: EntityBuilder::new(),
         }
     }
 
+    /// Returns an iterator over the loaded worlds.
+    ///
+    /// A `Game` currently has exactly one world
+    /// ([`Game::world`]), so this always yields a single item.
+    /// The ordering is stable, so it can be used to build
+    /// deterministic packets such as Join Game.
+    pub fn worlds(&self) -> impl Iterator<Item = &World> + '_ {
+        std::iter::once(&self.world)
+    }
+
     /// Inserts a new resource.
     ///
     /// An existing resource with type `T` is overriden.
@@ -158,6 +176,33 @@ impl Game {
         self.ecs.insert_entity_event(entity, EntityRemoveEvent)
     }
 
+    /// Removes all entities in the world, keeping
+    /// players if `keep_players` is set.
+    ///
+    /// Each entity is removed as with [`remove_entity`](Self::remove_entity),
+    /// so clients observing it are told to destroy it and it leaves
+    /// [`chunk_entities`](Self::chunk_entities) before it is despawned.
+    /// The server disconnects the clients of removed players.
+    /// Returns the number of removed entities.
+    pub fn clear_entities(&mut self, keep_players: bool) -> usize {
+        let entities: Vec<Entity> = self
+            .ecs
+            .query::<&Position>()
+            .iter()
+            .map(|(entity, _)| entity)
+            .filter(|&entity| {
+                self.ecs.get::<EntityRemoveEvent>(entity).is_err()
+                    && !(keep_players && self.ecs.get::<Player>(entity).is_ok())
+            })
+            .collect();
+
+        for &entity in &entities {
+            // The entity exists as it was just queried.
+            let _ = self.remove_entity(entity);
+        }
+        entities.len()
+    }
+
     /// Broadcasts a chat message to all entities with
     /// a `ChatBox` component (usually just players).
     pub fn broadcast_chat(&self, kind: ChatKind, message: impl Into<Text>) {
@@ -181,6 +226,22 @@ impl Game {
         Ok(())
     }
 
+    /// Gets the gamemode of a player.
+    pub fn gamemode(&self, player: Entity) -> anyhow::Result<Gamemode> {
+        Ok(*self.ecs.get::<Gamemode>(player)?)
+    }
+
+    /// Changes the gamemode of a player.
+    ///
+    /// The player's abilities are updated and the change is
+    /// sent to clients when the server systems next run.
+    pub fn set_gamemode(&mut self, player: Entity, gamemode: Gamemode) -> SysResult {
+        self.ecs.get::<Gamemode>(player)?;
+        self.ecs
+            .insert_entity_event(player, GamemodeEvent(gamemode))?;
+        Ok(())
+    }
+
     /// Gets the block at the given position.
     pub fn block(&self, pos: ValidBlockPosition) -> Option<BlockId> {
         self.world.block_at(pos)
@@ -190,13 +251,46 @@ impl Game {
     ///
     /// Triggers necessary `BlockChangeEvent`s.
     pub fn set_block(&mut self, pos: ValidBlockPosition, block: BlockId) -> bool {
+        let old = self.block(pos).unwrap_or_else(BlockId::air);
         let was_successful = self.world.set_block_at(pos, block);
         if was_successful {
-            self.ecs.insert_event(BlockChangeEvent::single(pos));
+            self.ecs
+                .insert_event(BlockChangeEvent::single(pos, old, block));
         }
         was_successful
     }
 
+    /// Sets the blocks at the given positions, skipping
+    /// positions in unloaded chunks.
+    ///
+    /// Triggers a single `BlockChangeEvent` for all changed blocks.
+    /// Returns the number of changed blocks.
+    pub fn set_blocks(
+        &mut self,
+        blocks: impl IntoIterator<Item = (ValidBlockPosition, BlockId)>,
+    ) -> usize {
+        let mut changes = Vec::new();
+        for (pos, block) in blocks {
+            let old = match self.block(pos) {
+                Some(old) => old,
+                None => continue,
+            };
+            if self.world.set_block_at(pos, block) {
+                changes.push(BlockChange {
+                    position: pos,
+                    old,
+                    new: block,
+                });
+            }
+        }
+
+        let count = changes.len();
+        if count > 0 {
+            self.ecs.insert_event(BlockChangeEvent::multiple(changes));
+        }
+        count
+    }
+
     /// Fills the given chunk section (16x16x16 blocks).
     ///
     /// All blocks in the chunk section are overwritten with `block`.
@@ -211,6 +305,7 @@ impl Game {
             None => return false,
         };
 
+        let old = chunk.section(section_y as isize).cloned();
         let was_successful = chunk.fill_section(section_y + 1, block);
 
         if !was_successful {
@@ -220,11 +315,122 @@ impl Game {
         self.ecs.insert_event(BlockChangeEvent::fill_chunk_section(
             chunk_pos,
             section_y as u32,
+            old,
+            block,
         ));
 
         true
     }
 
+    /// Returns the entity nearest to `position` within `max_distance`
+    /// blocks for which `filter` returns `true`.
+    ///
+    /// Entities are looked up in [`chunk_entities`](Self::chunk_entities),
+    /// so only entities indexed as of the last chunk entity update
+    /// in this tick are considered.
+    pub fn nearest_entity(
+        &self,
+        position: Position,
+        max_distance: f64,
+        mut filter: impl FnMut(Entity) -> bool,
+    ) -> Option<Entity> {
+        let center = position.chunk();
+        let radius = (max_distance / CHUNK_WIDTH as f64).ceil() as i32;
+
+        let mut nearest = None;
+        let mut nearest_distance = max_distance;
+        for x in center.x - radius..=center.x + radius {
+            for z in center.z - radius..=center.z + radius {
+                let chunk = ChunkPosition::new(x, z);
+                for &entity in self.chunk_entities.entities_in_chunk(chunk) {
+                    let entity_position = match self.ecs.get::<Position>(entity) {
+                        Ok(entity_position) => *entity_position,
+                        Err(_) => continue,
+                    };
+                    let distance = position.distance_to(entity_position);
+                    if distance <= nearest_distance && filter(entity) {
+                        nearest = Some(entity);
+                        nearest_distance = distance;
+                    }
+                }
+            }
+        }
+        nearest
+    }
+
+    /// Casts a ray from `origin` in `direction` and returns the first entity
+    /// whose bounding box it hits within `max_distance` blocks, along with
+    /// the distance to the hit.
+    ///
+    /// Entities for which `predicate` returns `false` are ignored, e.g.
+    /// the caster itself or players in spectator mode. A ray starting
+    /// inside an entity's bounding box hits it at distance 0. Only entities
+    /// with an [`EntityKind`] are considered, and, as with
+    /// [`nearest_entity`](Self::nearest_entity), they are looked up
+    /// in [`chunk_entities`](Self::chunk_entities).
+    pub fn raycast_entities(
+        &self,
+        origin: Position,
+        direction: Vec3d,
+        max_distance: f64,
+        mut predicate: impl FnMut(Entity) -> bool,
+    ) -> Option<(Entity, f64)> {
+        let length = direction.magnitude();
+        if length < f64::EPSILON || !max_distance.is_finite() || max_distance < 0.0 {
+            return None;
+        }
+        let direction = direction / length;
+
+        let mut nearest: Option<(Entity, f64)> = None;
+        let mut searched = AHashSet::new();
+        for (chunk, entry_distance) in chunks_along_ray(origin, direction, max_distance) {
+            // Hits in later chunks are further away than the nearest one
+            if entry_distance > nearest.map_or(max_distance, |(_, distance)| distance) {
+                break;
+            }
+            // Bounding boxes may extend into the chunks next to an entity's own
+            for x in chunk.x - 1..=chunk.x + 1 {
+                for z in chunk.z - 1..=chunk.z + 1 {
+                    let neighbor = ChunkPosition::new(x, z);
+                    if !searched.insert(neighbor) {
+                        continue;
+                    }
+                    for &entity in self.chunk_entities.entities_in_chunk(neighbor) {
+                        let distance = match self.ray_hit_distance(entity, origin, direction) {
+                            Some(distance) => distance,
+                            None => continue,
+                        };
+                        let is_nearer = nearest
+                            .map_or(distance <= max_distance, |(_, nearest)| distance < nearest);
+                        if is_nearer && predicate(entity) {
+                            nearest = Some((entity, distance));
+                        }
+                    }
+                }
+            }
+        }
+        nearest
+    }
+
+    /// Returns the distance along a ray at which it
+    /// enters the bounding box of `entity`.
+    fn ray_hit_distance(&self, entity: Entity, origin: Position, direction: Vec3d) -> Option<f64> {
+        let position = *self.ecs.get::<Position>(entity).ok()?;
+        let bounds = self.ecs.get::<EntityKind>(entity).ok()?.bounding_box();
+        let half_width = (bounds.max.x - bounds.min.x) / 2.0;
+        let height = bounds.max.y - bounds.min.y;
+        ray_box_intersection(
+            origin,
+            direction,
+            [position.x - half_width, position.y, position.z - half_width],
+            [
+                position.x + half_width,
+                position.y + height,
+                position.z + half_width,
+            ],
+        )
+    }
+
     /// Breaks the block at the given position, propagating any
     /// necessary block updates.
     pub fn break_block(&mut self, pos: ValidBlockPosition) -> bool {
@@ -232,6 +438,113 @@ impl Game {
     }
 }
 
+/// Returns the chunks a ray passes through within `max_distance`
+/// blocks, in order, each with the distance at which the ray enters it.
+///
+/// `direction` must be normalized. Chunks are computed as the
+/// iterator advances, so callers can stop at the first hit.
+/// A non-finite `max_distance` yields only the origin's chunk.
+fn chunks_along_ray(
+    origin: Position,
+    direction: Vec3d,
+    max_distance: f64,
+) -> impl Iterator<Item = (ChunkPosition, f64)> {
+    let (step_x, next_x, delta_x) = ray_axis_steps(origin.x, direction.x);
+    let (step_z, next_z, delta_z) = ray_axis_steps(origin.z, direction.z);
+    ChunksAlongRay {
+        next: Some((origin.chunk(), 0.0)),
+        max_distance: if max_distance.is_finite() {
+            max_distance
+        } else {
+            0.0
+        },
+        step: (step_x, step_z),
+        next_border: (next_x, next_z),
+        delta: (delta_x, delta_z),
+    }
+}
+
+/// Iterator returned by [`chunks_along_ray`]. Distances are
+/// given as `(x, z)` pairs, one for each horizontal axis.
+struct ChunksAlongRay {
+    /// The next chunk to return and the distance at which the ray enters it.
+    next: Option<(ChunkPosition, f64)>,
+    max_distance: f64,
+    step: (i32, i32),
+    /// Distance to the next chunk border crossed on each axis.
+    next_border: (f64, f64),
+    /// Distance between consecutive chunk borders on each axis.
+    delta: (f64, f64),
+}
+
+impl Iterator for ChunksAlongRay {
+    type Item = (ChunkPosition, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next.take()?;
+        let (next_x, next_z) = self.next_border;
+        let distance = next_x.min(next_z);
+        if distance <= self.max_distance {
+            let mut chunk = current.0;
+            if next_x < next_z {
+                chunk.x += self.step.0;
+                self.next_border.0 += self.delta.0;
+            } else {
+                chunk.z += self.step.1;
+                self.next_border.1 += self.delta.1;
+            }
+            self.next = Some((chunk, distance));
+        }
+        Some(current)
+    }
+}
+
+/// Returns, for one horizontal axis of a ray, the direction in which it
+/// crosses chunk borders, the distance to the first border and
+/// the distance between consecutive borders.
+fn ray_axis_steps(origin: f64, direction: f64) -> (i32, f64, f64) {
+    let width = CHUNK_WIDTH as f64;
+    if direction > 0.0 {
+        let border = ((origin / width).floor() + 1.0) * width;
+        (1, (border - origin) / direction, width / direction)
+    } else if direction < 0.0 {
+        let border = (origin / width).floor() * width;
+        (-1, (border - origin) / direction, -width / direction)
+    } else {
+        (0, f64::INFINITY, f64::INFINITY)
+    }
+}
+
+/// Returns the distance along a ray at which it enters the box
+/// from `min` to `max`, or 0 if it starts inside the box.
+fn ray_box_intersection(
+    origin: Position,
+    direction: Vec3d,
+    min: [f64; 3],
+    max: [f64; 3],
+) -> Option<f64> {
+    let origin = [origin.x, origin.y, origin.z];
+    let direction = [direction.x, direction.y, direction.z];
+    let mut near = 0.0f64;
+    let mut far = f64::INFINITY;
+    for axis in 0..3 {
+        if direction[axis].abs() < f64::EPSILON {
+            if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let to_min = (min[axis] - origin[axis]) / direction[axis];
+        let to_max = (max[axis] - origin[axis]) / direction[axis];
+        near = near.max(to_min.min(to_max));
+        far = far.min(to_min.max(to_max));
+        if near > far {
+            return None;
+        }
+    }
+    Some(near)
+}
+
 impl HasResources for Game {
     fn resources(&self) -> Arc<Resources> {
         Arc::clone(&self.resources)
@@ -247,3 +560,131 @@ impl HasEcs for Game {
         &mut self.ecs
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use base::position;
+
+    use super::*;
+
+    fn spawn_zombie(game: &mut Game, position: Position) -> Entity {
+        let entity = game.ecs.spawn((position, EntityKind::Zombie));
+        game.chunk_entities.add_entity(entity, position.chunk());
+        entity
+    }
+
+    #[test]
+    fn raycast_hits_nearest_entity_matching_predicate() {
+        let mut game = Game::new();
+        let caster = spawn_zombie(&mut game, position!(0.5, 64.0, 0.5));
+        let near = spawn_zombie(&mut game, position!(10.5, 64.0, 0.5));
+        let far = spawn_zombie(&mut game, position!(20.5, 64.0, 0.5));
+
+        let origin = position!(0.5, 65.0, 0.5);
+        let direction = Vec3d::new(1.0, 0.0, 0.0);
+        let (hit, distance) = game
+            .raycast_entities(origin, direction, 32.0, |entity| entity != caster)
+            .unwrap();
+        assert_eq!(hit, near);
+        assert!((distance - 9.7).abs() < 0.01);
+
+        let (hit, _) = game
+            .raycast_entities(origin, direction, 32.0, |entity| {
+                entity != caster && entity != near
+            })
+            .unwrap();
+        assert_eq!(hit, far);
+    }
+
+    #[test]
+    fn raycast_stops_at_max_distance() {
+        let mut game = Game::new();
+        spawn_zombie(&mut game, position!(40.5, 64.0, -3.5));
+
+        let origin = position!(0.5, 65.0, -3.5);
+        let direction = Vec3d::new(2.0, 0.0, 0.0);
+        assert!(game
+            .raycast_entities(origin, direction, 32.0, |_| true)
+            .is_none());
+        assert!(game
+            .raycast_entities(origin, direction, 48.0, |_| true)
+            .is_some());
+        assert!(game
+            .raycast_entities(origin, -direction, 48.0, |_| true)
+            .is_none());
+    }
+
+    #[test]
+    fn chunks_along_ray_are_in_order() {
+        let direction = Vec3d::new(-1.0, 0.0, 1.0).normalized();
+        let chunks: Vec<_> = chunks_along_ray(position!(1.0, 0.0, 14.0), direction, 8.0)
+            .map(|(chunk, _)| chunk)
+            .collect();
+        assert_eq!(
+            chunks,
+            vec![
+                ChunkPosition::new(0, 0),
+                ChunkPosition::new(-1, 0),
+                ChunkPosition::new(-1, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn chunks_along_ray_are_computed_lazily() {
+        let direction = Vec3d::new(1.0, 0.0, 0.0);
+        let chunks: Vec<_> = chunks_along_ray(position!(0.5, 0.0, 0.5), direction, 1e12)
+            .take(3)
+            .collect();
+        assert_eq!(
+            chunks,
+            vec![
+                (ChunkPosition::new(0, 0), 0.0),
+                (ChunkPosition::new(1, 0), 15.5),
+                (ChunkPosition::new(2, 0), 31.5),
+            ]
+        );
+
+        // Straight up, no chunk borders are ever crossed
+        let up = Vec3d::new(0.0, 1.0, 0.0);
+        assert_eq!(
+            chunks_along_ray(position!(0.5, 0.0, 0.5), up, 1e12).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn raycast_with_infinite_distance_hits_nothing() {
+        let game = Game::new();
+        let origin = position!(0.5, 65.0, 0.5);
+        let direction = Vec3d::new(1.0, 0.0, 0.0);
+        assert_eq!(
+            chunks_along_ray(origin, direction, f64::INFINITY).count(),
+            1
+        );
+        assert!(game
+            .raycast_entities(origin, direction, f64::INFINITY, |_| true)
+            .is_none());
+        assert!(game
+            .raycast_entities(origin, direction, f64::NAN, |_| true)
+            .is_none());
+    }
+
+    #[test]
+    fn ray_box_intersection_distance() {
+        let direction = Vec3d::new(0.0, -1.0, 0.0);
+        let (min, max) = ([0.0, 0.0, 0.0], [1.0, 1.0, 1.0]);
+        assert_eq!(
+            ray_box_intersection(position!(0.5, 3.0, 0.5), direction, min, max),
+            Some(2.0)
+        );
+        assert_eq!(
+            ray_box_intersection(position!(0.5, 0.5, 0.5), direction, min, max),
+            Some(0.0)
+        );
+        assert_eq!(
+            ray_box_intersection(position!(1.5, 3.0, 0.5), direction, min, max),
+            None
+        );
+    }
+}
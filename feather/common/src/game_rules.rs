@@ -0,0 +1,127 @@
+//! Game rules, as changed by the `/gamerule` command.
+
+use std::fmt;
+
+use anyhow::bail;
+
+use crate::Game;
+
+pub fn register(game: &mut Game) {
+    game.insert_resource(GameRules::default());
+}
+
+/// The game rules of the server.
+///
+/// Stored as a resource in the `Game`. Systems read the typed fields;
+/// commands use [`get`](Self::get) and [`set`](Self::set), which take
+/// the vanilla rule names.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameRules {
+    /// Whether mobs spawn naturally.
+    pub do_mob_spawning: bool,
+    /// Whether players keep their inventory when they die.
+    pub keep_inventory: bool,
+    /// Whether players regenerate health when their food level is high.
+    pub natural_regeneration: bool,
+}
+
+impl Default for GameRules {
+    fn default() -> Self {
+        Self {
+            do_mob_spawning: true,
+            keep_inventory: false,
+            natural_regeneration: true,
+        }
+    }
+}
+
+impl GameRules {
+    /// The names of all game rules.
+    pub const NAMES: &'static [&'static str] =
+        &["doMobSpawning", "keepInventory", "naturalRegeneration"];
+
+    /// Returns the value of the rule called `name`.
+    pub fn get(&self, name: &str) -> anyhow::Result<GameRuleValue> {
+        let value = match name {
+            "doMobSpawning" => self.do_mob_spawning,
+            "keepInventory" => self.keep_inventory,
+            "naturalRegeneration" => self.natural_regeneration,
+            _ => bail!("unknown game rule {}", name),
+        };
+        Ok(GameRuleValue::Bool(value))
+    }
+
+    /// Sets the rule called `name` from its string form,
+    /// e.g. `"true"` for boolean rules.
+    pub fn set(&mut self, name: &str, value: &str) -> anyhow::Result<()> {
+        let rule = match name {
+            "doMobSpawning" => &mut self.do_mob_spawning,
+            "keepInventory" => &mut self.keep_inventory,
+            "naturalRegeneration" => &mut self.natural_regeneration,
+            _ => bail!("unknown game rule {}", name),
+        };
+        *rule = match value {
+            "true" => true,
+            "false" => false,
+            _ => bail!(
+                "invalid value {:?} for game rule {}: expected true or false",
+                value,
+                name
+            ),
+        };
+        Ok(())
+    }
+}
+
+/// The value of a game rule.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GameRuleValue {
+    Bool(bool),
+}
+
+impl fmt::Display for GameRuleValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameRuleValue::Bool(value) => value.fmt(f),
+        }
+    }
+}
+
+/// Returns the game rules of `game`, or the defaults
+/// if it has no [`GameRules`] resource.
+pub fn game_rules(game: &Game) -> GameRules {
+    game.resources
+        .get::<GameRules>()
+        .map(|rules| rules.clone())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_are_set_by_name() {
+        let mut rules = GameRules::default();
+        rules.set("keepInventory", "true").unwrap();
+        assert!(rules.keep_inventory);
+        assert_eq!(
+            rules.get("keepInventory").unwrap(),
+            GameRuleValue::Bool(true)
+        );
+
+        for &name in GameRules::NAMES {
+            assert!(rules.get(name).is_ok());
+        }
+    }
+
+    #[test]
+    fn unknown_rules_and_invalid_values_are_rejected() {
+        let mut rules = GameRules::default();
+        let error = rules.set("keepInventoy", "true").unwrap_err();
+        assert_eq!(error.to_string(), "unknown game rule keepInventoy");
+        assert!(rules.get("keepInventoy").is_err());
+        assert!(rules.set("keepInventory", "yes").is_err());
+        assert_eq!(rules, GameRules::default());
+    }
+}
@@ -0,0 +1,245 @@
+//! Hunger: food level, saturation and exhaustion, and the
+//! health regeneration and starvation that depend on them.
+//!
+//! Actions such as sprinting add exhaustion with [`Food::add_exhaustion`].
+//! Every 4 points of exhaustion consume a point of saturation,
+//! or a point of food once saturation is depleted.
+
+use base::{Difficulty, Gamemode};
+use ecs::{Entity, SysResult, SystemExecutor};
+use quill_common::components::Health;
+
+use crate::{
+    events::{DamageEvent, HealthUpdateEvent},
+    game_rules, Game,
+};
+
+pub const MAX_FOOD_LEVEL: i32 = 20;
+pub const MAX_HEALTH: f32 = 20.0;
+
+/// Exhaustion added per meter sprinted.
+pub const SPRINT_EXHAUSTION_PER_METER: f32 = 0.1;
+/// Exhaustion added by a jump.
+pub const JUMP_EXHAUSTION: f32 = 0.05;
+/// Exhaustion added by a jump while sprinting.
+pub const SPRINT_JUMP_EXHAUSTION: f32 = 0.2;
+/// Exhaustion added per point of health regenerated.
+const REGENERATION_EXHAUSTION: f32 = 6.0;
+
+/// Exhaustion which consumes a point of saturation or food.
+const EXHAUSTION_PER_POINT: f32 = 4.0;
+const MAX_EXHAUSTION: f32 = 40.0;
+
+/// Ticks between regenerating health from saturation with a full food bar.
+const FAST_REGENERATION_TICKS: u32 = 10;
+/// Ticks between regenerating or starving a point of health.
+const SLOW_REGENERATION_TICKS: u32 = 80;
+/// Food level at or above which health regenerates.
+const REGENERATION_FOOD_LEVEL: i32 = 18;
+
+pub fn register(systems: &mut SystemExecutor<Game>) {
+    systems.add_system(update_hunger);
+}
+
+/// The hunger of a player.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Food {
+    /// Food level from 0 to 20.
+    pub level: i32,
+    /// Saturation, which is consumed before the food level.
+    /// Never exceeds the food level.
+    pub saturation: f32,
+    /// Exhaustion accumulated by actions.
+    pub exhaustion: f32,
+    /// Ticks since health was last regenerated or starved.
+    timer: u32,
+}
+
+impl Default for Food {
+    fn default() -> Self {
+        Self::new(MAX_FOOD_LEVEL, 5.0)
+    }
+}
+
+impl Food {
+    pub fn new(level: i32, saturation: f32) -> Self {
+        Self {
+            level,
+            saturation,
+            exhaustion: 0.0,
+            timer: 0,
+        }
+    }
+
+    pub fn add_exhaustion(&mut self, amount: f32) {
+        self.exhaustion = (self.exhaustion + amount).min(MAX_EXHAUSTION);
+    }
+
+    /// Advances hunger by one tick. Returns the damage
+    /// dealt by starvation, if any. Health only regenerates
+    /// if `regenerate` is set.
+    fn tick(&mut self, health: &mut f32, difficulty: Difficulty, regenerate: bool) -> Option<f32> {
+        if self.exhaustion > EXHAUSTION_PER_POINT {
+            self.exhaustion -= EXHAUSTION_PER_POINT;
+            if self.saturation > 0.0 {
+                self.saturation = (self.saturation - 1.0).max(0.0);
+            } else if difficulty != Difficulty::Peaceful {
+                self.level = (self.level - 1).max(0);
+            }
+        }
+
+        let mut damage = None;
+        if regenerate
+            && self.saturation > 0.0
+            && *health < MAX_HEALTH
+            && self.level >= MAX_FOOD_LEVEL
+        {
+            self.timer += 1;
+            if self.timer >= FAST_REGENERATION_TICKS {
+                let amount = self.saturation.min(REGENERATION_EXHAUSTION);
+                *health = (*health + amount / REGENERATION_EXHAUSTION).min(MAX_HEALTH);
+                self.add_exhaustion(amount);
+                self.timer = 0;
+            }
+        } else if regenerate && self.level >= REGENERATION_FOOD_LEVEL && *health < MAX_HEALTH {
+            self.timer += 1;
+            if self.timer >= SLOW_REGENERATION_TICKS {
+                *health = (*health + 1.0).min(MAX_HEALTH);
+                self.add_exhaustion(REGENERATION_EXHAUSTION);
+                self.timer = 0;
+            }
+        } else if self.level <= 0 {
+            self.timer += 1;
+            if self.timer >= SLOW_REGENERATION_TICKS {
+                if can_starve(*health, difficulty) {
+                    *health = (*health - 1.0).max(0.0);
+                    damage = Some(1.0);
+                }
+                self.timer = 0;
+            }
+        } else {
+            self.timer = 0;
+        }
+        damage
+    }
+}
+
+/// Starvation never kills on Easy and Normal: it stops at
+/// 10 and 1 health respectively. There is no starvation on Peaceful.
+fn can_starve(health: f32, difficulty: Difficulty) -> bool {
+    match difficulty {
+        Difficulty::Peaceful => false,
+        Difficulty::Easy => health > 10.0,
+        Difficulty::Normal => health > 1.0,
+        Difficulty::Hard => true,
+    }
+}
+
+/// Depletes hunger and regenerates or starves health.
+/// Creative and Spectator players are unaffected.
+fn update_hunger(game: &mut Game) -> SysResult {
+    let regenerate = game_rules::game_rules(game).natural_regeneration;
+    let mut updated = Vec::new();
+    let mut starved: Vec<Entity> = Vec::new();
+    for (entity, (food, health, &gamemode)) in game
+        .ecs
+        .query::<(&mut Food, &mut Health, &Gamemode)>()
+        .iter()
+    {
+        if matches!(gamemode, Gamemode::Creative | Gamemode::Spectator) {
+            continue;
+        }
+
+        let (old_food, old_health) = (*food, health.0);
+        if food
+            .tick(&mut health.0, game.difficulty, regenerate)
+            .is_some()
+        {
+            starved.push(entity);
+        }
+        if food.level != old_food.level
+            || food.saturation != old_food.saturation
+            || health.0 != old_health
+        {
+            updated.push(entity);
+        }
+    }
+
+    for entity in updated {
+        game.ecs.insert_entity_event(entity, HealthUpdateEvent)?;
+    }
+    for entity in starved {
+        game.ecs.insert_entity_event(
+            entity,
+            DamageEvent {
+                attacker: None,
+                amount: 1.0,
+            },
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sprinting_depletes_saturation_then_food() {
+        let mut food = Food::new(MAX_FOOD_LEVEL, 1.0);
+        let mut health = MAX_HEALTH;
+
+        // 41 meters of sprinting consumes a point
+        food.add_exhaustion(41.0 * SPRINT_EXHAUSTION_PER_METER);
+        food.tick(&mut health, Difficulty::Normal, true);
+        assert_eq!(food.saturation, 0.0);
+        assert_eq!(food.level, MAX_FOOD_LEVEL);
+
+        food.add_exhaustion(41.0 * SPRINT_EXHAUSTION_PER_METER);
+        food.tick(&mut health, Difficulty::Normal, true);
+        assert_eq!(food.level, MAX_FOOD_LEVEL - 1);
+    }
+
+    #[test]
+    fn starvation_respects_difficulty() {
+        let starve = |difficulty| {
+            let mut food = Food::new(0, 0.0);
+            let mut health = MAX_HEALTH;
+            for _ in 0..SLOW_REGENERATION_TICKS * 30 {
+                food.tick(&mut health, difficulty, true);
+            }
+            health
+        };
+        assert_eq!(starve(Difficulty::Peaceful), MAX_HEALTH);
+        assert_eq!(starve(Difficulty::Easy), 10.0);
+        assert_eq!(starve(Difficulty::Normal), 1.0);
+        assert_eq!(starve(Difficulty::Hard), 0.0);
+    }
+
+    #[test]
+    fn natural_regeneration_can_be_disabled() {
+        let regenerated = |regenerate| {
+            let mut food = Food::new(REGENERATION_FOOD_LEVEL, 0.0);
+            let mut health = 10.0;
+            for _ in 0..SLOW_REGENERATION_TICKS {
+                food.tick(&mut health, Difficulty::Normal, regenerate);
+            }
+            health
+        };
+        assert_eq!(regenerated(true), 11.0);
+        assert_eq!(regenerated(false), 10.0);
+    }
+
+    #[test]
+    fn creative_players_are_unaffected() {
+        let mut game = Game::new();
+        let player = game
+            .ecs
+            .spawn((Food::new(0, 0.0), Health(5.0), Gamemode::Creative));
+
+        for _ in 0..SLOW_REGENERATION_TICKS * 2 {
+            update_hunger(&mut game).unwrap();
+        }
+        assert_eq!(game.ecs.get::<Health>(player).unwrap().0, 5.0);
+    }
+}
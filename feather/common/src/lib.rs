@@ -14,6 +14,10 @@ pub use tick_loop::TickLoop;
 
 pub mod view;
 
+pub mod entity_tick;
+
+pub mod simulation;
+
 pub mod window;
 pub use window::Window;
 
@@ -25,6 +29,8 @@ mod region_worker;
 pub mod world;
 pub use world::World;
 
+pub mod light;
+
 pub mod chat;
 pub use chat::ChatBox;
 
@@ -32,12 +38,41 @@ pub mod entities;
 
 pub mod interactable;
 
+pub mod velocity;
+pub use velocity::Velocity;
+
+pub mod vehicle;
+
+pub mod hunger;
+
+pub mod experience;
+
+pub mod mob_spawning;
+
+pub mod game_rules;
+pub use game_rules::GameRules;
+
+pub mod death;
+
 /// Registers gameplay systems with the given `Game` and `SystemExecutor`.
 pub fn register(game: &mut Game, systems: &mut SystemExecutor<Game>) {
     view::register(game, systems);
     chunk::loading::register(game, systems);
     chunk::entities::register(systems);
+    simulation::register(game, systems);
+    // Entity AI runs once the chunk entity index is up to date for
+    // this tick. The server registers its systems after these,
+    // so movement made by AI is broadcast in the same tick.
+    entity_tick::register(systems);
+    entities::item::register(systems);
     interactable::register(game);
+    velocity::register(systems);
+    vehicle::register(systems);
+    hunger::register(systems);
+    experience::register(systems);
+    mob_spawning::register(game, systems);
+    game_rules::register(game);
+    death::register(systems);
 
     game.add_entity_spawn_callback(entities::add_entity_components);
 }
@@ -0,0 +1,460 @@
+//! Updates block and sky light around changed blocks.
+//!
+//! Rather than recomputing whole chunks, light is removed from and
+//! spread back into the blocks around each changed block, as in vanilla.
+//! Since light spreads at most 15 blocks, a change only affects the
+//! chunk of the block and the chunks next to it, so light crosses
+//! chunk borders.
+//!
+//! Block light is emitted by blocks like glowstone and torches. Sky
+//! light enters from the top of the world and keeps its full level while
+//! spreading straight down. Both lose one level per block otherwise, and
+//! opaque blocks stop them. Sections containing only air store no light;
+//! they are treated as lit by the sky only.
+
+use std::collections::VecDeque;
+
+use ahash::AHashMap;
+use base::{
+    chunk::SECTION_HEIGHT, BlockId, Chunk, ChunkPosition, ValidBlockPosition, CHUNK_HEIGHT,
+    CHUNK_WIDTH,
+};
+use parking_lot::RwLockWriteGuard;
+
+use crate::world::ChunkMap;
+
+/// Updates the light around the `changed` blocks, which must already
+/// have been set in `chunk_map`.
+///
+/// Returns the sections whose light changed, as indices into
+/// `Chunk::sections` by chunk. Chunks which are not loaded are
+/// left untouched, and light does not spread through them.
+pub fn update_light(
+    chunk_map: &ChunkMap,
+    changed: impl IntoIterator<Item = ValidBlockPosition>,
+) -> AHashMap<ChunkPosition, Vec<usize>> {
+    let changed: Vec<Pos> = changed
+        .into_iter()
+        .map(|pos| (pos.x(), pos.y(), pos.z()))
+        .collect();
+
+    let mut area_chunks = Vec::new();
+    for &pos in &changed {
+        let center = chunk_of(pos);
+        for x in center.x - 1..=center.x + 1 {
+            for z in center.z - 1..=center.z + 1 {
+                let chunk = ChunkPosition::new(x, z);
+                if !area_chunks.contains(&chunk) {
+                    area_chunks.push(chunk);
+                }
+            }
+        }
+    }
+    let handles: Vec<_> = area_chunks
+        .into_iter()
+        .filter_map(|chunk| Some((chunk, chunk_map.chunk_handle_at(chunk)?)))
+        .collect();
+
+    let mut area = LightArea {
+        chunks: handles
+            .iter()
+            .filter_map(|(position, handle)| Some((*position, handle.write()?)))
+            .collect(),
+        changed_sections: AHashMap::new(),
+    };
+    area.update(LightKind::Block, &changed);
+    area.update(LightKind::Sky, &changed);
+
+    let mut changed_sections = area.changed_sections;
+    for sections in changed_sections.values_mut() {
+        sections.sort_unstable();
+        sections.dedup();
+    }
+    changed_sections
+}
+
+/// A block position in world coordinates.
+type Pos = (i32, i32, i32);
+
+/// The offsets of the blocks next to a block, and
+/// whether the offset points straight down.
+const NEIGHBORS: [(Pos, bool); 6] = [
+    ((-1, 0, 0), false),
+    ((1, 0, 0), false),
+    ((0, -1, 0), true),
+    ((0, 1, 0), false),
+    ((0, 0, -1), false),
+    ((0, 0, 1), false),
+];
+
+fn neighbors((x, y, z): Pos) -> impl Iterator<Item = (Pos, bool)> {
+    NEIGHBORS
+        .iter()
+        .map(move |&((dx, dy, dz), downward)| ((x + dx, y + dy, z + dz), downward))
+}
+
+fn chunk_of((x, _, z): Pos) -> ChunkPosition {
+    ChunkPosition::new(
+        x.div_euclid(CHUNK_WIDTH as i32),
+        z.div_euclid(CHUNK_WIDTH as i32),
+    )
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum LightKind {
+    Block,
+    Sky,
+}
+
+impl LightKind {
+    /// Returns the light given by a block lit at `level`
+    /// to the block next to it.
+    fn spread(self, level: u8, downward: bool) -> u8 {
+        match self {
+            LightKind::Sky if downward && level == 15 => 15,
+            _ => level.saturating_sub(1),
+        }
+    }
+
+    /// Returns the light of blocks which store no light,
+    /// i.e. in air-only sections or above the world.
+    fn unstored_level(self) -> u8 {
+        match self {
+            LightKind::Block => 0,
+            LightKind::Sky => 15,
+        }
+    }
+}
+
+/// The chunks around changed blocks, locked while their light is updated.
+struct LightArea<'a> {
+    chunks: AHashMap<ChunkPosition, RwLockWriteGuard<'a, Chunk>>,
+    changed_sections: AHashMap<ChunkPosition, Vec<usize>>,
+}
+
+impl LightArea<'_> {
+    fn update(&mut self, kind: LightKind, changed: &[Pos]) {
+        let mut removals = VecDeque::new();
+        let mut additions = VecDeque::new();
+        for &pos in changed {
+            let old = match self.light(kind, pos) {
+                Some((old, true)) => old,
+                _ => continue,
+            };
+            let own = self.own_light(kind, pos);
+            if old != own {
+                self.set_light(kind, pos, own);
+            }
+            if old > own {
+                removals.push_back((pos, old));
+            }
+            // Light of the block itself and light passing through it
+            additions.push_back(pos);
+            additions.extend(neighbors(pos).map(|(neighbor, _)| neighbor));
+        }
+
+        // Remove the light which came from the changed blocks,
+        // collecting the blocks lit otherwise to spread their light again
+        while let Some((pos, level)) = removals.pop_front() {
+            for (neighbor, downward) in neighbors(pos) {
+                let current = match self.light(kind, neighbor) {
+                    Some((current, true)) if self.is_transparent(neighbor) => current,
+                    Some(_) => {
+                        additions.push_back(neighbor);
+                        continue;
+                    }
+                    None => continue,
+                };
+                if current == 0 {
+                    continue;
+                }
+                if current <= kind.spread(level, downward) {
+                    let own = self.own_light(kind, neighbor);
+                    self.set_light(kind, neighbor, own);
+                    removals.push_back((neighbor, current));
+                    if own > 0 {
+                        additions.push_back(neighbor);
+                    }
+                } else {
+                    additions.push_back(neighbor);
+                }
+            }
+        }
+
+        while let Some(pos) = additions.pop_front() {
+            let level = self.emitted_light(kind, pos);
+            for (neighbor, downward) in neighbors(pos) {
+                let spread = kind.spread(level, downward);
+                if spread == 0 {
+                    continue;
+                }
+                if let Some((current, true)) = self.light(kind, neighbor) {
+                    if spread > current && self.is_transparent(neighbor) {
+                        self.set_light(kind, neighbor, spread);
+                        additions.push_back(neighbor);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the chunk containing `pos` and the
+    /// position within it, if it is loaded.
+    fn local(&self, pos: Pos) -> Option<(ChunkPosition, usize, usize, usize)> {
+        let (x, y, z) = pos;
+        if !(0..CHUNK_HEIGHT as i32).contains(&y) {
+            return None;
+        }
+        let chunk = chunk_of(pos);
+        if !self.chunks.contains_key(&chunk) {
+            return None;
+        }
+        Some((
+            chunk,
+            x.rem_euclid(CHUNK_WIDTH as i32) as usize,
+            y as usize,
+            z.rem_euclid(CHUNK_WIDTH as i32) as usize,
+        ))
+    }
+
+    fn block(&self, pos: Pos) -> Option<BlockId> {
+        let (chunk, x, y, z) = self.local(pos)?;
+        self.chunks[&chunk].block_at(x, y, z)
+    }
+
+    fn is_transparent(&self, pos: Pos) -> bool {
+        if pos.1 >= CHUNK_HEIGHT as i32 {
+            return true;
+        }
+        matches!(self.block(pos), Some(block) if !block.is_opaque())
+    }
+
+    /// Returns the light at `pos` and whether it is stored,
+    /// or `None` if `pos` is outside the loaded chunks.
+    fn light(&self, kind: LightKind, pos: Pos) -> Option<(u8, bool)> {
+        if pos.1 >= CHUNK_HEIGHT as i32 {
+            // The open sky above the world
+            return self
+                .chunks
+                .get(&chunk_of(pos))
+                .map(|_| (kind.unstored_level(), false));
+        }
+        let (chunk, x, y, z) = self.local(pos)?;
+        let chunk = &self.chunks[&chunk];
+        if chunk.section((y / SECTION_HEIGHT) as isize).is_none() {
+            return Some((kind.unstored_level(), false));
+        }
+        let level = match kind {
+            LightKind::Block => chunk.block_light_at(x, y, z)?,
+            LightKind::Sky => chunk.sky_light_at(x, y, z)?,
+        };
+        Some((level, true))
+    }
+
+    /// Sets the light at `pos`, which must be stored.
+    fn set_light(&mut self, kind: LightKind, pos: Pos, level: u8) {
+        let (position, x, y, z) = match self.local(pos) {
+            Some(local) => local,
+            None => return,
+        };
+        let chunk = self.chunks.get_mut(&position).unwrap();
+        match kind {
+            LightKind::Block => chunk.set_block_light_at(x, y, z, level),
+            LightKind::Sky => chunk.set_sky_light_at(x, y, z, level),
+        };
+        // + 1 to account for the section below the world
+        self.changed_sections
+            .entry(position)
+            .or_default()
+            .push(y / SECTION_HEIGHT + 1);
+    }
+
+    /// Returns the light a block has regardless of its neighbors.
+    fn own_light(&self, kind: LightKind, pos: Pos) -> u8 {
+        match kind {
+            LightKind::Block => self.block(pos).map_or(0, BlockId::light_emission),
+            LightKind::Sky => 0,
+        }
+    }
+
+    /// Returns the light `pos` spreads to its neighbors. Opaque
+    /// blocks only spread the light they emit.
+    fn emitted_light(&self, kind: LightKind, pos: Pos) -> u8 {
+        let own = self.own_light(kind, pos);
+        if !self.is_transparent(pos) {
+            return own;
+        }
+        self.light(kind, pos)
+            .map_or(own, |(level, _)| level.max(own))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use base::BlockPosition;
+
+    use super::*;
+
+    /// A world of stone with a layer of air at y = 0,
+    /// covering the chunks within `radius` of the origin.
+    fn cave_world(radius: i32) -> ChunkMap {
+        let mut chunk_map = ChunkMap::new();
+        for x in -radius..=radius {
+            for z in -radius..=radius {
+                let mut chunk = Chunk::new(ChunkPosition::new(x, z));
+                chunk.fill_section(1, BlockId::stone());
+                for x in 0..CHUNK_WIDTH {
+                    for z in 0..CHUNK_WIDTH {
+                        chunk.set_block_at(x, 0, z, BlockId::air());
+                        chunk.set_block_light_at(x, 0, z, 0);
+                        chunk.set_sky_light_at(x, 0, z, 0);
+                    }
+                }
+                chunk_map.insert_chunk(chunk);
+            }
+        }
+        chunk_map
+    }
+
+    fn block(x: i32, y: i32, z: i32) -> ValidBlockPosition {
+        BlockPosition::new(x, y, z).try_into().unwrap()
+    }
+
+    fn block_light(chunk_map: &ChunkMap, x: i32, y: i32, z: i32) -> u8 {
+        let pos = block(x, y, z);
+        let chunk = chunk_map.chunk_at(pos.chunk()).unwrap();
+        chunk
+            .block_light_at(
+                x.rem_euclid(16) as usize,
+                y as usize,
+                z.rem_euclid(16) as usize,
+            )
+            .unwrap()
+    }
+
+    fn set_block(chunk_map: &ChunkMap, pos: ValidBlockPosition, block: BlockId) {
+        assert!(chunk_map.set_block_at(pos, block));
+    }
+
+    #[test]
+    fn block_light_spreads_across_chunk_borders() {
+        let chunk_map = cave_world(1);
+        let torch = block(15, 0, 8);
+        set_block(&chunk_map, torch, BlockId::glowstone());
+        let changed = update_light(&chunk_map, vec![torch]);
+
+        assert_eq!(block_light(&chunk_map, 15, 0, 8), 15);
+        assert_eq!(block_light(&chunk_map, 14, 0, 8), 14);
+        assert_eq!(block_light(&chunk_map, 16, 0, 8), 14);
+        assert_eq!(block_light(&chunk_map, 20, 0, 8), 10);
+        assert_eq!(block_light(&chunk_map, 15, 0, 22), 1);
+        // Opaque blocks are not lit
+        assert_eq!(block_light(&chunk_map, 15, 1, 8), 0);
+        // Sent for both chunks
+        assert_eq!(changed[&ChunkPosition::new(0, 0)], vec![1]);
+        assert_eq!(changed[&ChunkPosition::new(1, 0)], vec![1]);
+
+        set_block(&chunk_map, torch, BlockId::air());
+        update_light(&chunk_map, vec![torch]);
+        for x in 0..32 {
+            assert_eq!(
+                block_light(&chunk_map, x, 0, 8),
+                0,
+                "light left at x = {}",
+                x
+            );
+        }
+    }
+
+    #[test]
+    fn removing_one_emitter_keeps_the_light_of_others() {
+        let chunk_map = cave_world(1);
+        let first = block(4, 0, 4);
+        let second = block(10, 0, 4);
+        set_block(&chunk_map, first, BlockId::glowstone());
+        set_block(&chunk_map, second, BlockId::glowstone());
+        update_light(&chunk_map, vec![first, second]);
+        assert_eq!(block_light(&chunk_map, 7, 0, 4), 12);
+
+        set_block(&chunk_map, first, BlockId::air());
+        update_light(&chunk_map, vec![first]);
+        assert_eq!(block_light(&chunk_map, 7, 0, 4), 12);
+        assert_eq!(block_light(&chunk_map, 4, 0, 4), 9);
+        assert_eq!(block_light(&chunk_map, 0, 0, 4), 5);
+    }
+
+    #[test]
+    fn covering_a_column_shades_the_blocks_below() {
+        let mut chunk_map = ChunkMap::new();
+        let mut chunk = Chunk::new(ChunkPosition::new(0, 0));
+        chunk.set_block_at(0, 0, 0, BlockId::stone());
+        for y in 1..8 {
+            chunk.set_block_at(4, y, 4, BlockId::glass());
+        }
+        chunk_map.insert_chunk(chunk);
+        let sky_light = |y: usize| {
+            chunk_map
+                .chunk_at(ChunkPosition::new(0, 0))
+                .unwrap()
+                .sky_light_at(4, y, 4)
+                .unwrap()
+        };
+        assert_eq!(sky_light(2), 15);
+
+        let roof = block(4, 10, 4);
+        set_block(&chunk_map, roof, BlockId::stone());
+        update_light(&chunk_map, vec![roof]);
+        assert_eq!(sky_light(10), 0);
+        // Lit from the side, one level less
+        assert_eq!(sky_light(9), 14);
+        assert_eq!(sky_light(2), 14);
+
+        set_block(&chunk_map, roof, BlockId::air());
+        update_light(&chunk_map, vec![roof]);
+        assert_eq!(sky_light(10), 15);
+        assert_eq!(sky_light(2), 15);
+    }
+
+    /// Run with `cargo test -p feather-common light_updates
+    /// -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn light_updates() {
+        use std::time::Instant;
+
+        const EDITS: u32 = 1000;
+
+        let chunk_map = cave_world(2);
+        let start = Instant::now();
+        for i in 0..EDITS {
+            let pos = block((i % 32) as i32, 0, (i / 32 % 32) as i32);
+            set_block(&chunk_map, pos, BlockId::glowstone());
+            update_light(&chunk_map, vec![pos]);
+            set_block(&chunk_map, pos, BlockId::air());
+            update_light(&chunk_map, vec![pos]);
+        }
+        println!(
+            "{} emitters placed and removed: {:?} per change",
+            EDITS,
+            start.elapsed() / (EDITS * 2)
+        );
+
+        let mut section = Vec::new();
+        for x in 0..16 {
+            for y in 16..32 {
+                for z in 0..16 {
+                    section.push(block(x, y, z));
+                }
+            }
+        }
+        chunk_map
+            .chunk_at_mut(ChunkPosition::new(0, 0))
+            .unwrap()
+            .fill_section(2, BlockId::stone());
+        let start = Instant::now();
+        update_light(&chunk_map, section);
+        println!("Overwritten section: {:?}", start.elapsed());
+    }
+}
@@ -0,0 +1,500 @@
+//! Natural spawning of mobs around players.
+//!
+//! Every few ticks, depending on the [`MobCategory`], one spawn attempt
+//! is made in every loaded chunk within a player's view and simulation
+//! distance, as long as the number of mobs in those chunks is below
+//! the [`MobCaps`]. A successful attempt spawns a pack of mobs around
+//! the spawn position. Hostile mobs spawn in the dark and never on
+//! Peaceful; passive mobs spawn on lit grass, less often.
+
+use ahash::AHashSet;
+use base::{BlockKind, Chunk, ChunkPosition, Difficulty, EntityKind, Position, CHUNK_WIDTH};
+use ecs::{SysResult, SystemExecutor};
+use quill_common::{entities::Player, entity_init::EntityInit};
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{game_rules, simulation, view::View, Game};
+
+/// Mobs do not spawn within this distance in blocks of a player.
+const MIN_PLAYER_DISTANCE: f64 = 24.0;
+/// Hostile mobs only spawn at or below this light level.
+const MAX_HOSTILE_LIGHT: u8 = 7;
+/// Passive mobs only spawn at or above this light level.
+const MIN_PASSIVE_LIGHT: u8 = 9;
+/// Number of ticks between hostile spawn attempts.
+const HOSTILE_SPAWN_INTERVAL: u64 = 20;
+/// Number of ticks between passive spawn attempts.
+const PASSIVE_SPAWN_INTERVAL: u64 = 400;
+/// Maximum number of mobs spawned by a single spawn attempt.
+const MAX_PACK_SIZE: usize = 4;
+/// Maximum distance in blocks along each axis
+/// between the mobs of a pack.
+const PACK_SPREAD: i32 = 5;
+/// Number of chunks the mob caps apply to, i.e. the
+/// 17x17 chunk area around a single player.
+const CAP_AREA_CHUNKS: usize = 289;
+
+const HOSTILE_MOBS: [EntityInit; 4] = [
+    EntityInit::Zombie,
+    EntityInit::Skeleton,
+    EntityInit::Spider,
+    EntityInit::Creeper,
+];
+const PASSIVE_MOBS: [EntityInit; 4] = [
+    EntityInit::Pig,
+    EntityInit::Cow,
+    EntityInit::Sheep,
+    EntityInit::Chicken,
+];
+
+pub fn register(game: &mut Game, systems: &mut SystemExecutor<Game>) {
+    game.insert_resource(MobCaps::default());
+    systems.add_system(spawn_mobs);
+}
+
+/// The maximum number of mobs of each category around a single player.
+///
+/// Stored as a resource in the `Game`. When the view of players covers
+/// more or fewer than 17x17 chunks, the caps are scaled accordingly.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MobCaps {
+    pub hostile: usize,
+    pub passive: usize,
+}
+
+impl Default for MobCaps {
+    fn default() -> Self {
+        Self {
+            hostile: 70,
+            passive: 10,
+        }
+    }
+}
+
+/// A category of mobs sharing a mob cap.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MobCategory {
+    Hostile,
+    Passive,
+}
+
+impl MobCategory {
+    /// Returns the category counted against the caps
+    /// for entities of the given kind, if any.
+    pub fn of(kind: EntityKind) -> Option<Self> {
+        use EntityKind::*;
+        match kind {
+            Blaze | CaveSpider | Creeper | Drowned | Enderman | Endermite | Ghast | Guardian
+            | Husk | MagmaCube | Phantom | Silverfish | Skeleton | Slime | Spider | Stray
+            | Witch | WitherSkeleton | Zombie | ZombieVillager | ZombifiedPiglin => {
+                Some(MobCategory::Hostile)
+            }
+            Chicken | Cow | Donkey | Fox | Horse | Llama | Mooshroom | Panda | Pig | PolarBear
+            | Rabbit | Sheep | Wolf => Some(MobCategory::Passive),
+            _ => None,
+        }
+    }
+
+    const ALL: [MobCategory; 2] = [MobCategory::Hostile, MobCategory::Passive];
+
+    /// Returns the number of ticks between spawn attempts.
+    fn spawn_interval(self) -> u64 {
+        match self {
+            MobCategory::Hostile => HOSTILE_SPAWN_INTERVAL,
+            MobCategory::Passive => PASSIVE_SPAWN_INTERVAL,
+        }
+    }
+
+    /// Returns the mobs of this category that spawn naturally.
+    fn mobs(self) -> &'static [EntityInit] {
+        match self {
+            MobCategory::Hostile => &HOSTILE_MOBS,
+            MobCategory::Passive => &PASSIVE_MOBS,
+        }
+    }
+
+    fn cap(self, caps: MobCaps) -> usize {
+        match self {
+            MobCategory::Hostile => caps.hostile,
+            MobCategory::Passive => caps.passive,
+        }
+    }
+
+    /// Determines whether mobs of this category can
+    /// spawn at the given local position in `chunk`.
+    fn can_spawn_at(self, chunk: &Chunk, x: usize, y: usize, z: usize) -> bool {
+        if y == 0 {
+            return false;
+        }
+        let ground = match chunk.block_at(x, y - 1, z) {
+            Some(block) => block,
+            None => return false,
+        };
+        let is_free = |y| {
+            chunk
+                .block_at(x, y, z)
+                .map_or(false, |block| !block.is_solid() && !block.is_fluid())
+        };
+        if !ground.is_solid() || !is_free(y) || !is_free(y + 1) {
+            return false;
+        }
+
+        let light = chunk
+            .block_light_at(x, y, z)
+            .max(chunk.sky_light_at(x, y, z))
+            .unwrap_or(15);
+        match self {
+            MobCategory::Hostile => light <= MAX_HOSTILE_LIGHT,
+            MobCategory::Passive => {
+                light >= MIN_PASSIVE_LIGHT && ground.kind() == BlockKind::GrassBlock
+            }
+        }
+    }
+}
+
+/// Returns the mob cap for `chunks` spawnable chunks, given
+/// the cap for a single player's 17x17 chunk area.
+fn scaled_cap(cap: usize, chunks: usize) -> usize {
+    (cap * chunks + CAP_AREA_CHUNKS - 1) / CAP_AREA_CHUNKS
+}
+
+/// Returns the loaded chunks within the view
+/// and simulation distance of any player.
+fn spawnable_chunks(game: &Game) -> Vec<ChunkPosition> {
+    let mut chunks = AHashSet::new();
+    for (_, (&view, _)) in game.ecs.query::<(&View, &Player)>().iter() {
+        chunks.extend(view.iter().filter(|&chunk| {
+            game.world.is_chunk_loaded(chunk) && simulation::is_simulated(game, chunk)
+        }));
+    }
+    chunks.into_iter().collect()
+}
+
+/// System to spawn mobs around players.
+fn spawn_mobs(game: &mut Game) -> SysResult {
+    if !game_rules::game_rules(game).do_mob_spawning {
+        return Ok(());
+    }
+    let due: Vec<MobCategory> = MobCategory::ALL
+        .iter()
+        .copied()
+        .filter(|category| game.tick_count % category.spawn_interval() == 0)
+        .filter(|&category| {
+            category != MobCategory::Hostile || game.difficulty != Difficulty::Peaceful
+        })
+        .collect();
+    if due.is_empty() {
+        return Ok(());
+    }
+
+    let chunks = spawnable_chunks(game);
+    let mut rng = rand::thread_rng();
+    for category in due {
+        spawn_category(game, category, &chunks, &mut rng);
+    }
+    Ok(())
+}
+
+fn spawn_category(
+    game: &mut Game,
+    category: MobCategory,
+    chunks: &[ChunkPosition],
+    rng: &mut impl Rng,
+) {
+    let caps = game
+        .resources
+        .get::<MobCaps>()
+        .map(|caps| *caps)
+        .unwrap_or_default();
+    let cap = scaled_cap(category.cap(caps), chunks.len());
+
+    let mut count: usize = chunks
+        .iter()
+        .map(|&chunk| {
+            game.chunk_entities.count_where(chunk, |entity| {
+                game.ecs
+                    .get::<EntityKind>(entity)
+                    .map_or(false, |kind| MobCategory::of(*kind) == Some(category))
+            })
+        })
+        .sum();
+
+    for &chunk in chunks {
+        if count >= cap {
+            break;
+        }
+        let (x, y, z) = match find_spawn_position(game, chunk, category, rng) {
+            Some(position) => position,
+            None => continue,
+        };
+        let mob = *category
+            .mobs()
+            .choose(rng)
+            .expect("every category has mobs");
+
+        // The rest of the pack spawns around the first mob,
+        // within the same chunk and at the same height.
+        for i in 0..MAX_PACK_SIZE {
+            if count >= cap {
+                break;
+            }
+            let member = if i == 0 {
+                Some((x, z))
+            } else {
+                pack_member_position(game, chunk, category, (x, y, z), rng)
+            };
+            if let Some(position) = member.and_then(|(x, z)| spawn_position(game, chunk, x, y, z)) {
+                let builder = game.create_entity_builder(position, mob);
+                game.spawn_entity(builder);
+                count += 1;
+            }
+        }
+    }
+}
+
+/// Picks a random position in `chunk` and returns its local
+/// coordinates if mobs of `category` can spawn there.
+fn find_spawn_position(
+    game: &Game,
+    chunk_pos: ChunkPosition,
+    category: MobCategory,
+    rng: &mut impl Rng,
+) -> Option<(usize, usize, usize)> {
+    let chunk = game.world.chunk_map().chunk_at(chunk_pos)?;
+    let x = rng.gen_range(0..CHUNK_WIDTH);
+    let z = rng.gen_range(0..CHUNK_WIDTH);
+    let top = chunk.heightmaps().motion_blocking.height(x, z)?;
+    let y = rng.gen_range(1..=top.max(1));
+    if category.can_spawn_at(&chunk, x, y, z) {
+        Some((x, y, z))
+    } else {
+        None
+    }
+}
+
+/// Picks a random position near the first mob of a pack at `origin`,
+/// returning its local x and z coordinates if mobs of `category`
+/// can spawn there.
+fn pack_member_position(
+    game: &Game,
+    chunk_pos: ChunkPosition,
+    category: MobCategory,
+    (x, y, z): (usize, usize, usize),
+    rng: &mut impl Rng,
+) -> Option<(usize, usize)> {
+    let chunk = game.world.chunk_map().chunk_at(chunk_pos)?;
+    let mut offset = |coordinate: usize| {
+        let max = CHUNK_WIDTH as i32 - 1;
+        (coordinate as i32 + rng.gen_range(-PACK_SPREAD..=PACK_SPREAD)).clamp(0, max) as usize
+    };
+    let (x, z) = (offset(x), offset(z));
+    if category.can_spawn_at(&chunk, x, y, z) {
+        Some((x, z))
+    } else {
+        None
+    }
+}
+
+/// Returns the position of the given local coordinates in `chunk`
+/// unless it is too close to a player for mobs to spawn.
+fn spawn_position(
+    game: &Game,
+    chunk_pos: ChunkPosition,
+    x: usize,
+    y: usize,
+    z: usize,
+) -> Option<Position> {
+    let position = Position {
+        x: (chunk_pos.x * CHUNK_WIDTH as i32 + x as i32) as f64 + 0.5,
+        y: y as f64,
+        z: (chunk_pos.z * CHUNK_WIDTH as i32 + z as i32) as f64 + 0.5,
+        ..Default::default()
+    };
+    let near_player = game
+        .nearest_entity(position, MIN_PLAYER_DISTANCE, |entity| {
+            game.ecs.get::<Player>(entity).is_ok()
+        })
+        .is_some();
+    if near_player {
+        None
+    } else {
+        Some(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base::BlockId;
+
+    use super::*;
+    use crate::game_rules::GameRules;
+
+    /// Spawns a player far enough from chunk (0, 0) to spawn mobs in it,
+    /// with a view covering it. If `dark_floor` is set, chunk (0, 0) is
+    /// loaded with a floor on which hostile mobs can spawn everywhere.
+    fn game_with_player(dark_floor: bool) -> Game {
+        let mut game = Game::new();
+        let player_position = Position {
+            x: 56.0,
+            z: 8.0,
+            ..Default::default()
+        };
+        let player = game.ecs.spawn((
+            player_position,
+            Player,
+            View::new(player_position.chunk(), 4),
+        ));
+        game.chunk_entities
+            .add_entity(player, player_position.chunk());
+
+        if dark_floor {
+            let mut chunk = Chunk::new(ChunkPosition::new(0, 0));
+            for x in 0..CHUNK_WIDTH {
+                for z in 0..CHUNK_WIDTH {
+                    chunk.set_block_at(x, 0, z, BlockId::stone());
+                    for y in 1..3 {
+                        chunk.set_sky_light_at(x, y, z, 0);
+                    }
+                }
+            }
+            game.world.chunk_map_mut().insert_chunk(chunk);
+        }
+        game
+    }
+
+    fn hostile_count(game: &Game) -> usize {
+        game.ecs
+            .query::<&EntityKind>()
+            .iter()
+            .filter(|(_, &kind)| MobCategory::of(kind) == Some(MobCategory::Hostile))
+            .count()
+    }
+
+    #[test]
+    fn hostile_mobs_spawn_in_the_dark() {
+        let mut game = game_with_player(true);
+        game.tick_count = HOSTILE_SPAWN_INTERVAL;
+        game.add_entity_spawn_callback(crate::entities::add_entity_components);
+
+        spawn_mobs(&mut game).unwrap();
+        assert_eq!(hostile_count(&game), 1);
+
+        // The single loaded chunk is already at the cap.
+        spawn_mobs(&mut game).unwrap();
+        assert_eq!(hostile_count(&game), 1);
+    }
+
+    #[test]
+    fn peaceful_disables_hostile_spawning() {
+        let mut game = game_with_player(true);
+        game.add_entity_spawn_callback(crate::entities::add_entity_components);
+        game.difficulty = Difficulty::Peaceful;
+        game.tick_count = HOSTILE_SPAWN_INTERVAL;
+
+        spawn_mobs(&mut game).unwrap();
+        assert_eq!(hostile_count(&game), 0);
+    }
+
+    #[test]
+    fn do_mob_spawning_disables_spawning() {
+        let mut game = game_with_player(true);
+        game.add_entity_spawn_callback(crate::entities::add_entity_components);
+        let mut rules = GameRules::default();
+        rules.do_mob_spawning = false;
+        game.insert_resource(rules);
+        game.tick_count = HOSTILE_SPAWN_INTERVAL;
+
+        spawn_mobs(&mut game).unwrap();
+        assert_eq!(hostile_count(&game), 0);
+    }
+
+    #[test]
+    fn hostile_mobs_spawn_in_packs_at_intervals() {
+        let mut game = game_with_player(true);
+        game.add_entity_spawn_callback(crate::entities::add_entity_components);
+        // A cap of 8 for the single loaded chunk
+        game.insert_resource(MobCaps {
+            hostile: CAP_AREA_CHUNKS * 8,
+            passive: 0,
+        });
+
+        spawn_mobs(&mut game).unwrap();
+        assert_eq!(hostile_count(&game), MAX_PACK_SIZE);
+
+        for tick in 1..HOSTILE_SPAWN_INTERVAL {
+            game.tick_count = tick;
+            spawn_mobs(&mut game).unwrap();
+        }
+        assert_eq!(hostile_count(&game), MAX_PACK_SIZE);
+
+        game.tick_count = HOSTILE_SPAWN_INTERVAL;
+        spawn_mobs(&mut game).unwrap();
+        spawn_mobs(&mut game).unwrap();
+        assert_eq!(hostile_count(&game), 8);
+    }
+
+    /// Measures spawning mobs around 16 players, each
+    /// with a view of 17x17 chunks on which hostile mobs can spawn.
+    ///
+    /// Run with `cargo test -p feather-common mob_spawning_ticks
+    /// -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn mob_spawning_ticks() {
+        use std::time::Instant;
+
+        const TICKS: u64 = PASSIVE_SPAWN_INTERVAL;
+
+        let mut game = Game::new();
+        game.add_entity_spawn_callback(crate::entities::add_entity_components);
+        for i in 0..16 {
+            let center = ChunkPosition::new(i % 4 * 20, i / 4 * 20);
+            let view = View::new(center, 8);
+            let position = Position {
+                x: (center.x * CHUNK_WIDTH as i32) as f64,
+                z: (center.z * CHUNK_WIDTH as i32) as f64,
+                ..Default::default()
+            };
+            let player = game.ecs.spawn((position, Player, view));
+            game.chunk_entities.add_entity(player, center);
+            for chunk_pos in view.iter() {
+                let mut chunk = Chunk::new(chunk_pos);
+                for x in 0..CHUNK_WIDTH {
+                    for z in 0..CHUNK_WIDTH {
+                        chunk.set_block_at(x, 0, z, BlockId::stone());
+                        chunk.set_sky_light_at(x, 1, z, 0);
+                        chunk.set_sky_light_at(x, 2, z, 0);
+                    }
+                }
+                game.world.chunk_map_mut().insert_chunk(chunk);
+            }
+        }
+
+        let start = Instant::now();
+        for tick in 1..=TICKS {
+            game.tick_count = tick;
+            spawn_mobs(&mut game).unwrap();
+        }
+        let elapsed = start.elapsed();
+        println!(
+            "{} hostile mobs after {} ticks, {:?} per tick",
+            hostile_count(&game),
+            TICKS,
+            elapsed / TICKS as u32
+        );
+    }
+
+    #[test]
+    fn no_spawning_in_unloaded_chunks() {
+        let game = game_with_player(false);
+        assert!(spawnable_chunks(&game).is_empty());
+    }
+
+    #[test]
+    fn caps_scale_with_spawnable_chunks() {
+        assert_eq!(scaled_cap(70, 289), 70);
+        assert_eq!(scaled_cap(70, 578), 140);
+        assert_eq!(scaled_cap(70, 1), 1);
+        assert_eq!(scaled_cap(70, 0), 0);
+    }
+}
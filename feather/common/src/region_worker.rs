@@ -11,7 +11,9 @@ use base::anvil::{
 };
 use flume::{Receiver, Sender};
 
-use crate::chunk::worker::{ChunkLoadResult, LoadRequest, LoadedChunk, SaveRequest, WorkerRequest};
+use crate::chunk::worker::{
+    ChunkLoadResult, LoadRequest, LoadedChunk, PlayerSaveRequest, SaveRequest, WorkerRequest,
+};
 
 /// Duration to keep a region file open when not in use.
 const CACHE_TIME: Duration = Duration::from_secs(60);
@@ -74,6 +76,10 @@ impl RegionWorker {
                 Ok(req) => match req {
                     WorkerRequest::Load(load) => self.load_chunk(load),
                     WorkerRequest::Save(save) => self.save_chunk(save).unwrap(),
+                    WorkerRequest::SavePlayer(save) => self.save_player(save),
+                    WorkerRequest::Flush(done) => {
+                        let _ = done.send(());
+                    }
                 },
                 Err(flume::RecvTimeoutError::Timeout) => (),
                 Err(flume::RecvTimeoutError::Disconnected) => {
@@ -105,6 +111,14 @@ impl RegionWorker {
         Ok(())
     }
 
+    fn save_player(&mut self, req: PlayerSaveRequest) {
+        if let Err(e) =
+            anvil::player::save_player_data(&self.world_dir, req.uuid, &req.data, req.format)
+        {
+            log::error!("Failed to save player data for {}: {:?}", req.uuid, e);
+        }
+    }
+
     fn load_chunk(&mut self, req: LoadRequest) {
         let result = self.get_chunk_load_result(req);
         let _ = self.result_sender.send(result);
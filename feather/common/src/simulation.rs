@@ -0,0 +1,275 @@
+//! The chunks in which entities are ticked.
+//!
+//! Entity AI, mob spawning and other entity ticking, such as item
+//! pickup delays, velocity drag and experience orb merging, only
+//! run in chunks within the simulation distance of a player, which
+//! may be lower than the view distance used to send chunks. The [`SimulatedChunks`]
+//! resource is updated at the start of a tick, before those systems run,
+//! if a player joined, left or crossed into a new chunk, or the
+//! simulation was paused or resumed.
+//! While the simulation is paused, no chunks are simulated.
+
+use ahash::AHashSet;
+use base::{ChunkPosition, Position};
+use ecs::{SysResult, SystemExecutor};
+use quill_common::{
+    entities::Player,
+    events::{EntityRemoveEvent, PlayerJoinEvent},
+};
+
+use crate::{events::ChunkCrossEvent, view::View, Game};
+
+/// The default simulation distance in chunks.
+const DEFAULT_SIMULATION_DISTANCE: u32 = 10;
+
+pub fn register(game: &mut Game, systems: &mut SystemExecutor<Game>) {
+    game.insert_resource(SimulatedChunks::new(DEFAULT_SIMULATION_DISTANCE));
+    systems.add_system(update_simulated_chunks);
+}
+
+/// The chunks within the simulation distance of any player.
+///
+/// Stored as a resource in the `Game`. Systems ticking entities
+/// should skip entities outside these chunks.
+#[derive(Clone, Debug, Default)]
+pub struct SimulatedChunks {
+    distance: u32,
+    paused: bool,
+    /// Whether the chunks must be recomputed on the next update
+    /// even if no player moved.
+    outdated: bool,
+    chunks: AHashSet<ChunkPosition>,
+}
+
+impl SimulatedChunks {
+    /// Creates an empty set of chunks for the given
+    /// simulation distance in chunks.
+    pub fn new(distance: u32) -> Self {
+        Self {
+            distance,
+            paused: false,
+            outdated: true,
+            chunks: AHashSet::new(),
+        }
+    }
+
+    /// Returns the simulation distance in chunks.
+    pub fn distance(&self) -> u32 {
+        self.distance
+    }
+
+    /// Pauses or resumes the simulation, starting with the next
+    /// update of the simulated chunks.
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.paused != paused {
+            self.paused = paused;
+            self.outdated = true;
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Determines whether entities in `chunk` are ticked.
+    pub fn contains(&self, chunk: ChunkPosition) -> bool {
+        self.chunks.contains(&chunk)
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+}
+
+/// Determines whether entities in `chunk` are ticked.
+///
+/// If the `Game` has no [`SimulatedChunks`] resource,
+/// all chunks are simulated.
+pub fn is_simulated(game: &Game, chunk: ChunkPosition) -> bool {
+    game.resources
+        .get::<SimulatedChunks>()
+        .map_or(true, |simulated| simulated.contains(chunk))
+}
+
+/// System to recompute the chunks within simulation distance of players.
+pub(crate) fn update_simulated_chunks(game: &mut Game) -> SysResult {
+    let mut simulated = game.resources.get_mut::<SimulatedChunks>()?;
+    if !simulated.outdated && !players_changed_chunks(game) {
+        return Ok(());
+    }
+    simulated.outdated = false;
+
+    let distance = simulated.distance;
+    simulated.chunks.clear();
+    if simulated.paused {
+        return Ok(());
+    }
+    for (player, (&position, _)) in game.ecs.query::<(&Position, &Player)>().iter() {
+        // Leaving players are despawned at the end of the tick
+        if game.ecs.get::<EntityRemoveEvent>(player).is_ok() {
+            continue;
+        }
+        simulated
+            .chunks
+            .extend(View::new(position.chunk(), distance).iter());
+    }
+    Ok(())
+}
+
+/// Determines whether a player joined, left or crossed
+/// into a new chunk since the last update.
+fn players_changed_chunks(game: &Game) -> bool {
+    let ecs = &game.ecs;
+    ecs.query::<(&ChunkCrossEvent, &Player)>()
+        .iter()
+        .next()
+        .is_some()
+        || ecs
+            .query::<(&PlayerJoinEvent, &Player)>()
+            .iter()
+            .next()
+            .is_some()
+        || ecs
+            .query::<(&EntityRemoveEvent, &Player)>()
+            .iter()
+            .next()
+            .is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_around_players_are_simulated() {
+        let mut game = Game::new();
+        game.insert_resource(SimulatedChunks::new(2));
+        game.ecs.spawn((Position::default(), Player));
+
+        update_simulated_chunks(&mut game).unwrap();
+
+        let simulated = game.resources.get::<SimulatedChunks>().unwrap();
+        assert_eq!(simulated.len(), 25);
+        assert!(simulated.contains(ChunkPosition::new(2, -2)));
+        assert!(!simulated.contains(ChunkPosition::new(3, 0)));
+    }
+
+    #[test]
+    fn chunks_are_recomputed_when_players_join_move_or_leave() {
+        use base::position;
+        use ecs::EntityBuilder;
+
+        let mut game = Game::new();
+        let mut systems = SystemExecutor::new();
+        crate::chunk::entities::register(&mut systems);
+        game.insert_resource(SimulatedChunks::new(2));
+        systems.add_system(update_simulated_chunks);
+        systems.run(&mut game);
+        assert!(game.resources.get::<SimulatedChunks>().unwrap().is_empty());
+
+        let mut builder = EntityBuilder::new();
+        builder.add(Position::default()).add(Player);
+        let player = game.spawn_entity(builder);
+        systems.run(&mut game);
+        assert_eq!(game.resources.get::<SimulatedChunks>().unwrap().len(), 25);
+
+        *game.ecs.get_mut::<Position>(player).unwrap() = position!(100.0, 64.0, 0.0);
+        systems.run(&mut game);
+        {
+            let simulated = game.resources.get::<SimulatedChunks>().unwrap();
+            assert_eq!(simulated.len(), 25);
+            assert!(simulated.contains(ChunkPosition::new(6, 2)));
+            assert!(!simulated.contains(ChunkPosition::new(0, 0)));
+        }
+
+        game.remove_entity(player).unwrap();
+        systems.run(&mut game);
+        assert!(game.resources.get::<SimulatedChunks>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn pausing_simulates_no_chunks() {
+        let mut game = Game::new();
+        game.insert_resource(SimulatedChunks::new(2));
+        game.ecs.spawn((Position::default(), Player));
+
+        game.resources
+            .get_mut::<SimulatedChunks>()
+            .unwrap()
+            .set_paused(true);
+        update_simulated_chunks(&mut game).unwrap();
+        assert!(game.resources.get::<SimulatedChunks>().unwrap().is_empty());
+
+        game.resources
+            .get_mut::<SimulatedChunks>()
+            .unwrap()
+            .set_paused(false);
+        update_simulated_chunks(&mut game).unwrap();
+        assert_eq!(game.resources.get::<SimulatedChunks>().unwrap().len(), 25);
+    }
+
+    /// Run with `cargo test -p feather-common simulated_entity_ticking
+    /// -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn simulated_entity_ticking() {
+        for &distance in &[4, 32] {
+            tick_entities_within(distance);
+        }
+    }
+
+    fn tick_entities_within(distance: u32) {
+        use std::time::Instant;
+
+        use base::{position, Vec3d};
+
+        use crate::{entities::item::PickupDelay, velocity::Velocity};
+
+        const SIDE: i32 = 316;
+        const TICKS: u32 = 100;
+
+        let mut game = Game::new();
+        let mut systems = SystemExecutor::new();
+        crate::chunk::entities::register(&mut systems);
+        game.insert_resource(SimulatedChunks::new(distance));
+        systems.add_system(update_simulated_chunks);
+        crate::entities::item::register(&mut systems);
+        crate::velocity::register(&mut systems);
+        game.ecs.spawn((Position::default(), Player));
+        // Spread the entities 10 blocks apart over
+        // about 200x200 chunks around the player
+        for x in -SIDE / 2..SIDE / 2 {
+            for z in -SIDE / 2..SIDE / 2 {
+                game.ecs.spawn((
+                    position!(x as f64 * 10.0, 64.0, z as f64 * 10.0),
+                    PickupDelay(u32::MAX),
+                    Velocity(Vec3d::new(1.0, 0.0, 0.0)),
+                ));
+            }
+        }
+
+        let start = Instant::now();
+        for _ in 0..TICKS {
+            systems.run(&mut game);
+        }
+        let elapsed = start.elapsed();
+
+        let ticked = game
+            .ecs
+            .query::<&PickupDelay>()
+            .iter()
+            .filter(|(_, delay)| delay.0 != u32::MAX)
+            .count();
+        println!(
+            "simulation distance {}: {} of {} entities ticked, {:?} per tick",
+            distance,
+            ticked,
+            SIDE * SIDE,
+            elapsed / TICKS
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! Entities riding other entities, e.g. players in boats.
+//!
+//! The relationship is stored on both sides: a vehicle has
+//! a [`Passengers`] component, and each passenger a [`Vehicle`] component.
+//! Use [`mount`] and [`dismount`] to keep them in sync.
+
+use base::{EntityKind, Position};
+use ecs::{Entity, SysResult, SystemExecutor};
+use quill_common::events::EntityRemoveEvent;
+
+use crate::{events::PassengersChangeEvent, Game};
+
+pub fn register(systems: &mut SystemExecutor<Game>) {
+    systems
+        .add_system(dismount_removed_entities)
+        .add_system(move_passengers);
+}
+
+/// The entities riding an entity. The first passenger controls the vehicle.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Passengers(pub Vec<Entity>);
+
+/// The entity an entity is riding.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Vehicle(pub Entity);
+
+/// Makes `passenger` ride `vehicle`, dismounting it from
+/// any vehicle it is already riding.
+pub fn mount(game: &mut Game, passenger: Entity, vehicle: Entity) -> SysResult {
+    if passenger == vehicle {
+        anyhow::bail!("an entity cannot ride itself");
+    }
+    dismount(game, passenger)?;
+
+    let mut passengers = game.ecs.remove::<Passengers>(vehicle).unwrap_or_default();
+    passengers.0.push(passenger);
+    game.ecs.insert(vehicle, passengers)?;
+    game.ecs.insert(passenger, Vehicle(vehicle))?;
+    game.ecs
+        .insert_entity_event(vehicle, PassengersChangeEvent)?;
+    Ok(())
+}
+
+/// Makes `passenger` stop riding its vehicle.
+/// Does nothing if it is not riding anything.
+pub fn dismount(game: &mut Game, passenger: Entity) -> SysResult {
+    let vehicle = match game.ecs.remove::<Vehicle>(passenger) {
+        Ok(Vehicle(vehicle)) => vehicle,
+        Err(_) => return Ok(()),
+    };
+
+    let is_empty = match game.ecs.get_mut::<Passengers>(vehicle) {
+        Ok(mut passengers) => {
+            passengers.0.retain(|&entity| entity != passenger);
+            passengers.0.is_empty()
+        }
+        // The vehicle no longer exists
+        Err(_) => return Ok(()),
+    };
+    if is_empty {
+        game.ecs.remove::<Passengers>(vehicle)?;
+    }
+    game.ecs
+        .insert_entity_event(vehicle, PassengersChangeEvent)?;
+    Ok(())
+}
+
+/// Returns the height above a vehicle's position
+/// at which its passengers sit.
+fn riding_offset(vehicle: EntityKind) -> f64 {
+    match vehicle {
+        EntityKind::Boat => -0.1,
+        EntityKind::Minecart
+        | EntityKind::ChestMinecart
+        | EntityKind::CommandBlockMinecart
+        | EntityKind::FurnaceMinecart
+        | EntityKind::HopperMinecart
+        | EntityKind::SpawnerMinecart
+        | EntityKind::TntMinecart => 0.0,
+        kind => kind.bounding_box().max.y * 0.75,
+    }
+}
+
+/// Clears the riding relationship of removed entities on both sides.
+fn dismount_removed_entities(game: &mut Game) -> SysResult {
+    let mut passengers = Vec::new();
+    for (entity, _event) in game.ecs.query::<&EntityRemoveEvent>().iter() {
+        if game.ecs.get::<Vehicle>(entity).is_ok() {
+            passengers.push(entity);
+        }
+        if let Ok(riders) = game.ecs.get::<Passengers>(entity) {
+            passengers.extend(riders.0.iter().copied());
+        }
+    }
+
+    for passenger in passengers {
+        dismount(game, passenger)?;
+    }
+    Ok(())
+}
+
+/// Moves passengers along with their vehicles.
+fn move_passengers(game: &mut Game) -> SysResult {
+    let mut moves = Vec::new();
+    for (_, (passengers, &position, &kind)) in game
+        .ecs
+        .query::<(&Passengers, &Position, &EntityKind)>()
+        .iter()
+    {
+        for &passenger in &passengers.0 {
+            moves.push((passenger, position, riding_offset(kind)));
+        }
+    }
+
+    for (passenger, vehicle_position, offset) in moves {
+        if let Ok(mut position) = game.ecs.get_mut::<Position>(passenger) {
+            position.x = vehicle_position.x;
+            position.y = vehicle_position.y + offset;
+            position.z = vehicle_position.z;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use base::position;
+
+    use super::*;
+
+    #[test]
+    fn dismount_clears_both_sides() {
+        let mut game = Game::new();
+        let boat = game
+            .ecs
+            .spawn((position!(0.0, 64.0, 0.0), EntityKind::Boat));
+        let rider = game.ecs.spawn((position!(5.0, 64.0, 5.0),));
+
+        mount(&mut game, rider, boat).unwrap();
+        assert_eq!(*game.ecs.get::<Vehicle>(rider).unwrap(), Vehicle(boat));
+        assert_eq!(
+            *game.ecs.get::<Passengers>(boat).unwrap(),
+            Passengers(vec![rider])
+        );
+
+        move_passengers(&mut game).unwrap();
+        let position = *game.ecs.get::<Position>(rider).unwrap();
+        assert_eq!((position.x, position.z), (0.0, 0.0));
+        assert!((position.y - 63.9).abs() < 1e-9);
+
+        dismount(&mut game, rider).unwrap();
+        assert!(game.ecs.get::<Vehicle>(rider).is_err());
+        assert!(game.ecs.get::<Passengers>(boat).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! Entity velocity and knockback.
+
+use base::{Position, Vec3d};
+use ecs::{Entity, SysResult, SystemExecutor};
+
+use crate::{events::DamageEvent, simulation::SimulatedChunks, Game};
+
+/// Horizontal knockback applied to an entity hit by an attacker, in blocks per tick.
+const KNOCKBACK_HORIZONTAL: f64 = 0.4;
+/// Vertical knockback applied to an entity hit by an attacker, in blocks per tick.
+const KNOCKBACK_VERTICAL: f64 = 0.4;
+
+/// Factor by which horizontal velocity decays each tick, as for entities in the air.
+const HORIZONTAL_DRAG: f64 = 0.91;
+/// Factor by which vertical velocity decays each tick.
+const VERTICAL_DRAG: f64 = 0.98;
+/// Speed below which an entity is considered at rest, in blocks per tick.
+const REST_SPEED: f64 = 0.003;
+
+pub fn register(systems: &mut SystemExecutor<Game>) {
+    systems
+        .add_system(decay_velocities)
+        .add_system(apply_knockback);
+}
+
+/// The velocity of an entity in blocks per tick.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Velocity(pub Vec3d);
+
+/// Returns the velocity in blocks per tick with which an explosion
+/// of the given `radius` at `center` pushes an entity at `position`.
+///
+/// Like vanilla, entities within twice the radius are pushed
+/// directly away from the center, harder the closer they are.
+/// Blocks between the entity and the explosion are not considered.
+pub fn explosion_knockback(center: Position, radius: f64, position: Position) -> Vec3d {
+    let mut direction = Vec3d::new(
+        position.x - center.x,
+        position.y - center.y,
+        position.z - center.z,
+    );
+    let distance = direction.magnitude();
+    let range = radius * 2.0;
+    if distance >= range || distance < f64::EPSILON {
+        return Vec3d::default();
+    }
+    direction /= distance;
+    direction * (1.0 - distance / range)
+}
+
+/// Slows down moving entities in simulated chunks by drag,
+/// removing the velocity of entities which have come to rest.
+///
+/// Runs before knockback is applied, so that knockback
+/// is sent to clients at full strength.
+fn decay_velocities(game: &mut Game) -> SysResult {
+    let simulated = game.resources.get::<SimulatedChunks>().ok();
+    let mut resting = Vec::new();
+    for (entity, (velocity, &position)) in game.ecs.query::<(&mut Velocity, &Position)>().iter() {
+        if let Some(simulated) = &simulated {
+            if !simulated.contains(position.chunk()) {
+                continue;
+            }
+        }
+        velocity.0.x *= HORIZONTAL_DRAG;
+        velocity.0.y *= VERTICAL_DRAG;
+        velocity.0.z *= HORIZONTAL_DRAG;
+        if velocity.0.magnitude() < REST_SPEED {
+            resting.push(entity);
+        }
+    }
+
+    for entity in resting {
+        game.ecs.remove::<Velocity>(entity)?;
+    }
+    Ok(())
+}
+
+/// Knocks back entities damaged by an attacker, away from the attacker.
+fn apply_knockback(game: &mut Game) -> SysResult {
+    let mut knockbacks: Vec<(Entity, Velocity)> = Vec::new();
+    for (entity, (event, &position)) in game.ecs.query::<(&DamageEvent, &Position)>().iter() {
+        let attacker_position = match event.attacker {
+            Some(attacker) => match game.ecs.get::<Position>(attacker) {
+                Ok(attacker_position) => *attacker_position,
+                Err(_) => continue,
+            },
+            None => continue,
+        };
+
+        let mut direction = Vec3d::new(
+            position.x - attacker_position.x,
+            0.0,
+            position.z - attacker_position.z,
+        );
+        if direction.magnitude_squared() < f64::EPSILON {
+            // Attacker and target overlap; no meaningful direction.
+            continue;
+        }
+        direction.normalize();
+
+        let previous = game
+            .ecs
+            .get::<Velocity>(entity)
+            .map(|velocity| velocity.0)
+            .unwrap_or_default();
+        // Vanilla halves the existing horizontal velocity before adding knockback.
+        let velocity = Vec3d::new(
+            previous.x / 2.0 + direction.x * KNOCKBACK_HORIZONTAL,
+            KNOCKBACK_VERTICAL,
+            previous.z / 2.0 + direction.z * KNOCKBACK_HORIZONTAL,
+        );
+        knockbacks.push((entity, Velocity(velocity)));
+    }
+
+    for (entity, velocity) in knockbacks {
+        game.ecs.insert(entity, velocity)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use base::position;
+
+    use super::*;
+
+    #[test]
+    fn explosion_knockback_falls_off_with_distance() {
+        let center = position!(0.0, 64.0, 0.0);
+        let near = explosion_knockback(center, 4.0, position!(2.0, 64.0, 0.0));
+        let far = explosion_knockback(center, 4.0, position!(6.0, 64.0, 0.0));
+        assert!((near.x - 0.75).abs() < 1e-9);
+        assert!((far.x - 0.25).abs() < 1e-9);
+        assert_eq!((near.y, near.z), (0.0, 0.0));
+
+        let out_of_range = explosion_knockback(center, 4.0, position!(0.0, 64.0, -8.0));
+        assert_eq!(out_of_range, Vec3d::default());
+    }
+
+    #[test]
+    fn velocity_decays_until_at_rest() {
+        let mut game = Game::new();
+        let entity = game
+            .ecs
+            .spawn((Velocity(Vec3d::new(0.4, 0.4, 0.0)), Position::default()));
+
+        decay_velocities(&mut game).unwrap();
+        let velocity = game.ecs.get::<Velocity>(entity).unwrap().0;
+        assert!((velocity.x - 0.4 * HORIZONTAL_DRAG).abs() < 1e-9);
+        assert!((velocity.y - 0.4 * VERTICAL_DRAG).abs() < 1e-9);
+
+        for _ in 0..500 {
+            decay_velocities(&mut game).unwrap();
+        }
+        assert!(game.ecs.get::<Velocity>(entity).is_err());
+    }
+}
@@ -96,6 +96,16 @@ impl View {
         self.view_distance = view_distance;
     }
 
+    /// Returns this view with the view distance increased by `margin`.
+    /// The empty view stays empty.
+    pub fn with_margin(self, margin: u32) -> Self {
+        if self.is_empty() {
+            self
+        } else {
+            Self::new(self.center, self.view_distance + margin)
+        }
+    }
+
     /// Iterates over chunks visible to the player.
     pub fn iter(self) -> impl Iterator<Item = ChunkPosition> {
         if self.is_empty() {
@@ -344,6 +344,41 @@ impl Window {
         }
     }
 
+    /// Swaps the given slot with a hotbar slot, as when pressing
+    /// a number key (hotbar slot 0-8) while hovering over a slot.
+    /// Hotbar slot 40 is the offhand, swapped with the F key.
+    pub fn swap_with_hotbar(&mut self, slot: usize, hotbar_slot: usize) -> SysResult {
+        let (area, area_slot) = match hotbar_slot {
+            0..=8 => (Area::Hotbar, hotbar_slot),
+            40 => (Area::Offhand, 0),
+            _ => bail!("invalid hotbar slot {}", hotbar_slot),
+        };
+        let hotbar_index = self
+            .index_of(area, area_slot)
+            .ok_or_else(|| anyhow!("window has no {:?} slot {}", area, area_slot))?;
+        if hotbar_index == slot {
+            return Ok(());
+        }
+
+        let item = mem::replace(&mut *self.inner.item(slot)?, Empty);
+        let hotbar_item = mem::replace(&mut *self.inner.item(hotbar_index)?, item);
+        *self.inner.item(slot)? = hotbar_item;
+        Ok(())
+    }
+
+    /// Returns the protocol index of the first slot
+    /// in the window with the given area and slot.
+    fn index_of(&self, area: Area, slot: usize) -> Option<usize> {
+        let mut index = 0;
+        while let Some((_, slot_area, area_slot)) = self.inner.index_to_slot(index) {
+            if slot_area == area && area_slot == slot {
+                return Some(index);
+            }
+            index += 1;
+        }
+        None
+    }
+
     /// Gets the item currently held in the cursor.
     pub fn cursor_item(&self) -> &InventorySlot {
         &self.cursor_item
@@ -801,6 +836,27 @@ mod tests {
         assert_eq!(window.cursor_item, InventorySlot::Empty);
     }
 
+    #[test]
+    fn window_swap_with_hotbar() {
+        let mut window = window();
+        let stack = ItemStack::new(Item::Diamond, 3).unwrap();
+        window
+            .set_item(10, InventorySlot::Filled(stack.clone()))
+            .unwrap();
+
+        // Hotbar slot 2 is at protocol index 38 in the player window
+        window.swap_with_hotbar(10, 2).unwrap();
+        assert!(window.item(10).unwrap().is_empty());
+        assert_eq!(
+            *window.item(38).unwrap(),
+            InventorySlot::Filled(stack.clone())
+        );
+
+        window.swap_with_hotbar(38, 40).unwrap();
+        assert_eq!(*window.item(45).unwrap(), InventorySlot::Filled(stack));
+        assert!(window.swap_with_hotbar(10, 9).is_err());
+    }
+
     fn window() -> Window {
         Window::new(BackingWindow::Player {
             player: Inventory::player(),
@@ -4,7 +4,7 @@ use ahash::{AHashMap, AHashSet};
 use parking_lot::{RwLockReadGuard, RwLockWriteGuard};
 use uuid::Uuid;
 
-use base::anvil::player::PlayerData;
+use base::anvil::player::{PlayerData, PlayerDataFormat};
 use base::{
     BlockPosition, Chunk, ChunkHandle, ChunkLock, ChunkPosition, ValidBlockPosition, CHUNK_HEIGHT,
 };
@@ -14,7 +14,7 @@ use worldgen::{ComposableGenerator, WorldGenerator};
 
 use crate::{
     chunk::cache::ChunkCache,
-    chunk::worker::{ChunkWorker, LoadRequest, SaveRequest},
+    chunk::worker::{ChunkWorker, LoadRequest, PlayerSaveRequest, SaveRequest},
     events::ChunkLoadEvent,
 };
 
@@ -25,17 +25,36 @@ use crate::{
 /// NB: _not_ what most Rust ECSs call "world."
 /// This does not store entities; it only contains blocks.
 pub struct World {
+    name: String,
     chunk_map: ChunkMap,
     pub cache: ChunkCache,
     chunk_worker: ChunkWorker,
     loading_chunks: AHashSet<ChunkPosition>,
     canceled_chunk_loads: AHashSet<ChunkPosition>,
     world_dir: PathBuf,
+    player_data_format: PlayerDataFormat,
+    /// Whether clients are told this is a superflat world,
+    /// which changes the horizon and fog.
+    is_flat: bool,
+    /// Set if the world is kept in memory instead of on disk.
+    in_memory: Option<InMemoryStorage>,
+    /// Chunks generated in memory whose `ChunkLoadEvent`s
+    /// are yet to be triggered.
+    generated_chunks: Vec<ChunkPosition>,
+}
+
+/// Storage of a world which never touches the disk.
+struct InMemoryStorage {
+    generator: Arc<dyn WorldGenerator>,
+    /// Chunks that were unloaded, kept so that
+    /// their changes survive reloading them.
+    unloaded_chunks: ChunkMapInner,
 }
 
 impl Default for World {
     fn default() -> Self {
         Self {
+            name: "world".to_owned(),
             chunk_map: ChunkMap::new(),
             chunk_worker: ChunkWorker::new(
                 "world",
@@ -45,6 +64,10 @@ impl Default for World {
             loading_chunks: AHashSet::new(),
             canceled_chunk_loads: AHashSet::new(),
             world_dir: "world".into(),
+            player_data_format: PlayerDataFormat::default(),
+            is_flat: false,
+            in_memory: None,
+            generated_chunks: Vec::new(),
         }
     }
 }
@@ -65,10 +88,55 @@ impl World {
         }
     }
 
+    /// Creates a world that is generated and kept in memory,
+    /// e.g. for tests or throwaway servers.
+    ///
+    /// Chunks are generated as soon as they are queued for loading.
+    /// Nothing is read from or written to disk: players always
+    /// join as new players and saves do nothing.
+    pub fn in_memory(generator: Arc<dyn WorldGenerator>) -> Self {
+        Self {
+            in_memory: Some(InMemoryStorage {
+                generator,
+                unloaded_chunks: ChunkMapInner::new(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Returns whether this world is kept in memory.
+    /// See [`World::in_memory`].
+    pub fn is_in_memory(&self) -> bool {
+        self.in_memory.is_some()
+    }
+
+    /// Sets whether clients are told this is a superflat world.
+    pub fn set_flat(&mut self, is_flat: bool) {
+        self.is_flat = is_flat;
+    }
+
+    pub fn is_flat(&self) -> bool {
+        self.is_flat
+    }
+
+    /// Returns the name of this world, as sent to clients
+    /// in the Join Game packet.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     /// Queues the given chunk to be loaded. If the chunk was cached, it is loaded immediately.
     pub fn queue_chunk_load(&mut self, req: LoadRequest) {
         let pos = req.pos;
-        if self.cache.contains(&pos) {
+        if let Some(storage) = &mut self.in_memory {
+            let handle = match storage.unloaded_chunks.remove(&pos) {
+                Some(handle) => handle,
+                None => Arc::new(ChunkLock::new(storage.generator.generate_chunk(pos), true)),
+            };
+            handle.set_loaded();
+            self.chunk_map.0.insert(pos, handle);
+            self.generated_chunks.push(pos);
+        } else if self.cache.contains(&pos) {
             // Move the chunk from the cache to the map
             self.chunk_map
                 .0
@@ -83,6 +151,14 @@ impl World {
     /// Loads any chunks that have been loaded asynchronously
     /// after a call to [`World::queue_chunk_load`].
     pub fn load_chunks(&mut self, ecs: &mut Ecs) -> SysResult {
+        for pos in self.generated_chunks.drain(..) {
+            if let Some(chunk) = self.chunk_map.chunk_handle_at(pos) {
+                ecs.insert_event(ChunkLoadEvent {
+                    chunk,
+                    position: pos,
+                });
+            }
+        }
         while let Some(loaded) = self.chunk_worker.poll_loaded_chunk()? {
             self.loading_chunks.remove(&loaded.pos);
             if self.canceled_chunk_loads.remove(&loaded.pos) {
@@ -102,6 +178,14 @@ impl World {
 
     /// Unloads the given chunk.
     pub fn unload_chunk(&mut self, pos: ChunkPosition) -> anyhow::Result<()> {
+        if let Some(storage) = &mut self.in_memory {
+            if let Some(handle) = self.chunk_map.0.remove(&pos) {
+                handle.set_unloaded()?;
+                storage.unloaded_chunks.insert(pos, handle);
+            }
+            log::trace!("Unloaded chunk {:?}", pos);
+            return Ok(());
+        }
         if let Some((pos, handle)) = self.chunk_map.0.remove_entry(&pos) {
             handle.set_unloaded()?;
             self.chunk_worker.queue_chunk_save(SaveRequest {
@@ -159,15 +243,59 @@ impl World {
         &mut self.chunk_map
     }
 
+    /// Sets the format player data is saved in.
+    /// Saves in the other format are still loaded.
+    pub fn set_player_data_format(&mut self, format: PlayerDataFormat) {
+        self.player_data_format = format;
+    }
+
     pub fn load_player_data(&self, uuid: Uuid) -> anyhow::Result<PlayerData> {
-        Ok(base::anvil::player::load_player_data(
-            &self.world_dir,
-            uuid,
-        )?)
+        if self.is_in_memory() {
+            anyhow::bail!("in-memory worlds store no player data");
+        }
+        base::anvil::player::load_player_data(&self.world_dir, uuid, self.player_data_format)
     }
 
     pub fn save_player_data(&self, uuid: Uuid, data: &PlayerData) -> anyhow::Result<()> {
-        base::anvil::player::save_player_data(&self.world_dir, uuid, data)
+        if self.is_in_memory() {
+            return Ok(());
+        }
+        base::anvil::player::save_player_data(&self.world_dir, uuid, data, self.player_data_format)
+    }
+
+    /// Queues player data to be saved asynchronously.
+    /// Use [`World::flush`] to wait until it has been written.
+    pub fn queue_player_data_save(&mut self, uuid: Uuid, data: PlayerData) {
+        if self.is_in_memory() {
+            return;
+        }
+        self.chunk_worker.queue_player_save(PlayerSaveRequest {
+            uuid,
+            data,
+            format: self.player_data_format,
+        });
+    }
+
+    /// Queues all loaded chunks to be saved asynchronously.
+    /// Use [`World::flush`] to wait until they have been written.
+    pub fn queue_loaded_chunks_save(&mut self) {
+        if self.is_in_memory() {
+            return;
+        }
+        for (&pos, handle) in &self.chunk_map.0 {
+            self.chunk_worker.queue_chunk_save(SaveRequest {
+                pos,
+                chunk: handle.clone(),
+                entities: vec![],
+                block_entities: vec![],
+            });
+        }
+    }
+
+    /// Blocks until all queued chunk and player data saves
+    /// have been written to disk.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.chunk_worker.flush()
     }
 }
 
@@ -264,6 +392,8 @@ fn chunk_relative_pos(block_pos: BlockPosition) -> (usize, usize, usize) {
 mod tests {
     use std::convert::TryInto;
 
+    use worldgen::VoidWorldGenerator;
+
     use super::*;
 
     #[test]
@@ -280,4 +410,21 @@ mod tests {
             .block_at(BlockPosition::new(0, 0, 0).try_into().unwrap())
             .is_some());
     }
+
+    #[test]
+    fn in_memory_chunks_load_immediately_and_keep_changes() {
+        let mut world = World::in_memory(Arc::new(VoidWorldGenerator));
+        let pos = ChunkPosition::new(3, -2);
+        world.queue_chunk_load(LoadRequest { pos });
+        assert!(world.is_chunk_loaded(pos));
+        assert!(!world.is_chunk_loading(pos));
+
+        let block = BlockPosition::new(48, 10, -32).try_into().unwrap();
+        assert!(world.set_block_at(block, BlockId::stone()));
+        world.unload_chunk(pos).unwrap();
+        assert!(!world.is_chunk_loaded(pos));
+
+        world.queue_chunk_load(LoadRequest { pos });
+        assert_eq!(world.block_at(block), Some(BlockId::stone()));
+    }
 }
@@ -17,6 +17,16 @@ pub type CompressionThreshold = usize;
 /// An encryption key for use with AES-CFB8.
 pub type CryptKey = [u8; 16];
 
+/// Error returned when a received packet could not be decoded.
+#[derive(Debug, thiserror::Error)]
+#[error("malformed packet of length {length}")]
+pub struct DecodeError {
+    /// The ID of the packet, if it could be read.
+    pub packet_id: Option<i32>,
+    /// The length of the packet data, including the ID.
+    pub length: usize,
+}
+
 /// State to serialize and deserialize packets from a byte stream.
 #[derive(Default)]
 pub struct MinecraftCodec {
@@ -32,6 +42,8 @@ pub struct MinecraftCodec {
     staging_buf: Vec<u8>,
     /// Another auxilary buffer.
     compression_target: Vec<u8>,
+    /// Length of the last packet returned by `next_packet`.
+    last_packet_length: usize,
 }
 
 impl MinecraftCodec {
@@ -63,6 +75,7 @@ impl MinecraftCodec {
             received_buf: BytesMut::new(),
             staging_buf: Vec::new(),
             compression_target: Vec::new(),
+            last_packet_length: 0,
         }
     }
 
@@ -147,6 +160,12 @@ impl MinecraftCodec {
         }
     }
 
+    /// Returns the length in bytes, as received, of the last
+    /// packet returned by [`next_packet`](Self::next_packet).
+    pub fn last_packet_length(&self) -> usize {
+        self.last_packet_length
+    }
+
     /// Gets the next packet that was received, if any.
     pub fn next_packet<T>(&mut self) -> anyhow::Result<Option<T>>
     where
@@ -172,10 +191,20 @@ impl MinecraftCodec {
                     }
                 }
 
-                let packet = T::read(&mut cursor, ProtocolVersion::V1_16_2)?;
+                let remaining = cursor.get_ref().len() - cursor.position() as usize;
+                let packet_id = VarInt::read(&mut cursor.clone(), ProtocolVersion::V1_16_2)
+                    .map(|id| id.0)
+                    .ok();
+                let packet = T::read(&mut cursor, ProtocolVersion::V1_16_2).map_err(|e| {
+                    e.context(DecodeError {
+                        packet_id,
+                        length: remaining,
+                    })
+                })?;
 
                 let bytes_read = length.0 as usize + length_field_length;
                 self.received_buf = self.received_buf.split_off(bytes_read);
+                self.last_packet_length = bytes_read;
 
                 self.compression_target.clear();
                 Some(packet)
@@ -189,3 +218,34 @@ impl MinecraftCodec {
         Ok(packet)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientPlayPacket;
+
+    #[test]
+    fn truncated_packet_is_decode_error() {
+        let mut codec = MinecraftCodec::new();
+        // A ChatMessage (ID 0x03) missing its message
+        codec.accept(&[0x01, 0x03]);
+
+        let error = codec.next_packet::<ClientPlayPacket>().unwrap_err();
+        let error = error.downcast_ref::<DecodeError>().unwrap();
+        assert_eq!(error.packet_id, Some(0x03));
+        assert_eq!(error.length, 1);
+    }
+
+    #[test]
+    fn decoded_packet_is_consumed() {
+        let mut codec = MinecraftCodec::new();
+        // A ChatMessage with the message "hi", followed by the
+        // first byte of the next packet
+        codec.accept(&[0x04, 0x03, 0x02, b'h', b'i', 0x01]);
+
+        let packet = codec.next_packet::<ClientPlayPacket>().unwrap();
+        assert!(matches!(packet, Some(ClientPlayPacket::ChatMessage(_))));
+        assert_eq!(codec.last_packet_length(), 5);
+        assert!(codec.next_packet::<ClientPlayPacket>().unwrap().is_none());
+    }
+}
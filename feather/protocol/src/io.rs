@@ -497,6 +497,60 @@ impl<'a> From<LengthInferredVecU8<'a>> for Vec<u8> {
     }
 }
 
+/// Maximum length in characters of a chat
+/// message sent by a client.
+pub const MAX_CHAT_MESSAGE_LENGTH: usize = 256;
+
+/// A chat message sent by a client. Messages longer than
+/// [`MAX_CHAT_MESSAGE_LENGTH`] characters fail to decode.
+#[derive(Debug, Clone)]
+pub struct ChatString(pub String);
+
+impl Readable for ChatString {
+    fn read(buffer: &mut Cursor<&[u8]>, version: ProtocolVersion) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        // Reject messages which cannot fit before reading them,
+        // as a character takes at most 4 bytes in UTF-8
+        let start = buffer.position();
+        let byte_length = VarInt::read(buffer, version)
+            .context("failed to read chat message length")?
+            .0 as usize;
+        if byte_length > MAX_CHAT_MESSAGE_LENGTH * 4 {
+            bail!(
+                "chat message of {} bytes exceeds maximum allowed length of {} characters",
+                byte_length,
+                MAX_CHAT_MESSAGE_LENGTH
+            );
+        }
+        buffer.set_position(start);
+
+        let s = String::read(buffer, version)?;
+        let length = s.chars().count();
+        if length > MAX_CHAT_MESSAGE_LENGTH {
+            bail!(
+                "chat message length {} exceeds maximum allowed length of {}",
+                length,
+                MAX_CHAT_MESSAGE_LENGTH
+            );
+        }
+        Ok(ChatString(s))
+    }
+}
+
+impl Writeable for ChatString {
+    fn write(&self, buffer: &mut Vec<u8>, version: ProtocolVersion) -> anyhow::Result<()> {
+        self.0.write(buffer, version)
+    }
+}
+
+impl From<ChatString> for String {
+    fn from(x: ChatString) -> Self {
+        x.0
+    }
+}
+
 /// Wrapper over an arbitrary type that implements `Deserialize` and `Serialize`.
 ///
 /// The value will be written to a packet as NBT data.
@@ -915,3 +969,38 @@ impl Writeable for PreviousGamemode {
         self.id().write(buffer, version)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_chat_string(message: &str) -> anyhow::Result<ChatString> {
+        let mut buffer = Vec::new();
+        message
+            .to_owned()
+            .write(&mut buffer, ProtocolVersion::V1_16_2)
+            .unwrap();
+        ChatString::read(&mut Cursor::new(&buffer[..]), ProtocolVersion::V1_16_2)
+    }
+
+    #[test]
+    fn chat_string_length_counts_characters() {
+        // Two bytes per character in UTF-8
+        let longest = "é".repeat(MAX_CHAT_MESSAGE_LENGTH);
+        assert_eq!(read_chat_string(&longest).unwrap().0, longest);
+        assert!(read_chat_string(&"é".repeat(MAX_CHAT_MESSAGE_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn oversized_chat_strings_are_rejected_before_reading() {
+        // Claims more bytes than any allowed message, without sending them
+        let mut buffer = Vec::new();
+        VarInt((MAX_CHAT_MESSAGE_LENGTH * 4 + 1) as i32)
+            .write(&mut buffer, ProtocolVersion::V1_16_2)
+            .unwrap();
+        let error = ChatString::read(&mut Cursor::new(&buffer[..]), ProtocolVersion::V1_16_2)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("exceeds maximum"), "{}", error);
+    }
+}
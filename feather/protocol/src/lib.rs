@@ -19,6 +19,12 @@ pub use packets::{
 
 pub type Slot = InventorySlot;
 
+/// Number of the protocol version implemented by this crate,
+/// as sent by clients in the handshake.
+pub const PROTOCOL_VERSION: u32 = 754;
+/// Name of the Minecraft version using [`PROTOCOL_VERSION`].
+pub const VERSION_NAME: &str = "1.16.5";
+
 /// A protocol version.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum ProtocolVersion {
@@ -11,6 +11,9 @@ macro_rules! user_type {
     (LengthInferredVecU8) => {
         Vec<u8>
     };
+    (ChatString) => {
+        String
+    };
     (Angle) => {
         f32
     };
@@ -284,7 +287,9 @@ pub trait VariantOf<Enum> {
         Self: Sized;
 }
 
-use crate::io::{Angle, LengthInferredVecU8, Nbt, ShortPrefixedVec, VarInt, VarIntPrefixedVec};
+use crate::io::{
+    Angle, ChatString, LengthInferredVecU8, Nbt, ShortPrefixedVec, VarInt, VarIntPrefixedVec,
+};
 use crate::Slot;
 use base::BlockId;
 use nbt::Blob;
@@ -23,7 +23,7 @@ packets! {
     }
 
     ChatMessage {
-        message String;
+        message ChatString;
     }
 }
 
@@ -122,7 +122,7 @@ packets! {
     }
 
     KeepAlive {
-        id u64;
+        id i64;
     }
 
     LockDifficulty {
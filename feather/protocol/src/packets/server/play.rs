@@ -238,24 +238,6 @@ packets! {
         tooltip Option<String>;
     }
 
-    DeclareCommands {
-        // (not implemented)
-        __todo__ LengthInferredVecU8;
-        /* nodes LengthPrefixedVec<CommandNode>;
-        root_index VarInt; */
-    }
-
-    CommandNode {
-        flags u8;
-        children VarIntPrefixedVec<VarInt>;
-        redirect_node Option<VarInt>;
-        name Option<String>;
-        parser Option<String>;
-        // TODO: handle properties, which vary depending on the value of `parser`.
-        // This can be handled with an enum.
-        __todo__ LengthInferredVecU8;
-    }
-
     WindowConfirmation {
         window_id u8;
         action_number i16;
@@ -357,7 +339,7 @@ packets! {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum GameStateChange {
     /// Sends block.minecraft.spawn.not_valid to client
     SendNoRespawnBlockAvailableMessage,
@@ -393,7 +375,7 @@ pub enum GameStateChange {
     },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum DemoEventType {
     ShowWelcomeToDemoScreen,
     TellMovementControls,
@@ -402,26 +384,88 @@ pub enum DemoEventType {
     TellDemoIsOver,
 }
 
-impl Writeable for GameStateChange {
-    fn write(&self, buffer: &mut Vec<u8>, version: ProtocolVersion) -> anyhow::Result<()> {
-        // Reason
+impl GameStateChange {
+    pub const NO_RESPAWN_BLOCK_AVAILABLE: u8 = 0;
+    pub const END_RAINING: u8 = 1;
+    pub const BEGIN_RAINING: u8 = 2;
+    pub const CHANGE_GAMEMODE: u8 = 3;
+    pub const WIN_GAME: u8 = 4;
+    pub const DEMO_EVENT: u8 = 5;
+    pub const ARROW_HIT_PLAYER: u8 = 6;
+    pub const RAIN_LEVEL_CHANGE: u8 = 7;
+    pub const THUNDER_LEVEL_CHANGE: u8 = 8;
+    pub const PUFFERFISH_STING: u8 = 9;
+    pub const ELDER_GUARDIAN_APPEARANCE: u8 = 10;
+    pub const ENABLE_RESPAWN_SCREEN: u8 = 11;
+
+    /// Parses a change from its reason code and value, as sent in
+    /// the packet. Reasons with an integer value, such as a gamemode ID,
+    /// reject values with a fractional part.
+    pub fn from_reason(reason: u8, value: f32) -> anyhow::Result<Self> {
+        let integer = || {
+            if value.fract() != 0.0 || !(0.0..=255.0).contains(&value) {
+                bail!(
+                    "game state change reason {} expects an integer value, got {}",
+                    reason,
+                    value
+                );
+            }
+            Ok(value as u8)
+        };
+        Ok(match reason {
+            Self::NO_RESPAWN_BLOCK_AVAILABLE => GameStateChange::SendNoRespawnBlockAvailableMessage,
+            Self::END_RAINING => GameStateChange::EndRaining,
+            Self::BEGIN_RAINING => GameStateChange::BeginRaining,
+            Self::CHANGE_GAMEMODE => GameStateChange::ChangeGamemode {
+                gamemode: Gamemode::from_id(integer()?)
+                    .ok_or(anyhow!("Unsupported gamemode ID"))?,
+            },
+            Self::WIN_GAME => GameStateChange::WinGame {
+                show_credits: integer()? != 0,
+            },
+            Self::DEMO_EVENT => GameStateChange::DemoEvent(match integer()? {
+                0 => DemoEventType::ShowWelcomeToDemoScreen,
+                101 => DemoEventType::TellMovementControls,
+                102 => DemoEventType::TellJumpControl,
+                103 => DemoEventType::TellInventoryControl,
+                104 => DemoEventType::TellDemoIsOver,
+                other => bail!("Invalid demo event type: {}", other),
+            }),
+            Self::ARROW_HIT_PLAYER => GameStateChange::ArrowHitAnyPlayer,
+            Self::RAIN_LEVEL_CHANGE => GameStateChange::RainLevelChange { rain_level: value },
+            Self::THUNDER_LEVEL_CHANGE => GameStateChange::ThunderLevelChange {
+                thunder_level: value,
+            },
+            Self::PUFFERFISH_STING => GameStateChange::PlayPufferfishStingSound,
+            Self::ELDER_GUARDIAN_APPEARANCE => GameStateChange::PlayElderGuardianAppearance,
+            Self::ENABLE_RESPAWN_SCREEN => GameStateChange::EnableRespawnScreen {
+                enable: integer()? == 0,
+            },
+            other => bail!("Invalid game state change reason: {}", other),
+        })
+    }
+
+    /// Returns the reason code of this change.
+    pub fn reason(&self) -> u8 {
         match self {
-            GameStateChange::SendNoRespawnBlockAvailableMessage => 0u8,
-            GameStateChange::EndRaining => 1,
-            GameStateChange::BeginRaining => 2,
-            GameStateChange::ChangeGamemode { .. } => 3,
-            GameStateChange::WinGame { .. } => 4,
-            GameStateChange::DemoEvent(_) => 5,
-            GameStateChange::ArrowHitAnyPlayer => 6,
-            GameStateChange::RainLevelChange { .. } => 7,
-            GameStateChange::ThunderLevelChange { .. } => 8,
-            GameStateChange::PlayPufferfishStingSound => 9,
-            GameStateChange::PlayElderGuardianAppearance => 10,
-            GameStateChange::EnableRespawnScreen { .. } => 11,
+            GameStateChange::SendNoRespawnBlockAvailableMessage => Self::NO_RESPAWN_BLOCK_AVAILABLE,
+            GameStateChange::EndRaining => Self::END_RAINING,
+            GameStateChange::BeginRaining => Self::BEGIN_RAINING,
+            GameStateChange::ChangeGamemode { .. } => Self::CHANGE_GAMEMODE,
+            GameStateChange::WinGame { .. } => Self::WIN_GAME,
+            GameStateChange::DemoEvent(_) => Self::DEMO_EVENT,
+            GameStateChange::ArrowHitAnyPlayer => Self::ARROW_HIT_PLAYER,
+            GameStateChange::RainLevelChange { .. } => Self::RAIN_LEVEL_CHANGE,
+            GameStateChange::ThunderLevelChange { .. } => Self::THUNDER_LEVEL_CHANGE,
+            GameStateChange::PlayPufferfishStingSound => Self::PUFFERFISH_STING,
+            GameStateChange::PlayElderGuardianAppearance => Self::ELDER_GUARDIAN_APPEARANCE,
+            GameStateChange::EnableRespawnScreen { .. } => Self::ENABLE_RESPAWN_SCREEN,
         }
-        .write(buffer, version)?;
+    }
 
-        // Value
+    /// Returns the value sent with this change. Its meaning
+    /// depends on the reason.
+    pub fn value(&self) -> f32 {
         match self {
             GameStateChange::ChangeGamemode { gamemode } => *gamemode as u8 as f32,
             GameStateChange::WinGame { show_credits } => *show_credits as u8 as f32,
@@ -435,8 +479,13 @@ impl Writeable for GameStateChange {
             GameStateChange::EnableRespawnScreen { enable } => !enable as u8 as f32,
             _ => 0.0,
         }
-        .write(buffer, version)?;
+    }
+}
 
+impl Writeable for GameStateChange {
+    fn write(&self, buffer: &mut Vec<u8>, version: ProtocolVersion) -> anyhow::Result<()> {
+        self.reason().write(buffer, version)?;
+        self.value().write(buffer, version)?;
         Ok(())
     }
 }
@@ -448,37 +497,7 @@ impl Readable for GameStateChange {
     {
         let reason = u8::read(buffer, version)?;
         let value = f32::read(buffer, version)?;
-        Ok(match reason {
-            0 => GameStateChange::SendNoRespawnBlockAvailableMessage,
-            1 => GameStateChange::EndRaining,
-            2 => GameStateChange::BeginRaining,
-            3 => GameStateChange::ChangeGamemode {
-                gamemode: Gamemode::from_id(value as u8)
-                    .ok_or(anyhow!("Unsupported gamemode ID"))?,
-            },
-            4 => GameStateChange::WinGame {
-                show_credits: value as u8 != 0,
-            },
-            5 => GameStateChange::DemoEvent(match value as u8 {
-                0 => DemoEventType::ShowWelcomeToDemoScreen,
-                101 => DemoEventType::TellMovementControls,
-                102 => DemoEventType::TellJumpControl,
-                103 => DemoEventType::TellInventoryControl,
-                104 => DemoEventType::TellDemoIsOver,
-                other => bail!("Invalid demo event type: {}", other),
-            }),
-            6 => GameStateChange::ArrowHitAnyPlayer,
-            7 => GameStateChange::RainLevelChange { rain_level: value },
-            8 => GameStateChange::ThunderLevelChange {
-                thunder_level: value,
-            },
-            9 => GameStateChange::PlayPufferfishStingSound,
-            10 => GameStateChange::PlayElderGuardianAppearance,
-            11 => GameStateChange::EnableRespawnScreen {
-                enable: value as u8 == 0,
-            },
-            other => bail!("Invalid game state change reason: {}", other),
-        })
+        GameStateChange::from_reason(reason, value)
     }
 }
 
@@ -577,10 +596,39 @@ def_enum! {
     }
 }
 
+def_enum! {
+    WindowType (VarInt) {
+        0 = Generic9x1,
+        1 = Generic9x2,
+        2 = Generic9x3,
+        3 = Generic9x4,
+        4 = Generic9x5,
+        5 = Generic9x6,
+        6 = Generic3x3,
+        7 = Anvil,
+        8 = Beacon,
+        9 = BlastFurnace,
+        10 = BrewingStand,
+        11 = Crafting,
+        12 = Enchantment,
+        13 = Furnace,
+        14 = Grindstone,
+        15 = Hopper,
+        16 = Lectern,
+        17 = Loom,
+        18 = Merchant,
+        19 = ShulkerBox,
+        20 = Smithing,
+        21 = Smoker,
+        22 = CartographyTable,
+        23 = Stonecutter,
+    }
+}
+
 packets! {
     OpenWindow {
         window_id VarInt;
-        window_kind VarInt;
+        window_kind WindowType;
         window_title String;
     }
 
@@ -674,8 +722,7 @@ impl Readable for Particle {
                 *block_state = BlockState::from_id(state.0 as u16).unwrap();
             }
             ParticleKind::Item(ref mut item) => {
-                let _slot = Slot::read(buffer, version)?;
-                *item = None; // TODO: Use item from libcraft once fully moved
+                *item = Slot::read(buffer, version)?.item_kind();
             }
             _ => {}
         }
@@ -726,8 +773,12 @@ impl Writeable for Particle {
             ParticleKind::FallingDust(block_state) => {
                 VarInt(block_state.id() as i32).write(buffer, version)?;
             }
-            ParticleKind::Item(_item) => {
-                todo![];
+            ParticleKind::Item(item) => {
+                match item {
+                    Some(item) => Slot::new(item, 1),
+                    None => Slot::default(),
+                }
+                .write(buffer, version)?;
             }
             _ => {}
         }
@@ -735,6 +786,215 @@ impl Writeable for Particle {
     }
 }
 
+/// Describes the command graph to the client,
+/// enabling tab completion and syntax highlighting.
+#[derive(Debug, Clone)]
+pub struct DeclareCommands {
+    pub nodes: Vec<CommandNode>,
+    /// Index of the root node within `nodes`.
+    pub root_index: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct CommandNode {
+    pub kind: CommandNodeKind,
+    /// Whether the command is complete at this node.
+    pub executable: bool,
+    /// Indices of the child nodes within `DeclareCommands::nodes`.
+    pub children: Vec<i32>,
+    /// Index of the node this node redirects to, if any.
+    pub redirect_node: Option<i32>,
+    /// For argument nodes, the identifier of the server-side
+    /// suggestion provider, e.g. `minecraft:ask_server`.
+    pub suggestions_type: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum CommandNodeKind {
+    Root,
+    Literal {
+        name: String,
+    },
+    Argument {
+        name: String,
+        /// The parser identifier, e.g. `brigadier:integer`.
+        parser: String,
+        /// Parser-specific properties, already encoded.
+        properties: Vec<u8>,
+    },
+}
+
+const COMMAND_NODE_EXECUTABLE: u8 = 0x04;
+const COMMAND_NODE_HAS_REDIRECT: u8 = 0x08;
+const COMMAND_NODE_HAS_SUGGESTIONS_TYPE: u8 = 0x10;
+
+impl Readable for DeclareCommands {
+    fn read(buffer: &mut Cursor<&[u8]>, version: ProtocolVersion) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let num_nodes = VarInt::read(buffer, version)?.0;
+        let mut nodes = Vec::new();
+        for _ in 0..num_nodes {
+            nodes.push(CommandNode::read(buffer, version)?);
+        }
+        let root_index = VarInt::read(buffer, version)?.0;
+        Ok(DeclareCommands { nodes, root_index })
+    }
+}
+
+impl Writeable for DeclareCommands {
+    fn write(&self, buffer: &mut Vec<u8>, version: ProtocolVersion) -> anyhow::Result<()> {
+        VarInt(self.nodes.len() as i32).write(buffer, version)?;
+        for node in &self.nodes {
+            node.write(buffer, version)?;
+        }
+        VarInt(self.root_index).write(buffer, version)?;
+        Ok(())
+    }
+}
+
+impl Readable for CommandNode {
+    fn read(buffer: &mut Cursor<&[u8]>, version: ProtocolVersion) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let flags = u8::read(buffer, version)?;
+
+        let num_children = VarInt::read(buffer, version)?.0;
+        let mut children = Vec::new();
+        for _ in 0..num_children {
+            children.push(VarInt::read(buffer, version)?.0);
+        }
+
+        let redirect_node = if flags & COMMAND_NODE_HAS_REDIRECT != 0 {
+            Some(VarInt::read(buffer, version)?.0)
+        } else {
+            None
+        };
+
+        let kind = match flags & 0x03 {
+            0 => CommandNodeKind::Root,
+            1 => CommandNodeKind::Literal {
+                name: String::read(buffer, version)?,
+            },
+            2 => {
+                let name = String::read(buffer, version)?;
+                let parser = String::read(buffer, version)?;
+                let properties = read_parser_properties(&parser, buffer, version)?;
+                CommandNodeKind::Argument {
+                    name,
+                    parser,
+                    properties,
+                }
+            }
+            x => bail!("invalid command node type {}", x),
+        };
+
+        let suggestions_type = if flags & COMMAND_NODE_HAS_SUGGESTIONS_TYPE != 0 {
+            Some(String::read(buffer, version)?)
+        } else {
+            None
+        };
+
+        Ok(CommandNode {
+            kind,
+            executable: flags & COMMAND_NODE_EXECUTABLE != 0,
+            children,
+            redirect_node,
+            suggestions_type,
+        })
+    }
+}
+
+impl Writeable for CommandNode {
+    fn write(&self, buffer: &mut Vec<u8>, version: ProtocolVersion) -> anyhow::Result<()> {
+        let mut flags = match &self.kind {
+            CommandNodeKind::Root => 0u8,
+            CommandNodeKind::Literal { .. } => 1,
+            CommandNodeKind::Argument { .. } => 2,
+        };
+        if self.executable {
+            flags |= COMMAND_NODE_EXECUTABLE;
+        }
+        if self.redirect_node.is_some() {
+            flags |= COMMAND_NODE_HAS_REDIRECT;
+        }
+        // Suggestion types are only valid for argument nodes.
+        let suggestions_type = match &self.kind {
+            CommandNodeKind::Argument { .. } => self.suggestions_type.as_ref(),
+            _ => None,
+        };
+        if suggestions_type.is_some() {
+            flags |= COMMAND_NODE_HAS_SUGGESTIONS_TYPE;
+        }
+        flags.write(buffer, version)?;
+
+        VarInt(self.children.len() as i32).write(buffer, version)?;
+        for &child in &self.children {
+            VarInt(child).write(buffer, version)?;
+        }
+
+        if let Some(redirect_node) = self.redirect_node {
+            VarInt(redirect_node).write(buffer, version)?;
+        }
+
+        match &self.kind {
+            CommandNodeKind::Root => {}
+            CommandNodeKind::Literal { name } => name.write(buffer, version)?,
+            CommandNodeKind::Argument {
+                name,
+                parser,
+                properties,
+            } => {
+                name.write(buffer, version)?;
+                parser.write(buffer, version)?;
+                buffer.extend_from_slice(properties);
+            }
+        }
+
+        if let Some(suggestions_type) = suggestions_type {
+            suggestions_type.write(buffer, version)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the properties of an argument node, returning them
+/// in encoded form. The length of the properties depends on the parser.
+fn read_parser_properties(
+    parser: &str,
+    buffer: &mut Cursor<&[u8]>,
+    version: ProtocolVersion,
+) -> anyhow::Result<Vec<u8>> {
+    let mut properties = Vec::new();
+    match parser {
+        "brigadier:float" | "brigadier:double" | "brigadier:integer" | "brigadier:long" => {
+            let bound_size = match parser {
+                "brigadier:float" | "brigadier:integer" => 4,
+                _ => 8,
+            };
+            let flags = u8::read(buffer, version)?;
+            flags.write(&mut properties, version)?;
+            // Minimum and maximum bounds
+            for &bit in &[0x01, 0x02] {
+                if flags & bit != 0 {
+                    for _ in 0..bound_size {
+                        u8::read(buffer, version)?.write(&mut properties, version)?;
+                    }
+                }
+            }
+        }
+        "brigadier:string" => VarInt::read(buffer, version)?.write(&mut properties, version)?,
+        "minecraft:entity" | "minecraft:score_holder" | "minecraft:range" => {
+            u8::read(buffer, version)?.write(&mut properties, version)?
+        }
+        _ => {}
+    }
+    Ok(properties)
+}
+
 #[derive(Debug, Clone)]
 pub struct AddPlayer {
     pub uuid: Uuid,
@@ -964,7 +1224,7 @@ packets! {
         world_name String;
         hashed_seed u64;
         gamemode Gamemode;
-        previous_gamemode Gamemode;
+        previous_gamemode PreviousGamemode;
         is_debug bool;
         is_flat bool;
         copy_metadata bool;
@@ -1402,3 +1662,63 @@ packets! {
         entries VarIntPrefixedVec<VarInt>;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn game_state_changes_round_trip() {
+        let changes = vec![
+            GameStateChange::BeginRaining,
+            GameStateChange::ChangeGamemode {
+                gamemode: Gamemode::Creative,
+            },
+            GameStateChange::DemoEvent(DemoEventType::TellJumpControl),
+            GameStateChange::RainLevelChange { rain_level: 0.5 },
+            GameStateChange::EnableRespawnScreen { enable: false },
+        ];
+        for change in changes {
+            let mut buffer = Vec::new();
+            change.write(&mut buffer, ProtocolVersion::V1_16_2).unwrap();
+            let read =
+                GameStateChange::read(&mut Cursor::new(&buffer[..]), ProtocolVersion::V1_16_2)
+                    .unwrap();
+            assert_eq!(read, change);
+        }
+    }
+
+    #[test]
+    fn keepalive_ids_are_longs() {
+        // Supported versions all use long keepalive IDs,
+        // which 1.12.2 introduced in place of VarInts.
+        let id = -(1 << 40) - 7;
+        let mut buffer = Vec::new();
+        KeepAlive { id }
+            .write(&mut buffer, ProtocolVersion::V1_16_2)
+            .unwrap();
+        assert_eq!(buffer, id.to_be_bytes());
+
+        let response = crate::packets::client::KeepAlive::read(
+            &mut Cursor::new(&buffer[..]),
+            ProtocolVersion::V1_16_2,
+        )
+        .unwrap();
+        assert_eq!(response.id, id);
+    }
+
+    #[test]
+    fn gamemode_change_expects_integer_value() {
+        let change = GameStateChange::ChangeGamemode {
+            gamemode: Gamemode::Spectator,
+        };
+        assert_eq!(change.reason(), GameStateChange::CHANGE_GAMEMODE);
+        assert_eq!(change.value(), 3.0);
+        assert!(GameStateChange::from_reason(GameStateChange::CHANGE_GAMEMODE, 1.5).is_err());
+        assert!(GameStateChange::from_reason(GameStateChange::CHANGE_GAMEMODE, -1.0).is_err());
+        assert_eq!(
+            GameStateChange::from_reason(GameStateChange::RAIN_LEVEL_CHANGE, 0.25).unwrap(),
+            GameStateChange::RainLevelChange { rain_level: 0.25 }
+        );
+    }
+}
@@ -4,9 +4,27 @@ use base::{chunk::PackedArray, Chunk, ChunkHandle, ChunkLock, ChunkPosition, Chu
 
 use crate::{io::VarInt, ProtocolVersion, Readable, Writeable};
 
+/// Number of sections with light data: the chunk's sections
+/// plus one below and one above the world.
+const LIGHT_SECTIONS: usize = 18;
+
 #[derive(Clone)]
 pub struct UpdateLight {
     pub chunk: ChunkHandle,
+    /// Indices into [`Chunk::sections`] of the sections whose light
+    /// is sent, e.g. those affected by a block change.
+    /// `None` sends all sections.
+    pub sections: Option<Vec<usize>>,
+}
+
+impl UpdateLight {
+    /// Creates a packet sending the light of all sections of `chunk`.
+    pub fn new(chunk: ChunkHandle) -> Self {
+        Self {
+            chunk,
+            sections: None,
+        }
+    }
 }
 
 impl Debug for UpdateLight {
@@ -25,24 +43,42 @@ impl Writeable for UpdateLight {
 
         true.write(buffer, version)?; // trust edges?
 
-        let mut mask = 0;
+        let selected = match &self.sections {
+            Some(sections) => sections
+                .iter()
+                .filter(|&&y| y < LIGHT_SECTIONS)
+                .fold(0, |mask, &y| mask | 1 << y),
+            None => (1 << LIGHT_SECTIONS) - 1,
+        };
+        let mut present = 0;
         for (y, section) in chunk.sections().iter().enumerate() {
             if section.is_some() {
-                mask |= 1 << y;
+                present |= 1 << y;
             }
         }
+        let mask = selected & present;
 
         VarInt(mask).write(buffer, version)?; // sky light mask
         VarInt(mask).write(buffer, version)?; // block light mask
 
-        VarInt(!mask).write(buffer, version)?; // empty sky light mask
-        VarInt(!mask).write(buffer, version)?; // empty block light mask
-
-        for section in chunk.sections().iter().flatten() {
+        // Missing sections contain only air. Their block light is zero,
+        // but their sky light is left for the client to derive from
+        // the sections above; sending it as empty would make them dark.
+        VarInt(0).write(buffer, version)?; // empty sky light mask
+        VarInt(selected & !present).write(buffer, version)?; // empty block light mask
+
+        let sent_sections = || {
+            chunk
+                .sections()
+                .iter()
+                .enumerate()
+                .filter(|(y, _)| mask & (1 << y) != 0)
+                .filter_map(|(_, section)| section.as_ref())
+        };
+        for section in sent_sections() {
             encode_light(section.light().sky_light(), buffer, version);
         }
-
-        for section in chunk.sections().iter().flatten() {
+        for section in sent_sections() {
             encode_light(section.light().block_light(), buffer, version);
         }
 
@@ -122,8 +158,60 @@ impl Readable for UpdateLight {
             }
         }
 
-        Ok(Self {
-            chunk: Arc::new(ChunkLock::new(chunk, true)),
-        })
+        Ok(Self::new(Arc::new(ChunkLock::new(chunk, true))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn read_masks(packet: &UpdateLight) -> (Vec<i32>, usize) {
+        let mut buffer = Vec::new();
+        packet.write(&mut buffer, ProtocolVersion::V1_16_2).unwrap();
+        let mut cursor = Cursor::new(buffer.as_slice());
+        let _x = VarInt::read(&mut cursor, ProtocolVersion::V1_16_2).unwrap();
+        let _z = VarInt::read(&mut cursor, ProtocolVersion::V1_16_2).unwrap();
+        let _trust_edges = bool::read(&mut cursor, ProtocolVersion::V1_16_2).unwrap();
+        let masks = (0..4)
+            .map(|_| {
+                VarInt::read(&mut cursor, ProtocolVersion::V1_16_2)
+                    .unwrap()
+                    .0
+            })
+            .collect();
+        let remaining = buffer.len() - cursor.position() as usize;
+        (masks, remaining)
+    }
+
+    fn chunk_with_sections(sections: &[isize]) -> ChunkHandle {
+        let mut chunk = Chunk::new(ChunkPosition::new(0, 0));
+        for &y in sections {
+            chunk.set_section_at(y, Some(ChunkSection::default()));
+        }
+        Arc::new(ChunkLock::new(chunk, true))
+    }
+
+    #[test]
+    fn masks_cover_present_sections() {
+        // Sections 0 and 2 are at indices 1 and 3
+        let packet = UpdateLight::new(chunk_with_sections(&[0, 2]));
+        let (masks, remaining) = read_masks(&packet);
+        let present = 0b1010;
+        assert_eq!(masks, vec![present, present, 0, !present & 0x3ffff]);
+        assert_eq!(remaining, 4 * (2 + 2048));
+    }
+
+    #[test]
+    fn only_selected_sections_are_sent() {
+        let packet = UpdateLight {
+            chunk: chunk_with_sections(&[0, 2]),
+            sections: Some(vec![3, 5]),
+        };
+        let (masks, remaining) = read_masks(&packet);
+        assert_eq!(masks, vec![1 << 3, 1 << 3, 0, 1 << 5]);
+        assert_eq!(remaining, 2 * (2 + 2048));
     }
 }
@@ -0,0 +1,73 @@
+//! Assets embedded in the server binary.
+
+use std::io::Cursor;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::dimension::DimensionType;
+
+/// The dimension codec sent by the default vanilla server.
+/// (Data acquired via tools/proxy)
+const DIMENSION_CODEC: &[u8] = include_bytes!("../../../assets/dimension_codec.nbt");
+
+/// Checks that the embedded assets can be parsed, so that
+/// a corrupted build fails at startup rather than on the first join.
+pub(crate) fn validate() -> anyhow::Result<()> {
+    parse("dimension_codec.nbt", DIMENSION_CODEC)?;
+    dimension_codec_types()?;
+    Ok(())
+}
+
+/// Returns the dimension codec sent in Join Game.
+pub(crate) fn dimension_codec() -> nbt::Blob {
+    parse("dimension_codec.nbt", DIMENSION_CODEC).expect("assets are validated at startup")
+}
+
+/// Returns the dimension types in the dimension codec.
+pub(crate) fn dimension_codec_types() -> anyhow::Result<Vec<RegistryEntry>> {
+    let codec: DimensionCodec = nbt::from_reader(&mut Cursor::new(DIMENSION_CODEC))
+        .context("bundled asset dimension_codec.nbt is malformed")?;
+    Ok(codec.dimension_types.value)
+}
+
+#[derive(Deserialize)]
+struct DimensionCodec {
+    #[serde(rename = "minecraft:dimension_type")]
+    dimension_types: Registry,
+}
+
+#[derive(Deserialize)]
+struct Registry {
+    value: Vec<RegistryEntry>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RegistryEntry {
+    pub name: String,
+    pub element: DimensionType,
+}
+
+fn parse(name: &str, bytes: &[u8]) -> anyhow::Result<nbt::Blob> {
+    nbt::Blob::from_reader(&mut Cursor::new(bytes))
+        .with_context(|| format!("bundled asset {} is malformed", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_assets_are_valid() {
+        validate().unwrap();
+    }
+
+    #[test]
+    fn malformed_asset_is_named() {
+        let error = parse("dimension.nbt", &[0x0a, 0x00]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "bundled asset dimension.nbt is malformed"
+        );
+    }
+}
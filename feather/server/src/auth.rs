@@ -0,0 +1,175 @@
+//! Authentication of players logging in.
+//!
+//! The server hands each login to the [`Authenticator`] in its
+//! [`Options`](crate::Options). [`MojangAuthenticator`] verifies
+//! players against the Mojang session server, [`OfflineAuthenticator`]
+//! trusts the username sent by the client, and [`ForwardingAuthenticator`]
+//! trusts the profile forwarded by a BungeeCord or Velocity proxy.
+
+use std::{convert::TryInto, fmt::Debug};
+
+use anyhow::Context;
+use base::ProfileProperty;
+use md5::Digest;
+use serde::Deserialize;
+use uuid::Uuid;
+
+pub use crate::initial_handler::ProxyData;
+
+/// The identity of an authenticated player.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct GameProfile {
+    #[serde(rename = "id")]
+    pub uuid: Uuid,
+    pub name: String,
+    /// Profile properties, usually the player's skin.
+    pub properties: Vec<ProfileProperty>,
+}
+
+/// Verifies the identity of players logging in.
+///
+/// Authenticators are called from a blocking thread,
+/// so they may perform blocking I/O.
+pub trait Authenticator: Debug + Send + Sync {
+    /// Whether the login requires the encryption handshake.
+    /// If `false`, the connection stays unencrypted and
+    /// the `server_hash` passed to [`authenticate`](Self::authenticate)
+    /// is empty.
+    fn requires_encryption(&self) -> bool;
+
+    /// Returns the profile of the player logging in as `username`.
+    ///
+    /// `server_hash` is the hash of the login's shared secret and the
+    /// server's public key. `forwarded` holds the data forwarded by a
+    /// proxy, if proxy forwarding is enabled.
+    fn authenticate(
+        &self,
+        username: &str,
+        server_hash: &str,
+        forwarded: Option<&ProxyData>,
+    ) -> anyhow::Result<GameProfile>;
+}
+
+/// Authenticates players with the Mojang session server.
+#[derive(Debug, Default)]
+pub struct MojangAuthenticator;
+
+impl Authenticator for MojangAuthenticator {
+    fn requires_encryption(&self) -> bool {
+        true
+    }
+
+    fn authenticate(
+        &self,
+        username: &str,
+        server_hash: &str,
+        _forwarded: Option<&ProxyData>,
+    ) -> anyhow::Result<GameProfile> {
+        let url = format!(
+            "https://sessionserver.mojang.com/session/minecraft/hasJoined?username={}&serverId={}",
+            username, server_hash
+        );
+        let response = ureq::get(&url).call()?;
+        Ok(response.into_json()?)
+    }
+}
+
+/// Accepts any username without verification, deriving
+/// the player's UUID from it like the vanilla server.
+#[derive(Debug, Default)]
+pub struct OfflineAuthenticator;
+
+impl Authenticator for OfflineAuthenticator {
+    fn requires_encryption(&self) -> bool {
+        false
+    }
+
+    fn authenticate(
+        &self,
+        username: &str,
+        _server_hash: &str,
+        _forwarded: Option<&ProxyData>,
+    ) -> anyhow::Result<GameProfile> {
+        Ok(GameProfile {
+            uuid: offline_mode_uuid(username),
+            name: username.to_owned(),
+            properties: Vec::new(),
+        })
+    }
+}
+
+/// Uses the UUID and properties forwarded by a proxy,
+/// which has already authenticated the player.
+#[derive(Debug, Default)]
+pub struct ForwardingAuthenticator;
+
+impl Authenticator for ForwardingAuthenticator {
+    fn requires_encryption(&self) -> bool {
+        false
+    }
+
+    fn authenticate(
+        &self,
+        username: &str,
+        _server_hash: &str,
+        forwarded: Option<&ProxyData>,
+    ) -> anyhow::Result<GameProfile> {
+        let forwarded = forwarded.context("proxy did not forward the player's profile")?;
+        Ok(GameProfile {
+            uuid: forwarded.uuid,
+            name: username.to_owned(),
+            properties: forwarded.profile.clone(),
+        })
+    }
+}
+
+fn offline_mode_uuid(username: &str) -> Uuid {
+    // See: https://gist.github.com/games647/2b6a00a8fc21fd3b88375f03c9e2e603
+    let mut hasher = md5::Md5::default();
+    hasher.update(format!("OfflinePlayer:{}", username).as_bytes());
+    let hash = hasher.finalize();
+
+    let mut builder = uuid::Builder::from_bytes(hash.try_into().unwrap());
+
+    builder
+        .set_variant(uuid::Variant::RFC4122)
+        .set_version(uuid::Version::Md5);
+
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_uuids_match_vanilla() {
+        let profile = OfflineAuthenticator
+            .authenticate("Notch", "", None)
+            .unwrap();
+        assert_eq!(
+            profile.uuid,
+            Uuid::parse_str("b50ad385-829d-3141-a216-7e7d7539ba7f").unwrap()
+        );
+        assert_eq!(profile.name, "Notch");
+    }
+
+    #[test]
+    fn forwarding_requires_forwarded_data() {
+        assert!(ForwardingAuthenticator
+            .authenticate("Notch", "", None)
+            .is_err());
+
+        let uuid = Uuid::from_u128(1);
+        let forwarded = ProxyData {
+            host: "127.0.0.1".to_owned(),
+            client: "203.0.113.7".to_owned(),
+            uuid,
+            profile: Vec::new(),
+        };
+        let profile = ForwardingAuthenticator
+            .authenticate("Notch", "", Some(&forwarded))
+            .unwrap();
+        assert_eq!(profile.uuid, uuid);
+    }
+}
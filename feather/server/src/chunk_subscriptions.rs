@@ -1,26 +1,118 @@
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
 use base::ChunkPosition;
 use common::{events::ViewUpdateEvent, view::View, Game};
 use ecs::{SysResult, SystemExecutor};
 use quill_common::events::EntityRemoveEvent;
 use utils::vec_remove_item;
 
-use crate::{ClientId, Server};
+use crate::{Client, ClientId, Server};
 
 /// Data structure to query which clients should
 /// receive updates from a given chunk, fast.
+///
+/// Clients are subscribed to the chunks in their view, and stay
+/// subscribed until a chunk is more than the unsubscribe margin
+/// beyond their view distance. This avoids churn when a player
+/// moves back and forth across a chunk boundary.
 #[derive(Default)]
 pub struct ChunkSubscriptions {
     chunks: AHashMap<ChunkPosition, Vec<ClientId>>,
+    unsubscribe_margin: u32,
 }
 
 impl ChunkSubscriptions {
+    pub fn with_unsubscribe_margin(unsubscribe_margin: u32) -> Self {
+        Self {
+            unsubscribe_margin,
+            ..Default::default()
+        }
+    }
+
     pub fn subscriptions_for(&self, chunk: ChunkPosition) -> &[ClientId] {
         self.chunks
             .get(&chunk)
             .map(Vec::as_slice)
             .unwrap_or_default()
     }
+
+    /// Returns the clients subscribed to `chunk` or any of the
+    /// eight chunks adjacent to it. Each client is listed once,
+    /// even if it is subscribed to several of these chunks.
+    pub fn subscriptions_around(&self, chunk: ChunkPosition) -> Vec<ClientId> {
+        let mut seen = AHashSet::new();
+        let mut clients = Vec::new();
+        for x in chunk.x - 1..=chunk.x + 1 {
+            for z in chunk.z - 1..=chunk.z + 1 {
+                for &client_id in self.subscriptions_for(ChunkPosition::new(x, z)) {
+                    if seen.insert(client_id) {
+                        clients.push(client_id);
+                    }
+                }
+            }
+        }
+        clients
+    }
+
+    /// Returns the chunks the given client is subscribed to,
+    /// in no particular order.
+    pub fn chunks_for(&self, client_id: ClientId) -> Vec<ChunkPosition> {
+        self.chunks
+            .iter()
+            .filter(|(_, clients)| clients.contains(&client_id))
+            .map(|(&chunk, _)| chunk)
+            .collect()
+    }
+
+    /// Updates the subscriptions of a client whose view changed,
+    /// returning the chunks it was unsubscribed from.
+    pub(crate) fn update_view(
+        &mut self,
+        client_id: ClientId,
+        old_view: View,
+        new_view: View,
+    ) -> Vec<ChunkPosition> {
+        for new_chunk in new_view.difference(old_view) {
+            let clients = self.chunks.entry(new_chunk).or_default();
+            // Still subscribed if within the margin of the old view
+            if !clients.contains(&client_id) {
+                clients.push(client_id);
+            }
+        }
+
+        let mut unsubscribed = Vec::new();
+        let retained = new_view.with_margin(self.unsubscribe_margin);
+        for old_chunk in old_view
+            .with_margin(self.unsubscribe_margin)
+            .difference(retained)
+        {
+            if self.remove_subscription(old_chunk, client_id) {
+                unsubscribed.push(old_chunk);
+            }
+        }
+        unsubscribed
+    }
+
+    /// Removes all subscriptions of the given client.
+    pub(crate) fn remove_client(&mut self, client_id: ClientId) {
+        self.chunks.retain(|_, clients| {
+            vec_remove_item(clients, &client_id);
+            !clients.is_empty()
+        });
+    }
+
+    /// Returns whether the client was subscribed to the chunk.
+    fn remove_subscription(&mut self, chunk: ChunkPosition, client_id: ClientId) -> bool {
+        let mut removed = false;
+        if let Some(vec) = self.chunks.get_mut(&chunk) {
+            removed = vec.contains(&client_id);
+            vec_remove_item(vec, &client_id);
+
+            if vec.is_empty() {
+                self.chunks.remove(&chunk);
+            }
+        }
+        removed
+    }
 }
 
 pub fn register(systems: &mut SystemExecutor<Game>) {
@@ -32,39 +124,222 @@ pub fn register(systems: &mut SystemExecutor<Game>) {
 fn update_chunk_subscriptions(game: &mut Game, server: &mut Server) -> SysResult {
     // Update players whose views have changed
     for (_, (event, &client_id)) in game.ecs.query::<(&ViewUpdateEvent, &ClientId)>().iter() {
-        for new_chunk in event.new_view.difference(event.old_view) {
+        let unsubscribed =
             server
                 .chunk_subscriptions
-                .chunks
-                .entry(new_chunk)
-                .or_default()
-                .push(client_id);
-        }
-        for old_chunk in event.old_view.difference(event.new_view) {
-            remove_subscription(server, old_chunk, client_id);
+                .update_view(client_id, event.old_view, event.new_view);
+        if let Some(client) = server.clients.get(client_id) {
+            unload_chunks(client, &unsubscribed);
         }
     }
 
     // Update players that have left
-    for (_, (_event, &client_id, &view)) in game
-        .ecs
-        .query::<(&EntityRemoveEvent, &ClientId, &View)>()
-        .iter()
-    {
-        for chunk in view.iter() {
-            remove_subscription(server, chunk, client_id);
-        }
+    for (_, (_event, &client_id)) in game.ecs.query::<(&EntityRemoveEvent, &ClientId)>().iter() {
+        server.chunk_subscriptions.remove_client(client_id);
     }
 
     Ok(())
 }
 
-fn remove_subscription(server: &mut Server, chunk: ChunkPosition, client_id: ClientId) {
-    if let Some(vec) = server.chunk_subscriptions.chunks.get_mut(&chunk) {
-        vec_remove_item(vec, &client_id);
+/// Unloads the chunks a client was unsubscribed from
+/// on the client, if they were sent to it.
+fn unload_chunks(client: &Client, unsubscribed: &[ChunkPosition]) {
+    for &chunk in unsubscribed {
+        if client.knows_chunk(chunk) {
+            client.unload_chunk(chunk);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use base::{position, Chunk, ChunkLock, Position};
+    use protocol::ServerPlayPacket;
 
-        if vec.is_empty() {
-            server.chunk_subscriptions.chunks.remove(&chunk);
+    use crate::{
+        client::{
+            tests::{test_client, test_client_with_packets},
+            Clients,
+        },
+        tests::{join_test_player, test_game, test_server},
+    };
+
+    use super::*;
+
+    #[test]
+    fn chunks_for_reflects_view_changes() {
+        let mut clients = Clients::new();
+        let client = clients.insert(test_client());
+        let other = clients.insert(test_client());
+        let mut subscriptions = ChunkSubscriptions::default();
+
+        // Joining subscribes the player to its whole view
+        let own_chunk = ChunkPosition::new(3, -2);
+        let view = View::new(own_chunk, 2);
+        subscriptions.update_view(client, View::empty(), view);
+        let chunks = subscriptions.chunks_for(client);
+        assert!(chunks.contains(&own_chunk));
+        assert_eq!(chunks.len(), 25);
+
+        let mut smaller_view = view;
+        smaller_view.set_view_distance(1);
+        subscriptions.update_view(client, view, smaller_view);
+        assert_eq!(subscriptions.chunks_for(client).len(), 9);
+
+        assert!(subscriptions.chunks_for(other).is_empty());
+
+        subscriptions.update_view(other, View::empty(), view);
+        subscriptions.remove_client(client);
+        assert!(subscriptions.chunks_for(client).is_empty());
+        assert_eq!(subscriptions.chunks_for(other).len(), 25);
+    }
+
+    #[test]
+    fn subscriptions_around_include_adjacent_chunks_once() {
+        let mut clients = Clients::new();
+        let inside = clients.insert(test_client());
+        let adjacent = clients.insert(test_client());
+        let far = clients.insert(test_client());
+        let mut subscriptions = ChunkSubscriptions::default();
+
+        subscriptions.update_view(
+            inside,
+            View::empty(),
+            View::new(ChunkPosition::new(0, 0), 1),
+        );
+        // Sees the chunks west of (1, 0), but not (1, 0) itself
+        subscriptions.update_view(
+            adjacent,
+            View::empty(),
+            View::new(ChunkPosition::new(-1, 0), 1),
+        );
+        subscriptions.update_view(far, View::empty(), View::new(ChunkPosition::new(-5, 0), 1));
+
+        let chunk = ChunkPosition::new(1, 0);
+        assert_eq!(subscriptions.subscriptions_for(chunk), &[inside]);
+        let mut around = subscriptions.subscriptions_around(chunk);
+        around.sort_by_key(|&id| id != inside);
+        assert_eq!(around, vec![inside, adjacent]);
+    }
+
+    #[test]
+    fn oscillating_on_boundary_keeps_subscriptions() {
+        let mut clients = Clients::new();
+        let client = clients.insert(test_client());
+        let mut subscriptions = ChunkSubscriptions::with_unsubscribe_margin(1);
+
+        let west = View::new(ChunkPosition::new(0, 0), 2);
+        let east = View::new(ChunkPosition::new(1, 0), 2);
+        subscriptions.update_view(client, View::empty(), west);
+        for _ in 0..5 {
+            assert!(subscriptions.update_view(client, west, east).is_empty());
+            assert!(subscriptions.update_view(client, east, west).is_empty());
+        }
+        // Both views and nothing else
+        assert_eq!(subscriptions.chunks_for(client).len(), 30);
+        assert_eq!(
+            subscriptions.subscriptions_for(ChunkPosition::new(3, 0)),
+            &[client]
+        );
+
+        // Moving further than the margin unsubscribes
+        let far_east = View::new(ChunkPosition::new(3, 0), 2);
+        let unsubscribed = subscriptions.update_view(client, west, far_east);
+        assert_eq!(unsubscribed.len(), 10);
+        assert!(unsubscribed.iter().all(|chunk| chunk.x < 0));
+        assert!(subscriptions
+            .chunks_for(client)
+            .iter()
+            .all(|&chunk| far_east.with_margin(1).contains(chunk)));
+    }
+
+    #[test]
+    fn unsubscribing_unloads_chunks_once() {
+        let (client, sent_packets) = test_client_with_packets();
+        let mut clients = Clients::new();
+        let client_id = clients.insert(client);
+        let client = clients.get(client_id).unwrap();
+        let mut subscriptions = ChunkSubscriptions::with_unsubscribe_margin(0);
+
+        let view = View::new(ChunkPosition::new(0, 0), 1);
+        subscriptions.update_view(client_id, View::empty(), view);
+        for chunk in view.iter() {
+            client.send_chunk(&Arc::new(ChunkLock::new(Chunk::new(chunk), true)));
+        }
+
+        // Moving one chunk east drops the westmost column
+        let moved = View::new(ChunkPosition::new(1, 0), 1);
+        let unsubscribed = subscriptions.update_view(client_id, view, moved);
+        unload_chunks(client, &unsubscribed);
+        let mut unloaded: Vec<_> = sent_packets
+            .try_iter()
+            .map(|packet| match packet {
+                ServerPlayPacket::UnloadChunk(packet) => (packet.chunk_x, packet.chunk_z),
+                packet => panic!("expected UnloadChunk, got {:?}", packet),
+            })
+            .collect();
+        unloaded.sort_unstable();
+        assert_eq!(unloaded, vec![(-1, -1), (-1, 0), (-1, 1)]);
+        assert!(!client.knows_chunk(ChunkPosition::new(-1, 0)));
+
+        // Updating the same view again unloads nothing
+        let unsubscribed = subscriptions.update_view(client_id, moved, moved);
+        unload_chunks(client, &unsubscribed);
+        assert!(sent_packets.is_empty());
+    }
+
+    #[test]
+    fn moving_away_unloads_the_chunks_left_behind() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let mut views = SystemExecutor::new();
+        common::view::register(&mut game, &mut views);
+        let player = join_test_player(&mut game, &mut server, &new_players);
+        views.run(&mut game);
+        update_chunk_subscriptions(&mut game, &mut server).unwrap();
+
+        let view = *game.ecs.get::<View>(player.entity).unwrap();
+        let mut old_chunks: Vec<_> = view.iter().map(|chunk| (chunk.x, chunk.z)).collect();
+        old_chunks.sort_unstable();
+        assert_eq!(
+            server.subscribed_chunks_for(player.client_id).len(),
+            old_chunks.len()
+        );
+        let client = server.clients.get(player.client_id).unwrap();
+        for chunk in view.iter() {
+            client.send_chunk(&Arc::new(ChunkLock::new(Chunk::new(chunk), true)));
         }
+        player.connection.sent_packets.try_iter().for_each(drop);
+
+        // Far enough east that the margins of both views are disjoint
+        let margin = server.options().chunk_unsubscribe_margin;
+        let shift = 2 * (view.view_distance() + margin) as i32 + 1;
+        *game.ecs.get_mut::<Position>(player.entity).unwrap() =
+            position!(shift as f64 * 16.0 + 8.0, 64.0, 8.0);
+        let unloaded = |game: &mut Game, server: &mut Server, views: &mut SystemExecutor<Game>| {
+            views.run(game);
+            update_chunk_subscriptions(game, server).unwrap();
+            let mut unloaded: Vec<_> = player
+                .connection
+                .sent_packets
+                .try_iter()
+                .filter_map(|packet| match packet {
+                    ServerPlayPacket::UnloadChunk(packet) => Some((packet.chunk_x, packet.chunk_z)),
+                    _ => None,
+                })
+                .collect();
+            unloaded.sort_unstable();
+            unloaded
+        };
+        assert_eq!(unloaded(&mut game, &mut server, &mut views), old_chunks);
+        assert!(server
+            .subscribed_chunks_for(player.client_id)
+            .iter()
+            .all(|chunk| chunk.x > view.view_distance() as i32));
+
+        // Staying in the new chunk unloads nothing more
+        assert!(unloaded(&mut game, &mut server, &mut views).is_empty());
     }
 }
@@ -1,25 +1,30 @@
 use std::{
     cell::{Cell, RefCell},
     collections::VecDeque,
-    io::Cursor,
+    convert::TryFrom,
+    rc::Rc,
     sync::Arc,
+    time::{Duration, Instant},
 };
 
-use ahash::AHashSet;
-use flume::{Receiver, Sender};
+use ahash::{AHashMap, AHashSet};
+use flume::{Receiver, Sender, TryRecvError};
 use slab::Slab;
 use uuid::Uuid;
 
 use base::{
-    BlockId, ChunkHandle, ChunkPosition, EntityKind, EntityMetadata, Gamemode, Position,
-    ProfileProperty, Text, ValidBlockPosition,
+    BlockId, ChunkHandle, ChunkPosition, EntityKind, EntityMetadata, Gamemode, ParticleKind,
+    Position, ProfileProperty, SoundCategory, SoundId, Text, ValidBlockPosition, Vec3d,
 };
 use common::{
     chat::{ChatKind, ChatMessage},
-    Window,
+    experience::Experience,
+    Velocity, Window,
 };
 use libcraft_items::InventorySlot;
-use packets::server::{Particle, SetSlot, SpawnLivingEntity, UpdateLight, WindowConfirmation};
+use packets::server::{
+    Particle, SetSlot, SpawnLivingEntity, UpdateLight, WindowConfirmation, WindowType,
+};
 use protocol::packets::server::{
     ChangeGameState, EntityPosition, EntityPositionAndRotation, EntityTeleport, GameStateChange,
     HeldItemChange, PlayerAbilities,
@@ -28,34 +33,83 @@ use protocol::{
     packets::{
         self,
         server::{
-            AddPlayer, Animation, BlockChange, ChatPosition, ChunkData, ChunkDataKind,
-            DestroyEntities, Disconnect, EntityAnimation, EntityHeadLook, JoinGame, KeepAlive,
-            PlayerInfo, PlayerPositionAndLook, PluginMessage, SendEntityMetadata, SpawnPlayer,
-            Title, UnloadChunk, UpdateViewPosition, WindowItems,
+            AddPlayer, Animation, BlockAction, BlockBreakAnimation, BlockChange, ChatPosition,
+            ChunkData, ChunkDataKind, CollectItem, DestroyEntities, Disconnect, EntityAnimation,
+            EntityHeadLook, EntityStatus, EntityVelocity, Explosion, ExplosionRecord, JoinGame,
+            KeepAlive, MultiBlockChange, NamedSoundEffect, OpenWindow, PlayerInfo,
+            PlayerPositionAndLook, PluginMessage, Respawn, SendEntityMetadata, SetCooldown,
+            SetExperience, SetPassengers, SoundEffect, SpawnExperienceOrb, SpawnPlayer, Title,
+            UnloadChunk, UpdateHealth, UpdateViewPosition, WindowItems,
         },
     },
-    ClientPlayPacket, Nbt, ProtocolVersion, ServerPlayPacket, Writeable,
+    ClientPlayPacket, Nbt, ProtocolVersion, ServerPlayPacket, VarLong, Writeable,
 };
 use quill_common::components::{OnGround, PreviousGamemode};
 
 use crate::{
+    command_graph::CommandGraph,
+    connection_worker::{BandwidthMeter, BandwidthStats, PacketTrace},
+    dimension::Dimensions,
     entities::{PreviousOnGround, PreviousPosition},
     initial_handler::NewPlayer,
     network_id_registry::NetworkId,
-    Options,
+    SharedOptions,
 };
 
-/// Max number of chunks to send to a client per tick.
-const MAX_CHUNKS_PER_TICK: usize = 10;
+/// Highest window ID used for windows opened by the server.
+const MAX_WINDOW_ID: u8 = 100;
+
+/// Chunks within this distance of a client's own chunk
+/// are sent regardless of `max_chunk_sends_per_tick`.
+const IMMEDIATE_CHUNK_RADIUS: i32 = 1;
+
+/// Maximum number of packets deferred until a client is ready.
+/// Clients which are still loading once this many have
+/// accumulated are disconnected rather than buffered forever.
+const MAX_DEFERRED_PACKETS: usize = 4096;
+
+/// Whether `packet` carries state which a client that is
+/// still loading needs once it is ready. Other packets, such
+/// as entity movement, are superseded by the spawn packets.
+fn is_persistent_state(packet: &ServerPlayPacket) -> bool {
+    matches!(
+        packet,
+        ServerPlayPacket::SpawnPlayer(_)
+            | ServerPlayPacket::SpawnLivingEntity(_)
+            | ServerPlayPacket::SpawnExperienceOrb(_)
+            | ServerPlayPacket::DestroyEntities(_)
+            | ServerPlayPacket::SetPassengers(_)
+            | ServerPlayPacket::PlayerInfo(_)
+    )
+}
 
-/// ID of a client. Can be reused.
+/// ID of a client.
+///
+/// IDs are generational: once a client is removed, its ID
+/// never refers to another client, even if the slot is reused.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
-pub struct ClientId(usize);
+pub struct ClientId {
+    index: usize,
+    generation: u32,
+}
+
+/// What to do with an outbound packet, as decided by the
+/// filter set with [`Server::set_outbound_packet_filter`](crate::Server::set_outbound_packet_filter).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PacketAction {
+    Send,
+    Drop,
+}
+
+/// Inspects and possibly modifies packets before they are sent to a client.
+pub(crate) type OutboundPacketFilter = Rc<dyn Fn(ClientId, &mut ServerPlayPacket) -> PacketAction>;
 
 /// Stores all `Client`s.
 #[derive(Default)]
 pub struct Clients {
     slab: Slab<Client>,
+    /// Current generation of each slab slot, incremented when a client is removed.
+    generations: Vec<u32>,
 }
 
 impl Clients {
@@ -64,24 +118,121 @@ impl Clients {
     }
 
     pub fn insert(&mut self, client: Client) -> ClientId {
-        ClientId(self.slab.insert(client))
+        let index = self.slab.insert(client);
+        if index >= self.generations.len() {
+            self.generations.resize(index + 1, 0);
+        }
+        ClientId {
+            index,
+            generation: self.generations[index],
+        }
     }
 
     pub fn remove(&mut self, id: ClientId) -> Option<Client> {
-        self.slab.try_remove(id.0)
+        if !self.contains(id) {
+            return None;
+        }
+        self.generations[id.index] = self.generations[id.index].wrapping_add(1);
+        self.slab.try_remove(id.index)
     }
 
     pub fn get(&self, id: ClientId) -> Option<&Client> {
-        self.slab.get(id.0)
+        if self.contains(id) {
+            self.slab.get(id.index)
+        } else {
+            None
+        }
     }
 
     pub fn get_mut(&mut self, id: ClientId) -> Option<&mut Client> {
-        self.slab.get_mut(id.0)
+        if self.contains(id) {
+            self.slab.get_mut(id.index)
+        } else {
+            None
+        }
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &'_ Client> + '_ {
         self.slab.iter().map(|(_i, client)| client)
     }
+
+    pub fn iter_mut_with_ids(&mut self) -> impl Iterator<Item = (ClientId, &'_ mut Client)> + '_ {
+        let generations = &self.generations;
+        self.slab.iter_mut().map(move |(index, client)| {
+            (
+                ClientId {
+                    index,
+                    generation: generations[index],
+                },
+                client,
+            )
+        })
+    }
+
+    /// Invokes `callback` for each of the given clients and
+    /// returns the results, skipping clients that no longer exist.
+    ///
+    /// Allocates only if at least one client exists.
+    pub fn map_mut<T>(
+        &mut self,
+        ids: &[ClientId],
+        mut callback: impl FnMut(&mut Client) -> T,
+    ) -> Vec<T> {
+        let mut results = Vec::new();
+        for &id in ids {
+            if let Some(client) = self.get_mut(id) {
+                results.push(callback(client));
+            }
+        }
+        results
+    }
+
+    fn contains(&self, id: ClientId) -> bool {
+        self.generations.get(id.index) == Some(&id.generation) && self.slab.contains(id.index)
+    }
+}
+
+/// Parameters of the Join Game packet sent by [`Client::send_join_game`].
+///
+/// Construct with [`JoinParameters::new`] and override fields
+/// using struct update syntax.
+#[derive(Debug, Clone)]
+pub struct JoinParameters {
+    pub gamemode: Gamemode,
+    pub previous_gamemode: PreviousGamemode,
+    /// Names of all worlds on the server.
+    pub world_names: Vec<String>,
+    /// Name of the world the player is spawning in.
+    pub world_name: String,
+    pub hashed_seed: u64,
+    pub is_hardcore: bool,
+    pub reduced_debug_info: bool,
+    pub enable_respawn_screen: bool,
+    pub is_debug: bool,
+    pub is_flat: bool,
+}
+
+impl JoinParameters {
+    /// Creates parameters for a player spawning in `world_name`,
+    /// which is also the only world listed in `world_names`.
+    ///
+    /// Defaults to a non-hardcore, non-debug world with
+    /// the respawn screen enabled.
+    pub fn new(gamemode: Gamemode, world_name: impl Into<String>) -> Self {
+        let world_name = world_name.into();
+        Self {
+            gamemode,
+            previous_gamemode: PreviousGamemode(None),
+            world_names: vec![world_name.clone()],
+            world_name,
+            hashed_seed: 0,
+            is_hardcore: false,
+            reduced_debug_info: false,
+            enable_respawn_screen: true,
+            is_debug: false,
+            is_flat: false,
+        }
+    }
 }
 
 /// A client connected to a server.
@@ -91,50 +242,154 @@ impl Clients {
 pub struct Client {
     packets_to_send: Sender<ServerPlayPacket>,
     received_packets: Receiver<ClientPlayPacket>,
-    options: Arc<Options>,
+    worker_errors: Receiver<String>,
+    packet_trace: PacketTrace,
+    bandwidth: BandwidthMeter,
+    /// The server's outbound packet filter, along with this client's ID.
+    outbound_filter: Option<(ClientId, OutboundPacketFilter)>,
+    /// The current server options, so that reloaded
+    /// options apply to connected clients.
+    options: SharedOptions,
     username: String,
     profile: Vec<ProfileProperty>,
     uuid: Uuid,
+    protocol_version: u32,
 
     teleport_id_counter: Cell<i32>,
+    /// ID of the last teleport sent to the client,
+    /// until the client confirms it.
+    pending_teleport_id: Cell<Option<i32>>,
 
     network_id: Option<NetworkId>,
     sent_entities: RefCell<AHashSet<NetworkId>>,
+    /// Loaded entities whose movement updates were skipped because
+    /// they are beyond the movement update range, with their latest
+    /// position and on-ground state.
+    culled_entities: RefCell<AHashMap<NetworkId, (Position, bool)>>,
 
     knows_position: Cell<bool>,
     known_chunks: RefCell<AHashSet<ChunkPosition>>,
+    /// The chunk the client was last told it is in.
+    own_chunk: Cell<ChunkPosition>,
 
-    chunk_send_queue: RefCell<VecDeque<ChunkData>>,
+    chunk_send_queue: RefCell<VecDeque<(ChunkPosition, ChunkHandle)>>,
+    /// Whether `chunk_send_queue` is ordered by distance to
+    /// `own_chunk`, i.e. no chunk was queued and the client
+    /// did not change chunks since it was last sorted.
+    chunk_send_queue_sorted: Cell<bool>,
 
     /// The previous own position sent by the client.
     /// Used to detect when we need to teleport the client.
     client_known_position: Cell<Option<Position>>,
 
     disconnected: Cell<bool>,
+    /// Why the client was disconnected, if known.
+    disconnect_reason: RefCell<Option<String>>,
+
+    /// Whether the client has finished loading the world,
+    /// i.e. it has sent a position update or confirmed a teleport.
+    ready: Cell<bool>,
+    /// Packets waiting to be sent until the client is ready.
+    deferred_packets: RefCell<Vec<ServerPlayPacket>>,
+
+    /// ID of the window whose last click was rejected, until
+    /// the client acknowledges the rejection.
+    rejected_window_id: Cell<Option<u8>>,
+    /// ID of the window opened by the server, if any.
+    open_window_id: Cell<Option<u8>>,
+    /// ID to use for the next opened window.
+    next_window_id: Cell<u8>,
+
+    /// Remaining cooldown ticks of the items on cooldown, by item ID.
+    item_cooldowns: RefCell<AHashMap<i32, i32>>,
+
+    /// Whether this client replaced a previous session
+    /// of the same player (duplicate login).
+    replaced_previous: bool,
+    /// Whether this session was replaced by a new login
+    /// of the same player.
+    replaced: Cell<bool>,
+
+    /// Operator permission level, from 0 (regular player) to 4.
+    op_level: u8,
+
+    /// When the client was created, after finishing login.
+    connected_since: Instant,
+    /// The last time the player moved, chatted or interacted.
+    last_activity: Cell<Instant>,
+
+    /// Whether this client is exempt from keepalive timeouts.
+    /// Used for fake clients (e.g. load-testing bots)
+    /// that never respond to keepalives.
+    keepalive_exempt: bool,
+    /// The last time this client responded to a keepalive.
+    last_keepalive_response: Cell<Instant>,
+    /// ID and send time of the keepalive awaiting a response.
+    pending_keepalive: Cell<Option<(i64, Instant)>>,
+    /// ID to use for the next keepalive.
+    next_keepalive_id: Cell<i64>,
+    /// Round-trip time of the last answered keepalive.
+    latency: Cell<Option<Duration>>,
+    /// The ping last sent for this client in tab lists, in milliseconds.
+    tablist_ping: Cell<i32>,
+
+    /// Number of non-essential packets skipped because
+    /// the client was congested.
+    skipped_packets: Cell<u64>,
 }
 
 impl Client {
-    pub fn new(player: NewPlayer, options: Arc<Options>) -> Self {
+    pub fn new(player: NewPlayer, options: SharedOptions) -> Self {
+        let op_level = options.load().ops.get(&player.uuid).copied().unwrap_or(0);
         Self {
             packets_to_send: player.packets_to_send,
             received_packets: player.received_packets,
+            worker_errors: player.worker_errors,
+            packet_trace: player.packet_trace,
+            bandwidth: player.bandwidth,
+            outbound_filter: None,
             options,
             username: player.username,
             teleport_id_counter: Cell::new(0),
+            pending_teleport_id: Cell::new(None),
             network_id: None,
             profile: player.profile,
             uuid: player.uuid,
+            protocol_version: player.protocol_version,
+            connected_since: Instant::now(),
+            last_activity: Cell::new(Instant::now()),
             sent_entities: RefCell::new(AHashSet::new()),
+            culled_entities: RefCell::new(AHashMap::new()),
             knows_position: Cell::new(false),
             known_chunks: RefCell::new(AHashSet::new()),
+            own_chunk: Cell::new(ChunkPosition::new(0, 0)),
             chunk_send_queue: RefCell::new(VecDeque::new()),
+            chunk_send_queue_sorted: Cell::new(true),
             client_known_position: Cell::new(None),
             disconnected: Cell::new(false),
+            disconnect_reason: RefCell::new(None),
+            ready: Cell::new(false),
+            deferred_packets: RefCell::new(Vec::new()),
+            rejected_window_id: Cell::new(None),
+            open_window_id: Cell::new(None),
+            next_window_id: Cell::new(1),
+            item_cooldowns: RefCell::new(AHashMap::new()),
+            replaced_previous: false,
+            replaced: Cell::new(false),
+            op_level,
+            keepalive_exempt: player.keepalive_exempt,
+            last_keepalive_response: Cell::new(Instant::now()),
+            pending_keepalive: Cell::new(None),
+            next_keepalive_id: Cell::new(0),
+            latency: Cell::new(None),
+            tablist_ping: Cell::new(0),
+            skipped_packets: Cell::new(0),
         }
     }
 
     pub fn set_client_known_position(&self, pos: Position) {
         self.client_known_position.set(Some(pos));
+        self.teleport_culled_entities_in_range();
     }
 
     pub fn client_known_position(&self) -> Option<Position> {
@@ -153,6 +408,35 @@ impl Client {
         self.uuid
     }
 
+    /// Enables or disables logging of the IDs and lengths
+    /// of the packets sent to and received from this client.
+    pub fn set_packet_trace(&self, enabled: bool) {
+        self.packet_trace.set_enabled(enabled);
+    }
+
+    pub fn is_packet_traced(&self) -> bool {
+        self.packet_trace.is_enabled()
+    }
+
+    /// Returns the bytes per second currently sent and received
+    /// over the client's connection.
+    pub fn bandwidth_stats(&self) -> BandwidthStats {
+        self.bandwidth.stats()
+    }
+
+    pub(crate) fn set_outbound_filter(
+        &mut self,
+        id: ClientId,
+        filter: Option<OutboundPacketFilter>,
+    ) {
+        self.outbound_filter = filter.map(|filter| (id, filter));
+    }
+
+    /// Returns the protocol version the client connected with.
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version
+    }
+
     pub fn username(&self) -> &str {
         &self.username
     }
@@ -161,29 +445,270 @@ impl Client {
         self.received_packets.try_iter()
     }
 
+    /// Returns whether the client has disconnected or been disconnected.
+    /// The server removes disconnected clients on the next tick.
+    ///
+    /// A stopped connection worker is only noticed
+    /// once [`poll_connection`](Self::poll_connection) is called.
     pub fn is_disconnected(&self) -> bool {
-        self.received_packets.is_disconnected() || self.disconnected.get()
+        self.disconnected.get()
+    }
+
+    /// Marks the client as disconnected if its connection worker
+    /// stopped, with the error the worker reported, if any.
+    ///
+    /// The worker reports its error before it releases the
+    /// error channel, so the error is never missed.
+    pub fn poll_connection(&self) {
+        match self.worker_errors.try_recv() {
+            Ok(message) => self.mark_disconnected(message),
+            Err(TryRecvError::Disconnected) => {
+                self.mark_disconnected("connection closed".to_owned())
+            }
+            Err(TryRecvError::Empty) => {}
+        }
+    }
+
+    /// Returns why the client disconnected, if it has.
+    pub fn disconnect_reason(&self) -> Option<String> {
+        if !self.is_disconnected() {
+            return None;
+        }
+        let reason = self.disconnect_reason.borrow().clone();
+        Some(reason.unwrap_or_else(|| "connection closed".to_owned()))
+    }
+
+    /// Marks the client as disconnected, keeping the
+    /// first reason given.
+    fn mark_disconnected(&self, reason: String) {
+        self.disconnected.set(true);
+        self.disconnect_reason.borrow_mut().get_or_insert(reason);
+    }
+
+    /// Returns whether more packets are waiting to be sent
+    /// to this client than `congestion_threshold`.
+    pub fn is_congested(&self) -> bool {
+        let threshold = self.options.load().congestion_threshold;
+        threshold != 0 && self.packets_to_send.len() > threshold
+    }
+
+    /// Sends non-essential packets using `callback`,
+    /// unless the client is congested.
+    ///
+    /// Returns whether the packets were sent.
+    pub fn send_nonessential_with(&self, callback: impl FnOnce(&Client)) -> bool {
+        if self.is_congested() {
+            self.skipped_packets.set(self.skipped_packets.get() + 1);
+            return false;
+        }
+        callback(self);
+        true
+    }
+
+    /// Returns the number of times non-essential packets
+    /// were skipped because the client was congested.
+    pub fn skipped_packets(&self) -> u64 {
+        self.skipped_packets.get()
+    }
+
+    /// Returns whether the client has been sent the given chunk
+    /// and not told to unload it since.
+    pub fn knows_chunk(&self, pos: ChunkPosition) -> bool {
+        self.known_chunks.borrow().contains(&pos)
     }
 
     pub fn known_chunks(&self) -> usize {
         self.known_chunks.borrow().len()
     }
 
+    /// Returns the number of chunks sent to the client and not unloaded
+    /// since. Unlike [`known_chunks`](Self::known_chunks), chunks still
+    /// waiting in the send queue are not counted.
+    ///
+    /// A client with no loaded chunks long after joining
+    /// has a stuck view.
+    pub fn loaded_chunk_count(&self) -> usize {
+        self.known_chunks
+            .borrow()
+            .len()
+            .saturating_sub(self.chunk_send_queue.borrow().len())
+    }
+
+    /// Returns whether the client has finished loading after joining.
+    ///
+    /// Until then, entity spawns and tab list updates are queued
+    /// rather than sent, since the client may drop them.
+    pub fn is_ready(&self) -> bool {
+        self.ready.get()
+    }
+
+    /// Marks the client as ready, sending any packets
+    /// deferred while it was loading.
+    pub fn mark_ready(&self) {
+        if self.ready.replace(true) {
+            return;
+        }
+        log::trace!("{} is ready", self.username);
+        for packet in self.deferred_packets.take() {
+            self.send_packet(packet);
+        }
+    }
+
+    /// Returns whether this client replaced an existing session
+    /// of the same player, i.e. the player logged in from another location.
+    pub fn replaced_previous(&self) -> bool {
+        self.replaced_previous
+    }
+
+    pub(crate) fn set_replaced_previous(&mut self, replaced_previous: bool) {
+        self.replaced_previous = replaced_previous;
+    }
+
+    /// Returns whether this session was replaced by a newer
+    /// login of the same player.
+    pub fn was_replaced(&self) -> bool {
+        self.replaced.get()
+    }
+
+    pub(crate) fn mark_replaced(&self) {
+        self.replaced.set(true);
+    }
+
+    /// Returns the client's operator permission level,
+    /// which is 0 for regular players.
+    pub fn op_level(&self) -> u8 {
+        self.op_level
+    }
+
+    /// Returns whether the client has at least the given operator level.
+    pub fn is_op(&self, level: u8) -> bool {
+        self.op_level >= level
+    }
+
+    /// Returns when the client finished logging in.
+    pub fn connected_since(&self) -> Instant {
+        self.connected_since
+    }
+
+    /// Returns how long the client has been connected.
+    pub fn session_duration(&self) -> Duration {
+        self.connected_since.elapsed()
+    }
+
+    /// Records that the player is not idle. Called when the server
+    /// receives a packet showing activity: moving or turning,
+    /// chatting, or interacting with blocks, entities or windows.
+    pub fn mark_active(&self) {
+        self.last_activity.set(Instant::now());
+    }
+
+    /// Returns the last time the player was active, or when the
+    /// client connected if it hasn't been active since.
+    /// See [`mark_active`](Self::mark_active).
+    pub fn last_activity(&self) -> Instant {
+        self.last_activity.get()
+    }
+
+    /// Returns whether this client is exempt from keepalive timeouts.
+    ///
+    /// Exempt clients are otherwise ordinary clients; in particular,
+    /// they are included in the server's player count.
+    pub fn is_keepalive_exempt(&self) -> bool {
+        self.keepalive_exempt
+    }
+
+    /// Returns the last time this client responded to a keepalive.
+    pub fn last_keepalive_response(&self) -> Instant {
+        self.last_keepalive_response.get()
+    }
+
+    /// Returns the round-trip time of the last
+    /// keepalive the client responded to, if any.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency.get()
+    }
+
+    /// Returns the ping shown for this client in tab lists,
+    /// in milliseconds. This is 0, shown as full latency bars,
+    /// until the client responds to its first keepalive.
+    pub fn ping(&self) -> i32 {
+        self.latency.get().map_or(0, |latency| {
+            latency.as_millis().min(i32::MAX as u128) as i32
+        })
+    }
+
+    /// Returns the current [`ping`](Self::ping) if it changed since
+    /// it was last sent in tab lists, recording it as sent.
+    pub(crate) fn take_tablist_ping_change(&self) -> Option<i32> {
+        let ping = self.ping();
+        if ping == self.tablist_ping.replace(ping) {
+            None
+        } else {
+            Some(ping)
+        }
+    }
+
+    /// Handles a keepalive response echoing `id`.
+    ///
+    /// Like vanilla, a client responding with an ID other than
+    /// the one of the keepalive last sent, or without a keepalive
+    /// having been sent, is disconnected with "Timed out".
+    /// Returns whether the response was valid.
+    pub fn handle_keepalive_response(&self, id: i64) -> bool {
+        match self.pending_keepalive.get() {
+            Some((pending_id, sent_time)) if pending_id == id => {
+                self.pending_keepalive.set(None);
+                self.last_keepalive_response.set(Instant::now());
+                self.latency.set(Some(sent_time.elapsed()));
+                true
+            }
+            _ => {
+                log::debug!("{} sent an unexpected keepalive ID {}", self.username, id);
+                self.disconnect("Timed out");
+                false
+            }
+        }
+    }
+
     pub fn knows_own_position(&self) -> bool {
         self.knows_position.get()
     }
 
+    /// Advances item cooldowns and sends queued chunks,
+    /// closest to the client first.
+    ///
+    /// At most `max_chunk_sends_per_tick` chunks are sent per tick,
+    /// except that chunks right around the client are never held back.
     pub fn tick(&self) {
-        let num_to_send = MAX_CHUNKS_PER_TICK.min(self.chunk_send_queue.borrow().len());
-        for packet in self.chunk_send_queue.borrow_mut().drain(0..num_to_send) {
-            log::trace!(
-                "Sending chunk at {:?} to {}",
-                packet.chunk.read().position(),
-                self.username
-            );
-            let chunk = Arc::clone(&packet.chunk);
-            self.send_packet(UpdateLight { chunk });
-            self.send_packet(packet);
+        self.item_cooldowns.borrow_mut().retain(|_, ticks| {
+            *ticks -= 1;
+            *ticks > 0
+        });
+
+        let center = self.own_chunk.get();
+        let max_sends = self.options.load().max_chunk_sends_per_tick;
+        let mut queue = self.chunk_send_queue.borrow_mut();
+        if !self.chunk_send_queue_sorted.replace(true) {
+            queue
+                .make_contiguous()
+                .sort_by_key(|&(position, _)| chunk_distance(position, center));
+        }
+
+        let mut num_sent = 0;
+        while let Some(&(position, _)) = queue.front() {
+            let is_immediate = chunk_distance(position, center) <= IMMEDIATE_CHUNK_RADIUS;
+            if !is_immediate && max_sends != 0 && num_sent >= max_sends {
+                break;
+            }
+
+            let (_, chunk) = queue.pop_front().unwrap();
+            log::trace!("Sending chunk at {:?} to {}", position, self.username);
+            self.send_packet(UpdateLight::new(Arc::clone(&chunk)));
+            self.send_packet(ChunkData {
+                chunk,
+                kind: ChunkDataKind::LoadChunk,
+            });
+            num_sent += 1;
         }
     }
 
@@ -197,37 +722,68 @@ impl Client {
         self.network_id = Some(network_id);
     }
 
-    pub fn send_join_game(&self, gamemode: Gamemode, previous_gamemode: PreviousGamemode) {
+    /// Forgets everything sent to the client since it joined,
+    /// for when it is about to be sent Join Game again.
+    pub fn reset_play_state(&self) {
+        self.sent_entities.borrow_mut().clear();
+        self.culled_entities.borrow_mut().clear();
+        self.known_chunks.borrow_mut().clear();
+        self.chunk_send_queue.borrow_mut().clear();
+        self.knows_position.set(false);
+        self.client_known_position.set(None);
+        self.pending_teleport_id.set(None);
+        self.open_window_id.set(None);
+        self.rejected_window_id.set(None);
+        // Spawn packets are deferred until the client
+        // has loaded the terrain again.
+        self.ready.set(false);
+        self.deferred_packets.borrow_mut().clear();
+    }
+
+    pub fn send_join_game(&self, params: JoinParameters, dimensions: &Dimensions) {
         log::trace!("Sending Join Game to {}", self.username);
-        // Use the dimension codec sent by the default vanilla server. (Data acquired via tools/proxy)
-        let dimension_codec = nbt::Blob::from_reader(&mut Cursor::new(include_bytes!(
-            "../../../assets/dimension_codec.nbt"
-        )))
-        .expect("dimension codec asset is malformed");
-        let dimension = nbt::Blob::from_reader(&mut Cursor::new(include_bytes!(
-            "../../../assets/dimension.nbt"
-        )))
-        .expect("dimension asset is malformed");
+        let dimension_codec = dimensions.codec_nbt();
+        let dimension = dimensions.dimension_nbt();
 
         self.send_packet(JoinGame {
             entity_id: self.network_id.expect("No network id! Use client.set_network_id(NetworkId) before calling this method.").0,
-            is_hardcore: false,
-            gamemode,
-            previous_gamemode,
-            world_names: vec!["world".to_owned()],
+            is_hardcore: params.is_hardcore,
+            gamemode: params.gamemode,
+            previous_gamemode: params.previous_gamemode,
+            world_names: params.world_names,
             dimension_codec: Nbt(dimension_codec),
             dimension: Nbt(dimension),
-            world_name: "world".to_owned(),
-            hashed_seed: 0,
+            world_name: params.world_name,
+            hashed_seed: params.hashed_seed,
             max_players: 0,
-            view_distance: self.options.view_distance as i32,
-            reduced_debug_info: false,
-            enable_respawn_screen: true,
-            is_debug: false,
-            is_flat: false,
+            view_distance: self.options.load().view_distance as i32,
+            reduced_debug_info: params.reduced_debug_info,
+            enable_respawn_screen: params.enable_respawn_screen,
+            is_debug: params.is_debug,
+            is_flat: params.is_flat,
+        });
+    }
+
+    /// Sends Respawn, which makes the client discard its world
+    /// and entities like Join Game, without repeating the codec.
+    pub fn send_respawn(&self, params: JoinParameters, dimensions: &Dimensions) {
+        log::trace!("Sending Respawn to {}", self.username);
+        self.send_packet(Respawn {
+            dimension: Nbt(dimensions.dimension_nbt()),
+            world_name: params.world_name,
+            hashed_seed: params.hashed_seed,
+            gamemode: params.gamemode,
+            previous_gamemode: params.previous_gamemode,
+            is_debug: params.is_debug,
+            is_flat: params.is_flat,
+            copy_metadata: false,
         });
     }
 
+    pub fn send_command_graph(&self, graph: &CommandGraph) {
+        self.send_packet(graph.to_packet());
+    }
+
     pub fn send_brand(&self) {
         let mut data = Vec::new();
         "Feather"
@@ -261,14 +817,33 @@ impl Client {
             flags: 0,
             teleport_id: self.teleport_id_counter.get(),
         });
+        self.pending_teleport_id
+            .set(Some(self.teleport_id_counter.get()));
         self.teleport_id_counter
             .set(self.teleport_id_counter.get() + 1);
         self.knows_position.set(true);
         self.client_known_position.set(Some(new_position));
+        self.teleport_culled_entities_in_range();
+    }
+
+    /// Handles a teleport confirmation from the client.
+    pub fn confirm_teleport(&self, teleport_id: i32) {
+        if self.pending_teleport_id.get() == Some(teleport_id) {
+            self.pending_teleport_id.set(None);
+        }
+    }
+
+    /// Returns whether the client has yet to confirm
+    /// the last teleport sent to it.
+    pub fn has_pending_teleport(&self) -> bool {
+        self.pending_teleport_id.get().is_some()
     }
 
     pub fn update_own_chunk(&self, pos: ChunkPosition) {
         log::trace!("Updating chunk position of {} to {:?}", self.username, pos);
+        if self.own_chunk.replace(pos) != pos {
+            self.chunk_send_queue_sorted.set(false);
+        }
         self.send_packet(UpdateViewPosition {
             chunk_x: pos.x,
             chunk_z: pos.z,
@@ -276,13 +851,12 @@ impl Client {
     }
 
     pub fn send_chunk(&self, chunk: &ChunkHandle) {
-        self.chunk_send_queue.borrow_mut().push_back(ChunkData {
-            chunk: Arc::clone(chunk),
-            kind: ChunkDataKind::LoadChunk,
-        });
-        self.known_chunks
+        let position = chunk.read().position();
+        self.chunk_send_queue
             .borrow_mut()
-            .insert(chunk.read().position());
+            .push_back((position, Arc::clone(chunk)));
+        self.chunk_send_queue_sorted.set(false);
+        self.known_chunks.borrow_mut().insert(position);
     }
 
     pub fn overwrite_chunk_sections(&self, chunk: &ChunkHandle, sections: Vec<usize>) {
@@ -292,6 +866,28 @@ impl Client {
         });
     }
 
+    /// Sends the light of the given sections of a chunk, e.g. after blocks
+    /// in them changed. `sections` are indices into `Chunk::sections`.
+    ///
+    /// Does nothing if the chunk has not been sent yet,
+    /// since its light is sent along with it.
+    pub fn send_light_update(&self, chunk: &ChunkHandle, sections: Vec<usize>) {
+        let position = chunk.read().position();
+        let is_queued = || {
+            self.chunk_send_queue
+                .borrow()
+                .iter()
+                .any(|&(queued, _)| queued == position)
+        };
+        if !self.knows_chunk(position) || is_queued() {
+            return;
+        }
+        self.send_packet(UpdateLight {
+            chunk: Arc::clone(chunk),
+            sections: Some(sections),
+        });
+    }
+
     pub fn send_block_change(&self, position: ValidBlockPosition, new_block: BlockId) {
         self.send_packet(BlockChange {
             position,
@@ -299,8 +895,112 @@ impl Client {
         });
     }
 
+    pub fn send_block_action(
+        &self,
+        position: ValidBlockPosition,
+        action_id: u8,
+        action_param: u8,
+        block_type: i32,
+    ) {
+        self.send_packet(BlockAction {
+            position,
+            action_id,
+            action_param,
+            block_type,
+        });
+    }
+
+    /// Sets the passengers riding `vehicle`. An empty
+    /// list dismounts all passengers.
+    pub fn send_set_passengers(&self, vehicle: NetworkId, passengers: &[NetworkId]) {
+        self.send_deferrable_packet(SetPassengers {
+            entity_id: vehicle.0,
+            passengers: passengers
+                .iter()
+                .map(|passenger| passenger.0.into())
+                .collect(),
+        });
+    }
+
+    /// Updates the player's experience bar and level.
+    pub fn send_experience(&self, experience: Experience) {
+        self.send_packet(SetExperience {
+            experience_bar: experience.progress(),
+            level: (experience.level() as i32).into(),
+            total_experience: (experience.total as i32).into(),
+        });
+    }
+
+    /// Updates the player's health and food bars.
+    pub fn send_update_health(&self, health: f32, food: i32, food_saturation: f32) {
+        self.send_packet(UpdateHealth {
+            health,
+            food: food.into(),
+            food_saturation,
+        });
+    }
+
+    /// Plays the animation of `collector` picking up `count`
+    /// items from the entity `collected`.
+    pub fn send_collect_item(&self, collected: NetworkId, collector: NetworkId, count: u32) {
+        self.send_packet(CollectItem {
+            collected_entity_id: collected.0,
+            collector_entity_id: collector.0,
+            item_count: count as i32,
+        });
+    }
+
+    /// Displays the block break animation of the block at `position`
+    /// being dug by the given entity. `None` removes the animation.
+    pub fn send_block_break_animation(
+        &self,
+        network_id: NetworkId,
+        position: ValidBlockPosition,
+        stage: Option<u8>,
+    ) {
+        self.send_packet(BlockBreakAnimation {
+            entity_id: network_id.0,
+            position,
+            // Any stage outside 0..=9 removes the animation
+            destroy_stage: stage.unwrap_or(u8::MAX),
+        });
+    }
+
+    /// Sends a `MultiBlockChange` updating several blocks
+    /// within the given chunk section.
+    ///
+    /// All `changes` must lie within the section.
+    pub fn send_multi_block_change(
+        &self,
+        chunk: ChunkPosition,
+        section: usize,
+        changes: &[(ValidBlockPosition, BlockId)],
+    ) {
+        let chunk_section_coordinate = ((chunk.x as i64 & 0x3F_FFFF) << 42)
+            | ((chunk.z as i64 & 0x3F_FFFF) << 20)
+            | (section as i64 & 0xF_FFFF);
+        let records = changes
+            .iter()
+            .map(|(pos, block)| {
+                let local = (pos.x().rem_euclid(16) << 8)
+                    | (pos.z().rem_euclid(16) << 4)
+                    | pos.y().rem_euclid(16);
+                VarLong(((block.vanilla_id() as i64) << 12) | local as i64)
+            })
+            .collect::<Vec<_>>();
+        self.send_packet(MultiBlockChange {
+            chunk_section_coordinate: chunk_section_coordinate as u64,
+            dont_trust_edges: false,
+            records: records.into(),
+        });
+    }
+
     pub fn unload_chunk(&self, pos: ChunkPosition) {
         log::trace!("Unloading chunk at {:?} on {}", pos, self.username);
+        // Don't send the chunk if it is still queued
+        self.chunk_send_queue
+            .borrow_mut()
+            .retain(|&(position, _)| position != pos);
         self.send_packet(UnloadChunk {
             chunk_x: pos.x,
             chunk_z: pos.z,
@@ -314,6 +1014,8 @@ impl Client {
         name: String,
         profile: &[ProfileProperty],
         gamemode: Gamemode,
+        ping: i32,
+        display_name: Option<&Text>,
     ) {
         log::trace!("Sending AddPlayer({}) to {}", name, self.username);
         let action = AddPlayer {
@@ -321,25 +1023,40 @@ impl Client {
             name,
             properties: profile.to_vec(),
             gamemode,
-            ping: 0,
-            display_name: None,
+            ping,
+            display_name: display_name.map(Text::to_string),
         };
-        self.send_packet(PlayerInfo::AddPlayers(vec![action]));
+        self.send_deferrable_packet(PlayerInfo::AddPlayers(vec![action]));
     }
 
     pub fn remove_tablist_player(&self, uuid: Uuid) {
         log::trace!("Sending RemovePlayer({}) to {}", uuid, self.username);
-        self.send_packet(PlayerInfo::RemovePlayers(vec![uuid]));
+        self.send_deferrable_packet(PlayerInfo::RemovePlayers(vec![uuid]));
+    }
+
+    /// Changes the name shown for a player in the tab list.
+    /// `None` shows the player's username.
+    pub fn change_player_tablist_display_name(&self, uuid: Uuid, display_name: Option<&Text>) {
+        self.send_deferrable_packet(PlayerInfo::UpdateDisplayNames(vec![(
+            uuid,
+            display_name.map(Text::to_string),
+        )]));
     }
 
     pub fn change_player_tablist_gamemode(&self, uuid: Uuid, gamemode: Gamemode) {
-        self.send_packet(PlayerInfo::UpdateGamemodes(vec![(uuid, gamemode)]));
+        self.send_deferrable_packet(PlayerInfo::UpdateGamemodes(vec![(uuid, gamemode)]));
+    }
+
+    /// Changes the ping shown for players in the tab list.
+    pub fn change_player_tablist_pings(&self, pings: &[(Uuid, i32)]) {
+        self.send_deferrable_packet(PlayerInfo::UpdatePings(pings.to_vec()));
     }
 
     pub fn unload_entity(&self, id: NetworkId) {
         log::trace!("Unloading {:?} on {}", id, self.username);
         self.sent_entities.borrow_mut().remove(&id);
-        self.send_packet(DestroyEntities {
+        self.culled_entities.borrow_mut().remove(&id);
+        self.send_deferrable_packet(DestroyEntities {
             entity_ids: vec![id.0.into()],
         });
     }
@@ -347,7 +1064,7 @@ impl Client {
     pub fn send_player(&self, network_id: NetworkId, uuid: Uuid, pos: Position) {
         log::trace!("Sending {:?} to {}", uuid, self.username);
         assert!(!self.sent_entities.borrow().contains(&network_id));
-        self.send_packet(SpawnPlayer {
+        self.send_deferrable_packet(SpawnPlayer {
             entity_id: network_id.0,
             player_uuid: uuid,
             x: pos.x,
@@ -372,7 +1089,11 @@ impl Client {
             self.username,
             kind.id()
         );
-        self.send_packet(SpawnLivingEntity {
+        if self.is_entity_loaded(network_id) {
+            log::warn!("Not spawning {:?} on {} twice", network_id, self.username);
+            return;
+        }
+        self.send_deferrable_packet(SpawnLivingEntity {
             entity_id: network_id.0,
             entity_uuid: uuid,
             kind: kind.id() as i32,
@@ -386,8 +1107,32 @@ impl Client {
             velocity_y: 0,
             velocity_z: 0,
         });
+        self.register_entity(network_id);
+    }
+
+    pub fn send_experience_orb(&self, network_id: NetworkId, pos: Position, count: u16) {
+        log::trace!("Spawning an experience orb on {}", self.username);
+        assert!(!self.sent_entities.borrow().contains(&network_id));
+        self.send_deferrable_packet(SpawnExperienceOrb {
+            entity_id: network_id.0,
+            x: pos.x,
+            y: pos.y,
+            z: pos.z,
+            count,
+        });
+        self.register_entity(network_id);
     }
 
+    /// Sends a movement update for an entity.
+    ///
+    /// Relative move packets are used when possible. An absolute
+    /// `EntityTeleport` is sent instead if the movement is too large
+    /// to be expressed as a relative move, if the entity's on-ground
+    /// state changed, or if `force_teleport` is set.
+    ///
+    /// If a movement update range is configured, updates for entities
+    /// beyond it are skipped, and an entity coming back within range gets
+    /// an `EntityTeleport` so it doesn't appear stuck where it was.
     pub fn update_entity_position(
         &self,
         network_id: NetworkId,
@@ -395,6 +1140,7 @@ impl Client {
         prev_position: PreviousPosition,
         on_ground: OnGround,
         prev_on_ground: PreviousOnGround,
+        force_teleport: bool,
     ) {
         if self.network_id == Some(network_id) {
             // This entity is the client. Only update
@@ -405,65 +1151,151 @@ impl Client {
             }
             return;
         }
-
-        let no_change_yaw = (position.yaw - prev_position.0.yaw).abs() < 0.001;
-        let no_change_pitch = (position.pitch - prev_position.0.pitch).abs() < 0.001;
-
-        // If the entity jumps or falls we should send a teleport packet instead to keep relative movement in sync.
-        if on_ground != prev_on_ground.0 {
-            self.send_packet(EntityTeleport {
-                entity_id: network_id.0,
-                x: position.x,
-                y: position.y,
-                z: position.z,
-                yaw: position.yaw,
-                pitch: position.pitch,
-                on_ground: *on_ground,
-            });
-
+        if !self.is_entity_loaded(network_id) {
             return;
         }
+        let force_teleport = match self.movement_update_culled(network_id, position, on_ground.0) {
+            Some(was_culled) => force_teleport || was_culled,
+            None => return,
+        };
 
-        if no_change_yaw && no_change_pitch {
-            self.send_packet(EntityPosition {
-                entity_id: network_id.0,
-                delta_x: ((position.x * 32.0 - prev_position.0.x * 32.0) * 128.0) as i16,
-                delta_y: ((position.y * 32.0 - prev_position.0.y * 32.0) * 128.0) as i16,
-                delta_z: ((position.z * 32.0 - prev_position.0.z * 32.0) * 128.0) as i16,
-                on_ground: on_ground.0,
-            });
-        } else {
-            self.send_packet(EntityPositionAndRotation {
-                entity_id: network_id.0,
-                delta_x: ((position.x * 32.0 - prev_position.0.x * 32.0) * 128.0) as i16,
-                delta_y: ((position.y * 32.0 - prev_position.0.y * 32.0) * 128.0) as i16,
-                delta_z: ((position.z * 32.0 - prev_position.0.z * 32.0) * 128.0) as i16,
-                yaw: position.yaw,
-                pitch: position.pitch,
-                on_ground: on_ground.0,
-            });
+        for packet in entity_movement_packets(
+            network_id,
+            position,
+            prev_position.0,
+            on_ground.0,
+            prev_on_ground.0 .0,
+            force_teleport,
+        ) {
+            self.send_deferrable_packet(packet);
+        }
+    }
 
-            // Needed for head orientation
-            self.send_packet(EntityHeadLook {
-                entity_id: network_id.0,
-                head_yaw: position.yaw,
-            });
+    /// Returns `None` if movement updates for the entity at `position`
+    /// should be skipped, or otherwise whether they were skipped before.
+    fn movement_update_culled(
+        &self,
+        network_id: NetworkId,
+        position: Position,
+        on_ground: bool,
+    ) -> Option<bool> {
+        if self.is_within_movement_range(position) {
+            Some(
+                self.culled_entities
+                    .borrow_mut()
+                    .remove(&network_id)
+                    .is_some(),
+            )
+        } else {
+            self.culled_entities
+                .borrow_mut()
+                .insert(network_id, (position, on_ground));
+            None
         }
     }
 
-    pub fn send_keepalive(&self) {
-        log::trace!("Sending keepalive to {}", self.username);
-        self.send_packet(KeepAlive { id: 0 });
+    fn is_within_movement_range(&self, position: Position) -> bool {
+        match (
+            self.options.load().entity_tracking.movement,
+            self.client_known_position.get(),
+        ) {
+            (Some(range), Some(own_position)) => own_position.distance_to(position) <= range,
+            _ => true,
+        }
     }
 
-    pub fn send_entity_animation(&self, network_id: NetworkId, animation: Animation) {
-        if self.network_id == Some(network_id) {
+    /// Sends an `EntityTeleport` for each culled entity which
+    /// is back within range after the client itself moved.
+    fn teleport_culled_entities_in_range(&self) {
+        if self.culled_entities.borrow().is_empty() {
             return;
         }
-        self.send_packet(EntityAnimation {
-            entity_id: network_id.0,
-            animation,
-        })
+        let mut in_range = Vec::new();
+        self.culled_entities
+            .borrow_mut()
+            .retain(|&network_id, &mut (position, on_ground)| {
+                if self.is_within_movement_range(position) {
+                    in_range.push((network_id, position, on_ground));
+                    false
+                } else {
+                    true
+                }
+            });
+        for (network_id, position, on_ground) in in_range {
+            for packet in
+                entity_movement_packets(network_id, position, position, on_ground, on_ground, true)
+            {
+                self.send_deferrable_packet(packet);
+            }
+        }
+    }
+
+    /// Sends a keepalive, unless the client has yet
+    /// to respond to the previous one.
+    pub fn send_keepalive(&self) {
+        if self.pending_keepalive.get().is_some() {
+            return;
+        }
+        let id = self.next_keepalive_id.get();
+        self.next_keepalive_id.set(id.wrapping_add(1));
+        self.pending_keepalive.set(Some((id, Instant::now())));
+
+        log::trace!("Sending keepalive to {}", self.username);
+        self.send_packet(KeepAlive { id });
+    }
+
+    /// Sends the velocity of an entity. `velocity`
+    /// is in blocks per tick.
+    pub fn send_entity_velocity(&self, network_id: NetworkId, velocity: Velocity) {
+        self.send_deferrable_packet(EntityVelocity {
+            entity_id: network_id.0,
+            velocity_x: encode_velocity(velocity.0.x),
+            velocity_y: encode_velocity(velocity.0.y),
+            velocity_z: encode_velocity(velocity.0.z),
+        });
+    }
+
+    /// Sends an explosion at `center`, which plays its sound
+    /// and particles, removes `blocks` on the client and pushes
+    /// the player by `player_motion`, in blocks per tick.
+    ///
+    /// Blocks further than 127 blocks from the center
+    /// cannot be encoded and are skipped.
+    pub fn send_explosion(
+        &self,
+        center: Position,
+        radius: f32,
+        blocks: &[ValidBlockPosition],
+        player_motion: Vec3d,
+    ) {
+        self.send_packet(Explosion {
+            x: center.x as f32,
+            y: center.y as f32,
+            z: center.z as f32,
+            strength: radius,
+            records: explosion_records(center, blocks),
+            player_motion_x: player_motion.x as f32,
+            player_motion_y: player_motion.y as f32,
+            player_motion_z: player_motion.z as f32,
+        });
+    }
+
+    /// Sends an entity status. See [`entity_status`](crate::entity_status).
+    pub fn send_entity_status(&self, network_id: NetworkId, status: u8) {
+        self.send_deferrable_packet(EntityStatus {
+            entity_id: network_id.0,
+            status: status as i8,
+        });
+    }
+
+    pub fn send_entity_animation(&self, network_id: NetworkId, animation: Animation) {
+        if self.network_id == Some(network_id) {
+            return;
+        }
+        self.send_deferrable_packet(EntityAnimation {
+            entity_id: network_id.0,
+            animation,
+        })
     }
 
     pub fn send_chat_message(&self, message: ChatMessage) {
@@ -521,7 +1353,14 @@ impl Client {
         self.send_packet(Title::Hide);
     }
 
+    /// Accepts or rejects a click in a window.
+    ///
+    /// After a rejection, clicks in the window should be ignored until the
+    /// client acknowledges it; see [`is_window_synced`](Self::is_window_synced).
     pub fn confirm_window_action(&self, window_id: u8, action_number: i16, is_accepted: bool) {
+        if !is_accepted {
+            self.rejected_window_id.set(Some(window_id));
+        }
         self.send_packet(WindowConfirmation {
             window_id,
             action_number,
@@ -529,6 +1368,72 @@ impl Client {
         });
     }
 
+    /// Returns whether the client has acknowledged the
+    /// last rejected click in the given window, if any.
+    pub fn is_window_synced(&self, window_id: u8) -> bool {
+        self.rejected_window_id.get() != Some(window_id)
+    }
+
+    /// Handles the client acknowledging a rejected click.
+    pub fn handle_window_confirmation(&self, window_id: u8) {
+        if self.rejected_window_id.get() == Some(window_id) {
+            self.rejected_window_id.set(None);
+        }
+    }
+
+    /// Opens a window on the client, closing any window it
+    /// currently has open. Returns the ID of the new window.
+    ///
+    /// IDs cycle through 1 to 100; 0 is the player's own inventory.
+    /// Send the window's contents with [`set_window_items`](Self::set_window_items).
+    pub fn open_window(&self, window_type: WindowType, title: &Text) -> u8 {
+        let window_id = self.next_window_id.get();
+        self.next_window_id.set(window_id % MAX_WINDOW_ID + 1);
+        self.open_window_id.set(Some(window_id));
+
+        self.send_packet(OpenWindow {
+            window_id: window_id as i32,
+            window_kind: window_type,
+            window_title: title.to_string(),
+        });
+        window_id
+    }
+
+    /// Sets the contents of the window with the given ID.
+    pub fn set_window_items(&self, window_id: u8, items: &[InventorySlot]) {
+        self.send_packet(WindowItems {
+            window_id,
+            items: items.to_vec(),
+        });
+    }
+
+    /// Returns the ID of the window opened with
+    /// [`open_window`](Self::open_window), unless it has been closed.
+    pub fn open_window_id(&self) -> Option<u8> {
+        self.open_window_id.get()
+    }
+
+    /// Closes the currently open window, if any.
+    pub fn close_window(&self) {
+        if let Some(window_id) = self.open_window_id.take() {
+            self.send_packet(packets::server::CloseWindow { window_id });
+        }
+    }
+
+    /// Handles the client closing a window. Closes
+    /// of unknown windows are ignored.
+    pub(crate) fn handle_window_closed(&self, window_id: u8) {
+        if self.open_window_id.get() == Some(window_id) {
+            self.open_window_id.set(None);
+        } else if window_id != 0 {
+            log::debug!(
+                "{} closed unknown window {}; ignoring",
+                self.username,
+                window_id
+            );
+        }
+    }
+
     pub fn send_window_items(&self, window: &Window) {
         log::trace!("Updating window for {}", self.username);
         let packet = WindowItems {
@@ -547,30 +1452,119 @@ impl Client {
         });
     }
 
+    /// Puts the item with the given ID on cooldown for `ticks` ticks,
+    /// showing the cooldown on the client's hotbar. A cooldown of 0 clears it.
+    ///
+    /// Until the cooldown expires, the server ignores attempts
+    /// to use the item; see [`is_item_on_cooldown`](Self::is_item_on_cooldown).
+    pub fn set_item_cooldown(&self, item_id: i32, ticks: i32) {
+        if ticks > 0 {
+            self.item_cooldowns.borrow_mut().insert(item_id, ticks);
+        } else {
+            self.item_cooldowns.borrow_mut().remove(&item_id);
+        }
+        self.send_packet(SetCooldown {
+            item_id,
+            cooldown_ticks: ticks.max(0),
+        });
+    }
+
+    /// Returns whether the item with the given ID is on cooldown.
+    pub fn is_item_on_cooldown(&self, item_id: i32) -> bool {
+        self.item_cooldowns.borrow().contains_key(&item_id)
+    }
+
     pub fn send_particle(&self, particle: &base::Particle, position: &Position) {
+        self.send_particle_effect(
+            particle.kind,
+            *position,
+            particle.count,
+            (particle.offset_x, particle.offset_y, particle.offset_z),
+            0.0,
+            true,
+        );
+    }
+
+    /// Displays particles at the given position.
+    ///
+    /// If `long_distance` is set, the client renders the particles
+    /// up to 65536 blocks away instead of 256 blocks.
+    pub fn send_particle_effect(
+        &self,
+        kind: ParticleKind,
+        position: Position,
+        count: i32,
+        (offset_x, offset_y, offset_z): (f32, f32, f32),
+        speed: f32,
+        long_distance: bool,
+    ) {
         self.send_packet(Particle {
-            particle_kind: particle.kind,
-            long_distance: true,
+            particle_kind: kind,
+            long_distance,
             x: position.x,
             y: position.y,
             z: position.z,
-            offset_x: particle.offset_x,
-            offset_y: particle.offset_y,
-            offset_z: particle.offset_z,
-            particle_data: 0.0,
-            particle_count: particle.count,
+            offset_x,
+            offset_y,
+            offset_z,
+            particle_data: speed,
+            particle_count: count,
         })
     }
 
+    /// Plays a sound at the given position.
+    ///
+    /// `volume` and `pitch` are expected to be already clamped;
+    /// see [`Server::broadcast_sound_nearby`](crate::Server::broadcast_sound_nearby).
+    pub fn play_sound(
+        &self,
+        sound: &SoundId,
+        category: SoundCategory,
+        position: Position,
+        volume: f32,
+        pitch: f32,
+    ) {
+        // Sound positions are sent as fixed-point numbers with 3 fractional bits.
+        let position_x = (position.x * 8.0) as i32;
+        let position_y = (position.y * 8.0) as i32;
+        let position_z = (position.z * 8.0) as i32;
+        let category = category as i32;
+        match sound {
+            SoundId::Id(id) => self.send_packet(SoundEffect {
+                sound_id: *id as i32,
+                sound_category: category,
+                position_x,
+                position_y,
+                position_z,
+                volume,
+                pitch,
+            }),
+            SoundId::Named(name) => self.send_packet(NamedSoundEffect {
+                name: name.clone(),
+                category,
+                position_x,
+                position_y,
+                position_z,
+                volume,
+                pitch,
+            }),
+        }
+    }
+
     pub fn set_cursor_slot(&self, item: &InventorySlot) {
         log::trace!("Setting cursor slot of {} to {:?}", self.username, item);
-        self.set_slot(-1, item);
+        // Window -1 and slot -1 address the cursor
+        self.send_packet(SetSlot {
+            window_id: -1i8 as u8,
+            slot: -1,
+            slot_data: item.clone(),
+        });
     }
 
     pub fn send_player_model_flags(&self, netowrk_id: NetworkId, model_flags: u8) {
         let mut entity_metadata = EntityMetadata::new();
         entity_metadata.set(16, model_flags);
-        self.send_packet(SendEntityMetadata {
+        self.send_deferrable_packet(SendEntityMetadata {
             entity_id: netowrk_id.0,
             entries: entity_metadata,
         });
@@ -580,7 +1574,7 @@ impl Client {
         if self.network_id == Some(network_id) {
             return;
         }
-        self.send_packet(SendEntityMetadata {
+        self.send_deferrable_packet(SendEntityMetadata {
             entity_id: network_id.0,
             entries: metadata,
         });
@@ -612,27 +1606,189 @@ impl Client {
     }
 
     pub fn change_gamemode(&self, gamemode: Gamemode) {
-        self.send_packet(ChangeGameState {
-            state_change: GameStateChange::ChangeGamemode { gamemode },
-        })
+        self.send_game_state_change(GameStateChange::ChangeGamemode { gamemode });
+    }
+
+    pub fn send_game_state_change(&self, state_change: GameStateChange) {
+        self.send_packet(ChangeGameState { state_change });
     }
 
     fn register_entity(&self, network_id: NetworkId) {
         self.sent_entities.borrow_mut().insert(network_id);
+        // The spawn packet contains the current position
+        self.culled_entities.borrow_mut().remove(&network_id);
     }
 
     fn send_packet(&self, packet: impl Into<ServerPlayPacket>) {
-        let _ = self.packets_to_send.try_send(packet.into());
+        let mut packet = packet.into();
+        if let Some((id, filter)) = &self.outbound_filter {
+            if filter(*id, &mut packet) == PacketAction::Drop {
+                return;
+            }
+        }
+        if self.packets_to_send.try_send(packet).is_err() {
+            self.mark_disconnected("connection closed".to_owned());
+        }
+    }
+
+    /// Sends a non-essential packet, such as an entity spawn
+    /// or tab list update. If the client is not yet ready,
+    /// spawn and tab list packets are queued and sent once it
+    /// becomes ready, while transient ones such as movement
+    /// or metadata are dropped.
+    fn send_deferrable_packet(&self, packet: impl Into<ServerPlayPacket>) {
+        if self.is_ready() {
+            self.send_packet(packet);
+            return;
+        }
+        let packet = packet.into();
+        if self.disconnected.get() || !is_persistent_state(&packet) {
+            return;
+        }
+
+        let mut deferred_packets = self.deferred_packets.borrow_mut();
+        if deferred_packets.len() < MAX_DEFERRED_PACKETS {
+            deferred_packets.push(packet);
+            return;
+        }
+        deferred_packets.clear();
+        drop(deferred_packets);
+        log::warn!(
+            "Disconnecting {} after deferring {} packets while it loads",
+            self.username,
+            MAX_DEFERRED_PACKETS
+        );
+        self.disconnect("Took too long to load the world");
     }
 
     pub fn disconnect(&self, reason: &str) {
-        self.disconnected.set(true);
-        self.send_packet(Disconnect {
-            reason: Text::from(reason.to_owned()).to_string(),
-        });
+        self.disconnect_with_text(&Text::from(reason.to_owned()));
+    }
+
+    /// Like [`disconnect`](Self::disconnect), but with a formatted reason.
+    pub fn disconnect_with_text(&self, reason: &Text) {
+        let reason = reason.to_string();
+        self.mark_disconnected(reason.clone());
+        self.send_packet(Disconnect { reason });
+    }
+}
+
+/// Maximum velocity component the client accepts, in blocks per tick.
+const MAX_VELOCITY: f64 = 3.9;
+
+/// Encodes a velocity component in units of 1/8000 blocks per tick.
+fn encode_velocity(velocity: f64) -> i16 {
+    (velocity.clamp(-MAX_VELOCITY, MAX_VELOCITY) * 8000.0) as i16
+}
+
+/// Encodes affected blocks as offsets from the block containing `center`.
+fn explosion_records(center: Position, blocks: &[ValidBlockPosition]) -> Vec<ExplosionRecord> {
+    let origin = center.block();
+    let offset = |block: i32, origin: i32| i8::try_from(block - origin).ok();
+    blocks
+        .iter()
+        .filter_map(|block| {
+            Some(ExplosionRecord {
+                x_offset: offset(block.x(), origin.x)?,
+                y_offset: offset(block.y(), origin.y)?,
+                z_offset: offset(block.z(), origin.z)?,
+            })
+        })
+        .collect()
+}
+
+/// Creates the packets to update an entity's position on a client.
+/// See [`Client::update_entity_position`].
+fn entity_movement_packets(
+    network_id: NetworkId,
+    position: Position,
+    prev_position: Position,
+    on_ground: bool,
+    prev_on_ground: bool,
+    force_teleport: bool,
+) -> Vec<ServerPlayPacket> {
+    let no_change_yaw = (position.yaw - prev_position.yaw).abs() < 0.001;
+    let no_change_pitch = (position.pitch - prev_position.pitch).abs() < 0.001;
+
+    let deltas = (
+        relative_move_delta(position.x, prev_position.x),
+        relative_move_delta(position.y, prev_position.y),
+        relative_move_delta(position.z, prev_position.z),
+    );
+
+    let head_look = EntityHeadLook {
+        entity_id: network_id.0,
+        head_yaw: position.yaw,
+    };
+
+    // If the entity jumps or falls we should send a teleport packet instead to keep relative movement in sync.
+    let (delta_x, delta_y, delta_z) = match deltas {
+        (Some(x), Some(y), Some(z)) if !force_teleport && on_ground == prev_on_ground => (x, y, z),
+        _ => {
+            return vec![
+                EntityTeleport {
+                    entity_id: network_id.0,
+                    x: position.x,
+                    y: position.y,
+                    z: position.z,
+                    yaw: position.yaw,
+                    pitch: position.pitch,
+                    on_ground,
+                }
+                .into(),
+                head_look.into(),
+            ];
+        }
+    };
+
+    if no_change_yaw && no_change_pitch {
+        vec![EntityPosition {
+            entity_id: network_id.0,
+            delta_x,
+            delta_y,
+            delta_z,
+            on_ground,
+        }
+        .into()]
+    } else {
+        vec![
+            EntityPositionAndRotation {
+                entity_id: network_id.0,
+                delta_x,
+                delta_y,
+                delta_z,
+                yaw: position.yaw,
+                pitch: position.pitch,
+                on_ground,
+            }
+            .into(),
+            // Needed for head orientation
+            head_look.into(),
+        ]
     }
 }
 
+/// Encodes a coordinate in the fixed-point format used
+/// by relative move packets (1/4096 of a block).
+fn encode_fixed_point(coordinate: f64) -> i64 {
+    (coordinate * 4096.0).round() as i64
+}
+
+/// Computes the delta between two coordinates for a relative move packet,
+/// or `None` if it does not fit (the limit is just under 8 blocks.)
+///
+/// Both coordinates are quantized before subtracting, so the sum of
+/// consecutive deltas always equals the quantized total movement
+/// and no rounding error accumulates on the client.
+fn relative_move_delta(new: f64, old: f64) -> Option<i16> {
+    i16::try_from(encode_fixed_point(new) - encode_fixed_point(old)).ok()
+}
+
+/// Returns the distance between two chunks along the farthest axis.
+fn chunk_distance(a: ChunkPosition, b: ChunkPosition) -> i32 {
+    (a.x - b.x).abs().max((a.z - b.z).abs())
+}
+
 fn chat_packet(message: ChatMessage) -> packets::server::ChatMessage {
     packets::server::ChatMessage {
         message: message.text().to_string(),
@@ -644,3 +1800,917 @@ fn chat_packet(message: ChatMessage) -> packets::server::ChatMessage {
         sender: Uuid::default(),
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::convert::TryInto;
+
+    use base::{position, BlockPosition, Chunk, ChunkLock};
+
+    use protocol::PROTOCOL_VERSION;
+
+    use super::*;
+    use crate::Options;
+
+    /// The options of the bundled `config.toml`.
+    pub(crate) fn test_options() -> Options {
+        crate::config::default_config().to_options()
+    }
+
+    pub(crate) fn test_client() -> Client {
+        test_client_with_packets().0
+    }
+
+    /// A test client with the default options and
+    /// the packets sent to it.
+    pub(crate) fn test_client_with_packets() -> (Client, Receiver<ServerPlayPacket>) {
+        test_client_with_options(test_options())
+    }
+
+    pub(crate) fn test_client_with_options(
+        options: Options,
+    ) -> (Client, Receiver<ServerPlayPacket>) {
+        let connected = connected_test_client_with_options(options);
+        (connected.client, connected.sent_packets)
+    }
+
+    /// The channels of a test player's connection.
+    pub(crate) struct TestConnection {
+        pub(crate) received_packets: Sender<ClientPlayPacket>,
+        pub(crate) sent_packets: Receiver<ServerPlayPacket>,
+        pub(crate) worker_errors: Sender<String>,
+    }
+
+    /// A player as handed over by the initial handler,
+    /// and the other ends of its connection channels.
+    pub(crate) fn test_new_player() -> (NewPlayer, TestConnection) {
+        let (received_packets_tx, received_packets) = flume::unbounded();
+        let (packets_to_send, sent_packets) = flume::unbounded();
+        let (worker_errors_tx, worker_errors) = flume::bounded(1);
+        let player = NewPlayer {
+            uuid: Uuid::new_v4(),
+            username: "test".to_owned(),
+            profile: Vec::new(),
+            protocol_version: PROTOCOL_VERSION,
+            keepalive_exempt: false,
+            received_packets,
+            packets_to_send,
+            worker_errors,
+            packet_trace: PacketTrace::default(),
+            bandwidth: BandwidthMeter::default(),
+        };
+        let connection = TestConnection {
+            received_packets: received_packets_tx,
+            sent_packets,
+            worker_errors: worker_errors_tx,
+        };
+        (player, connection)
+    }
+
+    /// A test client whose connection channels are all kept open.
+    struct ConnectedTestClient {
+        client: Client,
+        _received_packets: Sender<ClientPlayPacket>,
+        sent_packets: Receiver<ServerPlayPacket>,
+        worker_errors: Sender<String>,
+    }
+
+    fn connected_test_client() -> ConnectedTestClient {
+        connected_test_client_with_options(test_options())
+    }
+
+    fn connected_test_client_with_options(options: Options) -> ConnectedTestClient {
+        let (player, connection) = test_new_player();
+        ConnectedTestClient {
+            client: Client::new(player, SharedOptions::new(options)),
+            _received_packets: connection.received_packets,
+            sent_packets: connection.sent_packets,
+            worker_errors: connection.worker_errors,
+        }
+    }
+
+    #[test]
+    fn outbound_filter_drops_packets_for_one_client() {
+        let (muted, muted_packets) = test_client_with_packets();
+        let (other, other_packets) = test_client_with_packets();
+        let mut clients = Clients::new();
+        let muted_id = clients.insert(muted);
+        clients.insert(other);
+
+        let filter: OutboundPacketFilter = Rc::new(move |id, packet| match packet {
+            ServerPlayPacket::ChatMessage(_) if id == muted_id => PacketAction::Drop,
+            _ => PacketAction::Send,
+        });
+        for (id, client) in clients.iter_mut_with_ids() {
+            client.set_outbound_filter(id, Some(Rc::clone(&filter)));
+        }
+
+        for client in clients.iter() {
+            client.send_chat_message(ChatMessage::new(
+                ChatKind::System,
+                Text::from("hi".to_owned()),
+            ));
+            client.send_brand();
+        }
+
+        let muted_packets: Vec<_> = muted_packets.drain().collect();
+        assert_eq!(muted_packets.len(), 1);
+        assert!(matches!(
+            muted_packets[0],
+            ServerPlayPacket::PluginMessage(_)
+        ));
+        assert!(other_packets
+            .drain()
+            .any(|packet| matches!(packet, ServerPlayPacket::ChatMessage(_))));
+    }
+
+    #[test]
+    fn rejected_login_is_sent_reason_and_closed() {
+        let (client, sent_packets) = test_client_with_packets();
+        let reason = Text::from("Server is restarting".to_owned());
+        client.disconnect_with_text(&reason);
+        drop(client);
+
+        match sent_packets.try_recv() {
+            Ok(ServerPlayPacket::Disconnect(packet)) => {
+                assert_eq!(packet.reason, reason.to_string())
+            }
+            packet => panic!("expected Disconnect, got {:?}", packet),
+        }
+        // The worker stops once the channel is drained,
+        // releasing the player's slot in the player count.
+        assert!(sent_packets.is_disconnected());
+    }
+
+    #[test]
+    fn worker_errors_are_captured_as_disconnect_reasons() {
+        let ConnectedTestClient {
+            client,
+            worker_errors,
+            ..
+        } = connected_test_client();
+
+        worker_errors.send("timed out".to_owned()).unwrap();
+        assert_eq!(client.disconnect_reason(), None);
+        client.poll_connection();
+        assert_eq!(client.disconnect_reason().as_deref(), Some("timed out"));
+        // Later reasons do not replace the first
+        client.disconnect("Kicked");
+        assert_eq!(client.disconnect_reason().as_deref(), Some("timed out"));
+    }
+
+    #[test]
+    fn explosion_records_are_relative_to_center() {
+        let center = position!(10.5, 64.2, -3.5);
+        let blocks: Vec<ValidBlockPosition> = vec![
+            BlockPosition::new(10, 64, -4).try_into().unwrap(),
+            BlockPosition::new(12, 63, -1).try_into().unwrap(),
+            BlockPosition::new(200, 64, -4).try_into().unwrap(),
+        ];
+        let records: Vec<_> = explosion_records(center, &blocks)
+            .iter()
+            .map(|record| (record.x_offset, record.y_offset, record.z_offset))
+            .collect();
+        assert_eq!(records, vec![(0, 0, 0), (2, -1, 3)]);
+    }
+
+    #[test]
+    fn session_duration_grows() {
+        let client = test_client();
+        let connected_since = client.connected_since();
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(client.session_duration() >= Duration::from_millis(5));
+        assert_eq!(client.connected_since(), connected_since);
+    }
+
+    #[test]
+    fn activity_is_recorded() {
+        let client = test_client();
+        assert_eq!(client.last_activity(), client.connected_since());
+        std::thread::sleep(Duration::from_millis(5));
+        client.mark_active();
+        assert!(client.last_activity() >= client.connected_since() + Duration::from_millis(5));
+    }
+
+    #[test]
+    fn keepalive_ids_are_compared_in_full() {
+        let ConnectedTestClient {
+            client,
+            sent_packets,
+            ..
+        } = connected_test_client();
+        client.send_keepalive();
+        let id = match sent_packets.try_recv() {
+            Ok(ServerPlayPacket::KeepAlive(packet)) => packet.id,
+            packet => panic!("expected KeepAlive, got {:?}", packet),
+        };
+
+        // Matches in the low 32 bits only
+        assert!(!client.handle_keepalive_response(id + (1 << 32)));
+        assert!(client.is_disconnected());
+    }
+
+    #[test]
+    fn matching_keepalive_response_is_accepted() {
+        let ConnectedTestClient {
+            client,
+            sent_packets,
+            ..
+        } = connected_test_client();
+        assert_eq!(client.latency(), None);
+
+        client.send_keepalive();
+        let id = match sent_packets.try_recv() {
+            Ok(ServerPlayPacket::KeepAlive(packet)) => packet.id,
+            packet => panic!("expected KeepAlive, got {:?}", packet),
+        };
+        // No new keepalive is sent until the client responds
+        client.send_keepalive();
+        assert!(sent_packets.try_recv().is_err());
+
+        let sent_time = client.last_keepalive_response();
+        assert!(client.handle_keepalive_response(id));
+        assert!(client.last_keepalive_response() >= sent_time);
+        assert!(client.latency().is_some());
+        assert!(!client.is_disconnected());
+    }
+
+    #[test]
+    fn mismatched_keepalive_response_disconnects() {
+        let ConnectedTestClient {
+            client,
+            sent_packets,
+            ..
+        } = connected_test_client();
+        client.send_keepalive();
+        let id = match sent_packets.try_recv() {
+            Ok(ServerPlayPacket::KeepAlive(packet)) => packet.id,
+            packet => panic!("expected KeepAlive, got {:?}", packet),
+        };
+
+        assert!(!client.handle_keepalive_response(id + 1));
+        assert!(client.is_disconnected());
+        assert_eq!(client.disconnect_reason().as_deref(), Some("Timed out"));
+    }
+
+    #[test]
+    fn unsolicited_keepalive_response_disconnects() {
+        let ConnectedTestClient { client, .. } = connected_test_client();
+        assert!(!client.handle_keepalive_response(0));
+        assert!(client.is_disconnected());
+    }
+
+    #[test]
+    fn rejected_window_clicks_await_acknowledgement() {
+        let client = test_client();
+        client.confirm_window_action(0, 1, true);
+        assert!(client.is_window_synced(0));
+
+        client.confirm_window_action(0, 2, false);
+        assert!(!client.is_window_synced(0));
+        assert!(client.is_window_synced(1));
+
+        client.handle_window_confirmation(1);
+        assert!(!client.is_window_synced(0));
+        client.handle_window_confirmation(0);
+        assert!(client.is_window_synced(0));
+    }
+
+    #[test]
+    fn join_game_is_sent() {
+        let options = test_options();
+        let view_distance = options.view_distance;
+        let (mut client, packets) = test_client_with_options(options);
+        client.set_network_id(NetworkId(1));
+
+        client.send_join_game(
+            JoinParameters::new(Gamemode::Survival, "world"),
+            &Dimensions::default(),
+        );
+
+        let packets: Vec<_> = packets.try_iter().collect();
+        match packets.as_slice() {
+            [ServerPlayPacket::JoinGame(packet)] => {
+                assert_eq!(packet.world_name, "world");
+                assert_eq!(packet.view_distance, view_distance as i32);
+                assert_eq!(packet.dimension.0, Dimensions::default().dimension_nbt());
+            }
+            packets => panic!("expected a single JoinGame packet, got {:?}", packets),
+        }
+    }
+
+    fn sent_chunks(packets: &Receiver<ServerPlayPacket>) -> Vec<ChunkPosition> {
+        packets
+            .try_iter()
+            .filter_map(|packet| match packet {
+                ServerPlayPacket::ChunkData(packet) => Some(packet.chunk.read().position()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chunk_sends_are_throttled_by_distance() {
+        let mut options = test_options();
+        options.max_chunk_sends_per_tick = 1;
+        let (client, packets) = test_client_with_options(options);
+
+        for &(x, z) in &[(5, 0), (0, 0), (1, 1), (3, 0)] {
+            let chunk = Chunk::new(ChunkPosition::new(x, z));
+            client.send_chunk(&Arc::new(ChunkLock::new(chunk, true)));
+        }
+
+        // Chunks around the client are never held back
+        client.tick();
+        assert_eq!(
+            sent_chunks(&packets),
+            vec![ChunkPosition::new(0, 0), ChunkPosition::new(1, 1)]
+        );
+        client.tick();
+        assert_eq!(sent_chunks(&packets), vec![ChunkPosition::new(3, 0)]);
+        client.tick();
+        assert_eq!(sent_chunks(&packets), vec![ChunkPosition::new(5, 0)]);
+    }
+
+    #[test]
+    fn stale_client_id_returns_none() {
+        let mut clients = Clients::new();
+        let old_id = clients.insert(test_client());
+        assert!(clients.remove(old_id).is_some());
+
+        // The new client reuses the slot of the removed one.
+        let new_id = clients.insert(test_client());
+        assert_ne!(old_id, new_id);
+        assert!(clients.get(old_id).is_none());
+        assert!(clients.get_mut(old_id).is_none());
+        assert!(clients.remove(old_id).is_none());
+        assert!(clients.get(new_id).is_some());
+    }
+
+    fn on_ground_flags(packets: &[ServerPlayPacket]) -> Vec<bool> {
+        packets
+            .iter()
+            .filter_map(|packet| match packet {
+                ServerPlayPacket::EntityTeleport(packet) => Some(packet.on_ground),
+                ServerPlayPacket::EntityPosition(packet) => Some(packet.on_ground),
+                ServerPlayPacket::EntityPositionAndRotation(packet) => Some(packet.on_ground),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn landing_sets_on_ground() {
+        let packets = entity_movement_packets(
+            NetworkId(1),
+            position!(0.0, 64.0, 0.0),
+            position!(0.0, 64.5, 0.0),
+            true,
+            false,
+            false,
+        );
+        assert_eq!(on_ground_flags(&packets), vec![true]);
+    }
+
+    #[test]
+    fn relative_move_keeps_on_ground() {
+        let packets = entity_movement_packets(
+            NetworkId(1),
+            position!(0.5, 64.0, 0.0),
+            position!(0.0, 64.0, 0.0),
+            true,
+            true,
+            false,
+        );
+        assert!(matches!(packets[0], ServerPlayPacket::EntityPosition(_)));
+        assert_eq!(on_ground_flags(&packets), vec![true]);
+    }
+
+    #[test]
+    fn large_moves_are_sent_as_teleports() {
+        let packets = entity_movement_packets(
+            NetworkId(1),
+            position!(0.0, 64.0, 20.0),
+            position!(0.0, 64.0, 0.0),
+            true,
+            true,
+            false,
+        );
+        match &packets[0] {
+            ServerPlayPacket::EntityTeleport(packet) => assert_eq!(packet.z, 20.0),
+            _ => panic!("expected EntityTeleport"),
+        }
+    }
+
+    #[test]
+    fn relative_moves_are_limited_to_eight_blocks() {
+        assert_eq!(relative_move_delta(7.999, 0.0), Some(32764));
+        assert_eq!(relative_move_delta(8.0, 0.0), None);
+        assert_eq!(relative_move_delta(-8.0, 0.0), Some(i16::MIN));
+        assert_eq!(relative_move_delta(-8.001, 0.0), None);
+    }
+
+    #[test]
+    fn reset_play_state_forgets_sent_entities_and_chunks() {
+        let client = test_client();
+        let chunk = Chunk::new(ChunkPosition::new(0, 0));
+        client.send_chunk(&Arc::new(ChunkLock::new(chunk, true)));
+        client.send_living_entity(
+            NetworkId(2),
+            Uuid::new_v4(),
+            position!(0.0, 64.0, 0.0),
+            EntityKind::Zombie,
+        );
+        assert!(client.is_entity_loaded(NetworkId(2)));
+
+        client.reset_play_state();
+        assert!(!client.is_entity_loaded(NetworkId(2)));
+        assert_eq!(client.known_chunks(), 0);
+        assert!(!client.knows_own_position());
+    }
+
+    #[test]
+    fn living_entities_are_spawned_once() {
+        let (client, packets) = test_client_with_packets();
+        client.mark_ready();
+        for _ in 0..2 {
+            client.send_living_entity(
+                NetworkId(2),
+                Uuid::new_v4(),
+                position!(0.0, 64.0, 0.0),
+                EntityKind::Zombie,
+            );
+        }
+        let spawns = packets
+            .try_iter()
+            .filter(|packet| matches!(packet, ServerPlayPacket::SpawnLivingEntity(_)))
+            .count();
+        assert_eq!(spawns, 1);
+    }
+
+    #[test]
+    fn joined_players_load_their_whole_view() {
+        use common::view::View;
+        use ecs::SystemExecutor;
+
+        use crate::{
+            tests::{join_test_player, test_game, test_server},
+            Server,
+        };
+
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let player = join_test_player(&mut game, &mut server, &new_players);
+        let mut systems = SystemExecutor::new();
+        common::view::register(&mut game, &mut systems);
+        common::chunk::loading::register(&mut game, &mut systems);
+        crate::systems::view::register(&mut game, &mut systems);
+        game.insert_resource(server);
+        systems.run(&mut game);
+
+        let server = game.resources.get::<Server>().unwrap();
+        let client = server.clients.get(player.client_id).unwrap();
+        let view = *game.ecs.get::<View>(player.entity).unwrap();
+        let view_width = 2 * view.view_distance() as usize + 1;
+        assert_eq!(client.known_chunks(), view_width * view_width);
+        assert_eq!(client.loaded_chunk_count(), 0);
+
+        client.tick();
+        assert!(client.loaded_chunk_count() >= 9);
+        for _ in 0..view_width * view_width {
+            if client.loaded_chunk_count() == client.known_chunks() {
+                break;
+            }
+            client.tick();
+        }
+        assert_eq!(client.loaded_chunk_count(), view_width * view_width);
+
+        client.unload_chunk(view.center());
+        assert_eq!(client.loaded_chunk_count(), view_width * view_width - 1);
+
+        // e.g. when respawning in another dimension
+        client.reset_play_state();
+        assert_eq!(client.loaded_chunk_count(), 0);
+    }
+
+    #[test]
+    fn light_updates_are_only_sent_for_loaded_chunks() {
+        let (client, packets) = test_client_with_packets();
+        let chunk = Arc::new(ChunkLock::new(Chunk::new(ChunkPosition::new(0, 0)), true));
+        client.send_chunk(&chunk);
+
+        // Still queued: the light is sent with the chunk
+        client.send_light_update(&chunk, vec![4]);
+        assert_eq!(packets.len(), 0);
+
+        client.tick();
+        packets.try_iter().for_each(drop);
+        client.send_light_update(&chunk, vec![4]);
+        match packets.try_recv() {
+            Ok(ServerPlayPacket::UpdateLight(packet)) => {
+                assert_eq!(packet.sections, Some(vec![4]))
+            }
+            packet => panic!("expected UpdateLight, got {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn tablist_display_names_are_sent() {
+        let (client, packets) = test_client_with_packets();
+        client.mark_ready();
+        let uuid = Uuid::new_v4();
+        let display_name = Text::from("[Admin] test".to_owned());
+
+        client.add_tablist_player(
+            uuid,
+            "test".to_owned(),
+            &[],
+            Gamemode::Survival,
+            0,
+            Some(&display_name),
+        );
+        client.change_player_tablist_display_name(uuid, None);
+
+        let packets: Vec<_> = packets.try_iter().collect();
+        assert_eq!(packets.len(), 2);
+        match &packets[0] {
+            ServerPlayPacket::PlayerInfo(PlayerInfo::AddPlayers(added)) => {
+                assert_eq!(added[0].display_name, Some(display_name.to_string()));
+            }
+            packet => panic!("expected AddPlayers, got {:?}", packet),
+        }
+        match &packets[1] {
+            ServerPlayPacket::PlayerInfo(PlayerInfo::UpdateDisplayNames(updated)) => {
+                assert_eq!(updated[..], [(uuid, None)]);
+            }
+            packet => panic!("expected UpdateDisplayNames, got {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn packet_trace_is_shared_with_worker() {
+        let client = test_client();
+        assert!(!client.is_packet_traced());
+
+        let worker_trace = client.packet_trace.clone();
+        client.set_packet_trace(true);
+        assert!(worker_trace.is_enabled());
+        client.set_packet_trace(false);
+        assert!(!worker_trace.is_enabled());
+    }
+
+    #[test]
+    fn congested_clients_skip_nonessential_packets() {
+        let options = Options {
+            congestion_threshold: 2,
+            ..test_options()
+        };
+        let (congested, congested_packets) = test_client_with_options(options.clone());
+        let (client, packets) = test_client_with_options(options);
+        for _ in 0..3 {
+            congested.send_brand();
+        }
+        assert!(congested.is_congested());
+        assert!(!client.is_congested());
+
+        for client in &[&congested, &client] {
+            client.send_nonessential_with(|client| client.send_brand());
+        }
+        assert_eq!(congested_packets.len(), 3);
+        assert_eq!(congested.skipped_packets(), 1);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(client.skipped_packets(), 0);
+    }
+
+    #[test]
+    fn movement_updates_beyond_range_are_skipped() {
+        let mut options = test_options();
+        let movement_packets = |options: Options| {
+            let (client, packets) = test_client_with_options(options);
+            client.mark_ready();
+            client.set_client_known_position(position!(0.0, 64.0, 0.0));
+            client.send_living_entity(
+                NetworkId(2),
+                Uuid::new_v4(),
+                position!(0.0, 64.0, 0.0),
+                EntityKind::Zombie,
+            );
+            packets.try_iter().for_each(drop);
+
+            // Walk 40 blocks away and back
+            let steps = (1..=40).chain((0..40).rev());
+            let mut prev_position = position!(0.0, 64.0, 0.0);
+            let mut sent = Vec::new();
+            for x in steps {
+                let position = position!(x as f64, 64.0, 0.0);
+                client.update_entity_position(
+                    NetworkId(2),
+                    position,
+                    PreviousPosition(prev_position),
+                    OnGround(true),
+                    PreviousOnGround(OnGround(true)),
+                    false,
+                );
+                prev_position = position;
+                sent.extend(packets.try_iter());
+            }
+            sent
+        };
+
+        let unculled = movement_packets(options.clone());
+        options.entity_tracking.movement = Some(16.0);
+        let culled = movement_packets(options);
+        assert_eq!(unculled.len(), 80);
+        // Moves within 16 blocks on the way out and back,
+        // plus the teleport when coming back into range
+        assert_eq!(culled.len(), 34);
+
+        let teleport = culled
+            .iter()
+            .position(|packet| matches!(packet, ServerPlayPacket::EntityTeleport(_)))
+            .unwrap();
+        match &culled[teleport] {
+            ServerPlayPacket::EntityTeleport(packet) => assert_eq!(packet.x, 16.0),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn culled_entities_are_teleported_when_the_viewer_moves_closer() {
+        let mut options = test_options();
+        options.entity_tracking.movement = Some(16.0);
+        let (client, packets) = test_client_with_options(options);
+        client.mark_ready();
+        client.set_client_known_position(position!(0.0, 64.0, 0.0));
+        client.send_living_entity(
+            NetworkId(2),
+            Uuid::new_v4(),
+            position!(0.0, 64.0, 0.0),
+            EntityKind::Zombie,
+        );
+        client.update_entity_position(
+            NetworkId(2),
+            position!(40.0, 64.0, 0.0),
+            PreviousPosition(position!(0.0, 64.0, 0.0)),
+            OnGround(true),
+            PreviousOnGround(OnGround(true)),
+            true,
+        );
+        packets.try_iter().for_each(drop);
+
+        // Still out of range
+        client.set_client_known_position(position!(20.0, 64.0, 0.0));
+        assert!(packets.is_empty());
+
+        // The entity hasn't moved since it was culled
+        client.set_client_known_position(position!(30.0, 64.0, 0.0));
+        match packets.try_iter().next() {
+            Some(ServerPlayPacket::EntityTeleport(packet)) => {
+                assert_eq!(packet.entity_id, 2);
+                assert_eq!(packet.x, 40.0);
+            }
+            packet => panic!("expected EntityTeleport, got {:?}", packet),
+        }
+        packets.try_iter().for_each(drop);
+
+        client.set_client_known_position(position!(31.0, 64.0, 0.0));
+        assert!(packets.is_empty());
+    }
+
+    /// Compares the movement packets sent for a crowd of wandering
+    /// entities with and without a movement update range.
+    ///
+    /// Run with `cargo test -p feather-server movement_packet_volume
+    /// -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn movement_packet_volume() {
+        let volume = |movement_range: Option<f64>| {
+            let mut options = test_options();
+            options.entity_tracking.movement = movement_range;
+            let (client, packets) = test_client_with_options(options);
+            client.mark_ready();
+            client.set_client_known_position(position!(0.0, 64.0, 0.0));
+
+            let mut positions: Vec<Position> = (0..200)
+                .map(|i| {
+                    let angle = i as f64;
+                    let distance = (i % 64) as f64 + 1.0;
+                    position!(angle.cos() * distance, 64.0, angle.sin() * distance)
+                })
+                .collect();
+            for (i, &position) in positions.iter().enumerate() {
+                client.send_living_entity(
+                    NetworkId(i as i32 + 2),
+                    Uuid::new_v4(),
+                    position,
+                    EntityKind::Zombie,
+                );
+            }
+            packets.try_iter().for_each(drop);
+
+            let start = Instant::now();
+            let (mut count, mut bytes) = (0, 0);
+            for tick in 0..1000 {
+                for (i, position) in positions.iter_mut().enumerate() {
+                    let prev_position = *position;
+                    let angle = (tick + i) as f64 / 10.0;
+                    position.x += angle.cos() * 0.2;
+                    position.z += angle.sin() * 0.2;
+                    client.update_entity_position(
+                        NetworkId(i as i32 + 2),
+                        *position,
+                        PreviousPosition(prev_position),
+                        OnGround(true),
+                        PreviousOnGround(OnGround(true)),
+                        false,
+                    );
+                }
+                for packet in packets.try_iter() {
+                    let mut buffer = Vec::new();
+                    packet.write(&mut buffer, ProtocolVersion::V1_16_2).unwrap();
+                    count += 1;
+                    bytes += buffer.len();
+                }
+            }
+            (count, bytes, start.elapsed())
+        };
+
+        let (unculled_count, unculled_bytes, unculled_time) = volume(None);
+        let (culled_count, culled_bytes, culled_time) = volume(Some(32.0));
+        println!(
+            "unculled: {} packets, {} bytes in {:?}",
+            unculled_count, unculled_bytes, unculled_time
+        );
+        println!(
+            "culled at 32 blocks: {} packets, {} bytes in {:?}",
+            culled_count, culled_bytes, culled_time
+        );
+        assert!(culled_bytes < unculled_bytes);
+    }
+
+    /// Returns the positions of the chunks in `packets`, in order.
+    fn sent_chunks(packets: &Receiver<ServerPlayPacket>) -> Vec<ChunkPosition> {
+        packets
+            .try_iter()
+            .filter_map(|packet| match packet {
+                ServerPlayPacket::ChunkData(data) => Some(data.chunk.read().position()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn queued_chunks_are_resorted_when_the_client_moves() {
+        let mut options = test_options();
+        options.max_chunk_sends_per_tick = 1;
+        let (client, packets) = test_client_with_options(options);
+        for x in -8..=8 {
+            let chunk = Chunk::new(ChunkPosition::new(x, 0));
+            client.send_chunk(&Arc::new(ChunkLock::new(chunk, true)));
+        }
+        client.tick();
+        assert_eq!(
+            sent_chunks(&packets),
+            vec![
+                ChunkPosition::new(0, 0),
+                ChunkPosition::new(-1, 0),
+                ChunkPosition::new(1, 0)
+            ]
+        );
+
+        client.update_own_chunk(ChunkPosition::new(8, 0));
+        client.tick();
+        assert_eq!(
+            sent_chunks(&packets),
+            vec![ChunkPosition::new(8, 0), ChunkPosition::new(7, 0)]
+        );
+        client.tick();
+        assert_eq!(sent_chunks(&packets), vec![ChunkPosition::new(6, 0)]);
+    }
+
+    /// Measures ticking a client while it loads a view
+    /// of 65×65 chunks, moving one chunk every 20 ticks.
+    ///
+    /// Run with `cargo test -p feather-server chunk_send_queue_ticks
+    /// -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn chunk_send_queue_ticks() {
+        let mut options = test_options();
+        options.max_chunk_sends_per_tick = 16;
+        let client = test_client_with_options(options).0;
+        for x in -32..=32 {
+            for z in -32..=32 {
+                let chunk = Chunk::new(ChunkPosition::new(x, z));
+                client.send_chunk(&Arc::new(ChunkLock::new(chunk, true)));
+            }
+        }
+
+        let start = Instant::now();
+        let mut ticks = 0;
+        while client.loaded_chunk_count() < client.known_chunks() {
+            if ticks % 20 == 19 {
+                client.update_own_chunk(ChunkPosition::new(ticks / 20, 0));
+            }
+            client.tick();
+            ticks += 1;
+        }
+        let elapsed = start.elapsed();
+        println!(
+            "{} chunks in {} ticks: {:?}, {:?} per tick",
+            client.known_chunks(),
+            ticks,
+            elapsed,
+            elapsed / ticks as u32
+        );
+    }
+
+    #[test]
+    fn map_mut_skips_removed_clients() {
+        let mut clients = Clients::new();
+        let removed = clients.insert(test_client());
+        let kept = clients.insert(test_client());
+        clients.remove(removed);
+
+        let results = clients.map_mut(&[removed, kept], |client| client.username().to_owned());
+        assert_eq!(results.len(), 1);
+
+        let results = clients.map_mut(&[removed], |_| ());
+        assert!(results.is_empty());
+        assert_eq!(results.capacity(), 0);
+    }
+
+    #[test]
+    fn item_cooldowns_expire_and_can_be_cleared() {
+        let (client, sent_packets) = test_client_with_packets();
+        client.set_item_cooldown(7, 2);
+        client.set_item_cooldown(8, 20);
+        assert!(client.is_item_on_cooldown(7));
+
+        client.tick();
+        assert!(client.is_item_on_cooldown(7));
+        client.tick();
+        assert!(!client.is_item_on_cooldown(7));
+        assert!(client.is_item_on_cooldown(8));
+
+        client.set_item_cooldown(8, 0);
+        assert!(!client.is_item_on_cooldown(8));
+        let cooldowns: Vec<_> = sent_packets
+            .try_iter()
+            .filter_map(|packet| match packet {
+                ServerPlayPacket::SetCooldown(packet) => {
+                    Some((packet.item_id, packet.cooldown_ticks))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(cooldowns, vec![(7, 2), (8, 20), (8, 0)]);
+    }
+
+    #[test]
+    fn clients_stuck_loading_are_disconnected() {
+        let connected = connected_test_client();
+        let client = &connected.client;
+        for _ in 0..MAX_DEFERRED_PACKETS {
+            client.remove_tablist_player(Uuid::nil());
+        }
+        assert!(!client.is_disconnected());
+        assert!(connected.sent_packets.try_iter().next().is_none());
+
+        client.remove_tablist_player(Uuid::nil());
+        client.remove_tablist_player(Uuid::nil());
+        let sent: Vec<_> = connected.sent_packets.try_iter().collect();
+        assert!(
+            matches!(sent.as_slice(), [ServerPlayPacket::Disconnect(_)]),
+            "{:?}",
+            sent
+        );
+        assert!(client.is_disconnected());
+        assert!(client.deferred_packets.borrow().is_empty());
+    }
+
+    #[test]
+    fn transient_packets_are_dropped_before_the_client_is_ready() {
+        let connected = connected_test_client();
+        let client = &connected.client;
+        for _ in 0..MAX_DEFERRED_PACKETS * 2 {
+            client.send_player_model_flags(NetworkId(2), 0x7f);
+            client.send_entity_animation(NetworkId(2), Animation::SwingMainArm);
+            client.send_entity_velocity(NetworkId(2), Velocity::default());
+        }
+        client.remove_tablist_player(Uuid::nil());
+        assert!(!client.is_disconnected());
+
+        client.mark_ready();
+        let sent: Vec<_> = connected.sent_packets.try_iter().collect();
+        assert!(
+            matches!(
+                sent.as_slice(),
+                [ServerPlayPacket::PlayerInfo(PlayerInfo::RemovePlayers(_))]
+            ),
+            "{:?}",
+            sent
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! Builder for the command graph sent to clients
+//! in the Declare Commands packet.
+
+use protocol::packets::server::{CommandNode, CommandNodeKind, DeclareCommands};
+
+/// Index of a node within a [`CommandGraph`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CommandNodeId(usize);
+
+/// A graph of commands and their arguments, used by
+/// the client for tab completion and syntax highlighting.
+///
+/// Commands are added as literal nodes below [`root`](Self::root).
+#[derive(Debug, Clone)]
+pub struct CommandGraph {
+    nodes: Vec<CommandNode>,
+}
+
+impl Default for CommandGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommandGraph {
+    /// Creates a graph containing only the root node.
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![CommandNode {
+                kind: CommandNodeKind::Root,
+                executable: false,
+                children: Vec::new(),
+                redirect_node: None,
+                suggestions_type: None,
+            }],
+        }
+    }
+
+    /// Creates a graph with an executable, argument-less
+    /// root command for each of the given names.
+    pub fn with_root_commands<'a>(names: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut graph = Self::new();
+        for name in names {
+            let node = graph.add_literal(graph.root(), name);
+            graph.set_executable(node);
+        }
+        graph
+    }
+
+    /// Returns the root node.
+    pub fn root(&self) -> CommandNodeId {
+        CommandNodeId(0)
+    }
+
+    /// Adds a literal node (a fixed word, such as a command name) below `parent`.
+    pub fn add_literal(&mut self, parent: CommandNodeId, name: impl Into<String>) -> CommandNodeId {
+        self.add_node(parent, CommandNodeKind::Literal { name: name.into() })
+    }
+
+    /// Adds an argument node below `parent`.
+    ///
+    /// `parser` is the parser identifier, e.g. `brigadier:integer`,
+    /// and `properties` are its encoded parser properties.
+    pub fn add_argument(
+        &mut self,
+        parent: CommandNodeId,
+        name: impl Into<String>,
+        parser: impl Into<String>,
+        properties: Vec<u8>,
+    ) -> CommandNodeId {
+        self.add_node(
+            parent,
+            CommandNodeKind::Argument {
+                name: name.into(),
+                parser: parser.into(),
+                properties,
+            },
+        )
+    }
+
+    /// Marks a node as a valid end of a command.
+    pub fn set_executable(&mut self, node: CommandNodeId) {
+        self.nodes[node.0].executable = true;
+    }
+
+    /// Makes `node` redirect to `target`, as with command aliases.
+    pub fn set_redirect(&mut self, node: CommandNodeId, target: CommandNodeId) {
+        self.nodes[node.0].redirect_node = Some(target.0 as i32);
+    }
+
+    /// Sets the suggestion provider of an argument node,
+    /// e.g. `minecraft:ask_server`.
+    pub fn set_suggestions_type(
+        &mut self,
+        node: CommandNodeId,
+        suggestions_type: impl Into<String>,
+    ) {
+        self.nodes[node.0].suggestions_type = Some(suggestions_type.into());
+    }
+
+    /// Creates the Declare Commands packet for this graph.
+    pub fn to_packet(&self) -> DeclareCommands {
+        DeclareCommands {
+            nodes: self.nodes.clone(),
+            root_index: self.root().0 as i32,
+        }
+    }
+
+    fn add_node(&mut self, parent: CommandNodeId, kind: CommandNodeKind) -> CommandNodeId {
+        let id = CommandNodeId(self.nodes.len());
+        self.nodes.push(CommandNode {
+            kind,
+            executable: false,
+            children: Vec::new(),
+            redirect_node: None,
+            suggestions_type: None,
+        });
+        self.nodes[parent.0].children.push(id.0 as i32);
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn root_commands() {
+        let packet = CommandGraph::with_root_commands(vec!["help", "stop"]).to_packet();
+        assert_eq!(packet.root_index, 0);
+        assert_eq!(packet.nodes.len(), 3);
+        assert_eq!(packet.nodes[0].children, vec![1, 2]);
+        for node in &packet.nodes[1..] {
+            assert!(node.executable);
+            assert!(node.children.is_empty());
+        }
+    }
+
+    #[test]
+    fn nested_nodes_and_redirects() {
+        let mut graph = CommandGraph::new();
+        let teleport = graph.add_literal(graph.root(), "teleport");
+        let target = graph.add_argument(teleport, "target", "minecraft:entity", vec![0x01]);
+        graph.set_executable(target);
+        let tp = graph.add_literal(graph.root(), "tp");
+        graph.set_redirect(tp, teleport);
+
+        let packet = graph.to_packet();
+        assert_eq!(packet.nodes[0].children, vec![1, 3]);
+        assert_eq!(packet.nodes[1].children, vec![2]);
+        assert_eq!(packet.nodes[3].redirect_node, Some(1));
+        assert!(packet.nodes[2].executable);
+    }
+}
@@ -1,12 +1,27 @@
 //! Loads an `Options` from a TOML config.
 
-use std::{fs, net::IpAddr, path::Path, str::FromStr};
+use std::{
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::Context;
-use base::Gamemode;
+use base::{anvil::player::PlayerDataFormat, chunk::SECTION_VOLUME, Difficulty, Gamemode};
+use common::mob_spawning::MobCaps;
+use protocol::io::MAX_CHAT_MESSAGE_LENGTH;
 use serde::{Deserialize, Deserializer};
+use uuid::Uuid;
 
-use crate::{favicon::Favicon, Options};
+use crate::{
+    auth::{ForwardingAuthenticator, MojangAuthenticator, OfflineAuthenticator},
+    favicon::Favicon,
+    options::EntityTrackingRanges,
+    Authenticator, IpRange, Options,
+};
 
 const DEFAULT_CONFIG: &str = include_str!("../config.toml");
 
@@ -31,6 +46,11 @@ pub fn load(path: &str) -> anyhow::Result<ConfigContainer> {
     })
 }
 
+/// Returns the config of the default `config.toml`.
+pub(crate) fn default_config() -> Config {
+    toml::from_str(DEFAULT_CONFIG).expect("default config is valid")
+}
+
 /// A wrapper for the result returned by [load].
 pub struct ConfigContainer {
     pub config: Config,
@@ -44,28 +64,80 @@ pub struct Config {
     pub log: Log,
     pub world: World,
     pub proxy: Proxy,
+    #[serde(default)]
+    pub entity_tracking: EntityTracking,
+    #[serde(default)]
+    pub mob_spawning: MobSpawning,
 }
 
 impl Config {
+    /// Returns the authenticator for the configured
+    /// online mode and proxy mode.
+    fn authenticator(&self) -> Arc<dyn Authenticator> {
+        if self.proxy.proxy_mode != ProxyMode::None {
+            Arc::new(ForwardingAuthenticator)
+        } else if self.server.online_mode {
+            Arc::new(MojangAuthenticator)
+        } else {
+            Arc::new(OfflineAuthenticator)
+        }
+    }
+
     pub fn to_options(&self) -> Options {
         Options {
-            port: self.network.port,
-            bind_address: self.network.address.to_string(),
+            bind_addresses: self.network.bind_addresses(),
+            connection_buffer_size: self.network.connection_buffer_size,
+            tcp_nodelay: self.network.tcp_nodelay,
+            congestion_threshold: self.network.congestion_threshold,
             favicon: Favicon::load_default(),
             motd: self.server.motd.clone(),
-            online_mode: if self.proxy.proxy_mode != ProxyMode::None {
-                false
-            } else {
-                self.server.online_mode
-            },
+            autosave_interval: Duration::from_secs(self.server.autosave_interval_secs),
+            tick_time_window: self.server.tick_time_window,
+            startup_timeout: Duration::from_secs(self.server.startup_timeout_secs),
+            authenticator: self.authenticator(),
             compression_threshold: if self.network.compression_threshold <= 0 {
                 None
             } else {
                 Some(self.network.compression_threshold as usize)
             },
+            compress_local: self.network.compress_local,
+            ip_allowlist: self.network.ip_allowlist.clone(),
+            ip_denylist: self.network.ip_denylist.clone(),
+            player_data_format: self.world.player_data_format,
+            in_memory_world: self.world.in_memory,
+            dimension_types_dir: PathBuf::from(&self.world.dimension_types_dir),
+            dimension_type: self.world.dimension_type.clone(),
             view_distance: self.server.view_distance,
+            chunk_unsubscribe_margin: self.server.chunk_unsubscribe_margin,
+            simulation_distance: self
+                .server
+                .simulation_distance
+                .min(self.server.view_distance),
+            max_chunk_sends_per_tick: self.server.max_chunk_sends_per_tick,
+            entity_tracking: self.entity_tracking.to_ranges(),
+            section_overwrite_threshold: self.server.section_overwrite_threshold.max(1),
+            spawn_protection: self.server.spawn_protection,
+            idle_timeout: match self.server.idle_timeout_mins {
+                0 => None,
+                mins => Some(Duration::from_secs(mins * 60)),
+            },
+            idle_timeout_exempts_ops: self.server.idle_timeout_exempts_ops,
             max_players: self.server.max_players,
             default_gamemode: self.server.default_gamemode,
+            force_gamemode: self.server.force_gamemode,
+            difficulty: self.server.difficulty,
+            mob_caps: self.mob_spawning.to_caps(),
+            max_chat_length: self.server.max_chat_length.min(MAX_CHAT_MESSAGE_LENGTH),
+            join_message: self.server.join_message.clone(),
+            leave_message: self.server.leave_message.clone(),
+            suppress_reconnect_messages: self.server.suppress_reconnect_messages,
+            pause_when_empty: self.server.pause_when_empty,
+            ops: self
+                .server
+                .ops
+                .iter()
+                .map(|op| (op.uuid, op.level))
+                .collect(),
             proxy_mode: match self.proxy.proxy_mode {
                 ProxyMode::None => None,
                 ProxyMode::Bungee => Some(crate::options::ProxyMode::Bungeecord),
@@ -81,6 +153,41 @@ pub struct Network {
     pub address: IpAddr,
     pub port: u16,
     pub compression_threshold: i32,
+    #[serde(default)]
+    pub compress_local: bool,
+    #[serde(default = "default_connection_buffer_size")]
+    pub connection_buffer_size: usize,
+    #[serde(default = "default_tcp_nodelay")]
+    pub tcp_nodelay: bool,
+    #[serde(default = "default_congestion_threshold")]
+    pub congestion_threshold: usize,
+    #[serde(default)]
+    pub additional_addresses: Vec<SocketAddr>,
+    #[serde(default, deserialize_with = "deserialize_ip_ranges")]
+    pub ip_allowlist: Vec<IpRange>,
+    #[serde(default, deserialize_with = "deserialize_ip_ranges")]
+    pub ip_denylist: Vec<IpRange>,
+}
+
+impl Network {
+    /// Returns the main address followed by the additional addresses.
+    pub fn bind_addresses(&self) -> Vec<SocketAddr> {
+        let mut addresses = vec![SocketAddr::new(self.address, self.port)];
+        addresses.extend(self.additional_addresses.iter().copied());
+        addresses
+    }
+}
+
+fn default_connection_buffer_size() -> usize {
+    512
+}
+
+fn default_tcp_nodelay() -> bool {
+    true
+}
+
+fn default_congestion_threshold() -> usize {
+    1024
 }
 
 #[derive(Debug, Deserialize)]
@@ -89,7 +196,162 @@ pub struct ServerConfig {
     pub motd: String,
     pub max_players: u32,
     pub default_gamemode: Gamemode,
+    #[serde(default)]
+    pub force_gamemode: bool,
+    #[serde(default)]
+    pub difficulty: Difficulty,
     pub view_distance: u32,
+    #[serde(default = "default_chunk_unsubscribe_margin")]
+    pub chunk_unsubscribe_margin: u32,
+    #[serde(default = "default_simulation_distance")]
+    pub simulation_distance: u32,
+    #[serde(default = "default_max_chunk_sends_per_tick")]
+    pub max_chunk_sends_per_tick: usize,
+    #[serde(default = "default_section_overwrite_threshold")]
+    pub section_overwrite_threshold: usize,
+    #[serde(default = "default_spawn_protection")]
+    pub spawn_protection: u32,
+    #[serde(default)]
+    pub idle_timeout_mins: u64,
+    #[serde(default = "default_idle_timeout_exempts_ops")]
+    pub idle_timeout_exempts_ops: bool,
+    #[serde(default = "default_startup_timeout_secs")]
+    pub startup_timeout_secs: u64,
+    #[serde(default = "default_autosave_interval_secs")]
+    pub autosave_interval_secs: u64,
+    #[serde(default = "default_tick_time_window")]
+    pub tick_time_window: usize,
+    #[serde(default = "default_max_chat_length")]
+    pub max_chat_length: usize,
+    #[serde(default)]
+    pub join_message: Option<String>,
+    #[serde(default)]
+    pub leave_message: Option<String>,
+    #[serde(default)]
+    pub suppress_reconnect_messages: bool,
+    #[serde(default)]
+    pub pause_when_empty: bool,
+    #[serde(default)]
+    pub ops: Vec<Op>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Op {
+    #[serde(deserialize_with = "deserialize_uuid")]
+    pub uuid: Uuid,
+    #[serde(deserialize_with = "deserialize_op_level")]
+    pub level: u8,
+}
+
+fn default_chunk_unsubscribe_margin() -> u32 {
+    1
+}
+
+fn default_simulation_distance() -> u32 {
+    10
+}
+
+fn default_max_chunk_sends_per_tick() -> usize {
+    10
+}
+
+/// A `MultiBlockChange` record takes 2 to 4 bytes, 4 for most blocks,
+/// while a section with a small palette takes about 2 KiB, so resending
+/// the section is smaller once about an eighth of it has changed.
+/// See the `soundless_block_update_volume` benchmark.
+fn default_section_overwrite_threshold() -> usize {
+    SECTION_VOLUME / 8
+}
+
+fn default_spawn_protection() -> u32 {
+    16
+}
+
+fn default_idle_timeout_exempts_ops() -> bool {
+    true
+}
+
+fn default_startup_timeout_secs() -> u64 {
+    30
+}
+
+fn default_autosave_interval_secs() -> u64 {
+    300
+}
+
+fn default_tick_time_window() -> usize {
+    100
+}
+
+fn default_max_chat_length() -> usize {
+    MAX_CHAT_MESSAGE_LENGTH
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct EntityTracking {
+    pub players: u32,
+    pub animals: u32,
+    pub monsters: u32,
+    pub misc: u32,
+    pub other: u32,
+    pub movement_updates: u32,
+}
+
+impl Default for EntityTracking {
+    fn default() -> Self {
+        let ranges = EntityTrackingRanges::default();
+        Self {
+            players: ranges.players as u32,
+            animals: ranges.animals as u32,
+            monsters: ranges.monsters as u32,
+            misc: ranges.misc as u32,
+            other: ranges.other as u32,
+            movement_updates: 0,
+        }
+    }
+}
+
+impl EntityTracking {
+    fn to_ranges(&self) -> EntityTrackingRanges {
+        EntityTrackingRanges {
+            players: self.players.into(),
+            animals: self.animals.into(),
+            monsters: self.monsters.into(),
+            misc: self.misc.into(),
+            other: self.other.into(),
+            movement: match self.movement_updates {
+                0 => None,
+                range => Some(range.into()),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct MobSpawning {
+    pub hostile_cap: usize,
+    pub passive_cap: usize,
+}
+
+impl Default for MobSpawning {
+    fn default() -> Self {
+        let caps = MobCaps::default();
+        Self {
+            hostile_cap: caps.hostile,
+            passive_cap: caps.passive,
+        }
+    }
+}
+
+impl MobSpawning {
+    fn to_caps(&self) -> MobCaps {
+        MobCaps {
+            hostile: self.hostile_cap,
+            passive: self.passive_cap,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -103,6 +365,22 @@ pub struct World {
     pub name: String,
     pub generator: String,
     pub seed: String,
+    #[serde(default)]
+    pub player_data_format: PlayerDataFormat,
+    #[serde(default)]
+    pub in_memory: bool,
+    #[serde(default = "default_dimension_types_dir")]
+    pub dimension_types_dir: String,
+    #[serde(default = "default_dimension_type")]
+    pub dimension_type: String,
+}
+
+fn default_dimension_types_dir() -> String {
+    "dimension_types".to_owned()
+}
+
+fn default_dimension_type() -> String {
+    "minecraft:overworld".to_owned()
 }
 
 #[derive(Debug, Deserialize)]
@@ -131,6 +409,32 @@ fn deserialize_log_level<'de, D: Deserializer<'de>>(
     Ok(level)
 }
 
+fn deserialize_uuid<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Uuid, D::Error> {
+    let string: String = String::deserialize(deserializer)?;
+    Uuid::parse_str(&string)
+        .map_err(|_| serde::de::Error::custom(format!("invalid UUID: {}", string)))
+}
+
+fn deserialize_ip_ranges<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<IpRange>, D::Error> {
+    let strings: Vec<String> = Vec::deserialize(deserializer)?;
+    strings
+        .iter()
+        .map(|string| IpRange::from_str(string).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn deserialize_op_level<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let level = u8::deserialize(deserializer)?;
+    if level > 4 {
+        return Err(serde::de::Error::custom(
+            "invalid op level: valid levels are 0 to 4",
+        ));
+    }
+    Ok(level)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -139,4 +443,133 @@ mod tests {
     fn default_config_is_valid() {
         let _config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
     }
+
+    #[test]
+    fn ops_are_loaded() {
+        let config = DEFAULT_CONFIG.replace(
+            "ops = []",
+            r#"ops = [{ uuid = "069a79f4-44e9-4726-a5be-fca90e38aaf5", level = 4 }]"#,
+        );
+        let config: Config = toml::from_str(&config).unwrap();
+        let uuid = Uuid::parse_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap();
+        assert_eq!(config.to_options().ops.get(&uuid), Some(&4));
+    }
+
+    #[test]
+    fn saved_gamemode_is_kept_unless_forced() {
+        let options = toml::from_str::<Config>(DEFAULT_CONFIG)
+            .unwrap()
+            .to_options();
+        assert_eq!(options.gamemode_on_join(None), Gamemode::Creative);
+        assert_eq!(
+            options.gamemode_on_join(Some(Gamemode::Survival)),
+            Gamemode::Survival
+        );
+
+        let config = DEFAULT_CONFIG.replace("force_gamemode = false", "force_gamemode = true");
+        let options = toml::from_str::<Config>(&config).unwrap().to_options();
+        assert!(options.force_gamemode);
+        assert_eq!(
+            options.gamemode_on_join(Some(Gamemode::Survival)),
+            Gamemode::Creative
+        );
+    }
+
+    #[test]
+    fn authenticator_follows_online_and_proxy_mode() {
+        let authenticator = |config: &str| {
+            let config: Config = toml::from_str(config).unwrap();
+            format!("{:?}", config.to_options().authenticator)
+        };
+        assert_eq!(authenticator(DEFAULT_CONFIG), "MojangAuthenticator");
+        assert_eq!(
+            authenticator(&DEFAULT_CONFIG.replace("online_mode = true", "online_mode = false")),
+            "OfflineAuthenticator"
+        );
+        assert_eq!(
+            authenticator(
+                &DEFAULT_CONFIG.replace(r#"proxy_mode = "none""#, r#"proxy_mode = "bungee""#)
+            ),
+            "ForwardingAuthenticator"
+        );
+    }
+
+    #[test]
+    fn zero_idle_timeout_disables_idle_kick() {
+        let options = toml::from_str::<Config>(DEFAULT_CONFIG)
+            .unwrap()
+            .to_options();
+        assert_eq!(options.idle_timeout, None);
+        assert!(options.idle_timeout_exempts_ops);
+
+        let config = DEFAULT_CONFIG.replace("idle_timeout_mins = 0", "idle_timeout_mins = 5");
+        let options = toml::from_str::<Config>(&config).unwrap().to_options();
+        assert_eq!(options.idle_timeout, Some(Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn max_chat_length_is_clamped_to_protocol_limit() {
+        let config = DEFAULT_CONFIG.replace("max_chat_length = 256", "max_chat_length = 100");
+        let options = toml::from_str::<Config>(&config).unwrap().to_options();
+        assert_eq!(options.max_chat_length, 100);
+
+        let config = DEFAULT_CONFIG.replace("max_chat_length = 256", "max_chat_length = 1000");
+        let options = toml::from_str::<Config>(&config).unwrap().to_options();
+        assert_eq!(options.max_chat_length, MAX_CHAT_MESSAGE_LENGTH);
+    }
+
+    #[test]
+    fn simulation_distance_is_clamped_to_view_distance() {
+        let options = toml::from_str::<Config>(DEFAULT_CONFIG)
+            .unwrap()
+            .to_options();
+        assert_eq!(options.simulation_distance, 10);
+
+        let config = DEFAULT_CONFIG.replace("simulation_distance = 10", "simulation_distance = 32");
+        let options = toml::from_str::<Config>(&config).unwrap().to_options();
+        assert_eq!(options.simulation_distance, options.view_distance);
+    }
+
+    #[test]
+    fn mob_caps_are_loaded() {
+        let config = DEFAULT_CONFIG.replace("hostile_cap = 70", "hostile_cap = 20");
+        let config: Config = toml::from_str(&config).unwrap();
+        assert_eq!(
+            config.to_options().mob_caps,
+            MobCaps {
+                hostile: 20,
+                passive: 10
+            }
+        );
+    }
+
+    #[test]
+    fn additional_addresses_are_loaded() {
+        let config = DEFAULT_CONFIG.replace(
+            "# additional_addresses = [\"[::]:25565\"]",
+            r#"additional_addresses = ["[::]:25565", "0.0.0.0:25566"]"#,
+        );
+        let config: Config = toml::from_str(&config).unwrap();
+        let addresses: Vec<String> = config
+            .to_options()
+            .bind_addresses
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(addresses, ["0.0.0.0:25565", "[::]:25565", "0.0.0.0:25566"]);
+    }
+
+    #[test]
+    fn ip_denylist_is_loaded() {
+        let config =
+            DEFAULT_CONFIG.replace("ip_denylist = []", r#"ip_denylist = ["10.0.0.0/8", "::1"]"#);
+        let config: Config = toml::from_str(&config).unwrap();
+        let options = config.to_options();
+        assert!(!options.is_ip_allowed("10.1.2.3".parse().unwrap()));
+        assert!(!options.is_ip_allowed("::1".parse().unwrap()));
+        assert!(options.is_ip_allowed("192.168.0.1".parse().unwrap()));
+
+        let config = DEFAULT_CONFIG.replace("ip_denylist = []", r#"ip_denylist = ["10.0.0.0/40"]"#);
+        assert!(toml::from_str::<Config>(&config).is_err());
+    }
 }
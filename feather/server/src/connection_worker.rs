@@ -1,12 +1,23 @@
-use std::{fmt::Debug, io, net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    fmt::Debug,
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use base::Text;
 use flume::{Receiver, Sender};
 use futures_lite::FutureExt;
 use io::ErrorKind;
+use parking_lot::Mutex;
 use protocol::{
-    codec::CryptKey, packets::server::Disconnect, ClientPlayPacket, MinecraftCodec, Readable,
-    ServerPlayPacket, Writeable,
+    codec::{CryptKey, DecodeError},
+    packets::server::Disconnect,
+    ClientPlayPacket, MinecraftCodec, Readable, ServerPlayPacket, Writeable,
 };
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
@@ -21,8 +32,13 @@ use crate::{
     initial_handler::{InitialHandling, NewPlayer},
     options::Options,
     player_count::PlayerCount,
+    readiness::Readiness,
 };
 
+/// Lower bound for the connection read/write buffer sizes.
+/// Smaller configured values are raised to this.
+const MIN_BUFFER_SIZE: usize = 64;
+
 /// Tokio task which handles a connection and processes
 /// packets.
 ///
@@ -34,35 +50,65 @@ use crate::{
 pub struct Worker {
     reader: Reader,
     writer: Writer,
+    addr: SocketAddr,
     options: Arc<Options>,
     player_count: PlayerCount,
+    readiness: Readiness,
     packets_to_send_tx: Sender<ServerPlayPacket>,
     received_packets_rx: Receiver<ClientPlayPacket>,
+    worker_errors_tx: Sender<String>,
+    worker_errors_rx: Receiver<String>,
+    packet_trace: PacketTrace,
+    bandwidth: BandwidthMeter,
     new_players: Sender<NewPlayer>,
 }
 
 impl Worker {
     pub fn new(
         stream: TcpStream,
-        _addr: SocketAddr,
+        addr: SocketAddr,
         options: Arc<Options>,
         player_count: PlayerCount,
+        readiness: Readiness,
         new_players: Sender<NewPlayer>,
     ) -> Self {
         let (reader, writer) = stream.into_split();
 
         let (received_packets_tx, received_packets_rx) = flume::bounded(32);
         let (packets_to_send_tx, packets_to_send_rx) = flume::unbounded();
-        let reader = Reader::new(reader, received_packets_tx);
-        let writer = Writer::new(writer, packets_to_send_rx);
+        let (worker_errors_tx, worker_errors_rx) = flume::bounded(1);
+        let buffer_size = options.connection_buffer_size.max(MIN_BUFFER_SIZE);
+        let packet_trace = PacketTrace::default();
+        let bandwidth = BandwidthMeter::default();
+        let reader = Reader::new(
+            reader,
+            received_packets_tx,
+            packets_to_send_tx.clone(),
+            buffer_size,
+            packet_trace.clone(),
+            bandwidth.clone(),
+        );
+        let writer = Writer::new(
+            writer,
+            packets_to_send_rx,
+            buffer_size,
+            packet_trace.clone(),
+            bandwidth.clone(),
+        );
 
         Self {
             reader,
             writer,
+            addr,
             options,
             player_count,
+            readiness,
             packets_to_send_tx,
             received_packets_rx,
+            worker_errors_tx,
+            worker_errors_rx,
+            packet_trace,
+            bandwidth,
             new_players,
         }
     }
@@ -101,10 +147,19 @@ impl Worker {
         }
     }
 
+    /// Returns the address of the connected client.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
     pub fn options(&self) -> &Options {
         &self.options
     }
 
+    pub fn readiness(&self) -> &Readiness {
+        &self.readiness
+    }
+
     pub fn player_count(&self) -> u32 {
         self.player_count.get()
     }
@@ -129,7 +184,8 @@ impl Worker {
     }
 
     pub async fn write(&mut self, packet: impl Writeable + Debug) -> anyhow::Result<()> {
-        self.writer.write(packet).await
+        self.writer.write(packet).await?;
+        Ok(())
     }
 
     pub fn split(self, username: String) {
@@ -137,17 +193,36 @@ impl Worker {
             reader,
             writer,
             player_count,
+            worker_errors_tx,
             ..
         } = self;
-        let reader = tokio::task::spawn(async move { reader.run().await });
-        let writer = tokio::task::spawn(async move { writer.run().await });
+        let reader_username = username.clone();
+        let writer_username = username.clone();
+        let reader = tokio::task::spawn(async move { reader.run(&reader_username).await });
+        let writer = tokio::task::spawn(async move { writer.run(&writer_username).await });
 
         tokio::task::spawn(async move {
-            let result = reader.race(writer).await.expect("task panicked");
+            let result = match reader.race(writer).await {
+                Ok(result) => result,
+                Err(e) => Err(anyhow::anyhow!("worker task failed: {}", e)),
+            };
             if let Err(e) = result {
+                if let Some(error) = e.downcast_ref::<DecodeError>() {
+                    log::debug!(
+                        "{} sent a malformed packet (ID {:?}, length {})",
+                        username,
+                        error.packet_id,
+                        error.length
+                    );
+                }
                 let message = disconnected_message(e);
                 log::debug!("{} lost connection: {}", username, message);
+                let _ = worker_errors_tx.try_send(message);
             }
+            // Dropping the sender tells the client that the worker
+            // stopped, after any error has been sent. The server
+            // removes the client on its next tick.
+            drop(worker_errors_tx);
             player_count.remove_player();
         });
     }
@@ -159,28 +234,201 @@ impl Worker {
     pub fn received_packets(&self) -> Receiver<ClientPlayPacket> {
         self.received_packets_rx.clone()
     }
+
+    pub fn worker_errors(&self) -> Receiver<String> {
+        self.worker_errors_rx.clone()
+    }
+
+    pub fn packet_trace(&self) -> PacketTrace {
+        self.packet_trace.clone()
+    }
+
+    pub fn bandwidth(&self) -> BandwidthMeter {
+        self.bandwidth.clone()
+    }
+}
+
+/// Switch to log the IDs and lengths of the packets sent and
+/// received over one connection, shared between the connection's
+/// worker and its [`Client`](crate::Client). Disabled by default.
+#[derive(Clone, Debug, Default)]
+pub struct PacketTrace(Arc<AtomicBool>);
+
+impl PacketTrace {
+    pub fn set_enabled(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Logs a packet at trace level if tracing is enabled.
+    /// `direction` is `"in"` or `"out"`.
+    fn log(&self, username: &str, direction: &str, id: u32, length: usize) {
+        if self.is_enabled() {
+            log::trace!(
+                "[{}] {} packet 0x{:02x}, {} bytes",
+                username,
+                direction,
+                id,
+                length
+            );
+        }
+    }
+}
+
+/// Number of seconds over which [`BandwidthStats`] are averaged.
+const BANDWIDTH_WINDOW_SECS: usize = 5;
+
+/// Bytes per second sent and received over a connection,
+/// as returned by [`Client::bandwidth_stats`](crate::Client::bandwidth_stats).
+///
+/// Counts the bytes on the wire, i.e. after compression, averaged
+/// over the last few seconds.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BandwidthStats {
+    pub bytes_in_per_sec: u64,
+    pub bytes_out_per_sec: u64,
+}
+
+/// Counts the bytes sent and received over one connection,
+/// shared between the connection's worker and its [`Client`](crate::Client).
+#[derive(Clone, Debug)]
+pub struct BandwidthMeter(Arc<BandwidthMeterInner>);
+
+#[derive(Debug)]
+struct BandwidthMeterInner {
+    start: Instant,
+    bytes_in: Mutex<RollingCounter>,
+    bytes_out: Mutex<RollingCounter>,
+}
+
+impl Default for BandwidthMeter {
+    fn default() -> Self {
+        Self(Arc::new(BandwidthMeterInner {
+            start: Instant::now(),
+            bytes_in: Mutex::new(RollingCounter::default()),
+            bytes_out: Mutex::new(RollingCounter::default()),
+        }))
+    }
+}
+
+impl BandwidthMeter {
+    pub fn stats(&self) -> BandwidthStats {
+        let second = self.second();
+        BandwidthStats {
+            bytes_in_per_sec: self.0.bytes_in.lock().per_second(second),
+            bytes_out_per_sec: self.0.bytes_out.lock().per_second(second),
+        }
+    }
+
+    fn record_in(&self, bytes: usize) {
+        self.0.bytes_in.lock().record(self.second(), bytes as u64);
+    }
+
+    fn record_out(&self, bytes: usize) {
+        self.0.bytes_out.lock().record(self.second(), bytes as u64);
+    }
+
+    /// Returns the number of whole seconds since the meter was created.
+    fn second(&self) -> u64 {
+        self.0.start.elapsed().as_secs()
+    }
+}
+
+/// Byte counts for each of the last [`BANDWIDTH_WINDOW_SECS`] seconds.
+#[derive(Debug, Default)]
+struct RollingCounter {
+    buckets: [u64; BANDWIDTH_WINDOW_SECS],
+    /// The second counted in the current bucket.
+    second: u64,
+}
+
+impl RollingCounter {
+    fn record(&mut self, second: u64, bytes: u64) {
+        self.advance(second);
+        self.buckets[second as usize % BANDWIDTH_WINDOW_SECS] += bytes;
+    }
+
+    /// Returns the average bytes per second over the
+    /// completed seconds in the window.
+    fn per_second(&mut self, second: u64) -> u64 {
+        self.advance(second);
+        let current = second as usize % BANDWIDTH_WINDOW_SECS;
+        let total: u64 = self
+            .buckets
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| index != current)
+            .map(|(_, bytes)| bytes)
+            .sum();
+        let seconds = second.min(BANDWIDTH_WINDOW_SECS as u64 - 1).max(1);
+        total / seconds
+    }
+
+    /// Clears the buckets of the seconds elapsed since the last update.
+    fn advance(&mut self, second: u64) {
+        if second <= self.second {
+            return;
+        }
+        let elapsed = (second - self.second).min(BANDWIDTH_WINDOW_SECS as u64);
+        for skipped in 1..=elapsed {
+            self.buckets[(self.second + skipped) as usize % BANDWIDTH_WINDOW_SECS] = 0;
+        }
+        self.second = second;
+    }
 }
 
 struct Reader {
     stream: OwnedReadHalf,
     codec: MinecraftCodec,
-    buffer: [u8; 512],
+    buffer: Vec<u8>,
     received_packets: Sender<ClientPlayPacket>,
+    /// Used to disconnect the client on protocol errors.
+    packets_to_send: Sender<ServerPlayPacket>,
+    packet_trace: PacketTrace,
+    bandwidth: BandwidthMeter,
 }
 
 impl Reader {
-    pub fn new(stream: OwnedReadHalf, received_packets: Sender<ClientPlayPacket>) -> Self {
+    pub fn new(
+        stream: OwnedReadHalf,
+        received_packets: Sender<ClientPlayPacket>,
+        packets_to_send: Sender<ServerPlayPacket>,
+        buffer_size: usize,
+        packet_trace: PacketTrace,
+        bandwidth: BandwidthMeter,
+    ) -> Self {
         Self {
             stream,
             codec: MinecraftCodec::new(),
-            buffer: [0; 512],
+            buffer: vec![0; buffer_size],
             received_packets,
+            packets_to_send,
+            packet_trace,
+            bandwidth,
         }
     }
 
-    pub async fn run(mut self) -> anyhow::Result<()> {
+    pub async fn run(mut self, username: &str) -> anyhow::Result<()> {
         loop {
-            let packet = self.read::<ClientPlayPacket>().await?;
+            let packet = match self.read::<ClientPlayPacket>().await {
+                Ok(packet) => packet,
+                Err(e) => {
+                    if e.downcast_ref::<DecodeError>().is_some() {
+                        // The writer keeps running until the server drops the client
+                        let _ =
+                            self.packets_to_send
+                                .send(ServerPlayPacket::Disconnect(Disconnect {
+                                    reason: Text::from("Protocol error").to_string(),
+                                }));
+                    }
+                    return Err(e);
+                }
+            };
+            self.packet_trace
+                .log(username, "in", packet.id(), self.codec.last_packet_length());
             let result = self.received_packets.send_async(packet).await;
             if result.is_err() {
                 // server dropped connection
@@ -201,6 +449,7 @@ impl Reader {
             if read_bytes == 0 {
                 return Err(io::Error::new(ErrorKind::UnexpectedEof, "read 0 bytes").into());
             }
+            self.bandwidth.record_in(read_bytes);
 
             let bytes = &self.buffer[..read_bytes];
             self.codec.accept(bytes);
@@ -213,30 +462,45 @@ struct Writer {
     codec: MinecraftCodec,
     packets_to_send: Receiver<ServerPlayPacket>,
     buffer: Vec<u8>,
+    packet_trace: PacketTrace,
+    bandwidth: BandwidthMeter,
 }
 
 impl Writer {
-    pub fn new(stream: OwnedWriteHalf, packets_to_send: Receiver<ServerPlayPacket>) -> Self {
+    pub fn new(
+        stream: OwnedWriteHalf,
+        packets_to_send: Receiver<ServerPlayPacket>,
+        buffer_size: usize,
+        packet_trace: PacketTrace,
+        bandwidth: BandwidthMeter,
+    ) -> Self {
         Self {
             stream,
             codec: MinecraftCodec::new(),
             packets_to_send,
-            buffer: Vec::new(),
+            buffer: Vec::with_capacity(buffer_size),
+            packet_trace,
+            bandwidth,
         }
     }
 
-    pub async fn run(mut self) -> anyhow::Result<()> {
+    pub async fn run(mut self, username: &str) -> anyhow::Result<()> {
         while let Ok(packet) = self.packets_to_send.recv_async().await {
-            self.write(packet).await?;
+            let id = packet.id();
+            let length = self.write(packet).await?;
+            self.packet_trace.log(username, "out", id, length);
         }
         Ok(())
     }
 
-    pub async fn write(&mut self, packet: impl Writeable + Debug) -> anyhow::Result<()> {
+    /// Writes a packet, returning its encoded length in bytes.
+    pub async fn write(&mut self, packet: impl Writeable + Debug) -> anyhow::Result<usize> {
         self.codec.encode(&packet, &mut self.buffer)?;
         self.stream.write_all(&self.buffer).await?;
+        let length = self.buffer.len();
+        self.bandwidth.record_out(length);
         self.buffer.clear();
-        Ok(())
+        Ok(length)
     }
 }
 
@@ -248,3 +512,104 @@ fn disconnected_message(e: anyhow::Error) -> String {
     }
     format!("{:?}", e)
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::client::tests::test_options;
+
+    #[test]
+    fn rolling_counter_averages_completed_seconds() {
+        let mut counter = RollingCounter::default();
+        counter.record(0, 100);
+        assert_eq!(counter.per_second(0), 0);
+        counter.record(1, 300);
+        assert_eq!(counter.per_second(2), 200);
+
+        // Seconds without traffic count as zero
+        assert_eq!(counter.per_second(5), 75);
+        // Old seconds leave the window
+        assert_eq!(counter.per_second(6), 0);
+        counter.record(6, 10);
+        counter.record(100, 40);
+        assert_eq!(counter.per_second(101), 10);
+    }
+
+    #[tokio::test]
+    async fn malformed_packet_disconnects_with_protocol_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, addr) = listener.accept().await.unwrap();
+
+        let player_count = PlayerCount::new(1);
+        player_count.try_add_player().unwrap();
+        let (new_players, _) = flume::unbounded();
+        let worker = Worker::new(
+            stream,
+            addr,
+            Arc::new(test_options()),
+            player_count.clone(),
+            Readiness::default(),
+            new_players,
+        );
+        let worker_errors = worker.worker_errors();
+        worker.split("test".to_owned());
+
+        // A ChatMessage (ID 0x03) missing its message
+        client.write_all(&[0x01, 0x03]).await.unwrap();
+
+        let error = worker_errors.recv_async().await.unwrap();
+        assert!(error.contains("malformed packet"), "{}", error);
+
+        // The connection is closed once the Disconnect is written
+        let mut received = Vec::new();
+        timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+            .await
+            .unwrap()
+            .unwrap();
+        let mut codec = MinecraftCodec::new();
+        codec.accept(&received);
+        match codec.next_packet::<ServerPlayPacket>().unwrap() {
+            Some(ServerPlayPacket::Disconnect(disconnect)) => {
+                assert_eq!(disconnect.reason, Text::from("Protocol error").to_string())
+            }
+            packet => panic!("expected a Disconnect, got {:?}", packet),
+        }
+        assert!(codec.next_packet::<ServerPlayPacket>().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn packets_larger_than_the_read_buffer_are_read() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, addr) = listener.accept().await.unwrap();
+
+        let mut options = test_options();
+        options.connection_buffer_size = 1;
+        let (new_players, _) = flume::unbounded();
+        let mut worker = Worker::new(
+            stream,
+            addr,
+            Arc::new(options),
+            PlayerCount::new(1),
+            Readiness::default(),
+            new_players,
+        );
+
+        // A ChatMessage (ID 0x03) of 200 characters
+        let mut packet = vec![0xCB, 0x01, 0x03, 0xC8, 0x01];
+        packet.extend(std::iter::repeat(b'a').take(200));
+        client.write_all(&packet).await.unwrap();
+
+        match worker.read::<ClientPlayPacket>().await.unwrap() {
+            ClientPlayPacket::ChatMessage(chat) => assert_eq!(chat.message, "a".repeat(200)),
+            packet => panic!("expected a ChatMessage, got {:?}", packet),
+        }
+    }
+}
@@ -0,0 +1,321 @@
+//! Dimension types sent to clients in Join Game.
+//!
+//! The bundled dimension codec contains the vanilla dimension types.
+//! Custom types are loaded from datapack-style JSON files in the
+//! dimension types directory, at `<namespace>/<name>.json` for the
+//! type `namespace:name`. A custom type with the name of a vanilla
+//! type replaces it.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use nbt::{Blob, Value};
+use serde::Deserialize;
+
+use crate::assets;
+
+/// The properties of a dimension type, as in the
+/// `dimension_type` files of vanilla datapacks.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct DimensionType {
+    pub piglin_safe: bool,
+    pub natural: bool,
+    pub ambient_light: f32,
+    /// Time of day the sun and moon are fixed at,
+    /// or `None` for a day-night cycle.
+    #[serde(default)]
+    pub fixed_time: Option<i64>,
+    pub infiniburn: String,
+    pub respawn_anchor_works: bool,
+    pub has_skylight: bool,
+    pub bed_works: bool,
+    #[serde(default = "default_effects")]
+    pub effects: String,
+    pub has_raids: bool,
+    /// Maximum height to which chorus fruits and
+    /// nether portals can teleport players.
+    pub logical_height: i32,
+    pub coordinate_scale: f32,
+    pub ultrawarm: bool,
+    pub has_ceiling: bool,
+}
+
+fn default_effects() -> String {
+    "minecraft:overworld".to_owned()
+}
+
+impl DimensionType {
+    /// Parses a dimension type from the contents of its JSON file.
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    fn to_nbt(&self) -> Vec<(&'static str, Value)> {
+        let mut fields = vec![
+            ("piglin_safe", Value::Byte(self.piglin_safe as i8)),
+            ("natural", Value::Byte(self.natural as i8)),
+            ("ambient_light", Value::Float(self.ambient_light)),
+            ("infiniburn", Value::String(self.infiniburn.clone())),
+            (
+                "respawn_anchor_works",
+                Value::Byte(self.respawn_anchor_works as i8),
+            ),
+            ("has_skylight", Value::Byte(self.has_skylight as i8)),
+            ("bed_works", Value::Byte(self.bed_works as i8)),
+            ("effects", Value::String(self.effects.clone())),
+            ("has_raids", Value::Byte(self.has_raids as i8)),
+            ("logical_height", Value::Int(self.logical_height)),
+            ("coordinate_scale", Value::Float(self.coordinate_scale)),
+            ("ultrawarm", Value::Byte(self.ultrawarm as i8)),
+            ("has_ceiling", Value::Byte(self.has_ceiling as i8)),
+        ];
+        if let Some(fixed_time) = self.fixed_time {
+            fields.push(("fixed_time", Value::Long(fixed_time)));
+        }
+        fields
+    }
+}
+
+/// The dimension types known to clients and the
+/// dimension type of the world.
+#[derive(Clone, Debug)]
+pub struct Dimensions {
+    types: Vec<(String, DimensionType)>,
+    world_type: String,
+}
+
+impl Dimensions {
+    /// Returns the vanilla dimension types, with the world
+    /// in the overworld.
+    pub fn vanilla() -> Self {
+        let codec = assets::dimension_codec_types().expect("assets are validated at startup");
+        Self {
+            types: codec
+                .into_iter()
+                .map(|entry| (entry.name, entry.element))
+                .collect(),
+            world_type: "minecraft:overworld".to_owned(),
+        }
+    }
+
+    /// Loads the vanilla dimension types and the custom types in
+    /// `dir`, if it exists, with the world in `world_type`.
+    pub fn load(dir: impl AsRef<Path>, world_type: &str) -> anyhow::Result<Self> {
+        let mut dimensions = Self::vanilla();
+        let dir = dir.as_ref();
+        if dir.exists() {
+            for (name, path) in dimension_type_files(dir)? {
+                let json = fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                let dimension_type = DimensionType::from_json(&json)
+                    .with_context(|| format!("invalid dimension type {}", path.display()))?;
+                log::info!("Loaded dimension type {}", name);
+                dimensions.insert(name, dimension_type);
+            }
+        }
+        dimensions.set_world_type(world_type)?;
+        Ok(dimensions)
+    }
+
+    /// Adds a dimension type, replacing any type with the same name.
+    pub fn insert(&mut self, name: impl Into<String>, dimension_type: DimensionType) {
+        let name = name.into();
+        match self
+            .types
+            .iter_mut()
+            .find(|(existing, _)| *existing == name)
+        {
+            Some((_, existing)) => *existing = dimension_type,
+            None => self.types.push((name, dimension_type)),
+        }
+    }
+
+    /// Sets the dimension type of the world.
+    pub fn set_world_type(&mut self, name: &str) -> anyhow::Result<()> {
+        if self.get(name).is_none() {
+            bail!("unknown dimension type {}", name);
+        }
+        self.world_type = name.to_owned();
+        Ok(())
+    }
+
+    /// Returns the dimension type called `name`.
+    pub fn get(&self, name: &str) -> Option<&DimensionType> {
+        self.types
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, dimension_type)| dimension_type)
+    }
+
+    /// Returns the dimension type of the world.
+    pub fn world_type(&self) -> &DimensionType {
+        self.get(&self.world_type)
+            .expect("world dimension type is registered")
+    }
+
+    /// Builds the dimension codec sent in Join Game,
+    /// containing the bundled biomes and all dimension types.
+    pub fn codec_nbt(&self) -> Blob {
+        let types = self
+            .types
+            .iter()
+            .enumerate()
+            .map(|(id, (name, dimension_type))| {
+                compound(vec![
+                    ("name", Value::String(name.clone())),
+                    ("id", Value::Int(id as i32)),
+                    ("element", compound(dimension_type.to_nbt())),
+                ])
+            })
+            .collect();
+        let registry = compound(vec![
+            ("type", Value::String("minecraft:dimension_type".to_owned())),
+            ("value", Value::List(types)),
+        ]);
+
+        let mut codec = assets::dimension_codec();
+        codec
+            .insert("minecraft:dimension_type", registry)
+            .expect("dimension types are valid NBT");
+        codec
+    }
+
+    /// Builds the dimension sent in Join Game
+    /// from the dimension type of the world.
+    pub fn dimension_nbt(&self) -> Blob {
+        let mut dimension = Blob::new();
+        for (name, value) in self.world_type().to_nbt() {
+            dimension
+                .insert(name, value)
+                .expect("dimension types are valid NBT");
+        }
+        dimension
+    }
+}
+
+impl Default for Dimensions {
+    fn default() -> Self {
+        Self::vanilla()
+    }
+}
+
+fn compound(fields: Vec<(&str, Value)>) -> Value {
+    Value::Compound(
+        fields
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value))
+            .collect(),
+    )
+}
+
+/// Returns the names and paths of the dimension type files in `dir`,
+/// sorted by name so that type IDs don't depend on the file system.
+fn dimension_type_files(dir: &Path) -> anyhow::Result<Vec<(String, PathBuf)>> {
+    let mut files = Vec::new();
+    let entries = fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?;
+    for namespace in entries {
+        let namespace = namespace?.path();
+        if !namespace.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(&namespace)? {
+            let path = file?.path();
+            if path
+                .extension()
+                .map_or(true, |extension| extension != "json")
+            {
+                continue;
+            }
+            let (namespace_name, file_name) = match (
+                namespace.file_name().and_then(|name| name.to_str()),
+                path.file_stem().and_then(|name| name.to_str()),
+            ) {
+                (Some(namespace_name), Some(file_name)) => (namespace_name, file_name),
+                _ => bail!("invalid dimension type file name {}", path.display()),
+            };
+            files.push((format!("{}:{}", namespace_name, file_name), path));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUSTOM: &str = r#"{
+        "piglin_safe": false,
+        "natural": false,
+        "ambient_light": 0.5,
+        "fixed_time": 6000,
+        "infiniburn": "minecraft:infiniburn_overworld",
+        "respawn_anchor_works": false,
+        "has_skylight": true,
+        "bed_works": false,
+        "effects": "minecraft:the_end",
+        "has_raids": false,
+        "logical_height": 128,
+        "coordinate_scale": 1.0,
+        "ultrawarm": false,
+        "has_ceiling": true
+    }"#;
+
+    fn field<'a>(fields: &'a [(&str, Value)], name: &str) -> Option<&'a Value> {
+        fields
+            .iter()
+            .find(|(existing, _)| *existing == name)
+            .map(|(_, value)| value)
+    }
+
+    #[test]
+    fn vanilla_types_are_bundled() {
+        let dimensions = Dimensions::vanilla();
+        assert!(dimensions.get("minecraft:the_nether").unwrap().has_ceiling);
+        assert!(!dimensions.world_type().has_ceiling);
+        assert_eq!(dimensions.world_type().fixed_time, None);
+    }
+
+    #[test]
+    fn custom_type_is_sent_as_world_dimension() {
+        let mut dimensions = Dimensions::vanilla();
+        dimensions.insert("custom:noon", DimensionType::from_json(CUSTOM).unwrap());
+        dimensions.set_world_type("custom:noon").unwrap();
+
+        let fields = dimensions.world_type().to_nbt();
+        assert_eq!(field(&fields, "fixed_time"), Some(&Value::Long(6000)));
+        assert_eq!(field(&fields, "has_ceiling"), Some(&Value::Byte(1)));
+        assert_eq!(field(&fields, "logical_height"), Some(&Value::Int(128)));
+
+        let mut expected = Blob::new();
+        for (name, value) in fields {
+            expected.insert(name, value).unwrap();
+        }
+        assert_eq!(dimensions.dimension_nbt(), expected);
+    }
+
+    #[test]
+    fn unknown_world_type_is_rejected() {
+        let mut dimensions = Dimensions::vanilla();
+        let error = dimensions.set_world_type("custom:missing").unwrap_err();
+        assert_eq!(error.to_string(), "unknown dimension type custom:missing");
+    }
+
+    #[test]
+    fn invalid_json_names_the_file() {
+        let dir = std::env::temp_dir().join(format!("feather-dimensions-{}", std::process::id()));
+        fs::create_dir_all(dir.join("custom")).unwrap();
+        fs::write(dir.join("custom/noon.json"), CUSTOM).unwrap();
+        let dimensions = Dimensions::load(&dir, "custom:noon").unwrap();
+        assert_eq!(dimensions.world_type().fixed_time, Some(6000));
+
+        fs::write(dir.join("custom/broken.json"), "{ \"natural\": ").unwrap();
+        let error = Dimensions::load(&dir, "custom:noon").unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(error.to_string().starts_with("invalid dimension type "));
+        assert!(error.to_string().ends_with("broken.json"));
+    }
+}
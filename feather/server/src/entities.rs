@@ -1,9 +1,10 @@
-use base::{EntityKind, Position};
+use base::{EntityKind, Position, Text};
+use common::experience::ExperienceOrb;
 use ecs::{EntityBuilder, EntityRef, SysResult};
 use quill_common::{components::OnGround, entity_init::EntityInit};
 use uuid::Uuid;
 
-use crate::{Client, NetworkId};
+use crate::{network_id_registry::NetworkIdAllocator, Client, NetworkId};
 
 /// Component that sends the spawn packet for an entity
 /// using its components.
@@ -16,19 +17,57 @@ impl SpawnPacketSender {
 }
 
 /// Stores the [`Position`] of an entity on
-/// the previous tick. Used to determine
-/// when to send movement updates.
+/// the previous tick, i.e. the last position broadcast
+/// to clients. Used to determine when to send movement updates.
 #[derive(Copy, Clone, Debug)]
 pub struct PreviousPosition(pub Position);
+/// Counts the relative movement updates sent for an entity
+/// since its last absolute teleport. Used to periodically
+/// resynchronize the entity's position on clients.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RelativeMovesSinceTeleport(pub u32);
 /// Stores the [`OnGround`] status of an entity on
 /// the previous tick. Used to determine
 /// what movement packet to send.
 #[derive(Copy, Clone, Debug)]
 pub struct PreviousOnGround(pub OnGround);
 
-pub fn add_entity_components(builder: &mut EntityBuilder, init: &EntityInit) {
+/// The name shown for a player in the tab list
+/// instead of its username.
+#[derive(Clone, Debug)]
+pub struct TablistDisplayName(pub Text);
+
+/// Height of a player's eyes above their feet.
+pub const PLAYER_EYE_HEIGHT: f64 = 1.62;
+
+/// Returns `position` rotated so that a player standing there
+/// looks at `target` with its eyes.
+///
+/// If `target` is directly above or below, the pitch is ±90 degrees
+/// and the yaw is left unchanged. If `target` is at the eyes, the
+/// rotation is left unchanged.
+pub fn facing(mut position: Position, target: Position) -> Position {
+    let dx = target.x - position.x;
+    let dy = target.y - (position.y + PLAYER_EYE_HEIGHT);
+    let dz = target.z - position.z;
+    let horizontal = (dx * dx + dz * dz).sqrt();
+    if horizontal > f64::EPSILON {
+        position.yaw = (-dx).atan2(dz).to_degrees() as f32;
+        position.pitch = (-dy).atan2(horizontal).to_degrees() as f32;
+    } else if dy.abs() > f64::EPSILON {
+        position.pitch = if dy > 0. { -90. } else { 90. };
+    }
+    position
+}
+
+pub fn add_entity_components(
+    builder: &mut EntityBuilder,
+    init: &EntityInit,
+    network_ids: &mut NetworkIdAllocator,
+) {
     if !builder.has::<NetworkId>() {
-        builder.add(NetworkId::new());
+        let network_id = network_ids.allocate().expect("all network IDs are in use");
+        builder.add(network_id);
     }
 
     // can't panic because this is only called after both position and onground is added to all entities.
@@ -39,7 +78,8 @@ pub fn add_entity_components(builder: &mut EntityBuilder, init: &EntityInit) {
 
     builder
         .add(PreviousPosition(prev_position))
-        .add(PreviousOnGround(on_ground));
+        .add(PreviousOnGround(on_ground))
+        .add(RelativeMovesSinceTeleport::default());
     add_spawn_packet(builder, init);
 }
 
@@ -48,6 +88,7 @@ fn add_spawn_packet(builder: &mut EntityBuilder, init: &EntityInit) {
     // (minecarts, items, ...)
     let spawn_packet = match init {
         EntityInit::Player => spawn_player,
+        EntityInit::ExperienceOrb => spawn_experience_orb,
         _ => spawn_living_entity,
     };
     builder.add(SpawnPacketSender(spawn_packet));
@@ -62,6 +103,17 @@ fn spawn_player(entity: &EntityRef, client: &Client) -> SysResult {
     Ok(())
 }
 
+fn spawn_experience_orb(entity: &EntityRef, client: &Client) -> SysResult {
+    let network_id = *entity.get::<NetworkId>()?;
+    let pos = *entity.get::<Position>()?;
+    let amount = entity.get::<ExperienceOrb>()?.amount;
+
+    // The protocol's count is a short
+    let count = amount.min(i16::MAX as u32) as u16;
+    client.send_experience_orb(network_id, pos, count);
+    Ok(())
+}
+
 fn spawn_living_entity(entity: &EntityRef, client: &Client) -> SysResult {
     let network_id = *entity.get::<NetworkId>()?;
     let uuid = *entity.get::<Uuid>()?;
@@ -71,3 +123,40 @@ fn spawn_living_entity(entity: &EntityRef, client: &Client) -> SysResult {
     client.send_living_entity(network_id, uuid, pos, kind);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use base::position;
+
+    use super::*;
+
+    fn eyes_at(x: f64, y: f64, z: f64) -> Position {
+        position!(x, y - PLAYER_EYE_HEIGHT, z)
+    }
+
+    #[test]
+    fn facing_horizontal_targets() {
+        let facing_south = facing(eyes_at(0., 64., 0.), position!(0., 64., 10.));
+        assert!(facing_south.yaw.abs() < 1e-4);
+        assert!(facing_south.pitch.abs() < 1e-4);
+
+        let facing_east = facing(eyes_at(0., 64., 0.), position!(10., 64., 0.));
+        assert!((facing_east.yaw + 90.).abs() < 1e-4);
+
+        let facing_down = facing(eyes_at(0., 64., 0.), position!(0., 54., 10.));
+        assert!((facing_down.pitch - 45.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn facing_targets_straight_above_or_below_keeps_yaw() {
+        let mut position = eyes_at(0., 64., 0.);
+        position.yaw = 30.;
+
+        let up = facing(position, position!(0., 70., 0.));
+        assert_eq!((up.yaw, up.pitch), (30., -90.));
+        let down = facing(position, position!(0., 60., 0.));
+        assert_eq!((down.yaw, down.pitch), (30., 90.));
+        let same = facing(position, position!(0., 64., 0.));
+        assert_eq!((same.yaw, same.pitch), (position.yaw, position.pitch));
+    }
+}
@@ -0,0 +1,26 @@
+//! Status codes sent with [`Server::broadcast_entity_status`](crate::Server::broadcast_entity_status).
+//!
+//! The meaning of a status depends on the kind of the entity.
+
+/// Plays the hurt animation and sound of a living entity.
+pub const LIVING_HURT: u8 = 2;
+/// Plays the death animation of a living entity.
+pub const LIVING_DEATH: u8 = 3;
+/// Spawns the particles of a snowball or egg hitting something.
+pub const PROJECTILE_HIT: u8 = 3;
+/// Plays the attack animation of an iron golem or evoker fangs.
+pub const ATTACK: u8 = 4;
+/// Spawns smoke particles as a wolf, cat or horse refuses taming.
+pub const TAMING_FAILED: u8 = 6;
+/// Spawns heart particles as a wolf, cat or horse is tamed.
+pub const TAMING_SUCCEEDED: u8 = 7;
+/// Tells a player it has finished using an item, e.g. eating.
+pub const PLAYER_ITEM_USE_FINISHED: u8 = 9;
+/// Spawns heart particles as an animal enters love mode.
+pub const ANIMAL_IN_LOVE: u8 = 18;
+/// Plays the sound of a shield blocking an attack.
+pub const SHIELD_BLOCK: u8 = 29;
+/// Plays the sound of a shield being disabled by an axe.
+pub const SHIELD_DISABLE: u8 = 30;
+/// Plays the totem of undying animation and sound.
+pub const TOTEM_OF_UNDYING: u8 = 35;
@@ -1,6 +1,10 @@
 //! Initial handling of a connection.
 
-use crate::{connection_worker::Worker, favicon::Favicon};
+use crate::{
+    auth::GameProfile,
+    connection_worker::{BandwidthMeter, PacketTrace, Worker},
+    favicon::Favicon,
+};
 use anyhow::bail;
 use base::{ProfileProperty, Text};
 use flume::{Receiver, Sender};
@@ -16,19 +20,16 @@ use protocol::{
         },
     },
     ClientHandshakePacket, ClientLoginPacket, ClientPlayPacket, ClientStatusPacket,
-    ServerLoginPacket, ServerPlayPacket, ServerStatusPacket,
+    ServerLoginPacket, ServerPlayPacket, ServerStatusPacket, PROTOCOL_VERSION, VERSION_NAME,
 };
 use rand::rngs::OsRng;
 use rsa::{PaddingScheme, PublicKeyParts, RsaPrivateKey};
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use sha1::Sha1;
-use std::convert::TryInto;
+use std::{convert::TryInto, net::IpAddr, sync::Arc};
 use uuid::Uuid;
 
-use self::proxy::ProxyData;
-
-const SERVER_NAME: &str = "Feather 1.16.5";
-const PROTOCOL_VERSION: i32 = 754;
+pub use self::proxy::ProxyData;
 
 mod proxy;
 
@@ -38,9 +39,21 @@ pub struct NewPlayer {
     pub uuid: Uuid,
     pub username: String,
     pub profile: Vec<ProfileProperty>,
+    /// The protocol version sent by the client in its handshake.
+    pub protocol_version: u32,
+    /// Whether the client is exempt from keepalive timeouts.
+    /// Always `false` for clients connecting over the network.
+    pub keepalive_exempt: bool,
 
     pub received_packets: Receiver<ClientPlayPacket>,
     pub packets_to_send: Sender<ServerPlayPacket>,
+    /// Receives the reason the connection was lost
+    /// if the worker stops because of an error.
+    pub worker_errors: Receiver<String>,
+    /// Enables logging of the connection's packets.
+    pub packet_trace: PacketTrace,
+    /// Counts the bytes sent and received over the connection.
+    pub bandwidth: BandwidthMeter,
 }
 
 /// Result of initial handling.
@@ -63,29 +76,44 @@ pub async fn handle(worker: &mut Worker) -> anyhow::Result<InitialHandling> {
     match handshake.next_state {
         HandshakeState::Status => handle_status(worker).await,
         HandshakeState::Login => {
-            if handshake.protocol_version < PROTOCOL_VERSION {
-                worker
-                    .write(ServerLoginPacket::DisconnectLogin(DisconnectLogin {
-                        reason: Text::from(
-                            "Invalid protocol! The server is running on version 1.16!",
-                        )
-                        .to_string(),
-                    }))
-                    .await
-                    .ok();
-                return Ok(InitialHandling::Disconnect);
-            }
+            let protocol_version = match login_protocol_version(handshake.protocol_version) {
+                Some(protocol_version) => protocol_version,
+                None => {
+                    worker
+                        .write(ServerLoginPacket::DisconnectLogin(DisconnectLogin {
+                            reason: Text::from(format!(
+                                "Invalid protocol! The server is running on version {}!",
+                                VERSION_NAME
+                            ))
+                            .to_string(),
+                        }))
+                        .await
+                        .ok();
+                    return Ok(InitialHandling::Disconnect);
+                }
+            };
             let proxy_data =
                 if let Some(crate::options::ProxyMode::Bungeecord) = worker.options().proxy_mode {
                     Some(proxy::do_bungee_ip_forwarding(&handshake)?)
                 } else {
                     None
                 };
-            handle_login(worker, proxy_data).await
+            handle_login(worker, proxy_data, protocol_version).await
         }
     }
 }
 
+/// Returns the protocol version of a client which sent
+/// `handshake_version` in its handshake, or `None` if the
+/// client is older than the server and can't log in.
+fn login_protocol_version(handshake_version: i32) -> Option<u32> {
+    if handshake_version < PROTOCOL_VERSION as i32 {
+        None
+    } else {
+        Some(handshake_version as u32)
+    }
+}
+
 #[derive(Debug, Serialize)]
 struct StatusResponse<'a> {
     version: Version,
@@ -97,7 +125,7 @@ struct StatusResponse<'a> {
 
 #[derive(Debug, Serialize)]
 struct Version {
-    name: &'static str,
+    name: String,
     protocol: i32,
 }
 
@@ -112,8 +140,8 @@ async fn handle_status(worker: &mut Worker) -> anyhow::Result<InitialHandling> {
 
     let payload = StatusResponse {
         version: Version {
-            name: SERVER_NAME,
-            protocol: PROTOCOL_VERSION,
+            name: format!("Feather {}", VERSION_NAME),
+            protocol: PROTOCOL_VERSION as i32,
         },
         players: Players {
             max: worker.options().max_players,
@@ -151,6 +179,7 @@ async fn handle_status(worker: &mut Worker) -> anyhow::Result<InitialHandling> {
 async fn handle_login(
     worker: &mut Worker,
     mut proxy_data: Option<ProxyData>,
+    protocol_version: u32,
 ) -> anyhow::Result<InitialHandling> {
     let login_start = match worker.read::<ClientLoginPacket>().await? {
         ClientLoginPacket::LoginStart(l) => l,
@@ -163,43 +192,23 @@ async fn handle_login(
         proxy_data = Some(proxy::do_velocity_ip_forwarding(worker).await?);
     }
 
-    if worker.options().online_mode {
-        enable_encryption(worker, login_start.name).await
+    let authenticator = Arc::clone(&worker.options().authenticator);
+    let server_hash = if authenticator.requires_encryption() {
+        log::debug!("Authenticating {}", login_start.name);
+        let shared_secret = do_encryption_handshake(worker).await?;
+        worker.enable_encryption(shared_secret);
+        compute_server_hash(shared_secret)
     } else {
-        let profile = match proxy_data {
-            Some(proxy_data) => AuthResponse {
-                id: proxy_data.uuid,
-                name: login_start.name.clone(),
-                properties: proxy_data.profile,
-            },
-            None => offline_mode_profile(login_start.name),
-        };
-        finish_login(worker, profile).await
-    }
-}
-
-fn offline_mode_profile(username: String) -> AuthResponse {
-    // TODO: correct offline mode handling
-    AuthResponse {
-        id: offline_mode_uuid(&username),
-        name: username,
-        properties: Vec::new(),
-    }
-}
-
-fn offline_mode_uuid(username: &str) -> Uuid {
-    // See: https://gist.github.com/games647/2b6a00a8fc21fd3b88375f03c9e2e603
-    let mut hasher = md5::Md5::default();
-    hasher.update(format!("OfflinePlayer:{}", username).as_bytes());
-    let hash = hasher.finalize();
-
-    let mut builder = uuid::Builder::from_bytes(hash.try_into().unwrap());
+        String::new()
+    };
 
-    builder
-        .set_variant(uuid::Variant::RFC4122)
-        .set_version(uuid::Version::Md5);
+    let username = login_start.name;
+    let profile = tokio::task::spawn_blocking(move || {
+        authenticator.authenticate(&username, &server_hash, proxy_data.as_ref())
+    })
+    .await??;
 
-    builder.build()
+    finish_login(worker, profile, protocol_version).await
 }
 
 const RSA_BITS: usize = 1024;
@@ -211,19 +220,6 @@ static RSA_KEY_ENCODED: Lazy<Vec<u8>> = Lazy::new(|| {
     rsa_der::public_key_to_der(&RSA_KEY.n().to_bytes_be(), &RSA_KEY.e().to_bytes_be())
 });
 
-async fn enable_encryption(
-    worker: &mut Worker,
-    username: String,
-) -> anyhow::Result<InitialHandling> {
-    log::debug!("Authenticating {}", username);
-    let shared_secret = do_encryption_handshake(worker).await?;
-    worker.enable_encryption(shared_secret);
-
-    let response = authenticate(shared_secret, username).await?;
-
-    finish_login(worker, response).await
-}
-
 async fn do_encryption_handshake(worker: &mut Worker) -> anyhow::Result<CryptKey> {
     let verify_token: [u8; 16] = rand::random();
     let request = EncryptionRequest {
@@ -252,30 +248,6 @@ async fn do_encryption_handshake(worker: &mut Worker) -> anyhow::Result<CryptKey
     Ok((&shared_secret[..]).try_into()?)
 }
 
-#[derive(Debug, Deserialize)]
-struct AuthResponse {
-    id: Uuid,
-    name: String,
-    properties: Vec<ProfileProperty>,
-}
-
-async fn authenticate(shared_secret: CryptKey, username: String) -> anyhow::Result<AuthResponse> {
-    let server_hash = compute_server_hash(shared_secret);
-
-    let response: AuthResponse = tokio::task::spawn_blocking(move || {
-        let url = format!(
-            "https://sessionserver.mojang.com/session/minecraft/hasJoined?username={}&serverId={}",
-            username, server_hash
-        );
-        let response = ureq::get(&url).call()?;
-
-        Result::<AuthResponse, anyhow::Error>::Ok(response.into_json()?)
-    })
-    .await??;
-
-    Ok(response)
-}
-
 fn compute_server_hash(shared_secret: CryptKey) -> String {
     let mut hasher = Sha1::new();
     hasher.update(b""); // server ID - always empty
@@ -292,12 +264,30 @@ fn hexdigest(bytes: &[u8]) -> String {
 
 async fn finish_login(
     worker: &mut Worker,
-    response: AuthResponse,
+    response: GameProfile,
+    protocol_version: u32,
 ) -> anyhow::Result<InitialHandling> {
+    if !worker.readiness().is_ready() {
+        log::debug!(
+            "Holding login of {} until the server is ready",
+            response.name
+        );
+        let timeout = worker.options().startup_timeout;
+        if !worker.readiness().wait(timeout).await {
+            worker
+                .write(ServerLoginPacket::DisconnectLogin(DisconnectLogin {
+                    reason: Text::from("Server is still starting. Please try again later.")
+                        .to_string(),
+                }))
+                .await?;
+            return Ok(InitialHandling::Disconnect);
+        }
+    }
+
     enable_compression(worker).await?;
 
     let success = LoginSuccess {
-        uuid: response.id,
+        uuid: response.uuid,
         username: response.name.clone(),
     };
     worker
@@ -306,16 +296,29 @@ async fn finish_login(
 
     let new_player = NewPlayer {
         username: response.name,
-        uuid: response.id,
+        uuid: response.uuid,
         profile: response.properties,
+        protocol_version,
+        keepalive_exempt: false,
         received_packets: worker.received_packets(),
         packets_to_send: worker.packets_to_send(),
+        worker_errors: worker.worker_errors(),
+        packet_trace: worker.packet_trace(),
+        bandwidth: worker.bandwidth(),
     };
     log::debug!("Completed initial handling for {}", new_player.username);
     Ok(InitialHandling::Join(new_player))
 }
 
 async fn enable_compression(worker: &mut Worker) -> anyhow::Result<()> {
+    if !worker.options().compress_local && is_loopback(worker.peer_addr().ip()) {
+        log::debug!(
+            "Not compressing local connection from {}",
+            worker.peer_addr()
+        );
+        return Ok(());
+    }
+
     if let Some(threshold) = worker.options().compression_threshold {
         let packet = ServerLoginPacket::SetCompression(SetCompression {
             threshold: threshold as i32,
@@ -325,3 +328,122 @@ async fn enable_compression(worker: &mut Worker) -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+/// Returns whether `ip` is a loopback address,
+/// including IPv4 loopback addresses mapped to IPv6.
+fn is_loopback(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback(),
+        IpAddr::V6(ip) => {
+            ip.is_loopback()
+                || matches!(ip.segments(), [0, 0, 0, 0, 0, 0xffff, _, _])
+                    && ip.to_ipv4().map_or(false, |ip| ip.is_loopback())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use protocol::codec::MinecraftCodec;
+    use tokio::{
+        io::AsyncReadExt,
+        net::{TcpListener, TcpStream},
+        time::timeout,
+    };
+
+    use super::*;
+    use crate::{client::tests::test_options, player_count::PlayerCount, readiness::Readiness};
+
+    /// Enables compression for a connection from `peer`, then sends
+    /// Login Success, returning the login packets the client decodes.
+    async fn login_packets_from(peer: SocketAddr, compress_local: bool) -> Vec<ServerLoginPacket> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        let mut options = test_options();
+        options.compression_threshold = Some(256);
+        options.compress_local = compress_local;
+        let (new_players, _) = flume::unbounded();
+        let mut worker = Worker::new(
+            stream,
+            peer,
+            Arc::new(options),
+            PlayerCount::new(1),
+            Readiness::default(),
+            new_players,
+        );
+        enable_compression(&mut worker).await.unwrap();
+        let success = LoginSuccess {
+            uuid: Uuid::nil(),
+            username: "test".to_owned(),
+        };
+        worker
+            .write(&ServerLoginPacket::LoginSuccess(success))
+            .await
+            .unwrap();
+        drop(worker);
+
+        let mut received = Vec::new();
+        timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+            .await
+            .unwrap()
+            .unwrap();
+        let mut codec = MinecraftCodec::new();
+        codec.accept(&received);
+        let mut packets = Vec::new();
+        while let Some(packet) = codec.next_packet::<ServerLoginPacket>().unwrap() {
+            if let ServerLoginPacket::SetCompression(set_compression) = &packet {
+                codec.enable_compression(set_compression.threshold as usize);
+            }
+            packets.push(packet);
+        }
+        packets
+    }
+
+    #[tokio::test]
+    async fn local_connections_are_compressed_only_if_configured() {
+        let remote: SocketAddr = "203.0.113.5:50000".parse().unwrap();
+        let local: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let mapped_local: SocketAddr = "[::ffff:127.0.0.1]:50000".parse().unwrap();
+
+        for &(peer, compress_local, compressed) in &[
+            (remote, false, true),
+            (local, false, false),
+            (mapped_local, false, false),
+            (local, true, true),
+        ] {
+            let packets = login_packets_from(peer, compress_local).await;
+            match packets.as_slice() {
+                [ServerLoginPacket::SetCompression(set_compression), ServerLoginPacket::LoginSuccess(_)] =>
+                {
+                    assert!(compressed, "compressed connection from {}", peer);
+                    assert_eq!(set_compression.threshold, 256);
+                }
+                [ServerLoginPacket::LoginSuccess(_)] => {
+                    assert!(!compressed, "uncompressed connection from {}", peer)
+                }
+                _ => panic!("unexpected packets {:?}", packets),
+            }
+        }
+    }
+
+    #[test]
+    fn handshake_protocol_version_is_kept_for_login() {
+        assert_eq!(
+            login_protocol_version(PROTOCOL_VERSION as i32),
+            Some(PROTOCOL_VERSION)
+        );
+        // Newer clients may still be able to join
+        assert_eq!(
+            login_protocol_version(PROTOCOL_VERSION as i32 + 1),
+            Some(PROTOCOL_VERSION + 1)
+        );
+        assert_eq!(login_protocol_version(PROTOCOL_VERSION as i32 - 1), None);
+        assert_eq!(login_protocol_version(-1), None);
+    }
+}
@@ -0,0 +1,161 @@
+use std::{fmt, net::IpAddr, str::FromStr};
+
+/// A range of IP addresses in CIDR notation, e.g. `192.168.0.0/16`.
+///
+/// IPv4 addresses mapped to IPv6 match IPv4 ranges.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    /// Creates a range from a network address and prefix length.
+    /// Returns `None` if the prefix length is too long for the address.
+    pub fn new(network: IpAddr, prefix_len: u8) -> Option<Self> {
+        let max_len = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max_len {
+            return None;
+        }
+        Some(Self {
+            network,
+            prefix_len,
+        })
+    }
+
+    /// Determines whether `ip` is in this range.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, unmap_ipv4(ip)) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => prefix_matches(
+                u32::from(network).into(),
+                u32::from(ip).into(),
+                32,
+                self.prefix_len,
+            ),
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                prefix_matches(network.into(), ip.into(), 128, self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = anyhow::Error;
+
+    /// Parses a CIDR range. A plain address is
+    /// parsed as a range containing only itself.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            anyhow::anyhow!(
+                "invalid IP range '{}': expected an address or CIDR range such as 10.0.0.0/8",
+                s
+            )
+        };
+        let (address, prefix_len) = match s.find('/') {
+            Some(index) => (&s[..index], Some(&s[index + 1..])),
+            None => (s, None),
+        };
+        let network = IpAddr::from_str(address).map_err(|_| invalid())?;
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse().map_err(|_| invalid())?,
+            None if network.is_ipv4() => 32,
+            None => 128,
+        };
+        Self::new(network, prefix_len).ok_or_else(invalid)
+    }
+}
+
+fn unmap_ipv4(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, _, _] => v6.to_ipv4().map_or(ip, IpAddr::V4),
+            _ => ip,
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+fn prefix_matches(network: u128, ip: u128, bits: u8, prefix_len: u8) -> bool {
+    if prefix_len == 0 {
+        return true;
+    }
+    let shift = bits - prefix_len;
+    network >> shift == ip >> shift
+}
+
+/// Determines whether connections from `ip` are accepted.
+///
+/// The denylist takes precedence over the allowlist.
+/// An empty allowlist allows all addresses.
+pub fn is_ip_allowed(ip: IpAddr, allowlist: &[IpRange], denylist: &[IpRange]) -> bool {
+    if denylist.iter().any(|range| range.contains(ip)) {
+        return false;
+    }
+    allowlist.is_empty() || allowlist.iter().any(|range| range.contains(ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(ranges: &[&str]) -> Vec<IpRange> {
+        ranges.iter().map(|range| range.parse().unwrap()).collect()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_ranges() {
+        assert_eq!(
+            "10.0.0.0/8".parse::<IpRange>().unwrap(),
+            IpRange::new(ip("10.0.0.0"), 8).unwrap()
+        );
+        assert_eq!(
+            "::1".parse::<IpRange>().unwrap(),
+            IpRange::new(ip("::1"), 128).unwrap()
+        );
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("10.0.0/8".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn ranges_match_ipv4_and_ipv6() {
+        let range: IpRange = "192.168.0.0/16".parse().unwrap();
+        assert!(range.contains(ip("192.168.4.20")));
+        assert!(range.contains(ip("::ffff:192.168.4.20")));
+        assert!(!range.contains(ip("192.169.0.1")));
+
+        let range: IpRange = "2001:db8::/32".parse().unwrap();
+        assert!(range.contains(ip("2001:db8:1234::1")));
+        assert!(!range.contains(ip("2001:db9::1")));
+        assert!(!range.contains(ip("192.168.4.20")));
+
+        let all: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains(ip("8.8.8.8")));
+    }
+
+    #[test]
+    fn denylist_takes_precedence() {
+        let allowlist = ranges(&["10.0.0.0/8"]);
+        let denylist = ranges(&["10.1.0.0/16"]);
+        assert!(is_ip_allowed(ip("10.2.0.1"), &allowlist, &denylist));
+        assert!(!is_ip_allowed(ip("10.1.0.1"), &allowlist, &denylist));
+        assert!(!is_ip_allowed(ip("172.16.0.1"), &allowlist, &denylist));
+
+        // An empty allowlist allows everything not denied
+        assert!(is_ip_allowed(ip("172.16.0.1"), &[], &denylist));
+        assert!(!is_ip_allowed(ip("10.1.0.1"), &[], &denylist));
+    }
+}
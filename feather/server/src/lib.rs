@@ -1,34 +1,79 @@
 #![allow(clippy::unnecessary_wraps)] // systems are required to return Results
 
-use std::{sync::Arc, time::Instant};
+use std::{
+    cell::{Cell, RefCell},
+    net::SocketAddr,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use base::Position;
+use base::{
+    ChunkPosition, Gamemode, ParticleKind, Position, SoundCategory, SoundId, Text,
+    ValidBlockPosition, Vec3d,
+};
 use chunk_subscriptions::ChunkSubscriptions;
-use common::Game;
-use ecs::SystemExecutor;
+use client::{JoinParameters, OutboundPacketFilter};
+use common::{
+    events::{ExperienceChangeEvent, HealthUpdateEvent, PlayerInteractEvent},
+    hunger::{Food, MAX_HEALTH},
+    velocity,
+    view::View,
+    Game, Velocity, Window,
+};
+use ecs::{Entity, SysResult, SystemExecutor};
+use entities::TablistDisplayName;
 use flume::Receiver;
 use initial_handler::NewPlayer;
 use listener::Listener;
+use packet_handlers::InteractionHandlers;
+use protocol::{packets::server::GameStateChange, ServerPlayPacket};
+use quill_common::{
+    components::{Health, PreviousGamemode},
+    entities::Player,
+};
+use uuid::Uuid;
 
+mod assets;
+pub mod auth;
 mod chunk_subscriptions;
 pub mod client;
+pub mod command_graph;
 pub mod config;
 mod connection_worker;
+pub mod dimension;
 mod entities;
+pub mod entity_status;
 pub mod favicon;
 mod initial_handler;
+mod ip_range;
 mod listener;
 mod network_id_registry;
 mod options;
 mod packet_handlers;
 mod player_count;
+mod properties;
+mod readiness;
 mod systems;
+mod tick_times;
 
-pub use client::{Client, ClientId, Clients};
-pub use network_id_registry::NetworkId;
-pub use options::Options;
+pub use auth::{Authenticator, GameProfile};
+pub use client::{Client, ClientId, Clients, PacketAction};
+pub use command_graph::CommandGraph;
+pub use connection_worker::BandwidthStats;
+pub use dimension::{DimensionType, Dimensions};
+pub use ip_range::IpRange;
+pub use network_id_registry::{NetworkId, NetworkIdAllocator};
+pub use options::{Options, SharedOptions};
 use player_count::PlayerCount;
-use systems::view::WaitingChunks;
+use readiness::Readiness;
+pub use systems::save::save_all;
+pub use systems::simulation::update_simulation_pause;
+use systems::{block::SectionChanges, view::WaitingChunks};
+use tick_times::TickTimes;
+
+/// Formats chat messages sent by players. See [`Server::set_chat_formatter`].
+type ChatFormatter = Box<dyn Fn(&Client, &str) -> Option<Text>>;
 
 /// A Minecraft server.
 ///
@@ -38,7 +83,8 @@ use systems::view::WaitingChunks;
 ///
 /// Uses asynchronous IO with Tokio.
 pub struct Server {
-    options: Arc<Options>,
+    options: SharedOptions,
+    local_addrs: Vec<SocketAddr>,
     clients: Clients,
     new_players: Receiver<NewPlayer>,
 
@@ -46,8 +92,22 @@ pub struct Server {
     chunk_subscriptions: ChunkSubscriptions,
 
     last_keepalive_time: Instant,
+    last_autosave_time: Instant,
+    tick_times: TickTimes,
+    skipped_broadcasts: Cell<u64>,
 
     player_count: PlayerCount,
+    readiness: Readiness,
+
+    command_graph: CommandGraph,
+    dimensions: Dimensions,
+    network_ids: Rc<RefCell<NetworkIdAllocator>>,
+
+    chat_formatter: Option<ChatFormatter>,
+    interaction_handlers: InteractionHandlers,
+    outbound_filter: Option<OutboundPacketFilter>,
+    /// Reason new logins are rejected with while draining.
+    drain_reason: Option<Text>,
 }
 
 impl Server {
@@ -55,40 +115,405 @@ impl Server {
     ///
     /// Must be called within the context of a Tokio runtime.
     pub async fn bind(options: Options) -> anyhow::Result<Self> {
-        let options = Arc::new(options);
-        let player_count = PlayerCount::new(options.max_players);
-
+        if options.bind_addresses.is_empty() {
+            anyhow::bail!("no addresses to listen on");
+        }
+        assets::validate()?;
+        let dimensions = Dimensions::load(&options.dimension_types_dir, &options.dimension_type)?;
+        let bind_addresses = options.bind_addresses.clone();
         let (new_players_tx, new_players) = flume::bounded(4);
-        Listener::start(Arc::clone(&options), player_count.clone(), new_players_tx).await?;
+        let mut server = Self::new(options, dimensions, new_players);
 
-        log::info!(
-            "Server is listening on {}:{}",
-            options.bind_address,
-            options.port
-        );
+        for address in bind_addresses {
+            let local_addr = Listener::start(
+                address,
+                server.options.clone(),
+                server.player_count.clone(),
+                server.readiness.clone(),
+                new_players_tx.clone(),
+            )
+            .await?;
+            log::info!("Server is listening on {}", local_addr);
+            server.local_addrs.push(local_addr);
+        }
 
-        Ok(Self {
-            options,
+        Ok(server)
+    }
+
+    /// Creates a server which does not listen for connections yet.
+    fn new(options: Options, dimensions: Dimensions, new_players: Receiver<NewPlayer>) -> Self {
+        let player_count = PlayerCount::new(options.max_players);
+        let chunk_subscriptions =
+            ChunkSubscriptions::with_unsubscribe_margin(options.chunk_unsubscribe_margin);
+        Self {
+            options: SharedOptions::new(options),
+            local_addrs: Vec::new(),
             clients: Clients::new(),
             new_players,
             waiting_chunks: WaitingChunks::default(),
-            chunk_subscriptions: ChunkSubscriptions::default(),
+            chunk_subscriptions,
             last_keepalive_time: Instant::now(),
+            last_autosave_time: Instant::now(),
+            tick_times: TickTimes::default(),
+            skipped_broadcasts: Cell::new(0),
             player_count,
-        })
+            readiness: Readiness::default(),
+            command_graph: CommandGraph::new(),
+            dimensions,
+            network_ids: Rc::new(RefCell::new(NetworkIdAllocator::new())),
+            chat_formatter: None,
+            interaction_handlers: InteractionHandlers::default(),
+            outbound_filter: None,
+            drain_reason: None,
+        }
     }
 
     /// Links this server with a `Game` so that players connecting
     /// to the server become part of this `Game`.
     pub fn link_with_game(self, game: &mut Game, systems: &mut SystemExecutor<Game>) {
+        let network_ids = Rc::clone(&self.network_ids);
         systems::register(self, game, systems);
-        game.add_entity_spawn_callback(entities::add_entity_components);
+        game.add_entity_spawn_callback(move |builder, init| {
+            entities::add_entity_components(builder, init, &mut network_ids.borrow_mut())
+        });
+    }
+
+    /// Returns the addresses the server is listening on.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// Returns whether the server has started and admits players.
+    pub fn is_ready(&self) -> bool {
+        self.readiness.is_ready()
+    }
+
+    /// Marks the server as started, admitting players
+    /// whose logins were held while it was starting.
+    ///
+    /// Called automatically on the first tick after
+    /// [`link_with_game`](Server::link_with_game).
+    pub fn mark_ready(&self) {
+        if !self.readiness.is_ready() {
+            log::info!("Server is ready");
+        }
+        self.readiness.mark_ready();
+    }
+
+    /// Returns a snapshot of the current server options.
+    pub fn options(&self) -> Arc<Options> {
+        self.options.load()
+    }
+
+    /// Replaces the server options without restarting.
+    ///
+    /// New logins observe the new MOTD, player limit, etc. immediately.
+    /// Lowering `max_players` below the current player count
+    /// does not kick anyone. Changes to the view distance apply to players
+    /// joining afterward, while connected clients pick up the other
+    /// options, such as the chunk send rate, as they use them.
+    /// The bind addresses cannot be changed while the server is running.
+    pub fn reload_options(&self, new: Options) {
+        let old = self.options.load();
+        if old.bind_addresses != new.bind_addresses {
+            log::warn!("Changing the bind addresses requires a restart");
+        }
+        self.player_count.set_max_players(new.max_players);
+        self.options.store(new);
+        log::info!("Reloaded server options");
+    }
+
+    /// Returns the command graph sent to players when they join.
+    pub fn command_graph(&self) -> &CommandGraph {
+        &self.command_graph
+    }
+
+    /// Returns the dimension types sent to players when they join.
+    pub fn dimensions(&self) -> &Dimensions {
+        &self.dimensions
+    }
+
+    /// Sets the command graph sent to players when they join.
+    ///
+    /// Already connected players are sent the new graph immediately.
+    pub fn set_command_graph(&mut self, graph: CommandGraph) {
+        self.broadcast_with(|client| client.send_command_graph(&graph));
+        self.command_graph = graph;
+    }
+
+    /// Enters or leaves drain mode. While draining, players finishing
+    /// login are disconnected with `reason`, but players already
+    /// connected are unaffected. Used for rolling restarts.
+    pub fn set_draining(&mut self, draining: bool, reason: Text) {
+        self.drain_reason = if draining { Some(reason) } else { None };
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.drain_reason.is_some()
+    }
+
+    /// Sets the function used to format chat messages sent by players.
+    ///
+    /// The formatter is given the sending client and the raw message.
+    /// Returning `None` cancels the message; returning `Some(text)`
+    /// broadcasts `text` instead. Without a formatter, messages
+    /// are broadcast as `<name> message`.
+    pub fn set_chat_formatter(
+        &mut self,
+        formatter: impl Fn(&Client, &str) -> Option<Text> + 'static,
+    ) {
+        self.chat_formatter = Some(Box::new(formatter));
+    }
+
+    /// Adds a function called before a player breaks or places a block
+    /// or interacts with an entity, which may cancel the interaction
+    /// by setting [`PlayerInteractEvent::cancelled`].
+    ///
+    /// Handlers are called in the order they were added, and
+    /// see the cancellations of earlier handlers.
+    pub fn add_interaction_handler(
+        &mut self,
+        handler: impl Fn(&Game, &mut PlayerInteractEvent) + 'static,
+    ) {
+        self.interaction_handlers.add(handler);
+    }
+
+    /// Sets a function called on every packet sent to a client,
+    /// just before it is handed to the client's connection worker.
+    ///
+    /// The filter may modify the packet. Returning [`PacketAction::Drop`]
+    /// drops the packet for that client only.
+    pub fn set_outbound_packet_filter(
+        &mut self,
+        filter: impl Fn(ClientId, &mut ServerPlayPacket) -> PacketAction + 'static,
+    ) {
+        let filter: OutboundPacketFilter = Rc::new(filter);
+        self.set_outbound_filter(Some(filter));
+    }
+
+    /// Removes the filter set with [`set_outbound_packet_filter`](Self::set_outbound_packet_filter).
+    pub fn clear_outbound_packet_filter(&mut self) {
+        self.set_outbound_filter(None);
+    }
+
+    fn set_outbound_filter(&mut self, filter: Option<OutboundPacketFilter>) {
+        for (id, client) in self.clients.iter_mut_with_ids() {
+            client.set_outbound_filter(id, filter.clone());
+        }
+        self.outbound_filter = filter;
+    }
+
+    /// Formats a chat message sent by `client` using the
+    /// chat formatter. Returns `None` if the message was cancelled.
+    pub fn format_chat_message(&self, client: &Client, message: &str) -> Option<Text> {
+        match &self.chat_formatter {
+            Some(formatter) => formatter(client, message),
+            None => Some(Text::translate_with(
+                "chat.type.text",
+                vec![client.username().to_owned(), message.to_owned()],
+            )),
+        }
+    }
+
+    /// Returns whether `position` lies within the spawn protection
+    /// radius, where only operators may break and place blocks.
+    pub fn is_spawn_protected(&self, position: ValidBlockPosition) -> bool {
+        // The world spawn is at the origin
+        let radius = self.options().spawn_protection as i32;
+        radius > 0 && position.x().abs() <= radius && position.z().abs() <= radius
+    }
+
+    /// Records how long a tick took to run,
+    /// for use in [`mspt`](Server::mspt).
+    pub fn record_tick_duration(&mut self, duration: Duration) {
+        let window = self.options().tick_time_window;
+        self.tick_times.record(duration, window);
+    }
+
+    /// Returns the average milliseconds per tick over
+    /// the last `tick_time_window` ticks.
+    pub fn mspt(&self) -> f32 {
+        self.tick_times.average_millis()
+    }
+
+    /// Returns the duration in milliseconds of the longest tick
+    /// among the last `tick_time_window` ticks.
+    pub fn max_mspt(&self) -> f32 {
+        self.tick_times.max_millis()
+    }
+
+    /// Teleports a player to `position`.
+    ///
+    /// Movement packets sent by the client are ignored until
+    /// it confirms the teleport. Nearby players see the move when entity
+    /// movement is next broadcast, and the player is moved between
+    /// chunks in the spatial index on the next tick.
+    pub fn teleport_player(
+        &self,
+        game: &mut Game,
+        player: Entity,
+        position: Position,
+    ) -> SysResult {
+        let client_id = *game.ecs.get::<ClientId>(player)?;
+        *game.ecs.get_mut::<Position>(player)? = position;
+        if let Some(client) = self.clients.get(client_id) {
+            client.update_own_position(position);
+        }
+        Ok(())
+    }
+
+    /// Rotates a player so that it looks at `target`, without moving it.
+    ///
+    /// Like a teleport, movement packets sent by the client are
+    /// ignored until it confirms the new rotation.
+    pub fn face_player_at(&self, game: &mut Game, id: ClientId, target: Position) -> SysResult {
+        let client = match self.clients.get(id) {
+            Some(client) => client,
+            None => anyhow::bail!("unknown client"),
+        };
+        let mut query = game.ecs.query::<(&ClientId, &mut Position)>();
+        let (_, (_, position)) = match query.iter().find(|(_, (client_id, _))| **client_id == id) {
+            Some(player) => player,
+            None => anyhow::bail!("client has no player"),
+        };
+        *position = entities::facing(*position, target);
+        client.update_own_position(*position);
+        Ok(())
+    }
+
+    /// Teleports a player by `offset` relative to its current position.
+    /// See [`teleport_player`](Server::teleport_player).
+    pub fn teleport_player_relative(
+        &self,
+        game: &mut Game,
+        player: Entity,
+        offset: Vec3d,
+    ) -> SysResult {
+        let position = *game.ecs.get::<Position>(player)? + offset;
+        self.teleport_player(game, player, position)
+    }
+
+    /// Makes a player's client start over as if it had just logged in,
+    /// without closing the connection.
+    ///
+    /// The client is sent Join Game, which discards its world and
+    /// entities, followed by its abilities and inventory. Its view
+    /// position, chunks and position are then sent again as on join,
+    /// and nearby entities are sent as they are for new players.
+    pub fn relogin_player(&mut self, game: &mut Game, player: Entity) -> SysResult {
+        let client = match self.clients.get(*game.ecs.get::<ClientId>(player)?) {
+            Some(client) => client,
+            None => anyhow::bail!("player has no client"),
+        };
+        log::debug!("Re-logging in {}", client.username());
+
+        client.reset_play_state();
+        client.send_join_game(join_parameters(game, player)?, &self.dimensions);
+        self.resend_play_state(game, player)
+    }
+
+    /// Respawns a dead player at the spawn point with full health
+    /// and food, as its client requests from the death screen.
+    /// Does nothing if the player is alive.
+    ///
+    /// The client is sent Respawn, which discards its world and
+    /// entities like Join Game, and then the rest of its state
+    /// as on [relogin](Server::relogin_player).
+    pub fn respawn_player(&mut self, game: &mut Game, player: Entity) -> SysResult {
+        if game.ecs.get::<Health>(player)?.0 > 0.0 {
+            return Ok(());
+        }
+        // Players spawn at the default position, as on join
+        let spawn = Position::default();
+        *game.ecs.get_mut::<Health>(player)? = Health(MAX_HEALTH);
+        *game.ecs.get_mut::<Food>(player)? = Food::default();
+        *game.ecs.get_mut::<Position>(player)? = spawn;
+        game.ecs.get_mut::<View>(player)?.set_center(spawn.chunk());
+
+        let client = match self.clients.get(*game.ecs.get::<ClientId>(player)?) {
+            Some(client) => client,
+            None => anyhow::bail!("player has no client"),
+        };
+        log::debug!("Respawning {}", client.username());
+
+        client.reset_play_state();
+        client.send_respawn(join_parameters(game, player)?, &self.dimensions);
+        self.resend_play_state(game, player)
+    }
+
+    /// Sends a client which was just sent Join Game or Respawn
+    /// its abilities and inventory, then its view position,
+    /// chunks and position as on join.
+    fn resend_play_state(&mut self, game: &mut Game, player: Entity) -> SysResult {
+        let client_id = *game.ecs.get::<ClientId>(player)?;
+        let view = *game.ecs.get::<View>(player)?;
+        let position = *game.ecs.get::<Position>(player)?;
+        let client = match self.clients.get(client_id) {
+            Some(client) => client,
+            None => anyhow::bail!("player has no client"),
+        };
+        let data = systems::save::player_data(game, player)?;
+        client.send_abilities(&data.abilities);
+        client.set_hotbar_slot(data.held_item as u8);
+        client.send_window_items(&*game.ecs.get::<Window>(player)?);
+
+        // Resubscribe from scratch so the client is subscribed
+        // to exactly the chunks it is sent below.
+        self.chunk_subscriptions.remove_client(client_id);
+        self.chunk_subscriptions
+            .update_view(client_id, View::empty(), view);
+        client.update_own_chunk(view.center());
+        for chunk in view.iter() {
+            match game.world.chunk_map().chunk_handle_at(chunk) {
+                Some(handle) => client.send_chunk(&handle),
+                None => self.waiting_chunks.insert(player, chunk),
+            }
+        }
+        // Otherwise spawned once the waiting chunks are loaded
+        systems::view::spawn_client_if_needed(client, position);
+
+        game.ecs.insert_entity_event(player, HealthUpdateEvent)?;
+        game.ecs
+            .insert_entity_event(player, ExperienceChangeEvent)?;
+        Ok(())
+    }
+
+    /// Sets the name shown for a player in the tab list.
+    /// `None` reverts to the player's username.
+    ///
+    /// The name is also shown to players who join later.
+    pub fn set_player_display_name(
+        &self,
+        game: &mut Game,
+        player: Entity,
+        display_name: Option<Text>,
+    ) -> SysResult {
+        let uuid = *game.ecs.get::<Uuid>(player)?;
+        self.broadcast_with(|client| {
+            client.change_player_tablist_display_name(uuid, display_name.as_ref())
+        });
+        match display_name {
+            Some(display_name) => game.ecs.insert(player, TablistDisplayName(display_name))?,
+            None => {
+                let _ = game.ecs.remove::<TablistDisplayName>(player);
+            }
+        }
+        Ok(())
     }
 
     /// Gets the number of online players.
     pub fn player_count(&self) -> u32 {
         self.player_count.get()
     }
+
+    /// Determines whether the world should be simulated this tick.
+    ///
+    /// Returns `false` if [`Options::pause_when_empty`] is set and
+    /// no players are online. Players count as online as soon as they
+    /// connect, so the simulation resumes before they join the game.
+    pub fn should_simulate(&self) -> bool {
+        !(self.options().pause_when_empty && self.player_count() == 0)
+    }
 }
 
 /// Low-level functions, mostly used internally.
@@ -97,11 +522,25 @@ impl Server {
     /// Polls for newly connected players. Returns the IDs of the new clients.
     pub fn accept_new_players(&mut self) -> Vec<ClientId> {
         let mut clients = Vec::new();
+        if !self.readiness.is_ready() {
+            return clients;
+        }
         for player in self.new_players.clone().try_iter() {
+            if let Some(reason) = &self.drain_reason {
+                log::info!(
+                    "Rejecting {} because the server is draining",
+                    player.username
+                );
+                Client::new(player, self.options.clone()).disconnect_with_text(reason);
+                continue;
+            }
+            let mut replaced_previous = false;
             if let Some(old_client) = self.clients.iter().find(|x| x.uuid() == player.uuid) {
+                old_client.mark_replaced();
                 old_client.disconnect("Logged in from another location!");
+                replaced_previous = true;
             }
-            let id = self.create_client(player);
+            let id = self.create_client(player, replaced_previous);
             clients.push(id);
         }
         clients
@@ -115,10 +554,21 @@ impl Server {
         }
     }
 
-    fn create_client(&mut self, player: NewPlayer) -> ClientId {
+    /// Returns the chunks a client currently receives updates for.
+    /// Empty if the client does not exist.
+    pub fn subscribed_chunks_for(&self, id: ClientId) -> Vec<ChunkPosition> {
+        self.chunk_subscriptions.chunks_for(id)
+    }
+
+    fn create_client(&mut self, player: NewPlayer, replaced_previous: bool) -> ClientId {
         log::debug!("Creating client for {}", player.username);
-        let client = Client::new(player, Arc::clone(&self.options));
-        self.clients.insert(client)
+        let mut client = Client::new(player, self.options.clone());
+        client.set_replaced_previous(replaced_previous);
+        let id = self.clients.insert(client);
+        if let Some(client) = self.clients.get_mut(id) {
+            client.set_outbound_filter(id, self.outbound_filter.clone());
+        }
+        id
     }
 
     /// Invokes a callback on all clients.
@@ -128,6 +578,82 @@ impl Server {
         }
     }
 
+    /// Like [`broadcast_nearby_with`](Server::broadcast_nearby_with),
+    /// but skips clients which are congested.
+    ///
+    /// Use this for frequent updates which clients can miss
+    /// without lasting effects, such as entity movement.
+    pub fn broadcast_nearby_nonessential_with(
+        &self,
+        position: Position,
+        mut callback: impl FnMut(&Client),
+    ) {
+        self.broadcast_nearby_with(position, |client| {
+            if !client.send_nonessential_with(&mut callback) {
+                self.skipped_broadcasts
+                    .set(self.skipped_broadcasts.get() + 1);
+            }
+        });
+    }
+
+    /// Returns the number of times a non-essential broadcast
+    /// skipped a congested client since the server started.
+    pub fn skipped_broadcasts(&self) -> u64 {
+        self.skipped_broadcasts.get()
+    }
+
+    /// Returns the clients which currently receive updates for
+    /// `position`, i.e. those subscribed to its chunk. These are
+    /// the clients reached by [`broadcast_nearby_with`](Self::broadcast_nearby_with).
+    ///
+    /// The returned list is a snapshot: comparing it between ticks
+    /// gives the clients which an entity at `position` should be spawned
+    /// for or destroyed for. There is a single world, so the position
+    /// alone identifies the chunk.
+    pub fn observers_of(&self, position: Position) -> Vec<ClientId> {
+        self.chunk_subscriptions
+            .subscriptions_for(position.chunk())
+            .iter()
+            .copied()
+            .filter(|&client_id| self.clients.get(client_id).is_some())
+            .collect()
+    }
+
+    /// Enables or disables trace logging of the packets
+    /// sent to and received from the given client.
+    pub fn set_packet_trace(&self, id: ClientId, enabled: bool) {
+        if let Some(client) = self.clients.get(id) {
+            client.set_packet_trace(enabled);
+        }
+    }
+
+    /// Sends the current blocks at `positions` to the clients with them
+    /// loaded, without sounds or particles. Use this for bulk edits
+    /// of blocks changed without a `BlockChangeEvent`.
+    ///
+    /// As for block change events, the changes are coalesced into one
+    /// packet per chunk section, or per chunk for sections with many
+    /// changed blocks, which each client receives once.
+    pub fn broadcast_soundless_block_update(
+        &self,
+        game: &Game,
+        positions: impl IntoIterator<Item = ValidBlockPosition>,
+    ) {
+        let mut changes = SectionChanges::new(self.options().section_overwrite_threshold);
+        changes.add_blocks(positions);
+        changes.broadcast(game, self);
+    }
+
+    /// Invokes `callback` for each of the given clients,
+    /// skipping clients that no longer exist.
+    pub fn broadcast_to_with(&self, ids: &[ClientId], mut callback: impl FnMut(&Client)) {
+        for &id in ids {
+            if let Some(client) = self.clients.get(id) {
+                callback(client);
+            }
+        }
+    }
+
     /// Sends a packet to all clients currently subscribed
     /// to the given position. This function should be
     /// used for entity updates, block updates, etc—
@@ -140,8 +666,674 @@ impl Server {
         }
     }
 
+    /// Like [`broadcast_nearby_with`](Self::broadcast_nearby_with), but also
+    /// reaches clients subscribed to the chunks adjacent to the chunk of
+    /// `position`, invoking `callback` once per client.
+    ///
+    /// Use this for entities on or near a chunk border, which clients
+    /// watching only the neighboring chunk can still see.
+    pub fn broadcast_nearby_including_adjacent_with(
+        &self,
+        position: Position,
+        mut callback: impl FnMut(&Client),
+    ) {
+        for client_id in self
+            .chunk_subscriptions
+            .subscriptions_around(position.chunk())
+        {
+            if let Some(client) = self.clients.get(client_id) {
+                callback(client);
+            }
+        }
+    }
+
+    /// Invokes `callback` for each client subscribed to the chunk of
+    /// `position`, like [`broadcast_nearby_with`](Self::broadcast_nearby_with),
+    /// and returns one result per invoked client.
+    ///
+    /// Removed clients still listed as subscribers are skipped and yield
+    /// no result. No allocation happens if no client is invoked.
+    pub fn map_nearby_mut<T>(
+        &mut self,
+        position: Position,
+        callback: impl FnMut(&mut Client) -> T,
+    ) -> Vec<T> {
+        let subscribers = self.chunk_subscriptions.subscriptions_for(position.chunk());
+        self.clients.map_mut(subscribers, callback)
+    }
+
+    /// Like [`broadcast_nearby_with`](Self::broadcast_nearby_with),
+    /// but only for clients controlling an entity with a `Player` component.
+    pub fn broadcast_nearby_players_with(
+        &self,
+        game: &Game,
+        position: Position,
+        mut callback: impl FnMut(&Client),
+    ) {
+        let observers = self.chunk_subscriptions.subscriptions_for(position.chunk());
+        for (_, (client_id, _)) in game.ecs.query::<(&ClientId, &Player)>().iter() {
+            if !observers.contains(client_id) {
+                continue;
+            }
+            if let Some(client) = self.clients.get(*client_id) {
+                callback(client);
+            }
+        }
+    }
+
+    /// Invokes `callback` for the clients subscribed to the chunk of
+    /// `center` whose position is within `max_distance` blocks of it,
+    /// including clients exactly `max_distance` away.
+    ///
+    /// Clients that have not reported a position yet are skipped.
+    pub fn broadcast_within_distance_with(
+        &self,
+        center: Position,
+        max_distance: f64,
+        mut callback: impl FnMut(&Client),
+    ) {
+        let max_distance_squared = max_distance * max_distance;
+        self.broadcast_nearby_with(center, |client| {
+            let in_range = client.client_known_position().map_or(false, |position| {
+                let (dx, dy, dz) = (
+                    position.x - center.x,
+                    position.y - center.y,
+                    position.z - center.z,
+                );
+                dx * dx + dy * dy + dz * dz <= max_distance_squared
+            });
+            if in_range {
+                callback(client);
+            }
+        });
+    }
+
+    /// Plays a sound at `position` for all nearby players.
+    ///
+    /// `volume` is clamped to be non-negative and `pitch`
+    /// to the range `0.5..=2.0` accepted by the client.
+    pub fn broadcast_sound_nearby(
+        &self,
+        position: Position,
+        sound: impl Into<SoundId>,
+        category: SoundCategory,
+        volume: f32,
+        pitch: f32,
+    ) {
+        let sound = sound.into();
+        let volume = if volume.is_nan() {
+            1.0
+        } else {
+            volume.max(0.0)
+        };
+        let pitch = if pitch.is_nan() {
+            1.0
+        } else {
+            pitch.clamp(0.5, 2.0)
+        };
+        self.broadcast_nearby_with(position, |client| {
+            client.play_sound(&sound, category, position, volume, pitch)
+        });
+    }
+
+    /// Displays particles at `position` for all nearby players.
+    ///
+    /// `offset` is the random spread of the particles along each axis
+    /// and `speed` their speed (the meaning depends on the particle).
+    /// Data specific to the particle kind, such as the block state
+    /// of block particles, is taken from `particle`.
+    /// Particles are only sent to players within view distance,
+    /// regardless of `long_distance`.
+    pub fn broadcast_particle_nearby(
+        &self,
+        position: Position,
+        particle: ParticleKind,
+        count: i32,
+        offset: (f32, f32, f32),
+        speed: f32,
+        long_distance: bool,
+    ) {
+        self.broadcast_nearby_with(position, |client| {
+            client.send_particle_effect(particle, position, count, offset, speed, long_distance)
+        });
+    }
+
+    /// Sends a change of the game state to all players, e.g.
+    /// [`BeginRaining`](GameStateChange::BeginRaining) to start rain.
+    ///
+    /// Use [`GameStateChange::from_reason`] to build a change
+    /// from a raw reason code and value.
+    pub fn broadcast_game_state(&self, state_change: GameStateChange) {
+        self.broadcast_with(|client| client.send_game_state_change(state_change.clone()));
+    }
+
+    /// Sends an entity status to all nearby players, e.g.
+    /// [`LIVING_DEATH`](entity_status::LIVING_DEATH) to play
+    /// an entity's death animation. See [`entity_status`] for common statuses.
+    pub fn broadcast_entity_status(&self, position: Position, network_id: NetworkId, status: u8) {
+        self.broadcast_nearby_with(position, |client| {
+            client.send_entity_status(network_id, status)
+        });
+    }
+
+    /// Sends an explosion of the given `radius` at `center` to all
+    /// nearby players, which plays its sound and particles and removes
+    /// `blocks` on the clients. Players are pushed away from the center
+    /// according to their distance to it.
+    ///
+    /// The blocks are not removed from the world.
+    pub fn broadcast_explosion_nearby(
+        &self,
+        game: &Game,
+        center: Position,
+        radius: f32,
+        blocks: &[ValidBlockPosition],
+    ) {
+        let observers = self.observers_of(center);
+        for (_, (client_id, &position)) in game.ecs.query::<(&ClientId, &Position)>().iter() {
+            if !observers.contains(client_id) {
+                continue;
+            }
+            if let Some(client) = self.clients.get(*client_id) {
+                let motion = velocity::explosion_knockback(center, radius.into(), position);
+                client.send_explosion(center, radius, blocks, motion);
+            }
+        }
+    }
+
+    /// Sends the velocity of an entity to all nearby players.
+    pub fn broadcast_velocity(
+        &self,
+        position: Position,
+        network_id: NetworkId,
+        velocity: Velocity,
+    ) {
+        self.broadcast_nearby_with(position, |client| {
+            client.send_entity_velocity(network_id, velocity)
+        });
+    }
+
+    /// Plays the animation of an item, arrow or experience orb
+    /// being collected to all players near `position`.
+    ///
+    /// `count` is the number of items picked up from the stack.
+    /// Must be sent before the collected entity is unloaded.
+    pub fn broadcast_collect_item(
+        &self,
+        position: Position,
+        collected_network_id: NetworkId,
+        collector_network_id: NetworkId,
+        count: u32,
+    ) {
+        self.broadcast_nearby_with(position, |client| {
+            client.send_collect_item(collected_network_id, collector_network_id, count)
+        });
+    }
+
+    /// Plays a block action, such as a chest lid opening
+    /// or a note block note, for all players near `position`.
+    ///
+    /// The meaning of `action_id` and `action_param` depends on
+    /// the block; `block_type` is the block's ID in the block registry.
+    pub fn broadcast_block_action_nearby(
+        &self,
+        position: ValidBlockPosition,
+        action_id: u8,
+        action_param: u8,
+        block_type: i32,
+    ) {
+        self.broadcast_nearby_with(position.position(), |client| {
+            client.send_block_action(position, action_id, action_param, block_type)
+        });
+    }
+
+    /// Sends the passengers of a vehicle at `position` to all nearby players.
+    pub fn broadcast_set_passengers(
+        &self,
+        position: Position,
+        vehicle_network_id: NetworkId,
+        passenger_network_ids: &[NetworkId],
+    ) {
+        self.broadcast_nearby_with(position, |client| {
+            client.send_set_passengers(vehicle_network_id, passenger_network_ids)
+        });
+    }
+
     pub fn broadcast_keepalive(&mut self) {
         self.broadcast_with(|client| client.send_keepalive());
         self.last_keepalive_time = Instant::now();
     }
 }
+
+/// The parameters of the Join Game and Respawn packets sent to a player.
+fn join_parameters(game: &Game, player: Entity) -> anyhow::Result<JoinParameters> {
+    Ok(JoinParameters {
+        previous_gamemode: *game.ecs.get::<PreviousGamemode>(player)?,
+        world_names: game.worlds().map(|world| world.name().to_owned()).collect(),
+        is_flat: game.world.is_flat(),
+        ..JoinParameters::new(*game.ecs.get::<Gamemode>(player)?, game.world.name())
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::convert::TryFrom;
+
+    use base::{position, BlockId, BlockPosition, BlockState, Chunk};
+    use common::World;
+    use flume::Sender;
+    use protocol::{
+        packets::server::{CommandNodeKind, DeclareCommands},
+        ProtocolVersion, Readable, Writeable,
+    };
+    use worldgen::VoidWorldGenerator;
+
+    use super::*;
+    use crate::client::tests::{
+        test_client_with_packets, test_new_player, test_options, TestConnection,
+    };
+
+    /// A server which does not listen for connections,
+    /// and the sender of the players joining it.
+    pub(crate) fn test_server() -> (Server, Sender<NewPlayer>) {
+        let (new_players_tx, new_players) = flume::bounded(4);
+        let server = Server::new(test_options(), Dimensions::vanilla(), new_players);
+        (server, new_players_tx)
+    }
+
+    /// A game kept in memory which spawns entities for `server`,
+    /// as after [`Server::link_with_game`] but without any systems.
+    pub(crate) fn test_game(server: &Server) -> Game {
+        let mut game = Game::new();
+        game.world = World::in_memory(Arc::new(VoidWorldGenerator));
+        common::interactable::register(&mut game);
+        let network_ids = Rc::clone(&server.network_ids);
+        game.add_entity_spawn_callback(move |builder, init| {
+            entities::add_entity_components(builder, init, &mut network_ids.borrow_mut())
+        });
+        game
+    }
+
+    /// A player who joined a test server.
+    pub(crate) struct TestPlayer {
+        pub(crate) entity: Entity,
+        pub(crate) client_id: ClientId,
+        pub(crate) connection: TestConnection,
+    }
+
+    /// Joins a new player through the join system.
+    pub(crate) fn join_test_player(
+        game: &mut Game,
+        server: &mut Server,
+        new_players: &Sender<NewPlayer>,
+    ) -> TestPlayer {
+        let (player, connection) = test_new_player();
+        join_new_test_player(game, server, new_players, player, connection)
+    }
+
+    /// Like [`join_test_player`], but the player joins
+    /// as an operator of the given level.
+    pub(crate) fn join_test_op(
+        game: &mut Game,
+        server: &mut Server,
+        new_players: &Sender<NewPlayer>,
+        level: u8,
+    ) -> TestPlayer {
+        let (player, connection) = test_new_player();
+        let mut options = (*server.options()).clone();
+        options.ops.insert(player.uuid, level);
+        server.reload_options(options);
+        join_new_test_player(game, server, new_players, player, connection)
+    }
+
+    fn join_new_test_player(
+        game: &mut Game,
+        server: &mut Server,
+        new_players: &Sender<NewPlayer>,
+        player: NewPlayer,
+        connection: TestConnection,
+    ) -> TestPlayer {
+        let uuid = player.uuid;
+        new_players.send(player).unwrap();
+        server.mark_ready();
+        systems::player_join::poll_new_players(game, server).unwrap();
+
+        let entity = game
+            .ecs
+            .query::<&Uuid>()
+            .iter()
+            .find(|(_, player_uuid)| **player_uuid == uuid)
+            .map(|(entity, _)| entity)
+            .expect("player joined");
+        let client_id = *game.ecs.get::<ClientId>(entity).unwrap();
+        TestPlayer {
+            entity,
+            client_id,
+            connection,
+        }
+    }
+
+    /// Adds a test client subscribed to the chunks in `view`,
+    /// returning its ID and the packets sent to it.
+    pub(crate) fn add_watching_client(
+        server: &mut Server,
+        view: View,
+    ) -> (ClientId, Receiver<ServerPlayPacket>) {
+        let (client, packets) = test_client_with_packets();
+        let id = server.clients.insert(client);
+        server
+            .chunk_subscriptions
+            .update_view(id, View::empty(), view);
+        (id, packets)
+    }
+
+    #[test]
+    fn relogin_resends_position() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let player = join_test_player(&mut game, &mut server, &new_players);
+        let view = *game.ecs.get::<View>(player.entity).unwrap();
+        for chunk in view.iter() {
+            game.world.chunk_map_mut().insert_chunk(Chunk::new(chunk));
+        }
+        player.connection.sent_packets.try_iter().for_each(drop);
+
+        server.relogin_player(&mut game, player.entity).unwrap();
+        let packets: Vec<_> = player.connection.sent_packets.try_iter().collect();
+        let index_of = |matches: fn(&ServerPlayPacket) -> bool| {
+            packets
+                .iter()
+                .position(matches)
+                .unwrap_or_else(|| panic!("missing packet in {:?}", packets))
+        };
+        let join_game = index_of(|packet| matches!(packet, ServerPlayPacket::JoinGame(_)));
+        let view_position =
+            index_of(|packet| matches!(packet, ServerPlayPacket::UpdateViewPosition(_)));
+        let position =
+            index_of(|packet| matches!(packet, ServerPlayPacket::PlayerPositionAndLook(_)));
+        assert!(join_game < view_position && view_position < position);
+
+        let client = server.clients.get(player.client_id).unwrap();
+        assert!(client.knows_own_position());
+        assert_eq!(
+            server.subscribed_chunks_for(player.client_id).len(),
+            view.iter().count()
+        );
+    }
+
+    #[test]
+    fn draining_rejects_only_new_logins() {
+        let (mut server, new_players) = test_server();
+        server.mark_ready();
+        let (existing, existing_connection) = test_new_player();
+        new_players.send(existing).unwrap();
+        let existing_id = server.accept_new_players()[0];
+
+        let reason = Text::from("Restarting");
+        server.set_draining(true, reason.clone());
+        let (rejected, rejected_connection) = test_new_player();
+        new_players.send(rejected).unwrap();
+        assert!(server.accept_new_players().is_empty());
+        let sent: Vec<_> = rejected_connection.sent_packets.try_iter().collect();
+        assert!(
+            matches!(sent.as_slice(), [ServerPlayPacket::Disconnect(disconnect)]
+                if disconnect.reason == reason.to_string()),
+            "{:?}",
+            sent
+        );
+
+        assert_eq!(server.clients.iter().count(), 1);
+        assert!(!server.clients.get(existing_id).unwrap().is_disconnected());
+        assert!(existing_connection
+            .sent_packets
+            .try_iter()
+            .all(|packet| !matches!(packet, ServerPlayPacket::Disconnect(_))));
+
+        server.set_draining(false, reason);
+        let (admitted, _admitted_connection) = test_new_player();
+        new_players.send(admitted).unwrap();
+        assert_eq!(server.accept_new_players().len(), 1);
+        assert_eq!(server.clients.iter().count(), 2);
+    }
+
+    #[test]
+    fn reloaded_options_reach_connected_clients() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let player = join_test_player(&mut game, &mut server, &new_players);
+        let client = server.clients.get(player.client_id).unwrap();
+        assert!(player.connection.sent_packets.len() > 1);
+
+        let mut options = (*server.options()).clone();
+        options.congestion_threshold = 1;
+        server.reload_options(options.clone());
+        assert!(client.is_congested());
+
+        options.congestion_threshold = 0;
+        server.reload_options(options);
+        assert!(!client.is_congested());
+    }
+
+    #[test]
+    fn joining_players_receive_a_decodable_command_graph() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let mut graph = CommandGraph::with_root_commands(vec!["help"]);
+        let time = graph.add_literal(graph.root(), "time");
+        let ticks = graph.add_argument(
+            time,
+            "ticks",
+            "brigadier:integer",
+            vec![0x03, 0, 0, 0, 0, 0, 0, 0x5d, 0xc0],
+        );
+        graph.set_executable(ticks);
+        graph.set_suggestions_type(ticks, "minecraft:ask_server");
+        let alias = graph.add_literal(graph.root(), "t");
+        graph.set_redirect(alias, time);
+        server.set_command_graph(graph);
+
+        let player = join_test_player(&mut game, &mut server, &new_players);
+        let packet = player
+            .connection
+            .sent_packets
+            .try_iter()
+            .find(|packet| matches!(packet, ServerPlayPacket::DeclareCommands(_)))
+            .expect("command graph sent on join");
+        let mut buffer = Vec::new();
+        packet.write(&mut buffer, ProtocolVersion::V1_16_2).unwrap();
+        let mut cursor = std::io::Cursor::new(&buffer[..]);
+        let packet = ServerPlayPacket::read(&mut cursor, ProtocolVersion::V1_16_2).unwrap();
+        assert_eq!(cursor.position() as usize, buffer.len());
+
+        let DeclareCommands { nodes, root_index } = match packet {
+            ServerPlayPacket::DeclareCommands(packet) => packet,
+            packet => panic!("{:?}", packet),
+        };
+        assert_eq!(root_index, 0);
+        assert!(matches!(nodes[0].kind, CommandNodeKind::Root));
+        assert_eq!(nodes[0].children, vec![1, 2, 4]);
+        assert!(matches!(&nodes[1].kind, CommandNodeKind::Literal { name } if name == "help"));
+        assert!(nodes[1].executable);
+        assert_eq!(nodes[2].children, vec![3]);
+        assert!(!nodes[2].executable);
+        match &nodes[3].kind {
+            CommandNodeKind::Argument {
+                name,
+                parser,
+                properties,
+            } => {
+                assert_eq!(name, "ticks");
+                assert_eq!(parser, "brigadier:integer");
+                assert_eq!(properties.len(), 9);
+            }
+            kind => panic!("{:?}", kind),
+        }
+        assert!(nodes[3].executable);
+        assert_eq!(
+            nodes[3].suggestions_type.as_deref(),
+            Some("minecraft:ask_server")
+        );
+        assert_eq!(nodes[4].redirect_node, Some(2));
+        assert!(nodes[4].children.is_empty());
+    }
+
+    #[test]
+    fn particles_reach_nearby_players_with_their_block_state() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let player = join_test_player(&mut game, &mut server, &new_players);
+        let (_, distant_packets) =
+            add_watching_client(&mut server, View::new(ChunkPosition::new(100, 100), 2));
+        player.connection.sent_packets.try_iter().for_each(drop);
+
+        let position = *game.ecs.get::<Position>(player.entity).unwrap();
+        let stone = BlockState::from_id(1).unwrap();
+        server.broadcast_particle_nearby(
+            position,
+            ParticleKind::Block(stone),
+            8,
+            (0.5, 0.5, 0.5),
+            0.1,
+            true,
+        );
+
+        let sent: Vec<_> = player.connection.sent_packets.try_iter().collect();
+        let packet = match sent.as_slice() {
+            [packet @ ServerPlayPacket::Particle(_)] => packet,
+            _ => panic!("{:?}", sent),
+        };
+        let mut buffer = Vec::new();
+        packet.write(&mut buffer, ProtocolVersion::V1_16_2).unwrap();
+        let particle = match ServerPlayPacket::read(
+            &mut std::io::Cursor::new(&buffer[..]),
+            ProtocolVersion::V1_16_2,
+        )
+        .unwrap()
+        {
+            ServerPlayPacket::Particle(particle) => particle,
+            packet => panic!("{:?}", packet),
+        };
+        assert_eq!(particle.particle_kind, ParticleKind::Block(stone));
+        assert!(particle.long_distance);
+        assert_eq!(particle.particle_count, 8);
+        assert_eq!((particle.x, particle.z), (position.x, position.z));
+
+        assert!(distant_packets.try_iter().next().is_none());
+    }
+
+    #[test]
+    fn broadcast_within_distance_includes_the_boundary() {
+        let (mut server, _) = test_server();
+        let view = View::new(ChunkPosition::new(0, 0), 2);
+        let center = position!(8.0, 64.0, 8.0);
+        for &x in &[8.0, 11.0, 12.0, 12.5] {
+            let (id, _) = add_watching_client(&mut server, view);
+            server
+                .clients
+                .get(id)
+                .unwrap()
+                .set_client_known_position(position!(x, 64.0, 8.0));
+        }
+        // Not reported a position yet
+        add_watching_client(&mut server, view);
+
+        let mut reached = Vec::new();
+        server.broadcast_within_distance_with(center, 4.0, |client| {
+            reached.push(client.client_known_position().unwrap().x);
+        });
+        reached.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(reached, vec![8.0, 11.0, 12.0]);
+    }
+
+    /// Measures broadcasting a bulk edit to ten watching clients at
+    /// several values of `section_overwrite_threshold`, against sending
+    /// a `BlockChange` per block. The edit fills 27 sections, changes
+    /// a half, a quarter, an eighth and a sixteenth of four others,
+    /// and a few scattered blocks. Light updates are the same for
+    /// every threshold and not counted.
+    ///
+    /// Run with `cargo test -p feather-server soundless_block_update_volume
+    /// -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn soundless_block_update_volume() {
+        let (mut server, _) = test_server();
+        let mut game = test_game(&server);
+        let view = View::new(ChunkPosition::new(0, 0), 4);
+        for chunk in view.iter() {
+            game.world.chunk_map_mut().insert_chunk(Chunk::new(chunk));
+        }
+        let watchers: Vec<_> = (0..10)
+            .map(|_| add_watching_client(&mut server, view))
+            .collect();
+        let block = |x, y, z| ValidBlockPosition::try_from(BlockPosition::new(x, y, z)).unwrap();
+        let mut edit: Vec<_> = (0..48)
+            .flat_map(|x| (64..112).flat_map(move |y| (0..48).map(move |z| block(x, y, z))))
+            .map(|position| (position, BlockId::stone()))
+            .collect();
+        for (i, &density) in [2, 4, 8, 16].iter().enumerate() {
+            let min_x = -16 * (i as i32 + 1);
+            let section = (0..4096).filter(|index| index % density == 0).map(|index| {
+                let position = block(min_x + index % 16, index / 256, index / 16 % 16 - 16);
+                (position, BlockId::white_wool())
+            });
+            edit.extend(section);
+        }
+        edit.extend((0..20).map(|i| (block(-60 + i * 3, 10, -50), BlockId::glass())));
+        for &(position, new_block) in &edit {
+            game.world.set_block_at(position, new_block);
+        }
+        let positions: Vec<_> = edit.iter().map(|&(position, _)| position).collect();
+
+        let count_packets = || {
+            let (mut count, mut bytes) = (0, 0);
+            for (_, packets) in &watchers {
+                for packet in packets.try_iter() {
+                    if let ServerPlayPacket::UpdateLight(_) = packet {
+                        continue;
+                    }
+                    let mut buffer = Vec::new();
+                    packet.write(&mut buffer, ProtocolVersion::V1_16_2).unwrap();
+                    count += 1;
+                    bytes += buffer.len();
+                }
+            }
+            (count, bytes)
+        };
+
+        let start = Instant::now();
+        for &(position, new_block) in &edit {
+            for &(id, _) in &watchers {
+                server
+                    .clients
+                    .get(id)
+                    .unwrap()
+                    .send_block_change(position, new_block);
+            }
+        }
+        let elapsed = start.elapsed();
+        let (count, bytes) = count_packets();
+        println!(
+            "{} blocks, one BlockChange each: {} packets, {} bytes in {:?}",
+            positions.len(),
+            count,
+            bytes,
+            elapsed
+        );
+
+        for &threshold in &[256, 512, 1024, 2048, 4096] {
+            let mut options = (*server.options()).clone();
+            options.section_overwrite_threshold = threshold;
+            server.reload_options(options);
+
+            let start = Instant::now();
+            server.broadcast_soundless_block_update(&game, positions.iter().copied());
+            let elapsed = start.elapsed();
+            let (count, bytes) = count_packets();
+            println!(
+                "section_overwrite_threshold = {}: {} packets, {} bytes in {:?}",
+                threshold, count, bytes, elapsed
+            );
+        }
+    }
+}
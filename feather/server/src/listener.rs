@@ -1,43 +1,53 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{convert::TryFrom, io, net::SocketAddr};
 
 use anyhow::Context;
 use flume::Sender;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
 
 use crate::{
-    connection_worker::Worker, initial_handler::NewPlayer, options::Options,
-    player_count::PlayerCount,
+    connection_worker::Worker, initial_handler::NewPlayer, options::SharedOptions,
+    player_count::PlayerCount, readiness::Readiness,
 };
 
 /// Listens for and accepts incoming connections.
 pub struct Listener {
     listener: TcpListener,
-    options: Arc<Options>,
+    options: SharedOptions,
     player_count: PlayerCount,
+    readiness: Readiness,
     new_players: Sender<NewPlayer>,
 }
 
 impl Listener {
+    /// Starts listening on `address`. Returns the bound address.
     pub async fn start(
-        options: Arc<Options>,
+        address: SocketAddr,
+        options: SharedOptions,
         player_count: PlayerCount,
+        readiness: Readiness,
         new_players: Sender<NewPlayer>,
-    ) -> anyhow::Result<()> {
-        let listener = TcpListener::bind(format!("{}:{}", options.bind_address, options.port))
-            .await
-            .context("failed to bind to port - maybe a server is already running?")?;
+    ) -> anyhow::Result<SocketAddr> {
+        let buffer_size = options.load().connection_buffer_size;
+        let listener = bind(address, buffer_size).with_context(|| {
+            format!(
+                "failed to bind to {} - maybe a server is already running?",
+                address
+            )
+        })?;
+        let local_addr = listener.local_addr()?;
 
         let listener = Listener {
             listener,
             options,
             player_count,
+            readiness,
             new_players,
         };
         tokio::task::spawn(async move {
             listener.run().await;
         });
 
-        Ok(())
+        Ok(local_addr)
     }
 
     async fn run(mut self) {
@@ -49,13 +59,117 @@ impl Listener {
     }
 
     async fn accept(&mut self, stream: TcpStream, addr: SocketAddr) {
+        let options = self.options.load();
+        if !options.is_ip_allowed(addr.ip()) {
+            log::debug!("Dropping connection from blocked address {}", addr);
+            return;
+        }
+        if let Err(e) = configure_stream(&stream, options.tcp_nodelay) {
+            log::warn!("Failed to configure socket for {}: {}", addr, e);
+        }
+
         let worker = Worker::new(
             stream,
             addr,
-            Arc::clone(&self.options),
+            options,
             self.player_count.clone(),
+            self.readiness.clone(),
             self.new_players.clone(),
         );
         worker.start();
     }
 }
+
+/// Number of pending connections the operating system queues
+/// before the listener accepts them.
+const BACKLOG: u32 = 1024;
+
+/// Starts listening on `address`.
+///
+/// Connections accepted by the listener inherit its kernel
+/// socket buffers, which are raised to `buffer_size` here
+/// so that the larger receive window is advertised from the
+/// start of each connection.
+fn bind(address: SocketAddr, buffer_size: usize) -> io::Result<TcpListener> {
+    let socket = if address.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    #[cfg(unix)]
+    socket.set_reuseaddr(true)?;
+    if let Err(e) = raise_buffer_sizes(&socket, buffer_size) {
+        log::warn!("Failed to set socket buffer sizes for {}: {}", address, e);
+    }
+    socket.bind(address)?;
+    socket.listen(BACKLOG)
+}
+
+/// Raises the kernel send and receive buffers of `socket`
+/// to at least `buffer_size`. Buffers already larger, as with
+/// the operating system defaults for small sizes, are kept.
+fn raise_buffer_sizes(socket: &TcpSocket, buffer_size: usize) -> io::Result<()> {
+    let buffer_size = u32::try_from(buffer_size).unwrap_or(u32::MAX);
+    if socket.recv_buffer_size()? < buffer_size {
+        socket.set_recv_buffer_size(buffer_size)?;
+    }
+    if socket.send_buffer_size()? < buffer_size {
+        socket.set_send_buffer_size(buffer_size)?;
+    }
+    Ok(())
+}
+
+/// Applies socket options to an accepted connection.
+fn configure_stream(stream: &TcpStream, tcp_nodelay: bool) -> io::Result<()> {
+    stream.set_nodelay(tcp_nodelay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn connected_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = TcpStream::connect(address).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (client, server)
+    }
+
+    #[tokio::test]
+    async fn tcp_nodelay_is_applied() {
+        let (_client, server) = connected_pair().await;
+
+        configure_stream(&server, true).unwrap();
+        assert!(server.nodelay().unwrap());
+
+        configure_stream(&server, false).unwrap();
+        assert!(!server.nodelay().unwrap());
+    }
+
+    #[test]
+    fn socket_buffers_are_raised_but_never_lowered() {
+        let socket = TcpSocket::new_v4().unwrap();
+        let default_recv = socket.recv_buffer_size().unwrap();
+        let default_send = socket.send_buffer_size().unwrap();
+
+        raise_buffer_sizes(&socket, 1).unwrap();
+        assert_eq!(socket.recv_buffer_size().unwrap(), default_recv);
+        assert_eq!(socket.send_buffer_size().unwrap(), default_send);
+
+        let large = default_recv.max(default_send) as usize * 2;
+        raise_buffer_sizes(&socket, large).unwrap();
+        assert!(socket.recv_buffer_size().unwrap() > default_recv);
+        assert!(socket.send_buffer_size().unwrap() > default_send);
+    }
+
+    #[tokio::test]
+    async fn bound_listeners_accept_connections() {
+        let listener = bind("127.0.0.1:0".parse().unwrap(), 1 << 20).unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = TcpStream::connect(address).await.unwrap();
+        let (server, peer) = listener.accept().await.unwrap();
+        assert_eq!(peer, client.local_addr().unwrap());
+        configure_stream(&server, true).unwrap();
+    }
+}
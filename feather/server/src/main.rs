@@ -1,10 +1,18 @@
-use std::{cell::RefCell, rc::Rc, sync::Arc};
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
 use anyhow::Context;
 use base::anvil::level::SuperflatGeneratorOptions;
-use common::{Game, TickLoop, World};
+use common::{simulation::SimulatedChunks, Game, TickLoop, World};
 use ecs::SystemExecutor;
-use feather_server::{config::Config, Server};
+use feather_server::{config::Config, update_simulation_pause, Server};
 use plugin_host::PluginManager;
 use worldgen::{ComposableGenerator, SuperflatWorldGenerator, VoidWorldGenerator, WorldGenerator};
 
@@ -38,8 +46,14 @@ async fn main() -> anyhow::Result<()> {
 
 fn init_game(server: Server, config: &Config) -> anyhow::Result<Game> {
     let mut game = Game::new();
+    let options = server.options();
+    game.difficulty = options.difficulty;
     init_systems(&mut game, server);
-    init_world_source(&mut game, config);
+    game.insert_resource(options.mob_caps);
+    game.insert_resource(SimulatedChunks::new(options.simulation_distance));
+    init_world_source(&mut game, config, options.in_memory_world);
+    game.world
+        .set_player_data_format(options.player_data_format);
     init_plugin_manager(&mut game)?;
     Ok(game)
 }
@@ -58,7 +72,7 @@ fn init_systems(game: &mut Game, server: Server) {
     game.system_executor = Rc::new(RefCell::new(systems));
 }
 
-fn init_world_source(game: &mut Game, config: &Config) {
+fn init_world_source(game: &mut Game, config: &Config, in_memory: bool) {
     // Load chunks from the world save first,
     // and fall back to generating a superflat
     // world otherwise. This is a placeholder:
@@ -73,7 +87,13 @@ fn init_world_source(game: &mut Game, config: &Config) {
         "void" => Arc::new(VoidWorldGenerator),
         _ => Arc::new(ComposableGenerator::default_with_seed(seed)),
     };
-    game.world = World::with_gen_and_path(generator, config.world.name.clone());
+    game.world = if in_memory {
+        log::info!("Generating the world in memory");
+        World::in_memory(generator)
+    } else {
+        World::with_gen_and_path(generator, config.world.name.clone())
+    };
+    game.world.set_flat(config.world.generator == "flat");
 }
 
 fn init_plugin_manager(game: &mut Game) -> anyhow::Result<()> {
@@ -91,17 +111,44 @@ fn print_systems(systems: &SystemExecutor<Game>) {
 }
 
 fn run(game: Game) {
-    let tick_loop = create_tick_loop(game);
+    let shutdown = Arc::new(AtomicBool::new(false));
+    listen_for_shutdown(Arc::clone(&shutdown));
+
+    let tick_loop = create_tick_loop(game, shutdown);
     log::debug!("Launching the game loop");
     tick_loop.run();
 }
 
-fn create_tick_loop(mut game: Game) -> TickLoop {
+/// Requests a shutdown when the process receives Ctrl+C.
+fn listen_for_shutdown(shutdown: Arc<AtomicBool>) {
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            log::info!("Stopping the server");
+            shutdown.store(true, Ordering::SeqCst);
+        }
+    });
+}
+
+fn create_tick_loop(mut game: Game, shutdown: Arc<AtomicBool>) -> TickLoop {
     TickLoop::new(move || {
+        if shutdown.load(Ordering::SeqCst) {
+            // Always save on shutdown, regardless of the autosave interval
+            if let Err(e) = feather_server::save_all(&mut game) {
+                log::error!("Failed to save the game: {:?}", e);
+            }
+            return true;
+        }
+
+        let start = Instant::now();
+        update_simulation_pause(&mut game);
         let systems = Rc::clone(&game.system_executor);
         systems.borrow_mut().run(&mut game);
         game.tick_count += 1;
 
+        if let Ok(mut server) = game.resources.get_mut::<Server>() {
+            server.record_tick_duration(start.elapsed());
+        }
+
         false
     })
 }
@@ -1,17 +1,118 @@
-use std::sync::atomic::{AtomicI32, Ordering};
+use ahash::AHashSet;
 
 /// An entity's ID used by the protocol
 /// in `entity_id` fields.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct NetworkId(pub i32);
 
-impl NetworkId {
-    /// Creates a new, unique network ID.
-    pub(crate) fn new() -> Self {
-        static NEXT: AtomicI32 = AtomicI32::new(0);
-        // In theory, this can overflow if the server
-        // creates 4 billion entities. The hope is that
-        // old entities will have died out at that point.
-        Self(NEXT.fetch_add(1, Ordering::SeqCst))
+/// Allocates the network IDs of entities.
+///
+/// IDs are handed out in increasing order, starting over from zero
+/// once every non-negative `i32` has been used and skipping the IDs
+/// still held by live entities. This way an ID is only reused long
+/// after its entity was destroyed on clients, and an ID is never held
+/// by two live entities.
+#[derive(Debug)]
+pub struct NetworkIdAllocator {
+    next: i32,
+    max: i32,
+    /// The IDs held by live entities.
+    live: AHashSet<i32>,
+}
+
+impl Default for NetworkIdAllocator {
+    fn default() -> Self {
+        Self::with_max(i32::MAX)
+    }
+}
+
+impl NetworkIdAllocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an allocator handing out IDs up to `max`.
+    fn with_max(max: i32) -> Self {
+        Self {
+            next: 0,
+            max,
+            live: AHashSet::new(),
+        }
+    }
+
+    /// Allocates an ID for a new entity, or returns `None`
+    /// if all IDs are held by live entities.
+    pub fn allocate(&mut self) -> Option<NetworkId> {
+        if self.live.len() as i64 > self.max as i64 {
+            return None;
+        }
+        loop {
+            let id = self.next;
+            self.next = if id == self.max { 0 } else { id + 1 };
+            if self.live.insert(id) {
+                return Some(NetworkId(id));
+            }
+        }
+    }
+
+    /// Frees the ID of a removed entity for reuse.
+    pub fn free(&mut self, id: NetworkId) {
+        self.live.remove(&id.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exhausted_ids_reuse_freed_ids_only() {
+        let mut allocator = NetworkIdAllocator::with_max(3);
+        let mut live: AHashSet<NetworkId> = (0..4).map(|_| allocator.allocate().unwrap()).collect();
+        assert_eq!(live.len(), 4);
+        assert_eq!(allocator.allocate(), None);
+
+        allocator.free(NetworkId(2));
+        allocator.free(NetworkId(0));
+        live.remove(&NetworkId(2));
+        live.remove(&NetworkId(0));
+
+        // In increasing order from the start, skipping live IDs
+        for &expected in &[NetworkId(0), NetworkId(2)] {
+            let id = allocator.allocate().unwrap();
+            assert_eq!(id, expected);
+            assert!(live.insert(id), "{:?} is already in use", id);
+        }
+        assert_eq!(allocator.allocate(), None);
+    }
+
+    #[test]
+    fn wrapping_around_skips_live_ids() {
+        let mut allocator = NetworkIdAllocator::with_max(3);
+        let ids: Vec<_> = (0..4).map(|_| allocator.allocate().unwrap()).collect();
+        allocator.free(ids[1]);
+        allocator.free(ids[3]);
+        assert_eq!(allocator.allocate(), Some(NetworkId(1)));
+        assert_eq!(allocator.allocate(), Some(NetworkId(3)));
+        allocator.free(ids[0]);
+        assert_eq!(allocator.allocate(), Some(NetworkId(0)));
+    }
+
+    #[test]
+    fn freed_ids_are_not_retained() {
+        let mut allocator = NetworkIdAllocator::with_max(3);
+        for _ in 0..1000 {
+            let id = allocator.allocate().unwrap();
+            allocator.free(id);
+        }
+        assert!(allocator.live.is_empty());
+    }
+
+    #[test]
+    fn full_range_does_not_overflow() {
+        let mut allocator = NetworkIdAllocator::new();
+        allocator.next = i32::MAX;
+        assert_eq!(allocator.allocate(), Some(NetworkId(i32::MAX)));
+        assert_eq!(allocator.allocate(), Some(NetworkId(0)));
     }
 }
@@ -1,32 +1,135 @@
-use base::Gamemode;
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
-use crate::favicon::Favicon;
+use ahash::AHashMap;
+use base::{
+    anvil::player::PlayerDataFormat, Difficulty, EntityKind, Gamemode, Text, TextComponentBuilder,
+};
+use common::mob_spawning::MobCaps;
+use parking_lot::RwLock;
+use uuid::Uuid;
+
+use crate::{favicon::Favicon, ip_range, Authenticator, IpRange};
 
 /// Options for building a [`Server`](crate::Server).
 #[derive(Debug, Clone)]
 pub struct Options {
-    /// Port to listen on.
-    pub port: u16,
-    /// Addresses to bind to.
-    pub bind_address: String,
+    /// Addresses to listen on. Players connecting
+    /// to any of them join the same server.
+    pub bind_addresses: Vec<SocketAddr>,
+
+    /// Size in bytes of the buffer each connection is read into,
+    /// and the initial capacity of its write buffer.
+    ///
+    /// The kernel socket buffers are raised to this size
+    /// where the operating system defaults are smaller.
+    pub connection_buffer_size: usize,
+    /// Whether to set `TCP_NODELAY` on connections,
+    /// disabling Nagle's algorithm to reduce latency.
+    pub tcp_nodelay: bool,
+    /// Number of packets waiting to be sent to a client above which
+    /// non-essential broadcasts, such as entity movement, skip it.
+    /// 0 never skips.
+    pub congestion_threshold: usize,
 
     /// The server favicon.
     pub favicon: Option<Favicon>,
     /// The server MOTD.
     pub motd: String,
 
-    /// Whether the server should authenticate players.
-    pub online_mode: bool,
+    /// Interval between automatic saves of player and world data.
+    /// A zero interval disables autosaving.
+    pub autosave_interval: Duration,
+
+    /// Number of recent ticks averaged by [`Server::mspt`](crate::Server::mspt).
+    pub tick_time_window: usize,
+
+    /// How long to hold logins made while the server
+    /// is still starting before disconnecting them.
+    pub startup_timeout: Duration,
+
+    /// Verifies the identity of players logging in.
+    /// [`Config`](crate::config::Config) uses a
+    /// [`MojangAuthenticator`](crate::auth::MojangAuthenticator)
+    /// unless offline mode or proxy forwarding is enabled.
+    pub authenticator: Arc<dyn Authenticator>,
 
     /// The maximum view distance, which determines
     /// how far players can see.
     pub view_distance: u32,
+    /// Number of chunks beyond the view distance within which
+    /// chunks stay loaded on a client after it moves away.
+    pub chunk_unsubscribe_margin: u32,
+    /// The distance in chunks around players within which
+    /// entities are ticked. At most `view_distance`.
+    pub simulation_distance: u32,
+
+    /// Maximum number of chunks sent to each player per tick,
+    /// excluding the chunks immediately around the player.
+    /// 0 removes the limit.
+    pub max_chunk_sends_per_tick: usize,
+
+    /// Distances within which entities are sent to players.
+    pub entity_tracking: EntityTrackingRanges,
+
+    /// Number of blocks changed in a chunk section during a tick at
+    /// and above which the whole section is resent instead of the
+    /// changed blocks.
+    pub section_overwrite_threshold: usize,
+
+    /// Radius in blocks around the world spawn within which
+    /// only operators can break and place blocks. 0 disables
+    /// spawn protection.
+    pub spawn_protection: u32,
+
+    /// Time without activity after which players are kicked,
+    /// or `None` to never kick idle players.
+    /// See [`Client::mark_active`](crate::Client::mark_active).
+    pub idle_timeout: Option<Duration>,
+    /// Whether operators are never kicked for inactivity.
+    pub idle_timeout_exempts_ops: bool,
 
     /// Maximum number of players to allow on the server.
     pub max_players: u32,
 
     /// The default gamemode for new players.
     pub default_gamemode: Gamemode,
+    /// Whether players are put in the default gamemode on every
+    /// join, instead of keeping their saved gamemode.
+    pub force_gamemode: bool,
+
+    /// The difficulty of the game.
+    pub difficulty: Difficulty,
+    /// Maximum numbers of naturally spawned mobs around each player.
+    pub mob_caps: MobCaps,
+
+    /// Chat messages longer than this many characters are rejected.
+    /// At most the protocol limit of 256 characters.
+    pub max_chat_length: usize,
+
+    /// Message broadcast when a player joins the game, replacing
+    /// the vanilla message if set. `{player}` is replaced with
+    /// the player's username. An empty template disables the message.
+    pub join_message: Option<String>,
+    /// Message broadcast when a player leaves the game, replacing
+    /// the vanilla message if set. `{player}` is replaced with
+    /// the player's username. An empty template disables the message.
+    pub leave_message: Option<String>,
+    /// Whether to suppress the join and leave messages when a player
+    /// logs in from another location, replacing their previous session.
+    pub suppress_reconnect_messages: bool,
+
+    /// Whether to pause entity AI and mob spawning
+    /// while no players are online.
+    pub pause_when_empty: bool,
+
+    /// Operator permission levels (1 to 4) by player UUID.
+    /// Players not listed have level 0.
+    pub ops: AHashMap<Uuid, u8>,
 
     /// Proxy IP forwarding mode
     pub proxy_mode: Option<ProxyMode>,
@@ -35,6 +138,161 @@ pub struct Options {
 
     /// Packet size threshold at which to compress data
     pub compression_threshold: Option<usize>,
+    /// Whether to compress connections from localhost.
+    pub compress_local: bool,
+
+    /// If not empty, only connections from these ranges are accepted.
+    pub ip_allowlist: Vec<IpRange>,
+    /// Connections from these ranges are dropped,
+    /// even if they are in the allowlist.
+    pub ip_denylist: Vec<IpRange>,
+
+    /// The format player data is saved in.
+    pub player_data_format: PlayerDataFormat,
+    /// Whether the world is generated and kept in memory
+    /// instead of being loaded from and saved to disk.
+    pub in_memory_world: bool,
+    /// Directory containing custom dimension types,
+    /// as `<namespace>/<name>.json` files.
+    pub dimension_types_dir: PathBuf,
+    /// Name of the dimension type of the world.
+    pub dimension_type: String,
+}
+
+impl Options {
+    /// Determines whether connections from `ip` are accepted.
+    pub fn is_ip_allowed(&self, ip: IpAddr) -> bool {
+        ip_range::is_ip_allowed(ip, &self.ip_allowlist, &self.ip_denylist)
+    }
+
+    /// Returns the gamemode of a joining player
+    /// whose saved gamemode is `saved`, if any.
+    pub fn gamemode_on_join(&self, saved: Option<Gamemode>) -> Gamemode {
+        match saved {
+            Some(gamemode) if !self.force_gamemode => gamemode,
+            _ => self.default_gamemode,
+        }
+    }
+
+    /// Formats the join message for the given player,
+    /// or returns `None` if join messages are disabled.
+    pub fn join_message_for(&self, username: &str) -> Option<Text> {
+        format_player_message(
+            self.join_message.as_deref(),
+            "multiplayer.player.joined",
+            username,
+        )
+    }
+
+    /// Formats the leave message for the given player,
+    /// or returns `None` if leave messages are disabled.
+    pub fn leave_message_for(&self, username: &str) -> Option<Text> {
+        format_player_message(
+            self.leave_message.as_deref(),
+            "multiplayer.player.left",
+            username,
+        )
+    }
+}
+
+/// Distances in blocks within which each category
+/// of entity is sent to players.
+///
+/// Entities are additionally only sent to players
+/// who have the entity's chunk loaded, so ranges larger
+/// than the view distance have no effect.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityTrackingRanges {
+    pub players: f64,
+    pub animals: f64,
+    pub monsters: f64,
+    /// Dropped items, experience orbs, item frames and paintings.
+    pub misc: f64,
+    /// Entities in no other category, such as
+    /// projectiles, vehicles and armor stands.
+    pub other: f64,
+    /// Distance beyond which tracked entities get no movement
+    /// updates, to save bandwidth. `None` always sends them.
+    pub movement: Option<f64>,
+}
+
+impl Default for EntityTrackingRanges {
+    fn default() -> Self {
+        Self {
+            players: 48.,
+            animals: 48.,
+            monsters: 48.,
+            misc: 32.,
+            other: 64.,
+            movement: None,
+        }
+    }
+}
+
+impl EntityTrackingRanges {
+    /// Returns the tracking range for entities of the given kind.
+    pub fn range_for(&self, kind: EntityKind) -> f64 {
+        use EntityKind::*;
+        match kind {
+            Player => self.players,
+            Bat | Bee | Cat | Chicken | Cod | Cow | Dolphin | Donkey | Fox | Horse | IronGolem
+            | Llama | Mooshroom | Mule | Ocelot | Panda | Parrot | Pig | PolarBear | Pufferfish
+            | Rabbit | Salmon | Sheep | SkeletonHorse | SnowGolem | Squid | Strider
+            | TraderLlama | TropicalFish | Turtle | Villager | WanderingTrader | Wolf
+            | ZombieHorse => self.animals,
+            Blaze | CaveSpider | Creeper | Drowned | ElderGuardian | EnderDragon | Enderman
+            | Endermite | Evoker | Ghast | Giant | Guardian | Hoglin | Husk | Illusioner
+            | MagmaCube | Phantom | Piglin | PiglinBrute | Pillager | Ravager | Shulker
+            | Silverfish | Skeleton | Slime | Spider | Stray | Vex | Vindicator | Witch
+            | Wither | WitherSkeleton | Zoglin | Zombie | ZombieVillager | ZombifiedPiglin => {
+                self.monsters
+            }
+            Item | ExperienceOrb | ItemFrame | Painting => self.misc,
+            _ => self.other,
+        }
+    }
+}
+
+/// A handle to the current server [`Options`] which
+/// can be replaced at runtime.
+///
+/// Readers obtain an `Arc<Options>` snapshot via [`load`](Self::load),
+/// so a reload never changes options halfway through an operation.
+#[derive(Debug, Clone)]
+pub struct SharedOptions {
+    current: Arc<RwLock<Arc<Options>>>,
+}
+
+impl SharedOptions {
+    pub fn new(options: Options) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(options))),
+        }
+    }
+
+    /// Returns a snapshot of the current options.
+    pub fn load(&self) -> Arc<Options> {
+        Arc::clone(&self.current.read())
+    }
+
+    /// Atomically replaces the current options.
+    pub fn store(&self, options: Options) {
+        *self.current.write() = Arc::new(options);
+    }
+}
+
+/// Formats a join or leave message from `template`, or
+/// as the yellow vanilla message `translation` without one.
+fn format_player_message(
+    template: Option<&str>,
+    translation: &'static str,
+    username: &str,
+) -> Option<Text> {
+    match template {
+        Some("") => None,
+        Some(template) => Some(Text::from(template.replace("{player}", username))),
+        None => Some(Text::translate_with(translation, vec![username.to_owned()]).yellow()),
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -42,3 +300,20 @@ pub enum ProxyMode {
     Bungeecord,
     Velocity,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracking_ranges_are_per_category() {
+        let ranges = EntityTrackingRanges {
+            misc: 8.,
+            ..Default::default()
+        };
+        assert_eq!(ranges.range_for(EntityKind::Item), 8.);
+        assert_eq!(ranges.range_for(EntityKind::Player), 48.);
+        assert_eq!(ranges.range_for(EntityKind::Zombie), 48.);
+        assert_eq!(ranges.range_for(EntityKind::Arrow), 64.);
+    }
+}
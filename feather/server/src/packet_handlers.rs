@@ -1,9 +1,13 @@
 use base::{Position, Text};
-use common::{chat::ChatKind, Game};
+use common::{
+    chat::{ChatKind, ChatMessage},
+    Game,
+};
 use ecs::{Entity, EntityRef, SysResult};
+pub(crate) use interaction::InteractionHandlers;
 use interaction::{
     handle_held_item_change, handle_interact_entity, handle_player_block_placement,
-    handle_player_digging,
+    handle_player_digging, handle_use_item,
 };
 use protocol::{
     packets::{
@@ -12,16 +16,22 @@ use protocol::{
     },
     ClientPlayPacket,
 };
-use quill_common::components::Name;
 
-use crate::{NetworkId, Server};
+use crate::{ClientId, NetworkId, Server};
 
 mod entity_action;
 mod interaction;
 pub mod inventory;
 mod movement;
+mod vehicle;
 
 /// Handles a packet received from a client.
+///
+/// Until the client has been sent its own position after joining,
+/// only packets which don't act on the player's state in the world are
+/// handled, such as the `ClientSettings` sent right after Join Game.
+/// Others, like movement, are dropped: the client can't have acted on
+/// a world it hasn't been placed in yet. See [`is_handled_before_spawn`].
 pub fn handle_packet(
     game: &mut Game,
     server: &mut Server,
@@ -29,6 +39,24 @@ pub fn handle_packet(
     packet: ClientPlayPacket,
 ) -> SysResult {
     let player = game.ecs.entity(player_id)?;
+    if !is_handled_before_spawn(&packet) {
+        let client_id = *player.get::<ClientId>()?;
+        if let Some(client) = server.clients.get(client_id) {
+            if !client.knows_own_position() {
+                log::trace!(
+                    "Dropping packet {:#x} sent by {} before spawning",
+                    packet.id(),
+                    client.username()
+                );
+                return Ok(());
+            }
+        }
+    }
+    if is_activity(&packet, *player.get::<Position>()?) {
+        if let Some(client) = server.clients.get(*player.get::<ClientId>()?) {
+            client.mark_active();
+        }
+    }
     match packet {
         ClientPlayPacket::PlayerPosition(packet) => {
             movement::handle_player_position(server, player, packet)
@@ -45,7 +73,7 @@ pub fn handle_packet(
 
         ClientPlayPacket::Animation(packet) => handle_animation(server, player, packet),
 
-        ClientPlayPacket::ChatMessage(packet) => handle_chat_message(game, player, packet),
+        ClientPlayPacket::ChatMessage(packet) => handle_chat_message(game, server, player, packet),
 
         ClientPlayPacket::PlayerDigging(packet) => {
             handle_player_digging(game, server, packet, player_id)
@@ -57,12 +85,16 @@ pub fn handle_packet(
         ClientPlayPacket::ClickWindow(packet) => {
             inventory::handle_click_window(server, player, packet)
         }
+        ClientPlayPacket::WindowConfirmation(packet) => {
+            inventory::handle_window_confirmation(server, player, packet)
+        }
 
         ClientPlayPacket::PlayerBlockPlacement(packet) => {
             handle_player_block_placement(game, server, packet, player_id)
         }
 
         ClientPlayPacket::HeldItemChange(packet) => handle_held_item_change(player, packet),
+        ClientPlayPacket::UseItem(packet) => handle_use_item(game, server, packet, player_id),
         ClientPlayPacket::InteractEntity(packet) => {
             handle_interact_entity(game, server, packet, player_id)
         }
@@ -77,25 +109,40 @@ pub fn handle_packet(
             entity_action::handle_entity_action(game, player_id, packet)
         }
 
-        ClientPlayPacket::TeleportConfirm(_)
-        | ClientPlayPacket::QueryBlockNbt(_)
+        ClientPlayPacket::SteerVehicle(packet) => {
+            vehicle::handle_steer_vehicle(game, server, player_id, packet)
+        }
+        ClientPlayPacket::VehicleMove(packet) => {
+            vehicle::handle_vehicle_move(game, player_id, packet)
+        }
+
+        ClientPlayPacket::KeepAlive(packet) => handle_keepalive(server, player, packet),
+
+        ClientPlayPacket::TeleportConfirm(packet) => {
+            handle_teleport_confirm(server, player, packet)
+        }
+
+        ClientPlayPacket::CloseWindow(packet) => {
+            inventory::handle_close_window(server, player, packet)
+        }
+
+        ClientPlayPacket::ClientStatus(client::ClientStatus::PerformRespawn) => {
+            server.respawn_player(game, player_id)
+        }
+
+        ClientPlayPacket::QueryBlockNbt(_)
         | ClientPlayPacket::SetDifficulty(_)
-        | ClientPlayPacket::ClientStatus(_)
+        | ClientPlayPacket::ClientStatus(client::ClientStatus::RequestStats)
         | ClientPlayPacket::TabComplete(_)
-        | ClientPlayPacket::WindowConfirmation(_)
         | ClientPlayPacket::ClickWindowButton(_)
-        | ClientPlayPacket::CloseWindow(_)
         | ClientPlayPacket::PluginMessage(_)
         | ClientPlayPacket::EditBook(_)
         | ClientPlayPacket::QueryEntityNbt(_)
         | ClientPlayPacket::GenerateStructure(_)
-        | ClientPlayPacket::KeepAlive(_)
         | ClientPlayPacket::LockDifficulty(_)
-        | ClientPlayPacket::VehicleMove(_)
         | ClientPlayPacket::SteerBoat(_)
         | ClientPlayPacket::PickItem(_)
         | ClientPlayPacket::CraftRecipeRequest(_)
-        | ClientPlayPacket::SteerVehicle(_)
         | ClientPlayPacket::SetDisplayedRecipe(_)
         | ClientPlayPacket::SetRecipeBookState(_)
         | ClientPlayPacket::NameItem(_)
@@ -108,8 +155,57 @@ pub fn handle_packet(
         | ClientPlayPacket::UpdateJigsawBlock(_)
         | ClientPlayPacket::UpdateStructureBlock(_)
         | ClientPlayPacket::UpdateSign(_)
-        | ClientPlayPacket::Spectate(_)
-        | ClientPlayPacket::UseItem(_) => Ok(()),
+        | ClientPlayPacket::Spectate(_) => Ok(()),
+    }
+}
+
+/// Returns whether `packet` is handled even if the client has not been
+/// spawned yet, because it concerns the client itself rather than
+/// the player's state in the world.
+fn is_handled_before_spawn(packet: &ClientPlayPacket) -> bool {
+    matches!(
+        packet,
+        ClientPlayPacket::ClientSettings(_)
+            | ClientPlayPacket::PluginMessage(_)
+            | ClientPlayPacket::KeepAlive(_)
+            | ClientPlayPacket::TeleportConfirm(_)
+            | ClientPlayPacket::ChatMessage(_)
+            | ClientPlayPacket::TabComplete(_)
+            | ClientPlayPacket::HeldItemChange(_)
+            | ClientPlayPacket::ResourcePackStatus(_)
+            | ClientPlayPacket::SetRecipeBookState(_)
+    )
+}
+
+/// Returns whether `packet` shows that a player at `position` is not
+/// idle: moving or turning, chatting, or interacting with blocks,
+/// entities or windows. Movement packets which the client sends
+/// while standing still are not activity.
+///
+/// As in vanilla, any change of position counts, including movement
+/// the player did not cause, e.g. being carried by water or pushed by
+/// pistons, since the client reports both alike.
+fn is_activity(packet: &ClientPlayPacket, position: Position) -> bool {
+    let turned = |yaw: f32, pitch: f32| yaw != position.yaw || pitch != position.pitch;
+    let moved = |x: f64, y: f64, z: f64| x != position.x || y != position.y || z != position.z;
+    match packet {
+        ClientPlayPacket::PlayerPosition(packet) => moved(packet.x, packet.feet_y, packet.z),
+        ClientPlayPacket::PlayerPositionAndRotation(packet) => {
+            moved(packet.x, packet.feet_y, packet.z) || turned(packet.yaw, packet.pitch)
+        }
+        ClientPlayPacket::PlayerRotation(packet) => turned(packet.yaw, packet.pitch),
+        ClientPlayPacket::ChatMessage(_)
+        | ClientPlayPacket::Animation(_)
+        | ClientPlayPacket::PlayerDigging(_)
+        | ClientPlayPacket::PlayerBlockPlacement(_)
+        | ClientPlayPacket::UseItem(_)
+        | ClientPlayPacket::InteractEntity(_)
+        | ClientPlayPacket::HeldItemChange(_)
+        | ClientPlayPacket::ClickWindow(_)
+        | ClientPlayPacket::CreativeInventoryAction(_)
+        | ClientPlayPacket::EntityAction(_)
+        | ClientPlayPacket::SteerVehicle(_) => true,
+        _ => false,
     }
 }
 
@@ -126,16 +222,60 @@ fn handle_animation(
         Hand::Off => Animation::SwingOffhand,
     };
 
-    server.broadcast_nearby_with(pos, |client| {
+    server.broadcast_nearby_nonessential_with(pos, |client| {
         client.send_entity_animation(network_id, animation.clone())
     });
     Ok(())
 }
 
-fn handle_chat_message(game: &Game, player: EntityRef, packet: client::ChatMessage) -> SysResult {
-    let name = player.get::<Name>()?;
-    let message = Text::translate_with("chat.type.text", vec![name.to_string(), packet.message]);
-    game.broadcast_chat(ChatKind::PlayerChat, message);
+fn handle_keepalive(
+    server: &mut Server,
+    player: EntityRef,
+    packet: client::KeepAlive,
+) -> SysResult {
+    if let Some(client) = server.clients.get(*player.get::<ClientId>()?) {
+        client.handle_keepalive_response(packet.id);
+    }
+    Ok(())
+}
+
+fn handle_teleport_confirm(
+    server: &mut Server,
+    player: EntityRef,
+    packet: client::TeleportConfirm,
+) -> SysResult {
+    if let Some(client) = server.clients.get(*player.get::<ClientId>()?) {
+        client.confirm_teleport(packet.teleport_id);
+        client.mark_ready();
+    }
+    Ok(())
+}
+
+fn handle_chat_message(
+    game: &Game,
+    server: &Server,
+    player: EntityRef,
+    packet: client::ChatMessage,
+) -> SysResult {
+    let client_id = *player.get::<ClientId>()?;
+    let client = match server.clients.get(client_id) {
+        Some(client) => client,
+        None => return Ok(()),
+    };
+    let max_length = server.options().max_chat_length;
+    if packet.message.chars().count() > max_length {
+        client.send_chat_message(ChatMessage::new(
+            ChatKind::System,
+            Text::from(format!(
+                "Your message is too long (at most {} characters)",
+                max_length
+            )),
+        ));
+        return Ok(());
+    }
+    if let Some(message) = server.format_chat_message(client, &packet.message) {
+        game.broadcast_chat(ChatKind::PlayerChat, message);
+    }
     Ok(())
 }
 
@@ -150,3 +290,259 @@ fn handle_client_settings(
     });
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use common::hunger::Food;
+    use protocol::{
+        io::ChatString,
+        packets::client::{
+            ChatMessage, ChatMode, ClientSettings, ClientStatus, PlayerPosition, PlayerRotation,
+            TeleportConfirm,
+        },
+        ServerPlayPacket,
+    };
+    use quill_common::components::Health;
+
+    use super::*;
+    use crate::systems::player_join::tests::received_chat;
+    use crate::tests::{join_test_player, test_game, test_server};
+
+    fn chat(message: &str) -> ClientPlayPacket {
+        ClientPlayPacket::ChatMessage(ChatMessage {
+            message: ChatString(message.to_owned()),
+        })
+    }
+
+    #[test]
+    fn movement_before_spawn_is_dropped() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let player = join_test_player(&mut game, &mut server, &new_players);
+        let network_id = *game.ecs.get::<NetworkId>(player.entity).unwrap();
+        let client = server.clients.get(player.client_id).unwrap();
+        assert!(!client.knows_own_position());
+        // Loaded, but still waiting for its position
+        client.mark_ready();
+        let position = *game.ecs.get::<Position>(player.entity).unwrap();
+        player.connection.sent_packets.try_iter().for_each(drop);
+
+        let movement = ClientPlayPacket::PlayerPosition(PlayerPosition {
+            x: position.x + 100.0,
+            feet_y: 64.0,
+            z: position.z,
+            on_ground: false,
+        });
+        handle_packet(&mut game, &mut server, player.entity, movement).unwrap();
+        assert_eq!(*game.ecs.get::<Position>(player.entity).unwrap(), position);
+
+        let settings = ClientPlayPacket::ClientSettings(ClientSettings {
+            locale: "en_us".to_owned(),
+            view_distance: 10,
+            chat_mode: ChatMode::Enabled,
+            chat_colors: true,
+            displayed_skin_parts: 0x7f,
+            main_hand: 1,
+        });
+        handle_packet(&mut game, &mut server, player.entity, settings).unwrap();
+        assert!(
+            player
+                .connection
+                .sent_packets
+                .try_iter()
+                .any(|packet| matches!(packet,
+                ServerPlayPacket::SendEntityMetadata(metadata) if metadata.entity_id == network_id.0)),
+            "skin parts not applied"
+        );
+        assert!(!server
+            .clients
+            .get(player.client_id)
+            .unwrap()
+            .knows_own_position());
+    }
+
+    #[test]
+    fn standing_still_is_not_activity() {
+        let position = Position::default();
+        let still = ClientPlayPacket::PlayerPosition(PlayerPosition {
+            x: position.x,
+            feet_y: position.y,
+            z: position.z,
+            on_ground: true,
+        });
+        assert!(!is_activity(&still, position));
+
+        let turned = ClientPlayPacket::PlayerRotation(PlayerRotation {
+            yaw: position.yaw + 10.0,
+            pitch: position.pitch,
+            on_ground: true,
+        });
+        assert!(is_activity(&turned, position));
+
+        assert!(is_activity(&chat("hello"), position));
+    }
+
+    #[test]
+    fn dead_players_respawn_when_requested() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let player = join_test_player(&mut game, &mut server, &new_players);
+        let died_at = Position {
+            x: 100.0,
+            z: -100.0,
+            ..Position::default()
+        };
+        *game.ecs.get_mut::<Position>(player.entity).unwrap() = died_at;
+        *game.ecs.get_mut::<Health>(player.entity).unwrap() = Health(0.0);
+        game.ecs.get_mut::<Food>(player.entity).unwrap().level = 3;
+        let client = server.clients.get(player.client_id).unwrap();
+        client.update_own_position(died_at);
+        player.connection.sent_packets.try_iter().for_each(drop);
+
+        let respawn = || ClientPlayPacket::ClientStatus(ClientStatus::PerformRespawn);
+        handle_packet(&mut game, &mut server, player.entity, respawn()).unwrap();
+        assert_eq!(game.ecs.get::<Health>(player.entity).unwrap().0, 20.0);
+        assert_eq!(
+            *game.ecs.get::<Food>(player.entity).unwrap(),
+            Food::default()
+        );
+        let position = *game.ecs.get::<Position>(player.entity).unwrap();
+        assert_eq!((position.x, position.z), (0.0, 0.0));
+        let packets: Vec<_> = player.connection.sent_packets.try_iter().collect();
+        assert!(
+            matches!(packets.first(), Some(ServerPlayPacket::Respawn(_))),
+            "{:?}",
+            packets
+        );
+
+        // Alive players can't respawn
+        let client = server.clients.get(player.client_id).unwrap();
+        client.update_own_position(position);
+        player.connection.sent_packets.try_iter().for_each(drop);
+        handle_packet(&mut game, &mut server, player.entity, respawn()).unwrap();
+        assert!(player.connection.sent_packets.try_iter().next().is_none());
+    }
+
+    #[test]
+    fn chat_is_broadcast_through_the_formatter() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let sender = join_test_player(&mut game, &mut server, &new_players);
+        let watcher = join_test_player(&mut game, &mut server, &new_players);
+        received_chat(&game, watcher.entity);
+
+        handle_packet(&mut game, &mut server, sender.entity, chat("hello")).unwrap();
+        assert_eq!(
+            received_chat(&game, watcher.entity),
+            vec![String::from(Text::translate_with(
+                "chat.type.text",
+                vec!["test".to_owned(), "hello".to_owned()]
+            ))]
+        );
+
+        server.set_chat_formatter(|client, message| {
+            if message == "secret" {
+                None
+            } else {
+                Some(Text::from(format!("[{}] {}", client.username(), message)))
+            }
+        });
+        handle_packet(&mut game, &mut server, sender.entity, chat("hello")).unwrap();
+        handle_packet(&mut game, &mut server, sender.entity, chat("secret")).unwrap();
+        assert_eq!(
+            received_chat(&game, watcher.entity),
+            vec![String::from(Text::from("[test] hello"))]
+        );
+    }
+
+    #[test]
+    fn overlong_chat_is_refused_with_a_warning() {
+        let (mut server, new_players) = test_server();
+        let mut options = (*server.options()).clone();
+        options.max_chat_length = 10;
+        server.reload_options(options);
+        let mut game = test_game(&server);
+        let sender = join_test_player(&mut game, &mut server, &new_players);
+        let watcher = join_test_player(&mut game, &mut server, &new_players);
+        received_chat(&game, watcher.entity);
+        received_chat(&game, sender.entity);
+        sender.connection.sent_packets.try_iter().for_each(drop);
+
+        handle_packet(&mut game, &mut server, sender.entity, chat(&"a".repeat(11))).unwrap();
+        assert!(received_chat(&game, watcher.entity).is_empty());
+        assert!(received_chat(&game, sender.entity).is_empty());
+        assert!(sender
+            .connection
+            .sent_packets
+            .try_iter()
+            .any(|packet| matches!(packet,
+                ServerPlayPacket::ChatMessage(message)
+                    if message.message.contains("at most 10 characters"))));
+
+        // Messages at the limit are broadcast
+        handle_packet(&mut game, &mut server, sender.entity, chat(&"a".repeat(10))).unwrap();
+        assert_eq!(received_chat(&game, watcher.entity).len(), 1);
+    }
+
+    /// Returns the ID of the last teleport sent in `packets`.
+    fn last_teleport_id(packets: impl Iterator<Item = ServerPlayPacket>) -> Option<i32> {
+        packets
+            .filter_map(|packet| match packet {
+                ServerPlayPacket::PlayerPositionAndLook(teleport) => Some(teleport.teleport_id),
+                _ => None,
+            })
+            .last()
+    }
+
+    #[test]
+    fn movement_is_skipped_until_teleports_are_confirmed() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let player = join_test_player(&mut game, &mut server, &new_players);
+        let move_to = |x: f64| {
+            ClientPlayPacket::PlayerPosition(PlayerPosition {
+                x,
+                feet_y: 64.0,
+                z: 0.0,
+                on_ground: true,
+            })
+        };
+        let confirm =
+            |teleport_id| ClientPlayPacket::TeleportConfirm(TeleportConfirm { teleport_id });
+        let join_teleport = last_teleport_id(player.connection.sent_packets.try_iter()).unwrap();
+        handle_packet(
+            &mut game,
+            &mut server,
+            player.entity,
+            confirm(join_teleport),
+        )
+        .unwrap();
+
+        let target = Position {
+            x: 100.0,
+            y: 64.0,
+            ..Position::default()
+        };
+        server
+            .teleport_player(&mut game, player.entity, target)
+            .unwrap();
+        let teleport = last_teleport_id(player.connection.sent_packets.try_iter()).unwrap();
+        assert_ne!(teleport, join_teleport);
+
+        // Sent before the client received the teleport
+        handle_packet(&mut game, &mut server, player.entity, move_to(1.0)).unwrap();
+        handle_packet(
+            &mut game,
+            &mut server,
+            player.entity,
+            confirm(join_teleport),
+        )
+        .unwrap();
+        handle_packet(&mut game, &mut server, player.entity, move_to(2.0)).unwrap();
+        assert_eq!(*game.ecs.get::<Position>(player.entity).unwrap(), target);
+
+        handle_packet(&mut game, &mut server, player.entity, confirm(teleport)).unwrap();
+        handle_packet(&mut game, &mut server, player.entity, move_to(101.0)).unwrap();
+        assert_eq!(game.ecs.get::<Position>(player.entity).unwrap().x, 101.0);
+    }
+}
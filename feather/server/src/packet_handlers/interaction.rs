@@ -1,6 +1,14 @@
-use crate::{ClientId, NetworkId, Server};
+use crate::systems::digging::{
+    dig_progress_per_tick, held_item, stop_digging, Digging, FINISH_DIGGING_THRESHOLD,
+};
+use crate::{entities::PLAYER_EYE_HEIGHT, Client, ClientId, NetworkId, Server};
 use base::inventory::{SLOT_HOTBAR_OFFSET, SLOT_OFFHAND};
+use base::{vec3, BlockId, BlockPosition, Gamemode, Position, ValidBlockPosition};
 use common::entities::player::HotbarSlot;
+use common::events::{
+    DamageEvent, HealthUpdateEvent, InteractionKind, InteractionTarget, ItemUseEvent,
+    PlayerInteractEvent,
+};
 use common::interactable::InteractableRegistry;
 use common::{Game, Window};
 use ecs::{Entity, EntityRef, SysResult};
@@ -8,16 +16,58 @@ use libcraft_core::{BlockFace as LibcraftBlockFace, Hand};
 use libcraft_core::{InteractionType, Vec3f};
 use protocol::packets::client::{
     BlockFace, HeldItemChange, InteractEntity, InteractEntityKind, PlayerBlockPlacement,
-    PlayerDigging, PlayerDiggingStatus,
+    PlayerDigging, PlayerDiggingStatus, UseItem,
 };
 use quill_common::{
+    components::{CanBuild, Health, Instabreak, Invulnerable},
+    entities::Player,
     events::{BlockInteractEvent, BlockPlacementEvent, InteractEntityEvent},
     EntityId,
 };
-/// Handles the player block placement packet. Currently just removes the block client side for the player.
+use std::convert::TryFrom;
+
+/// Height of a player's bounding box.
+const PLAYER_HEIGHT: f64 = 1.8;
+/// Half the width of a player's bounding box.
+const PLAYER_HALF_WIDTH: f64 = 0.3;
+
+/// Maximum distance from a player's eyes to the
+/// center of a block they can break or place.
+const MAX_REACH_DISTANCE: f64 = 6.0;
+
+/// Damage dealt by an attack, which is that of
+/// a fist whatever item the attacker holds.
+const ATTACK_DAMAGE: f32 = 1.0;
+
+/// Handlers called before player interactions apply.
+/// See [`Server::add_interaction_handler`].
+#[derive(Default)]
+pub(crate) struct InteractionHandlers(Vec<Box<dyn Fn(&Game, &mut PlayerInteractEvent)>>);
+
+impl InteractionHandlers {
+    pub fn add(&mut self, handler: impl Fn(&Game, &mut PlayerInteractEvent) + 'static) {
+        self.0.push(Box::new(handler));
+    }
+
+    /// Runs the handlers on an interaction and
+    /// returns whether none of them cancelled it.
+    pub fn is_allowed(&self, game: &Game, mut event: PlayerInteractEvent) -> bool {
+        for handler in &self.0 {
+            handler(game, &mut event);
+        }
+        !event.cancelled
+    }
+}
+
+/// Handles the player block placement packet.
+///
+/// If the clicked block is registered in the [`InteractableRegistry`],
+/// this triggers a [`BlockInteractEvent`]. Otherwise, the block held in the
+/// used hand is placed against the clicked face, triggering a
+/// [`BlockPlacementEvent`]. A rejected placement is reverted on the client.
 pub fn handle_player_block_placement(
     game: &mut Game,
-    _server: &mut Server,
+    server: &mut Server,
     packet: PlayerBlockPlacement,
     player: Entity,
 ) -> SysResult {
@@ -27,7 +77,7 @@ pub fn handle_player_block_placement(
         _ => {
             let client_id = game.ecs.get::<ClientId>(player).unwrap();
 
-            let client = _server.clients.get(*client_id).unwrap();
+            let client = server.clients.get(*client_id).unwrap();
 
             client.disconnect("Malformed Packet!");
 
@@ -53,31 +103,24 @@ pub fn handle_player_block_placement(
         packet.cursor_position_z,
     );
 
-    let block_kind = {
-        let result = game.block(packet.position);
-        match result {
-            Some(block) => block.kind(),
-            None => {
-                let client_id = game.ecs.get::<ClientId>(player).unwrap();
-
-                let client = _server.clients.get(*client_id).unwrap();
-
-                client.disconnect("Attempted to interact with an unloaded block!");
-
-                anyhow::bail!(
-                    "Player attempted to interact with an unloaded block. {:?}",
-                    packet
-                )
-            }
+    let block_kind = match game.block(packet.position) {
+        Some(block) => block.kind(),
+        None => {
+            log::trace!(
+                "Player attempted to interact with an unloaded block. {:?}",
+                packet
+            );
+            return reject_block_placement(game, server, player, &hand, &packet);
         }
     };
 
-    let interactable_registry = game
+    let is_interactable = game
         .resources
         .get::<InteractableRegistry>()
-        .expect("Failed to get the interactable registry");
+        .expect("Failed to get the interactable registry")
+        .is_registered(block_kind);
 
-    if interactable_registry.is_registered(block_kind) {
+    if is_interactable {
         // Handle this as a block interaction
         let event = BlockInteractEvent {
             hand,
@@ -89,10 +132,20 @@ pub fn handle_player_block_placement(
 
         game.ecs.insert_entity_event(player, event)?;
     } else {
+        // Like using them in the air, items on cooldown can't be placed
+        if is_held_item_on_cooldown(game, server, player, &hand)? {
+            return reject_block_placement(game, server, player, &hand, &packet);
+        }
+
         // Handle this as a block placement
+        let location = match place_block(game, server, player, &hand, &packet)? {
+            Some(location) => location,
+            None => return reject_block_placement(game, server, player, &hand, &packet),
+        };
+
         let event = BlockPlacementEvent {
             hand,
-            location: packet.position.into(),
+            location: location.into(),
             face,
             cursor_position,
             inside_block: packet.inside_block,
@@ -104,6 +157,146 @@ pub fn handle_player_block_placement(
     Ok(())
 }
 
+/// Places the block held in `hand` against the clicked block face,
+/// taking one item from the hand unless the player is in creative mode.
+///
+/// Returns the position of the placed block, or `None` if
+/// the placement is not allowed.
+fn place_block(
+    game: &mut Game,
+    server: &Server,
+    player: Entity,
+    hand: &Hand,
+    packet: &PlayerBlockPlacement,
+) -> anyhow::Result<Option<ValidBlockPosition>> {
+    // Replaceable blocks such as tall grass are replaced
+    // directly instead of placing against them.
+    let clicked_replaceable = game
+        .block(packet.position)
+        .map_or(false, |block| block.is_replaceable());
+    let target = if clicked_replaceable {
+        packet.position
+    } else {
+        match adjacent_block(packet.position, &packet.face) {
+            Some(target) => target,
+            None => return Ok(None),
+        }
+    };
+
+    match game.block(target) {
+        Some(block) if block.is_replaceable() => (),
+        // Occupied, or in an unloaded chunk
+        _ => return Ok(None),
+    }
+    if !can_modify_block(game, server, player, target)? {
+        return Ok(None);
+    }
+
+    let slot = hand_slot(game, player, hand)?;
+    let item = match game.ecs.get::<Window>(player)?.item(slot)?.item_kind() {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+    // Items which are not blocks can't be placed
+    let block = match BlockId::from_identifier(&format!("minecraft:{}", item.name())) {
+        Some(block) => block,
+        None => return Ok(None),
+    };
+
+    if block.is_solid() && intersects_player(game, target) {
+        return Ok(None);
+    }
+    let event = PlayerInteractEvent::new(
+        player,
+        InteractionKind::PlaceBlock,
+        InteractionTarget::Block(target),
+    );
+    if !server.interaction_handlers.is_allowed(game, event) {
+        return Ok(None);
+    }
+
+    game.set_block(target, block);
+
+    if *game.ecs.get::<Gamemode>(player)? != Gamemode::Creative {
+        let window = game.ecs.get::<Window>(player)?;
+        let _placed = window.item(slot)?.try_take(1);
+    }
+
+    Ok(Some(target))
+}
+
+/// Reverts a rejected block placement which the client
+/// predicted, resending the affected blocks and the held item.
+fn reject_block_placement(
+    game: &Game,
+    server: &Server,
+    player: Entity,
+    hand: &Hand,
+    packet: &PlayerBlockPlacement,
+) -> SysResult {
+    let client_id = *game.ecs.get::<ClientId>(player)?;
+    let client = match server.clients.get(client_id) {
+        Some(client) => client,
+        None => return Ok(()),
+    };
+
+    let adjacent = adjacent_block(packet.position, &packet.face);
+    for position in std::iter::once(packet.position).chain(adjacent) {
+        if let Some(block) = game.block(position) {
+            client.send_block_change(position, block);
+        }
+    }
+
+    let slot = hand_slot(game, player, hand)?;
+    let item = game.ecs.get::<Window>(player)?.item(slot)?.clone();
+    client.set_slot(slot as i16, &item);
+
+    Ok(())
+}
+
+/// Returns the index of the window slot held in `hand`.
+fn hand_slot(game: &Game, player: Entity, hand: &Hand) -> anyhow::Result<usize> {
+    Ok(match hand {
+        Hand::Main => SLOT_HOTBAR_OFFSET + game.ecs.get::<HotbarSlot>(player)?.get(),
+        Hand::Offhand => SLOT_OFFHAND,
+    })
+}
+
+/// Returns the position of the block touching the given face of a block.
+fn adjacent_block(position: ValidBlockPosition, face: &BlockFace) -> Option<ValidBlockPosition> {
+    let position = BlockPosition::from(position);
+    let adjacent = match face {
+        BlockFace::Bottom => position.down(),
+        BlockFace::Top => position.up(),
+        BlockFace::North => position.north(),
+        BlockFace::South => position.south(),
+        BlockFace::West => position.west(),
+        BlockFace::East => position.east(),
+    };
+    ValidBlockPosition::try_from(adjacent).ok()
+}
+
+/// Returns whether a block at `position` would
+/// intersect the bounding box of any player.
+fn intersects_player(game: &Game, position: ValidBlockPosition) -> bool {
+    let (x, y, z) = (
+        position.x() as f64,
+        position.y() as f64,
+        position.z() as f64,
+    );
+    game.ecs
+        .query::<(&Position, &Player)>()
+        .iter()
+        .any(|(_, (player, _))| {
+            player.x + PLAYER_HALF_WIDTH > x
+                && player.x - PLAYER_HALF_WIDTH < x + 1.
+                && player.y + PLAYER_HEIGHT > y
+                && player.y < y + 1.
+                && player.z + PLAYER_HALF_WIDTH > z
+                && player.z - PLAYER_HALF_WIDTH < z + 1.
+        })
+}
+
 /// Handles the Player Digging packet sent for the following
 /// actions:
 /// * Breaking blocks.
@@ -119,10 +312,9 @@ pub fn handle_player_digging(
 ) -> SysResult {
     log::trace!("Got player digging with status {:?}", packet.status);
     match packet.status {
-        PlayerDiggingStatus::StartDigging | PlayerDiggingStatus::CancelDigging => {
-            game.break_block(packet.position);
-            Ok(())
-        }
+        PlayerDiggingStatus::StartDigging
+        | PlayerDiggingStatus::CancelDigging
+        | PlayerDiggingStatus::FinishDigging => handle_digging(game, server, packet, player),
         PlayerDiggingStatus::SwapItemInHand => {
             let window = game.ecs.get::<Window>(player)?;
 
@@ -149,9 +341,132 @@ pub fn handle_player_digging(
     }
 }
 
+/// Handles starting, cancelling and finishing digging a block.
+///
+/// Players who can break blocks instantly (i.e. in creative mode)
+/// break the block when they start digging it. Otherwise, the block
+/// is broken when the player finishes digging, provided enough time
+/// has passed; see [`crate::systems::digging`].
+fn handle_digging(
+    game: &mut Game,
+    server: &mut Server,
+    packet: PlayerDigging,
+    player: Entity,
+) -> SysResult {
+    if let PlayerDiggingStatus::CancelDigging = packet.status {
+        return stop_digging(game, server, player);
+    }
+
+    let client_id = *game.ecs.get::<ClientId>(player)?;
+    let client = match server.clients.get(client_id) {
+        Some(client) => client,
+        None => return Ok(()),
+    };
+    let block = match game.block(packet.position) {
+        Some(block) => block,
+        // Unloaded chunk
+        None => return Ok(()),
+    };
+
+    if !can_modify_block(game, server, player, packet.position)? {
+        log::trace!(
+            "{} is not allowed to break the block at {:?}",
+            client.username(),
+            packet.position
+        );
+        // The client has already broken the block on its end
+        client.send_block_change(packet.position, block);
+        return stop_digging(game, server, player);
+    }
+
+    match packet.status {
+        PlayerDiggingStatus::StartDigging => {
+            stop_digging(game, server, player)?;
+            let instabreak = game.ecs.get::<Instabreak>(player)?.0;
+            if instabreak || dig_progress_per_tick(block, held_item(game, player)?) >= 1. {
+                break_block(game, server, client, player, packet.position);
+            } else {
+                game.ecs.insert(player, Digging::new(packet.position))?;
+            }
+        }
+        PlayerDiggingStatus::FinishDigging => {
+            let finished = match game.ecs.get::<Digging>(player) {
+                Ok(digging) => {
+                    digging.position == packet.position
+                        && digging.progress >= FINISH_DIGGING_THRESHOLD
+                }
+                Err(_) => false,
+            };
+            stop_digging(game, server, player)?;
+            if finished {
+                break_block(game, server, client, player, packet.position);
+            } else {
+                log::trace!("{} finished digging too early", client.username());
+                client.send_block_change(packet.position, block);
+            }
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// Breaks the block at `position` unless an interaction handler
+/// cancels it, in which case the client is sent the block back.
+fn break_block(
+    game: &mut Game,
+    server: &Server,
+    client: &Client,
+    player: Entity,
+    position: ValidBlockPosition,
+) {
+    let event = PlayerInteractEvent::new(
+        player,
+        InteractionKind::BreakBlock,
+        InteractionTarget::Block(position),
+    );
+    if server.interaction_handlers.is_allowed(game, event) {
+        game.break_block(position);
+    } else if let Some(block) = game.block(position) {
+        client.send_block_change(position, block);
+    }
+}
+
+/// Returns whether the player may break or place the block at
+/// `position`, checking the player's gamemode, reach and spawn protection.
+fn can_modify_block(
+    game: &Game,
+    server: &Server,
+    player: Entity,
+    position: ValidBlockPosition,
+) -> anyhow::Result<bool> {
+    let gamemode = *game.ecs.get::<Gamemode>(player)?;
+    if gamemode == Gamemode::Spectator || !game.ecs.get::<CanBuild>(player)?.0 {
+        return Ok(false);
+    }
+
+    let player_position = *game.ecs.get::<Position>(player)?;
+    let eyes = player_position + vec3(0., PLAYER_EYE_HEIGHT, 0.);
+    let block_center = position.position() + vec3(0.5, 0.5, 0.5);
+    if eyes.distance_to(block_center) > MAX_REACH_DISTANCE {
+        return Ok(false);
+    }
+
+    let client_id = *game.ecs.get::<ClientId>(player)?;
+    let is_op = server
+        .clients
+        .get(client_id)
+        .map_or(false, |client| client.is_op(1));
+    if server.is_spawn_protected(position) && !is_op {
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
 pub fn handle_interact_entity(
     game: &mut Game,
-    _server: &mut Server,
+    server: &mut Server,
     packet: InteractEntity,
     player: Entity,
 ) -> SysResult {
@@ -168,7 +483,7 @@ pub fn handle_interact_entity(
             None => {
                 let client_id = game.ecs.get::<ClientId>(player).unwrap();
 
-                let client = _server.clients.get(*client_id).unwrap();
+                let client = server.clients.get(*client_id).unwrap();
 
                 client.disconnect("Interacted with an invalid entity!");
 
@@ -219,11 +534,115 @@ pub fn handle_interact_entity(
         }
     };
 
+    let kind = match packet.kind {
+        InteractEntityKind::Attack => InteractionKind::AttackEntity,
+        _ => InteractionKind::InteractEntity,
+    };
+    let interaction = PlayerInteractEvent::new(player, kind, InteractionTarget::Entity(target));
+    if !server.interaction_handlers.is_allowed(game, interaction) {
+        // The client may have predicted the use of the held item
+        let client_id = *game.ecs.get::<ClientId>(player)?;
+        if let Some(client) = server.clients.get(client_id) {
+            let slot = hand_slot(game, player, &event.hand.unwrap_or(Hand::Main))?;
+            let item = game.ecs.get::<Window>(player)?.item(slot)?.clone();
+            client.set_slot(slot as i16, &item);
+        }
+        return Ok(());
+    }
+
+    if let InteractionType::Interact = event.ty {
+        if !packet.sneaking {
+            super::vehicle::try_mount(game, player, target)?;
+        }
+    }
+    if let InteractEntityKind::Attack = packet.kind {
+        attack(game, player, target)?;
+    }
     game.ecs.insert_entity_event(player, event)?;
 
     Ok(())
 }
 
+/// Damages an entity attacked by `player`, triggering a
+/// [`DamageEvent`] by which the target is knocked back.
+/// Dead or invulnerable entities and those without health are unaffected.
+fn attack(game: &mut Game, player: Entity, target: Entity) -> SysResult {
+    let invulnerable = game
+        .ecs
+        .get::<Invulnerable>(target)
+        .map_or(false, |invulnerable| invulnerable.0);
+    match game.ecs.get_mut::<Health>(target) {
+        Ok(mut health) if health.0 > 0.0 && !invulnerable => {
+            health.0 = (health.0 - ATTACK_DAMAGE).max(0.0);
+        }
+        _ => return Ok(()),
+    }
+
+    game.ecs.insert_entity_event(
+        target,
+        DamageEvent {
+            attacker: Some(player),
+            amount: ATTACK_DAMAGE,
+        },
+    )?;
+    if game.ecs.get::<Player>(target).is_ok() {
+        game.ecs.insert_entity_event(target, HealthUpdateEvent)?;
+    }
+    Ok(())
+}
+
+/// Handles the Use Item packet, sent when a player uses the held
+/// item without targeting a block, e.g. to throw an ender pearl.
+///
+/// This triggers an [`ItemUseEvent`], unless the item is on cooldown.
+/// The cooldown is checked here rather than trusting the client.
+pub fn handle_use_item(
+    game: &mut Game,
+    server: &Server,
+    packet: UseItem,
+    player: Entity,
+) -> SysResult {
+    let hand = match packet.hand {
+        0 => Hand::Main,
+        1 => Hand::Offhand,
+        _ => anyhow::bail!("Player sent a malformed `UseItem` packet. {:?}", packet),
+    };
+
+    let slot = hand_slot(game, player, &hand)?;
+    let item = match game.ecs.get::<Window>(player)?.item(slot)?.item_kind() {
+        Some(item) => item,
+        None => return Ok(()),
+    };
+    if is_held_item_on_cooldown(game, server, player, &hand)? {
+        log::trace!("Ignoring use of {:?} on cooldown", item);
+        return Ok(());
+    }
+
+    game.ecs
+        .insert_entity_event(player, ItemUseEvent { item, hand })?;
+    Ok(())
+}
+
+/// Returns whether the item held in `hand` is on
+/// cooldown for the player's client.
+fn is_held_item_on_cooldown(
+    game: &Game,
+    server: &Server,
+    player: Entity,
+    hand: &Hand,
+) -> anyhow::Result<bool> {
+    let slot = hand_slot(game, player, hand)?;
+    let item = match game.ecs.get::<Window>(player)?.item(slot)?.item_kind() {
+        Some(item) => item,
+        None => return Ok(false),
+    };
+    let client_id = *game.ecs.get::<ClientId>(player)?;
+    Ok(server
+        .clients
+        .get(client_id)
+        .map_or(false, |client| client.is_item_on_cooldown(item.id() as i32)))
+}
+
 pub fn handle_held_item_change(player: EntityRef, packet: HeldItemChange) -> SysResult {
     let new_id = packet.slot as usize;
     let mut slot = player.get_mut::<HotbarSlot>()?;
@@ -236,10 +655,88 @@ pub fn handle_held_item_change(player: EntityRef, packet: HeldItemChange) -> Sys
 
 #[cfg(test)]
 mod tests {
+    use base::{Chunk, ChunkPosition, Item, ItemStack};
+    use common::vehicle::Vehicle;
+    use common::view::View;
     use common::Game;
-    use protocol::packets::client::HeldItemChange;
+    use libcraft_items::InventorySlot;
+    use protocol::{packets::client::HeldItemChange, ServerPlayPacket};
+    use quill_common::entity_init::EntityInit;
 
     use super::*;
+    use crate::systems::digging::advance_digging;
+    use crate::tests::{add_watching_client, join_test_player, test_game, test_server, TestPlayer};
+
+    /// Joins a player standing outside spawn protection, next to
+    /// the returned air block in a loaded chunk, holding `item`.
+    fn player_next_to_air(item: Item) -> (Game, Server, TestPlayer, ValidBlockPosition) {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let player = join_test_player(&mut game, &mut server, &new_players);
+        server.clients.get(player.client_id).unwrap().mark_ready();
+
+        game.world
+            .chunk_map_mut()
+            .insert_chunk(Chunk::new(ChunkPosition::new(2, 0)));
+        *game.ecs.get_mut::<Position>(player.entity).unwrap() = Position {
+            x: 40.5,
+            y: 64.0,
+            z: 0.5,
+            ..Position::default()
+        };
+        let slot = hand_slot(&game, player.entity, &Hand::Main).unwrap();
+        game.ecs
+            .get::<Window>(player.entity)
+            .unwrap()
+            .set_item(
+                slot,
+                InventorySlot::Filled(ItemStack::new(item, 1).unwrap()),
+            )
+            .unwrap();
+        player.connection.sent_packets.try_iter().for_each(drop);
+
+        let air = ValidBlockPosition::try_from(BlockPosition::new(40, 64, 2)).unwrap();
+        (game, server, player, air)
+    }
+
+    fn placement_at(position: ValidBlockPosition) -> PlayerBlockPlacement {
+        PlayerBlockPlacement {
+            hand: 0,
+            position,
+            face: BlockFace::Top,
+            cursor_position_x: 0.5,
+            cursor_position_y: 0.5,
+            cursor_position_z: 0.5,
+            inside_block: false,
+        }
+    }
+
+    #[test]
+    fn interaction_handlers_can_cancel() {
+        let mut game = Game::new();
+        let player = game.ecs.spawn((Player,));
+        let protected = ValidBlockPosition::try_from(BlockPosition::new(0, 64, 0)).unwrap();
+        let event = |position| {
+            PlayerInteractEvent::new(
+                player,
+                InteractionKind::BreakBlock,
+                InteractionTarget::Block(position),
+            )
+        };
+
+        let mut handlers = InteractionHandlers::default();
+        assert!(handlers.is_allowed(&game, event(protected)));
+
+        handlers.add(move |_, event| {
+            if event.target == InteractionTarget::Block(protected) {
+                event.cancelled = true;
+            }
+        });
+        assert!(!handlers.is_allowed(&game, event(protected)));
+
+        let other = ValidBlockPosition::try_from(BlockPosition::new(1, 64, 0)).unwrap();
+        assert!(handlers.is_allowed(&game, event(other)));
+    }
 
     #[test]
     fn held_item_change() {
@@ -256,4 +753,270 @@ mod tests {
             HotbarSlot::new(8)
         );
     }
+
+    #[test]
+    fn attacks_damage_the_target() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let attacker = join_test_player(&mut game, &mut server, &new_players);
+        let target = join_test_player(&mut game, &mut server, &new_players);
+        let attack = |game: &Game| InteractEntity {
+            entity_id: game.ecs.get::<NetworkId>(target.entity).unwrap().0,
+            kind: InteractEntityKind::Attack,
+            sneaking: false,
+        };
+
+        let packet = attack(&game);
+        handle_interact_entity(&mut game, &mut server, packet, attacker.entity).unwrap();
+        assert_eq!(game.ecs.get::<Health>(target.entity).unwrap().0, 19.0);
+        let damage = game.ecs.get::<DamageEvent>(target.entity).unwrap();
+        assert_eq!(damage.attacker, Some(attacker.entity));
+        assert_eq!(damage.amount, ATTACK_DAMAGE);
+        drop(damage);
+
+        // Dead players take no more damage
+        *game.ecs.get_mut::<Health>(target.entity).unwrap() = Health(0.0);
+        let packet = attack(&game);
+        handle_interact_entity(&mut game, &mut server, packet, attacker.entity).unwrap();
+        assert_eq!(game.ecs.get::<Health>(target.entity).unwrap().0, 0.0);
+    }
+
+    #[test]
+    fn items_on_cooldown_are_not_used() {
+        let (mut game, server, player, _) = player_next_to_air(Item::EnderPearl);
+        let client = server.clients.get(player.client_id).unwrap();
+        client.set_item_cooldown(Item::EnderPearl.id() as i32, 20);
+
+        handle_use_item(&mut game, &server, UseItem { hand: 0 }, player.entity).unwrap();
+        assert!(game.ecs.get::<ItemUseEvent>(player.entity).is_err());
+
+        client.set_item_cooldown(Item::EnderPearl.id() as i32, 0);
+        handle_use_item(&mut game, &server, UseItem { hand: 0 }, player.entity).unwrap();
+        assert!(game.ecs.get::<ItemUseEvent>(player.entity).is_ok());
+    }
+
+    #[test]
+    fn items_on_cooldown_are_not_placed() {
+        let (mut game, mut server, player, air) = player_next_to_air(Item::Stone);
+        let client = server.clients.get(player.client_id).unwrap();
+        client.set_item_cooldown(Item::Stone.id() as i32, 20);
+        player.connection.sent_packets.try_iter().for_each(drop);
+
+        handle_player_block_placement(&mut game, &mut server, placement_at(air), player.entity)
+            .unwrap();
+        assert_eq!(game.block(air), Some(BlockId::air()));
+        let packets: Vec<_> = player.connection.sent_packets.try_iter().collect();
+        assert!(packets.iter().any(|packet| matches!(packet,
+            ServerPlayPacket::BlockChange(change) if change.position == air)));
+        assert!(packets
+            .iter()
+            .any(|packet| matches!(packet, ServerPlayPacket::SetSlot(_))));
+
+        let client = server.clients.get(player.client_id).unwrap();
+        client.set_item_cooldown(Item::Stone.id() as i32, 0);
+        handle_player_block_placement(&mut game, &mut server, placement_at(air), player.entity)
+            .unwrap();
+        assert_eq!(game.block(air), Some(BlockId::stone()));
+    }
+
+    /// Returns whether `packets` resend the block at `position` as `block`.
+    fn resends_block(
+        packets: &[ServerPlayPacket],
+        position: ValidBlockPosition,
+        block: BlockId,
+    ) -> bool {
+        packets.iter().any(|packet| {
+            matches!(packet, ServerPlayPacket::BlockChange(change)
+                if change.position == position && change.block == block)
+        })
+    }
+
+    #[test]
+    fn cancelled_breaking_resends_the_block() {
+        let (mut game, mut server, player, position) = player_next_to_air(Item::Stone);
+        game.set_block(position, BlockId::stone());
+        *game.ecs.get_mut::<Instabreak>(player.entity).unwrap() = Instabreak(true);
+        server.add_interaction_handler(|_, event| event.cancelled = true);
+
+        let packet = PlayerDigging {
+            status: PlayerDiggingStatus::StartDigging,
+            position,
+            face: BlockFace::Top,
+        };
+        handle_player_digging(&mut game, &mut server, packet, player.entity).unwrap();
+        assert_eq!(game.block(position), Some(BlockId::stone()));
+        let packets: Vec<_> = player.connection.sent_packets.try_iter().collect();
+        assert!(
+            resends_block(&packets, position, BlockId::stone()),
+            "{:?}",
+            packets
+        );
+    }
+
+    #[test]
+    fn cancelled_entity_interactions_resend_the_held_slot() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let player = join_test_player(&mut game, &mut server, &new_players);
+        let target = join_test_player(&mut game, &mut server, &new_players);
+        server.clients.get(player.client_id).unwrap().mark_ready();
+        let builder = game.create_entity_builder(Position::default(), EntityInit::Boat);
+        let boat = game.spawn_entity(builder);
+        player.connection.sent_packets.try_iter().for_each(drop);
+        server.add_interaction_handler(|_, event| event.cancelled = true);
+
+        let interactions = vec![
+            (boat, InteractEntityKind::Interact),
+            (target.entity, InteractEntityKind::Attack),
+        ];
+        for (entity, kind) in interactions {
+            let packet = InteractEntity {
+                entity_id: game.ecs.get::<NetworkId>(entity).unwrap().0,
+                kind,
+                sneaking: false,
+            };
+            handle_interact_entity(&mut game, &mut server, packet, player.entity).unwrap();
+            let packets: Vec<_> = player.connection.sent_packets.try_iter().collect();
+            assert!(
+                packets
+                    .iter()
+                    .any(|packet| matches!(packet, ServerPlayPacket::SetSlot(_))),
+                "{:?}",
+                packets
+            );
+        }
+
+        assert!(game.ecs.get::<Vehicle>(player.entity).is_err());
+        assert_eq!(game.ecs.get::<Health>(target.entity).unwrap().0, 20.0);
+        assert!(game.ecs.get::<DamageEvent>(target.entity).is_err());
+        assert!(game.ecs.get::<InteractEntityEvent>(player.entity).is_err());
+    }
+
+    fn dig(status: PlayerDiggingStatus, position: ValidBlockPosition) -> PlayerDigging {
+        PlayerDigging {
+            status,
+            position,
+            face: BlockFace::Top,
+        }
+    }
+
+    #[test]
+    fn survival_digging_breaks_the_block_once_dug() {
+        let (mut game, mut server, player, position) = player_next_to_air(Item::Stone);
+        game.set_block(position, BlockId::dirt());
+        let (_, watcher) = add_watching_client(&mut server, View::new(ChunkPosition::new(2, 0), 2));
+        let start = dig(PlayerDiggingStatus::StartDigging, position);
+        let finish = dig(PlayerDiggingStatus::FinishDigging, position);
+
+        handle_player_digging(&mut game, &mut server, start.clone(), player.entity).unwrap();
+        assert_eq!(game.block(position), Some(BlockId::dirt()));
+        assert!(game.ecs.get::<Digging>(player.entity).is_ok());
+
+        // Finishing before the block is dug resends it
+        handle_player_digging(&mut game, &mut server, finish.clone(), player.entity).unwrap();
+        assert_eq!(game.block(position), Some(BlockId::dirt()));
+        let packets: Vec<_> = player.connection.sent_packets.try_iter().collect();
+        assert!(
+            resends_block(&packets, position, BlockId::dirt()),
+            "{:?}",
+            packets
+        );
+        watcher.try_iter().for_each(drop);
+
+        handle_player_digging(&mut game, &mut server, start, player.entity).unwrap();
+        while game.ecs.get::<Digging>(player.entity).unwrap().progress < FINISH_DIGGING_THRESHOLD {
+            advance_digging(&mut game, &mut server).unwrap();
+        }
+        let stages: Vec<_> = watcher
+            .try_iter()
+            .filter_map(|packet| match packet {
+                ServerPlayPacket::BlockBreakAnimation(animation) => {
+                    assert_eq!(animation.position, position);
+                    Some(animation.destroy_stage)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stages, (0..=7).collect::<Vec<_>>());
+
+        handle_player_digging(&mut game, &mut server, finish, player.entity).unwrap();
+        assert_eq!(game.block(position), Some(BlockId::air()));
+        assert!(game.ecs.get::<Digging>(player.entity).is_err());
+        assert!(
+            watcher.try_iter().any(|packet| matches!(packet,
+                ServerPlayPacket::BlockBreakAnimation(animation) if animation.destroy_stage > 9)),
+            "animation not removed"
+        );
+    }
+
+    #[test]
+    fn instabreak_is_refused_within_spawn_protection() {
+        let (mut game, mut server, player, position) = player_next_to_air(Item::Stone);
+        game.set_block(position, BlockId::dirt());
+        *game.ecs.get_mut::<Instabreak>(player.entity).unwrap() = Instabreak(true);
+        let start = |position| dig(PlayerDiggingStatus::StartDigging, position);
+
+        handle_player_digging(&mut game, &mut server, start(position), player.entity).unwrap();
+        assert_eq!(game.block(position), Some(BlockId::air()));
+        assert!(game.ecs.get::<Digging>(player.entity).is_err());
+
+        game.world
+            .chunk_map_mut()
+            .insert_chunk(Chunk::new(ChunkPosition::new(0, 0)));
+        *game.ecs.get_mut::<Position>(player.entity).unwrap() = Position {
+            x: 0.5,
+            y: 64.0,
+            z: 0.5,
+            ..Position::default()
+        };
+        let protected = ValidBlockPosition::try_from(BlockPosition::new(0, 64, 2)).unwrap();
+        assert!(server.is_spawn_protected(protected));
+        game.set_block(protected, BlockId::dirt());
+        player.connection.sent_packets.try_iter().for_each(drop);
+
+        handle_player_digging(&mut game, &mut server, start(protected), player.entity).unwrap();
+        assert_eq!(game.block(protected), Some(BlockId::dirt()));
+        let packets: Vec<_> = player.connection.sent_packets.try_iter().collect();
+        assert!(
+            resends_block(&packets, protected, BlockId::dirt()),
+            "{:?}",
+            packets
+        );
+    }
+
+    #[test]
+    fn cancelled_placement_resends_the_block_and_slot() {
+        let (mut game, mut server, player, air) = player_next_to_air(Item::Stone);
+        server.add_interaction_handler(|_, event| event.cancelled = true);
+
+        handle_player_block_placement(&mut game, &mut server, placement_at(air), player.entity)
+            .unwrap();
+        assert_eq!(game.block(air), Some(BlockId::air()));
+        let slot = hand_slot(&game, player.entity, &Hand::Main).unwrap();
+        let held = game
+            .ecs
+            .get::<Window>(player.entity)
+            .unwrap()
+            .item(slot)
+            .unwrap()
+            .clone();
+        assert_eq!(
+            held,
+            InventorySlot::Filled(ItemStack::new(Item::Stone, 1).unwrap())
+        );
+
+        let packets: Vec<_> = player.connection.sent_packets.try_iter().collect();
+        assert!(
+            resends_block(&packets, air, BlockId::air()),
+            "{:?}",
+            packets
+        );
+        assert!(
+            packets.iter().any(|packet| matches!(packet,
+                ServerPlayPacket::SetSlot(set_slot)
+                    if set_slot.slot == slot as i16 && set_slot.slot_data == held)),
+            "{:?}",
+            packets
+        );
+    }
 }
@@ -2,7 +2,9 @@ use anyhow::bail;
 use base::Gamemode;
 use common::{window::BackingWindow, Window};
 use ecs::{EntityRef, SysResult};
-use protocol::packets::client::{ClickWindow, CreativeInventoryAction};
+use protocol::packets::client::{
+    ClickWindow, CloseWindow, CreativeInventoryAction, WindowConfirmation,
+};
 
 use crate::{ClientId, Server};
 
@@ -35,34 +37,68 @@ pub fn handle_creative_inventory_action(
     Ok(())
 }
 
+pub fn handle_close_window(
+    server: &mut Server,
+    player: EntityRef,
+    packet: CloseWindow,
+) -> SysResult {
+    if let Some(client) = server.clients.get(*player.get::<ClientId>()?) {
+        client.handle_window_closed(packet.window_id);
+    }
+    Ok(())
+}
+
+/// Applies a click predicted by the client and accepts it if the
+/// result matches the client's, or rejects it and sends the
+/// actual window contents to roll back the client otherwise.
 pub fn handle_click_window(
     server: &mut Server,
     player: EntityRef,
     packet: ClickWindow,
 ) -> SysResult {
-    let result = _handle_click_window(&player, &packet);
-
     let client = server.clients.get(*player.get::<ClientId>()?).unwrap();
-    client.confirm_window_action(
-        packet.window_id,
-        packet.action_number as i16,
-        result.is_ok(),
-    );
+    if !client.is_window_synced(packet.window_id) {
+        // Like vanilla, ignore clicks until the client
+        // has acknowledged the last rejection.
+        return Ok(());
+    }
 
-    let window = player.get::<Window>()?;
+    let result = _handle_click_window(&player, &packet);
+    let accepted = matches!(result, Ok(true));
+    client.confirm_window_action(packet.window_id, packet.action_number as i16, accepted);
 
-    if packet.slot >= 0 {
-        client.set_slot(packet.slot, &*window.item(packet.slot as usize)?);
+    if !accepted {
+        let window = player.get::<Window>()?;
+        client.set_window_items(packet.window_id, &window.inner().to_vec());
+        client.set_cursor_slot(window.cursor_item());
     }
-    client.set_cursor_slot(window.cursor_item());
 
-    client.send_window_items(&*window);
+    result.map(|_| ())
+}
 
-    result
+pub fn handle_window_confirmation(
+    server: &mut Server,
+    player: EntityRef,
+    packet: WindowConfirmation,
+) -> SysResult {
+    if let Some(client) = server.clients.get(*player.get::<ClientId>()?) {
+        client.handle_window_confirmation(packet.window_id);
+    }
+    Ok(())
 }
 
-fn _handle_click_window(player: &EntityRef, packet: &ClickWindow) -> SysResult {
+/// Applies a click, returning whether the clicked slot
+/// held the item the client says it did.
+fn _handle_click_window(player: &EntityRef, packet: &ClickWindow) -> anyhow::Result<bool> {
     let mut window = player.get_mut::<Window>()?;
+    // The client sends the item in the clicked slot before clicking,
+    // except for drags, where it sends nothing.
+    let clicked_item = match packet.mode {
+        5 => None,
+        _ if packet.slot < 0 => None,
+        _ => Some(window.item(packet.slot as usize)?.clone()),
+    };
+
     match packet.mode {
         0 => match packet.button {
             0 => window.left_click(packet.slot as usize)?,
@@ -70,6 +106,7 @@ fn _handle_click_window(player: &EntityRef, packet: &ClickWindow) -> SysResult {
             _ => bail!("unrecgonized click"),
         },
         1 => window.shift_click(packet.slot as usize)?,
+        2 => window.swap_with_hotbar(packet.slot as usize, packet.button as usize)?,
         5 => match packet.button {
             0 => window.begin_left_mouse_paint(),
             4 => window.begin_right_mouse_paint(),
@@ -80,5 +117,169 @@ fn _handle_click_window(player: &EntityRef, packet: &ClickWindow) -> SysResult {
         _ => bail!("unsupported window click mode"),
     };
 
-    Ok(())
+    Ok(clicked_item.map_or(true, |item| item == packet.clicked_item))
+}
+
+#[cfg(test)]
+mod tests {
+    use base::{Item, ItemStack, Text};
+    use common::Game;
+    use libcraft_items::InventorySlot;
+    use protocol::{packets::server::WindowType, ServerPlayPacket};
+
+    use super::*;
+    use crate::tests::{join_test_player, test_game, test_server, TestPlayer};
+
+    const HOTBAR_SLOT: i16 = 36;
+
+    /// Joins a player holding stone in the first hotbar slot.
+    fn player_with_stone(count: u32) -> (Game, Server, TestPlayer) {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let player = join_test_player(&mut game, &mut server, &new_players);
+        game.ecs
+            .get::<Window>(player.entity)
+            .unwrap()
+            .set_item(HOTBAR_SLOT as usize, stone(count))
+            .unwrap();
+        player.connection.sent_packets.try_iter().for_each(drop);
+        (game, server, player)
+    }
+
+    fn stone(count: u32) -> InventorySlot {
+        InventorySlot::Filled(ItemStack::new(Item::Stone, count).unwrap())
+    }
+
+    fn click(
+        game: &Game,
+        server: &mut Server,
+        player: &TestPlayer,
+        (slot, button, mode): (i16, i8, i32),
+        clicked_item: InventorySlot,
+    ) -> Vec<ServerPlayPacket> {
+        let packet = ClickWindow {
+            window_id: 0,
+            slot,
+            button,
+            action_number: 1,
+            mode,
+            clicked_item,
+        };
+        let entity = game.ecs.entity(player.entity).unwrap();
+        handle_click_window(server, entity, packet).unwrap();
+        player.connection.sent_packets.try_iter().collect()
+    }
+
+    fn item(game: &Game, player: &TestPlayer, slot: i16) -> InventorySlot {
+        let window = game.ecs.get::<Window>(player.entity).unwrap();
+        let item = window.item(slot as usize).unwrap().clone();
+        item
+    }
+
+    #[test]
+    fn mispredicted_click_resends_window_and_cursor() {
+        use ServerPlayPacket::{SetSlot, WindowConfirmation, WindowItems};
+        let (game, mut server, player) = player_with_stone(10);
+
+        // The client thinks the slot is empty
+        let left_click = (HOTBAR_SLOT, 0, 0);
+        let sent = click(
+            &game,
+            &mut server,
+            &player,
+            left_click,
+            InventorySlot::Empty,
+        );
+        match sent.as_slice() {
+            [WindowConfirmation(confirmation), WindowItems(items), SetSlot(cursor)] => {
+                assert!(!confirmation.is_accepted);
+                assert_eq!(items.items[HOTBAR_SLOT as usize], InventorySlot::Empty);
+                assert_eq!((cursor.window_id as i8, cursor.slot), (-1, -1));
+                assert_eq!(cursor.slot_data, stone(10));
+            }
+            _ => panic!("unexpected packets {:?}", sent),
+        }
+
+        // Clicks are ignored until the rejection is acknowledged
+        let sent = click(&game, &mut server, &player, left_click, stone(10));
+        assert!(sent.is_empty());
+    }
+
+    #[test]
+    fn shift_click_moves_the_stack_into_the_inventory() {
+        let (game, mut server, player) = player_with_stone(10);
+
+        let sent = click(&game, &mut server, &player, (HOTBAR_SLOT, 0, 1), stone(10));
+        assert!(
+            matches!(sent.as_slice(), [ServerPlayPacket::WindowConfirmation(confirmation)]
+                if confirmation.is_accepted),
+            "{:?}",
+            sent
+        );
+        assert_eq!(item(&game, &player, HOTBAR_SLOT), InventorySlot::Empty);
+        assert!((9..36).any(|slot| item(&game, &player, slot) == stone(10)));
+    }
+
+    #[test]
+    fn drag_splits_the_cursor_stack() {
+        let (game, mut server, player) = player_with_stone(4);
+        click(&game, &mut server, &player, (HOTBAR_SLOT, 0, 0), stone(4));
+
+        let outside = -999;
+        for &(slot, button) in &[(outside, 0), (9, 1), (10, 1), (outside, 2)] {
+            let sent = click(
+                &game,
+                &mut server,
+                &player,
+                (slot, button, 5),
+                InventorySlot::Empty,
+            );
+            assert!(
+                matches!(sent.as_slice(), [ServerPlayPacket::WindowConfirmation(confirmation)]
+                    if confirmation.is_accepted),
+                "{:?}",
+                sent
+            );
+        }
+        assert_eq!(item(&game, &player, 9), stone(2));
+        assert_eq!(item(&game, &player, 10), stone(2));
+        let window = game.ecs.get::<Window>(player.entity).unwrap();
+        assert_eq!(*window.cursor_item(), InventorySlot::Empty);
+    }
+
+    #[test]
+    fn closing_unknown_windows_is_ignored() {
+        let (game, mut server, player) = player_with_stone(1);
+        let close = |server: &mut Server, window_id| {
+            let entity = game.ecs.entity(player.entity).unwrap();
+            handle_close_window(server, entity, CloseWindow { window_id }).unwrap();
+        };
+        let client = server.clients.get(player.client_id).unwrap();
+        let window_id = client.open_window(WindowType::Generic9x3, &Text::from("Chest"));
+        assert_ne!(window_id, 0);
+        let sent: Vec<_> = player.connection.sent_packets.try_iter().collect();
+        assert!(
+            matches!(sent.as_slice(), [ServerPlayPacket::OpenWindow(open)]
+                if open.window_id.0 == window_id as i32),
+            "{:?}",
+            sent
+        );
+
+        close(&mut server, 0);
+        close(&mut server, window_id.wrapping_add(1));
+        let client = server.clients.get(player.client_id).unwrap();
+        assert_eq!(client.open_window_id(), Some(window_id));
+
+        close(&mut server, window_id);
+        let client = server.clients.get(player.client_id).unwrap();
+        assert_eq!(client.open_window_id(), None);
+        close(&mut server, window_id);
+
+        // Window IDs never reach the player inventory
+        for _ in 0..=u8::MAX {
+            let next = client.open_window(WindowType::Generic9x1, &Text::from("Chest"));
+            assert_ne!(next, 0);
+            client.close_window();
+        }
+    }
 }
@@ -1,11 +1,15 @@
 use base::Position;
-use common::Game;
+use common::{
+    hunger::{Food, JUMP_EXHAUSTION, SPRINT_EXHAUSTION_PER_METER, SPRINT_JUMP_EXHAUSTION},
+    vehicle::Vehicle,
+    Game,
+};
 use ecs::{Entity, EntityRef, SysResult};
 use protocol::packets::client::{
     PlayerAbilities, PlayerMovement, PlayerPosition, PlayerPositionAndRotation, PlayerRotation,
 };
 use quill_common::{
-    components::{CreativeFlying, OnGround},
+    components::{CreativeFlying, OnGround, Sprinting},
     events::CreativeFlyingEvent,
 };
 
@@ -17,7 +21,15 @@ use crate::{ClientId, Server};
 /// is aware of the position update.
 fn should_skip_movement(server: &Server, player: &EntityRef) -> SysResult<bool> {
     if let Some(client) = server.clients.get(*player.get::<ClientId>()?) {
+        if client.has_pending_teleport() {
+            return Ok(true);
+        }
         let server_position = *player.get::<Position>()?;
+        // Passengers are moved along with their vehicle
+        if player.get::<Vehicle>().is_ok() {
+            client.set_client_known_position(server_position);
+            return Ok(true);
+        }
         let client_position = client.client_known_position();
         if let Some(client_position) = client_position {
             if client_position != server_position {
@@ -40,14 +52,18 @@ pub fn handle_player_position(
     player: EntityRef,
     packet: PlayerPosition,
 ) -> SysResult {
+    mark_client_ready(server, &player)?;
     if should_skip_movement(server, &player)? {
         return Ok(());
     }
     let mut pos = player.get_mut::<Position>()?;
+    let old_pos = *pos;
     pos.x = packet.x;
     pos.y = packet.feet_y;
     pos.z = packet.z;
+    let was_on_ground = player.get::<OnGround>()?.0;
     player.get_mut::<OnGround>()?.0 = packet.on_ground;
+    exhaust_from_movement(&player, old_pos, *pos, was_on_ground)?;
     update_client_position(server, player, *pos)?;
     Ok(())
 }
@@ -57,16 +73,20 @@ pub fn handle_player_position_and_rotation(
     player: EntityRef,
     packet: PlayerPositionAndRotation,
 ) -> SysResult {
+    mark_client_ready(server, &player)?;
     if should_skip_movement(server, &player)? {
         return Ok(());
     }
     let mut pos = player.get_mut::<Position>()?;
+    let old_pos = *pos;
     pos.x = packet.x;
     pos.y = packet.feet_y;
     pos.z = packet.z;
     pos.yaw = packet.yaw;
     pos.pitch = packet.pitch;
+    let was_on_ground = player.get::<OnGround>()?.0;
     player.get_mut::<OnGround>()?.0 = packet.on_ground;
+    exhaust_from_movement(&player, old_pos, *pos, was_on_ground)?;
     update_client_position(server, player, *pos)?;
     Ok(())
 }
@@ -76,7 +96,8 @@ pub fn handle_player_rotation(
     player: EntityRef,
     packet: PlayerRotation,
 ) -> SysResult {
-    if should_skip_movement(server, &player)? {
+    let is_riding = player.get::<Vehicle>().is_ok();
+    if !is_riding && should_skip_movement(server, &player)? {
         return Ok(());
     }
     let mut pos = player.get_mut::<Position>()?;
@@ -87,6 +108,42 @@ pub fn handle_player_rotation(
     Ok(())
 }
 
+/// Adds the exhaustion caused by sprinting and jumping.
+fn exhaust_from_movement(
+    player: &EntityRef,
+    old_pos: Position,
+    new_pos: Position,
+    was_on_ground: bool,
+) -> SysResult {
+    let mut food = match player.get_mut::<Food>() {
+        Ok(food) => food,
+        Err(_) => return Ok(()),
+    };
+    let sprinting = player.get::<Sprinting>()?.0;
+    if sprinting {
+        let distance = (new_pos.x - old_pos.x).hypot(new_pos.z - old_pos.z);
+        food.add_exhaustion(SPRINT_EXHAUSTION_PER_METER * distance as f32);
+    }
+    let jumped = was_on_ground && !player.get::<OnGround>()?.0 && new_pos.y > old_pos.y;
+    if jumped {
+        food.add_exhaustion(if sprinting {
+            SPRINT_JUMP_EXHAUSTION
+        } else {
+            JUMP_EXHAUSTION
+        });
+    }
+    Ok(())
+}
+
+/// A position update from the client means it has
+/// finished loading the terrain around it.
+fn mark_client_ready(server: &Server, player: &EntityRef) -> SysResult {
+    if let Some(client) = server.clients.get(*player.get::<ClientId>()?) {
+        client.mark_ready();
+    }
+    Ok(())
+}
+
 fn update_client_position(server: &Server, player: EntityRef, pos: Position) -> SysResult {
     if let Some(client) = server.clients.get(*player.get::<ClientId>()?) {
         client.set_client_known_position(pos);
@@ -0,0 +1,93 @@
+//! Mounting, steering and dismounting vehicles.
+
+use base::{EntityKind, Position};
+use common::{
+    vehicle::{self, Passengers, Vehicle},
+    Game,
+};
+use ecs::{Entity, SysResult};
+use protocol::packets::client::{SteerVehicle, VehicleMove};
+
+use crate::Server;
+
+/// Flag set in `SteerVehicle` when the player wants to dismount.
+const DISMOUNT_FLAG: u8 = 0x02;
+
+/// Returns how many passengers an entity can carry.
+/// Entities which cannot be ridden return 0.
+fn max_passengers(kind: EntityKind) -> usize {
+    match kind {
+        EntityKind::Boat => 2,
+        EntityKind::Minecart => 1,
+        _ => 0,
+    }
+}
+
+/// Mounts `player` on `vehicle` if it can be ridden and has room.
+/// Returns whether the player was mounted.
+pub fn try_mount(game: &mut Game, player: Entity, vehicle: Entity) -> SysResult<bool> {
+    let kind = match game.ecs.get::<EntityKind>(vehicle) {
+        Ok(kind) => *kind,
+        Err(_) => return Ok(false),
+    };
+    let num_passengers = game
+        .ecs
+        .get::<Passengers>(vehicle)
+        .map_or(0, |passengers| passengers.0.len());
+    if num_passengers >= max_passengers(kind) {
+        return Ok(false);
+    }
+
+    vehicle::mount(game, player, vehicle)?;
+    Ok(true)
+}
+
+pub fn handle_steer_vehicle(
+    game: &mut Game,
+    server: &mut Server,
+    player: Entity,
+    packet: SteerVehicle,
+) -> SysResult {
+    if packet.flags & DISMOUNT_FLAG == 0 {
+        return Ok(());
+    }
+    let vehicle = match game.ecs.get::<Vehicle>(player) {
+        Ok(vehicle) => vehicle.0,
+        Err(_) => return Ok(()),
+    };
+    vehicle::dismount(game, player)?;
+
+    // Place the player on top of the vehicle
+    let mut position = *game.ecs.get::<Position>(player)?;
+    if let (Ok(vehicle_position), Ok(kind)) = (
+        game.ecs.get::<Position>(vehicle),
+        game.ecs.get::<EntityKind>(vehicle),
+    ) {
+        position.y = vehicle_position.y + kind.bounding_box().max.y;
+    }
+    server.teleport_player(game, player, position)
+}
+
+/// Moves a vehicle controlled by the player.
+pub fn handle_vehicle_move(game: &mut Game, player: Entity, packet: VehicleMove) -> SysResult {
+    let vehicle = match game.ecs.get::<Vehicle>(player) {
+        Ok(vehicle) => vehicle.0,
+        Err(_) => return Ok(()),
+    };
+    // Only the first passenger controls the vehicle
+    let is_driver = game
+        .ecs
+        .get::<Passengers>(vehicle)
+        .map_or(false, |passengers| passengers.0.first() == Some(&player));
+    if !is_driver {
+        return Ok(());
+    }
+
+    let mut position = game.ecs.get_mut::<Position>(vehicle)?;
+    position.x = packet.x;
+    position.y = packet.y;
+    position.z = packet.z;
+    position.yaw = packet.yaw;
+    position.pitch = packet.pitch;
+    Ok(())
+}
@@ -19,7 +19,7 @@ impl PlayerCount {
         Self {
             inner: Arc::new(Inner {
                 count: AtomicU32::new(0),
-                max_players,
+                max_players: AtomicU32::new(max_players),
             }),
         }
     }
@@ -28,7 +28,7 @@ impl PlayerCount {
         loop {
             let current_count = self.inner.count.load(Ordering::SeqCst);
             let new_count = current_count + 1;
-            if new_count > self.inner.max_players {
+            if new_count > self.inner.max_players.load(Ordering::SeqCst) {
                 return Err(MaxPlayersReached);
             }
 
@@ -47,6 +47,15 @@ impl PlayerCount {
         self.inner.count.fetch_sub(1, Ordering::SeqCst);
     }
 
+    /// Changes the maximum player count.
+    ///
+    /// Lowering the maximum below the current count
+    /// does not remove any players; new players are
+    /// rejected until enough players have left.
+    pub fn set_max_players(&self, max_players: u32) {
+        self.inner.max_players.store(max_players, Ordering::SeqCst);
+    }
+
     pub fn get(&self) -> u32 {
         self.inner.count.load(Ordering::Acquire)
     }
@@ -54,7 +63,7 @@ impl PlayerCount {
 
 struct Inner {
     count: AtomicU32,
-    max_players: u32,
+    max_players: AtomicU32,
 }
 
 #[cfg(test)]
@@ -76,6 +85,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn lowering_max_players_keeps_existing() {
+        let count = PlayerCount::new(3);
+        for _ in 0..3 {
+            count.try_add_player().unwrap();
+        }
+
+        count.set_max_players(1);
+        assert_eq!(count.get(), 3);
+        count.try_add_player().unwrap_err();
+
+        count.remove_player();
+        count.remove_player();
+        count.try_add_player().unwrap_err();
+        count.remove_player();
+        count.try_add_player().unwrap();
+        assert_eq!(count.get(), 1);
+    }
+
     #[test]
     fn no_race_conditions() {
         let threads = 8;
@@ -0,0 +1,207 @@
+//! Loads the config from a vanilla `server.properties` file,
+//! for servers migrating from the vanilla server.
+//!
+//! Keys missing from the file take their value from the
+//! default `config.toml`. Unknown keys are ignored with a warning.
+
+use std::{fs, net::IpAddr, path::Path, str::FromStr};
+
+use anyhow::{bail, Context};
+use base::{Difficulty, Gamemode};
+
+use crate::{
+    config::{self, Config},
+    Options,
+};
+
+impl Options {
+    /// Loads options from a `server.properties` file.
+    pub fn from_properties_file(path: impl AsRef<Path>) -> anyhow::Result<Options> {
+        Ok(Config::from_properties_file(path)?.to_options())
+    }
+}
+
+impl Config {
+    /// Loads a config from a `server.properties` file.
+    ///
+    /// Unlike [`Options::from_properties_file`], this includes
+    /// the world settings, such as `level-name` and `level-seed`.
+    pub fn from_properties_file(path: impl AsRef<Path>) -> anyhow::Result<Config> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Config::from_properties(&contents)
+            .with_context(|| format!("invalid properties file {}", path.display()))
+    }
+
+    /// Parses a config from the contents of a `server.properties` file.
+    pub fn from_properties(contents: &str) -> anyhow::Result<Config> {
+        let mut config = config::default_config();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim_start();
+            if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(separator) => (&line[..separator], &line[separator + 1..]),
+                None => (line, ""),
+            };
+            let key = key.trim();
+            let value = unescape(value.trim());
+            apply_property(&mut config, key, &value).with_context(|| {
+                format!(
+                    "invalid value {:?} for {} on line {}",
+                    value,
+                    key,
+                    index + 1
+                )
+            })?;
+        }
+        Ok(config)
+    }
+}
+
+fn apply_property(config: &mut Config, key: &str, value: &str) -> anyhow::Result<()> {
+    match key {
+        "motd" => config.server.motd = value.to_owned(),
+        "max-players" => config.server.max_players = value.parse()?,
+        "view-distance" => config.server.view_distance = value.parse()?,
+        "simulation-distance" => config.server.simulation_distance = value.parse()?,
+        "gamemode" => config.server.default_gamemode = parse_gamemode(value)?,
+        "force-gamemode" => config.server.force_gamemode = parse_bool(value)?,
+        "difficulty" => config.server.difficulty = parse_difficulty(value)?,
+        "online-mode" => config.server.online_mode = parse_bool(value)?,
+        "spawn-protection" => config.server.spawn_protection = value.parse()?,
+        "player-idle-timeout" => config.server.idle_timeout_mins = value.parse()?,
+        "server-port" => config.network.port = value.parse()?,
+        "server-ip" => {
+            // Empty means all interfaces, as in the default config
+            if !value.is_empty() {
+                config.network.address = IpAddr::from_str(value)?;
+            }
+        }
+        "network-compression-threshold" => config.network.compression_threshold = value.parse()?,
+        "level-name" => config.world.name = value.to_owned(),
+        "level-seed" => config.world.seed = value.to_owned(),
+        "level-type" => config.world.generator = parse_level_type(value)?.to_owned(),
+        _ => log::warn!("Ignoring unknown server.properties key {}", key),
+    }
+    Ok(())
+}
+
+fn parse_bool(value: &str) -> anyhow::Result<bool> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => bail!("expected true or false"),
+    }
+}
+
+fn parse_gamemode(value: &str) -> anyhow::Result<Gamemode> {
+    Ok(match value {
+        "survival" | "0" => Gamemode::Survival,
+        "creative" | "1" => Gamemode::Creative,
+        "adventure" | "2" => Gamemode::Adventure,
+        "spectator" | "3" => Gamemode::Spectator,
+        _ => bail!("expected one of survival, creative, adventure, spectator"),
+    })
+}
+
+fn parse_difficulty(value: &str) -> anyhow::Result<Difficulty> {
+    Ok(match value {
+        "peaceful" | "0" => Difficulty::Peaceful,
+        "easy" | "1" => Difficulty::Easy,
+        "normal" | "2" => Difficulty::Normal,
+        "hard" | "3" => Difficulty::Hard,
+        _ => bail!("expected one of peaceful, easy, normal, hard"),
+    })
+}
+
+/// Returns the world generator of the config for a vanilla level type.
+fn parse_level_type(value: &str) -> anyhow::Result<&'static str> {
+    let value = value.strip_prefix("minecraft:").unwrap_or(value);
+    Ok(match value.to_ascii_lowercase().as_str() {
+        "default" | "normal" => "default",
+        "flat" => "flat",
+        _ => bail!("expected default or flat"),
+    })
+}
+
+/// Resolves the escape sequences of the Java properties format,
+/// e.g. `\:` and `§` written by the vanilla server.
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('u') => {
+                let code: String = chars.by_ref().take(4).collect();
+                match u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+                    Some(c) => result.push(c),
+                    None => {
+                        result.push_str("\\u");
+                        result.push_str(&code);
+                    }
+                }
+            }
+            Some(c) => result.push(c),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROPERTIES: &str = r"#Minecraft server properties
+#Mon Oct 12 09:41:07 UTC 2026
+enable-jmx-monitoring=false
+gamemode=survival
+difficulty=hard
+spawn-protection=0
+max-players=40
+online-mode=false
+level-name=survival_world
+level-type=minecraft\:flat
+motd=§aA Minecraft Server
+server-port=25570
+server-ip=
+view-distance=8
+";
+
+    #[test]
+    fn vanilla_properties_are_applied() {
+        let config = Config::from_properties(PROPERTIES).unwrap();
+        assert_eq!(config.server.default_gamemode, Gamemode::Survival);
+        assert!(matches!(config.server.difficulty, Difficulty::Hard));
+        assert_eq!(config.server.spawn_protection, 0);
+        assert_eq!(config.server.max_players, 40);
+        assert!(!config.server.online_mode);
+        assert_eq!(config.world.name, "survival_world");
+        assert_eq!(config.world.generator, "flat");
+        assert_eq!(config.server.motd, "\u{a7}aA Minecraft Server");
+        assert_eq!(config.network.port, 25570);
+        assert_eq!(config.server.view_distance, 8);
+
+        // Missing keys keep their defaults
+        let default = config::default_config();
+        assert_eq!(config.network.address, default.network.address);
+        assert_eq!(config.server.force_gamemode, default.server.force_gamemode);
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let error = Config::from_properties("max-players=lots").unwrap_err();
+        assert!(format!("{:#}", error).contains("max-players on line 1"));
+        assert!(Config::from_properties("gamemode=hardcore").is_err());
+        assert!(Config::from_properties("online-mode=yes").is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::sync::Notify;
+
+/// Tracks whether the server has finished starting up.
+///
+/// Connections that complete login before the server
+/// is ready are held until it is.
+///
+/// Can be cloned to create a new handle.
+#[derive(Clone, Default)]
+pub struct Readiness {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    ready: AtomicBool,
+    notify: Notify,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.inner.ready.load(Ordering::Acquire)
+    }
+
+    /// Marks the server as ready, admitting any held connections.
+    pub fn mark_ready(&self) {
+        if !self.inner.ready.swap(true, Ordering::AcqRel) {
+            self.inner.notify.notify_waiters();
+        }
+    }
+
+    /// Waits until the server is ready or `timeout` elapses.
+    /// Returns whether the server is ready.
+    pub async fn wait(&self, timeout: Duration) -> bool {
+        // Create the future before checking the flag so that
+        // a concurrent `mark_ready` cannot be missed.
+        let notified = self.inner.notify.notified();
+        if self.is_ready() {
+            return true;
+        }
+        tokio::time::timeout(timeout, notified).await.is_ok() || self.is_ready()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn wait_times_out() {
+        let readiness = Readiness::default();
+        assert!(!readiness.wait(Duration::from_millis(10)).await);
+    }
+
+    #[tokio::test]
+    async fn wait_admits_once_ready() {
+        let readiness = Readiness::default();
+        let waiter = {
+            let readiness = readiness.clone();
+            tokio::spawn(async move { readiness.wait(Duration::from_secs(10)).await })
+        };
+        tokio::task::yield_now().await;
+        readiness.mark_ready();
+        assert!(waiter.await.unwrap());
+        assert!(readiness.wait(Duration::from_millis(0)).await);
+    }
+}
@@ -1,13 +1,19 @@
 //! Systems linking a `Server` and a `Game`.
 
-mod block;
+pub(crate) mod block;
 mod chat;
+pub mod digging;
 mod entity;
+mod experience;
 mod gamemode;
+mod health;
+mod item_pickup;
 mod particle;
-mod player_join;
+pub(crate) mod player_join;
 mod player_leave;
 mod plugin_message;
+pub mod save;
+pub mod simulation;
 mod tablist;
 pub mod view;
 
@@ -23,25 +29,43 @@ use crate::{client::ClientId, Server};
 pub fn register(server: Server, game: &mut Game, systems: &mut SystemExecutor<Game>) {
     game.insert_resource(server);
 
+    systems.group::<Server>().add_system(mark_ready);
     player_join::register(systems);
     systems
         .group::<Server>()
         .add_system(handle_packets)
-        .add_system(send_keepalives);
+        .add_system(send_keepalives)
+        .add_system(disconnect_timed_out_clients)
+        .add_system(kick_idle_clients);
     view::register(game, systems);
     crate::chunk_subscriptions::register(systems);
     player_leave::register(systems);
     tablist::register(systems);
     block::register(systems);
+    digging::register(systems);
+    // Before entity removals are broadcast
+    item_pickup::register(systems);
+    experience::register(systems);
     entity::register(game, systems);
     chat::register(game, systems);
     particle::register(systems);
     plugin_message::register(systems);
     gamemode::register(systems);
+    health::register(systems);
+    save::register(systems);
 
     systems.group::<Server>().add_system(tick_clients);
 }
 
+/// Admits players once the game has run its first tick,
+/// i.e. after the world and plugins have been set up.
+fn mark_ready(_game: &mut Game, server: &mut Server) -> SysResult {
+    if !server.is_ready() {
+        server.mark_ready();
+    }
+    Ok(())
+}
+
 /// Polls for packets received from clients
 /// and handles them.
 fn handle_packets(game: &mut Game, server: &mut Server) -> SysResult {
@@ -77,6 +101,45 @@ fn send_keepalives(_game: &mut Game, server: &mut Server) -> SysResult {
     Ok(())
 }
 
+/// Time after which a client that has not responded
+/// to keepalives is disconnected.
+const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Disconnects clients that have not responded to keepalives
+/// within [`KEEPALIVE_TIMEOUT`]. Keepalive-exempt clients are skipped.
+fn disconnect_timed_out_clients(_game: &mut Game, server: &mut Server) -> SysResult {
+    for client in server.clients.iter() {
+        if client.is_keepalive_exempt() || client.is_disconnected() {
+            continue;
+        }
+        if client.last_keepalive_response().elapsed() > KEEPALIVE_TIMEOUT {
+            log::debug!("{} timed out", client.username());
+            client.disconnect("Timed out");
+        }
+    }
+    Ok(())
+}
+
+/// Disconnects clients that have not been active within the idle
+/// timeout, except operators if they are exempt from it.
+fn kick_idle_clients(_game: &mut Game, server: &mut Server) -> SysResult {
+    let options = server.options();
+    let timeout = match options.idle_timeout {
+        Some(timeout) => timeout,
+        None => return Ok(()),
+    };
+    for client in server.clients.iter() {
+        if client.is_disconnected() || (options.idle_timeout_exempts_ops && client.is_op(1)) {
+            continue;
+        }
+        if client.last_activity().elapsed() > timeout {
+            log::info!("Kicking {} for inactivity", client.username());
+            client.disconnect("Kicked for inactivity");
+        }
+    }
+    Ok(())
+}
+
 /// Ticks `Client`s.
 fn tick_clients(_game: &mut Game, server: &mut Server) -> SysResult {
     for client in server.clients.iter() {
@@ -85,3 +148,80 @@ fn tick_clients(_game: &mut Game, server: &mut Server) -> SysResult {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use base::Text;
+
+    use super::*;
+    use crate::tests::{join_test_op, join_test_player, test_game, test_server};
+
+    const IDLE_TIMEOUT: Duration = Duration::from_millis(20);
+
+    fn set_idle_timeout(server: &Server, timeout: Option<Duration>, exempts_ops: bool) {
+        let mut options = (*server.options()).clone();
+        options.idle_timeout = timeout;
+        options.idle_timeout_exempts_ops = exempts_ops;
+        server.reload_options(options);
+    }
+
+    fn kick_reason() -> Option<String> {
+        Some(Text::from("Kicked for inactivity".to_owned()).to_string())
+    }
+
+    #[test]
+    fn idle_players_are_kicked() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        set_idle_timeout(&server, Some(IDLE_TIMEOUT), true);
+        let idle = join_test_player(&mut game, &mut server, &new_players);
+        let active = join_test_player(&mut game, &mut server, &new_players);
+
+        std::thread::sleep(IDLE_TIMEOUT * 2);
+        server.clients.get(active.client_id).unwrap().mark_active();
+        kick_idle_clients(&mut game, &mut server).unwrap();
+
+        let client = |id| server.clients.get(id).unwrap();
+        assert_eq!(client(idle.client_id).disconnect_reason(), kick_reason());
+        assert!(!client(active.client_id).is_disconnected());
+    }
+
+    #[test]
+    fn ops_are_exempt_from_the_idle_timeout() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        set_idle_timeout(&server, Some(IDLE_TIMEOUT), true);
+        let op = join_test_op(&mut game, &mut server, &new_players, 1);
+
+        std::thread::sleep(IDLE_TIMEOUT * 2);
+        kick_idle_clients(&mut game, &mut server).unwrap();
+        assert!(!server.clients.get(op.client_id).unwrap().is_disconnected());
+
+        set_idle_timeout(&server, Some(IDLE_TIMEOUT), false);
+        kick_idle_clients(&mut game, &mut server).unwrap();
+        assert_eq!(
+            server
+                .clients
+                .get(op.client_id)
+                .unwrap()
+                .disconnect_reason(),
+            kick_reason()
+        );
+    }
+
+    #[test]
+    fn no_idle_timeout_kicks_nobody() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        set_idle_timeout(&server, None, false);
+        let player = join_test_player(&mut game, &mut server, &new_players);
+
+        std::thread::sleep(IDLE_TIMEOUT * 2);
+        kick_idle_clients(&mut game, &mut server).unwrap();
+        assert!(!server
+            .clients
+            .get(player.client_id)
+            .unwrap()
+            .is_disconnected());
+    }
+}
@@ -12,10 +12,25 @@
 //! Feather is optimized for bulk block updates to cater to plugins
 //! like WorldEdit. This module chooses the optimal packet from
 //! the above three options to achieve ideal performance.
+//! Edits made during a tick are grouped by chunk section, so a section
+//! with several changed blocks receives a single `MultiBlockChange`.
+//! Sections with at least `section_overwrite_threshold` changed blocks
+//! are overwritten instead, with one `ChunkData` per chunk.
+//! No sounds or particles are sent for the changed blocks.
+//!
+//! Since 1.16, light is sent separately from blocks, so light is
+//! updated around the changed blocks (see `common::light`) and the
+//! sections whose light changed are sent after the block changes,
+//! once per chunk. These may include sections of neighboring chunks.
+
+use std::convert::TryFrom;
 
 use ahash::AHashMap;
-use base::{chunk::SECTION_VOLUME, position, ChunkPosition, CHUNK_WIDTH};
-use common::{events::BlockChangeEvent, Game};
+use base::{
+    chunk::SECTION_HEIGHT, position, BlockId, BlockPosition, ChunkPosition, ValidBlockPosition,
+    CHUNK_WIDTH,
+};
+use common::{events::BlockChangeEvent, light, Game};
 use ecs::{SysResult, SystemExecutor};
 
 use crate::Server;
@@ -27,56 +42,320 @@ pub fn register(systems: &mut SystemExecutor<Game>) {
 }
 
 fn broadcast_block_changes(game: &mut Game, server: &mut Server) -> SysResult {
+    // Changes are accumulated across all events of this tick
+    // so that edits within the same chunk section share one packet.
+    let mut changes = SectionChanges::new(server.options().section_overwrite_threshold);
     for (_, event) in game.ecs.query::<&BlockChangeEvent>().iter() {
-        broadcast_block_change(event, game, server);
+        changes.add_event(event);
     }
+    changes.broadcast(game, server);
     Ok(())
 }
 
-/// Threshold at which to switch from block change to chunk
-// overwrite packets.
-const CHUNK_OVERWRITE_THRESHOLD: usize = SECTION_VOLUME / 2;
+/// Changed blocks grouped by chunk section.
+pub(crate) struct SectionChanges {
+    overwrite_threshold: usize,
+    /// Changed blocks by chunk and section index.
+    blocks: AHashMap<(ChunkPosition, usize), Vec<ValidBlockPosition>>,
+    /// Sections overwritten in their entirety, by chunk.
+    /// Indices account for the section below the world.
+    overwritten: AHashMap<ChunkPosition, Vec<usize>>,
+}
+
+impl SectionChanges {
+    pub(crate) fn new(overwrite_threshold: usize) -> Self {
+        Self {
+            overwrite_threshold,
+            blocks: AHashMap::new(),
+            overwritten: AHashMap::new(),
+        }
+    }
+
+    pub(crate) fn add_event(&mut self, event: &BlockChangeEvent) {
+        if event.count() < self.overwrite_threshold {
+            self.add_blocks(event.iter_changed_blocks());
+            return;
+        }
+        // Large events, e.g. filled sections, are not
+        // iterated block by block unless needed.
+        let mut partial = false;
+        for (chunk, section, count) in event.iter_affected_chunk_sections() {
+            if count >= self.overwrite_threshold {
+                self.overwrite(chunk, section);
+            } else {
+                partial = true;
+            }
+        }
+        if partial {
+            self.add_blocks(event.iter_changed_blocks());
+        }
+    }
+
+    pub(crate) fn add_blocks(&mut self, positions: impl IntoIterator<Item = ValidBlockPosition>) {
+        for pos in positions {
+            let section = pos.y() as usize / SECTION_HEIGHT;
+            self.blocks
+                .entry((pos.chunk(), section))
+                .or_default()
+                .push(pos);
+        }
+    }
+
+    fn overwrite(&mut self, chunk: ChunkPosition, section: usize) {
+        let sections = self.overwritten.entry(chunk).or_default();
+        // + 1 to account for the void air chunk
+        if !sections.contains(&(section + 1)) {
+            sections.push(section + 1);
+        }
+    }
+
+    /// Moves sections with enough changed blocks to the overwritten
+    /// sections, and drops changed blocks of overwritten sections.
+    fn coalesce(&mut self) {
+        let threshold = self.overwrite_threshold;
+        let mut overwrites = Vec::new();
+        self.blocks.retain(|&(chunk, section), positions| {
+            positions.sort_unstable();
+            positions.dedup();
+            if positions.len() >= threshold {
+                overwrites.push((chunk, section));
+                false
+            } else {
+                true
+            }
+        });
+        for (chunk, section) in overwrites {
+            self.overwrite(chunk, section);
+        }
+
+        let overwritten = &self.overwritten;
+        self.blocks.retain(|(chunk, section), _| {
+            overwritten
+                .get(chunk)
+                .map_or(true, |sections| !sections.contains(&(section + 1)))
+        });
+    }
+
+    pub(crate) fn broadcast(mut self, game: &Game, server: &Server) {
+        self.coalesce();
+
+        let mut changed_blocks = Vec::new();
+        for (&chunk, sections) in &self.overwritten {
+            broadcast_chunk_overwrite(chunk, sections, game, server);
+            for &section in sections {
+                changed_blocks.extend(section_blocks(chunk, section - 1));
+            }
+        }
+        for (&(chunk, section), positions) in &self.blocks {
+            broadcast_section_block_changes(chunk, section, positions, game, server);
+            changed_blocks.extend(positions.iter().copied());
+        }
+
+        if changed_blocks.is_empty() {
+            return;
+        }
+        let light_sections = light::update_light(game.world.chunk_map(), changed_blocks);
+        for (chunk, sections) in light_sections {
+            broadcast_light_update(chunk, sections, game, server);
+        }
+    }
+}
+
+/// Returns the positions of all blocks in the given chunk section.
+fn section_blocks(
+    chunk: ChunkPosition,
+    section: usize,
+) -> impl Iterator<Item = ValidBlockPosition> {
+    let min_x = chunk.x * CHUNK_WIDTH as i32;
+    let min_y = (section * SECTION_HEIGHT) as i32;
+    let min_z = chunk.z * CHUNK_WIDTH as i32;
+    (0..SECTION_HEIGHT as i32).flat_map(move |y| {
+        (0..CHUNK_WIDTH as i32).flat_map(move |z| {
+            (0..CHUNK_WIDTH as i32).filter_map(move |x| {
+                ValidBlockPosition::try_from(BlockPosition::new(min_x + x, min_y + y, min_z + z))
+                    .ok()
+            })
+        })
+    })
+}
+
+/// Returns a position in the given chunk and section,
+/// used to find the clients observing it.
+fn section_position(chunk: ChunkPosition, section: usize) -> base::Position {
+    position!(
+        (chunk.x * CHUNK_WIDTH as i32) as f64,
+        (section * SECTION_HEIGHT) as f64,
+        (chunk.z * CHUNK_WIDTH as i32) as f64,
+    )
+}
 
-fn broadcast_block_change(event: &BlockChangeEvent, game: &Game, server: &mut Server) {
-    if event.count() >= CHUNK_OVERWRITE_THRESHOLD {
-        broadcast_block_change_chunk_overwrite(event, game, server);
-    } else {
-        broadcast_block_change_simple(event, game, server);
+/// Sends the light of the given sections of a chunk to its observers.
+fn broadcast_light_update(
+    chunk_pos: ChunkPosition,
+    sections: Vec<usize>,
+    game: &Game,
+    server: &Server,
+) {
+    if let Some(chunk) = game.world.chunk_map().chunk_handle_at(chunk_pos) {
+        server.broadcast_nearby_with(section_position(chunk_pos, 0), |client| {
+            client.send_light_update(&chunk, sections.clone());
+        });
     }
 }
 
-fn broadcast_block_change_chunk_overwrite(
-    event: &BlockChangeEvent,
+fn broadcast_chunk_overwrite(
+    chunk_pos: ChunkPosition,
+    sections: &[usize],
     game: &Game,
-    server: &mut Server,
+    server: &Server,
 ) {
-    let mut sections: AHashMap<ChunkPosition, Vec<usize>> = AHashMap::new();
-    for (chunk, section, _) in event.iter_affected_chunk_sections() {
-        sections.entry(chunk).or_default().push(section + 1); // + 1 to account for the void air chunk
+    if let Some(chunk) = game.world.chunk_map().chunk_handle_at(chunk_pos) {
+        server.broadcast_nearby_with(section_position(chunk_pos, 0), |client| {
+            client.overwrite_chunk_sections(&chunk, sections.to_vec());
+        })
     }
+}
 
-    for (chunk_pos, sections) in sections {
-        let chunk = game.world.chunk_map().chunk_handle_at(chunk_pos);
-        if let Some(chunk) = chunk {
-            let position = position!(
-                (chunk_pos.x * CHUNK_WIDTH as i32) as f64,
-                0.0,
-                (chunk_pos.z * CHUNK_WIDTH as i32) as f64,
-            );
-            server.broadcast_nearby_with(position, |client| {
-                client.overwrite_chunk_sections(&chunk, sections.clone());
-            })
-        }
+fn broadcast_section_block_changes(
+    chunk: ChunkPosition,
+    section: usize,
+    positions: &[ValidBlockPosition],
+    game: &Game,
+    server: &Server,
+) {
+    let changes: Vec<(ValidBlockPosition, BlockId)> = positions
+        .iter()
+        .filter_map(|&pos| game.block(pos).map(|block| (pos, block)))
+        .collect();
+
+    match changes.as_slice() {
+        [] => {}
+        [(pos, new_block)] => server.broadcast_nearby_with(pos.position(), |client| {
+            client.send_block_change(*pos, *new_block)
+        }),
+        _ => server.broadcast_nearby_with(section_position(chunk, section), |client| {
+            client.send_multi_block_change(chunk, section, &changes)
+        }),
     }
 }
 
-fn broadcast_block_change_simple(event: &BlockChangeEvent, game: &Game, server: &mut Server) {
-    for pos in event.iter_changed_blocks() {
-        let new_block = game.block(pos);
-        if let Some(new_block) = new_block {
-            server.broadcast_nearby_with(pos.position(), |client| {
-                client.send_block_change(pos, new_block)
-            });
+#[cfg(test)]
+mod tests {
+    use base::Chunk;
+    use common::view::View;
+    use protocol::ServerPlayPacket;
+
+    use super::*;
+    use crate::tests::{add_watching_client, test_server};
+
+    fn block(x: i32, y: i32, z: i32) -> ValidBlockPosition {
+        ValidBlockPosition::try_from(BlockPosition::new(x, y, z)).unwrap()
+    }
+
+    #[test]
+    fn bulk_edits_are_coalesced_per_section() {
+        let mut changes = SectionChanges::new(1024);
+        // A 32x32x32 cube: eight full sections across four chunks
+        changes.add_blocks(
+            (0..32).flat_map(|x| (0..32).flat_map(move |y| (0..32).map(move |z| block(x, y, z)))),
+        );
+        // and a few blocks elsewhere
+        changes.add_blocks(vec![block(100, 5, 0), block(101, 5, 0), block(200, 5, 0)]);
+        changes.coalesce();
+
+        // 32768 changed blocks are sent in 4 + 2 packets to each client
+        assert_eq!(changes.overwritten.len(), 4);
+        for sections in changes.overwritten.values() {
+            let mut sections = sections.clone();
+            sections.sort_unstable();
+            assert_eq!(sections, vec![1, 2]);
+        }
+        assert_eq!(changes.blocks.len(), 2);
+    }
+
+    #[test]
+    fn sections_below_threshold_are_sent_block_by_block() {
+        let mut changes = SectionChanges::new(4);
+        changes.add_blocks(vec![block(0, 0, 0), block(0, 0, 0), block(1, 0, 0)]);
+        changes.add_blocks((0..4).map(|x| block(x, 16, 0)));
+        changes.coalesce();
+
+        assert_eq!(
+            changes.blocks.get(&(ChunkPosition::new(0, 0), 0)),
+            Some(&vec![block(0, 0, 0), block(1, 0, 0)])
+        );
+        assert_eq!(
+            changes.overwritten.get(&ChunkPosition::new(0, 0)),
+            Some(&vec![2])
+        );
+    }
+
+    #[test]
+    fn placed_light_sources_reach_observers_lit() {
+        let (mut server, _) = test_server();
+        let mut game = Game::new();
+        let chunk_pos = ChunkPosition::new(0, 0);
+        game.world
+            .chunk_map_mut()
+            .insert_chunk(Chunk::new(chunk_pos));
+        let (client_id, packets) = add_watching_client(&mut server, View::new(chunk_pos, 1));
+        let client = server.clients.get(client_id).unwrap();
+        client.send_chunk(&game.world.chunk_map().chunk_handle_at(chunk_pos).unwrap());
+        client.tick();
+        packets.try_iter().for_each(drop);
+
+        game.set_block(block(0, 64, 0), BlockId::glowstone());
+        broadcast_block_changes(&mut game, &mut server).unwrap();
+
+        let packets: Vec<_> = packets.try_iter().collect();
+        match packets.as_slice() {
+            [ServerPlayPacket::BlockChange(_), ServerPlayPacket::UpdateLight(light)] => {
+                // The section at y = 64, after the one below the world
+                assert!(light.sections.as_ref().unwrap().contains(&5));
+                let chunk = light.chunk.read();
+                assert_eq!(chunk.block_light_at(0, 64, 0), Some(15));
+                assert_eq!(chunk.block_light_at(1, 64, 0), Some(14));
+            }
+            packets => panic!("expected BlockChange and UpdateLight, got {:?}", packets),
+        }
+    }
+
+    #[test]
+    fn light_crossing_chunk_borders_is_sent_for_both_chunks() {
+        let (mut server, _) = test_server();
+        let mut game = Game::new();
+        let (client_id, packets) =
+            add_watching_client(&mut server, View::new(ChunkPosition::new(0, 0), 1));
+        let client = server.clients.get(client_id).unwrap();
+        for x in 0..2 {
+            let chunk_pos = ChunkPosition::new(x, 0);
+            let mut chunk = Chunk::new(chunk_pos);
+            // Air-only sections store no light
+            chunk.set_block_at(8, 72, 8, BlockId::stone());
+            game.world.chunk_map_mut().insert_chunk(chunk);
+            client.send_chunk(&game.world.chunk_map().chunk_handle_at(chunk_pos).unwrap());
+        }
+        client.tick();
+        packets.try_iter().for_each(drop);
+
+        game.set_block(block(15, 64, 0), BlockId::glowstone());
+        broadcast_block_changes(&mut game, &mut server).unwrap();
+
+        let mut lit_chunks = Vec::new();
+        for packet in packets.try_iter() {
+            if let ServerPlayPacket::UpdateLight(light) = packet {
+                let chunk = light.chunk.read();
+                lit_chunks.push(chunk.position());
+                if chunk.position() == ChunkPosition::new(1, 0) {
+                    assert_eq!(chunk.block_light_at(0, 64, 0), Some(14));
+                    assert_eq!(light.sections, Some(vec![5]));
+                }
+            }
         }
+        lit_chunks.sort_by_key(|chunk| chunk.x);
+        assert_eq!(
+            lit_chunks,
+            vec![ChunkPosition::new(0, 0), ChunkPosition::new(1, 0)]
+        );
     }
 }
@@ -0,0 +1,149 @@
+//! Timed block breaking for players who cannot break blocks instantly.
+//!
+//! A player digging a block has the [`Digging`] component. Its progress
+//! is advanced every tick based on the block and the held tool,
+//! and the block break animation is shown to nearby players.
+
+use base::{inventory::SLOT_HOTBAR_OFFSET, BlockId, Item, ValidBlockPosition};
+use common::{entities::player::HotbarSlot, Game, Window};
+use ecs::{Entity, SysResult, SystemExecutor};
+
+use crate::{NetworkId, Server};
+
+/// Fraction of a block which must have been dug before
+/// the server accepts that the client finished digging it.
+/// Allows for latency between the client and the server.
+pub const FINISH_DIGGING_THRESHOLD: f32 = 0.7;
+
+/// Component for a player digging a block which
+/// cannot be broken instantly.
+#[derive(Copy, Clone, Debug)]
+pub struct Digging {
+    pub position: ValidBlockPosition,
+    /// Fraction of the block dug so far. The block
+    /// is broken once this reaches 1.
+    pub progress: f32,
+    /// The destroy stage last sent to nearby players.
+    sent_stage: Option<u8>,
+}
+
+impl Digging {
+    pub fn new(position: ValidBlockPosition) -> Self {
+        Self {
+            position,
+            progress: 0.,
+            sent_stage: None,
+        }
+    }
+
+    /// Returns the destroy stage (0 to 9) of the block break animation.
+    pub fn stage(&self) -> u8 {
+        ((self.progress * 10.) as u8).min(9)
+    }
+}
+
+pub fn register(systems: &mut SystemExecutor<Game>) {
+    systems.group::<Server>().add_system(advance_digging);
+}
+
+/// Returns the fraction of `block` dug each tick
+/// by a player holding `tool`.
+///
+/// See <https://minecraft.fandom.com/wiki/Breaking#Speed>.
+pub fn dig_progress_per_tick(block: BlockId, tool: Option<Item>) -> f32 {
+    let kind = block.kind();
+    if !kind.diggable() {
+        // Unbreakable, e.g. bedrock
+        return 0.;
+    }
+    let hardness = kind.hardness();
+    if hardness == 0. {
+        return 1.;
+    }
+
+    let speed = tool
+        .and_then(|tool| {
+            kind.dig_multipliers()
+                .iter()
+                .find(|(item, _)| *item == tool)
+                .map(|(_, multiplier)| *multiplier)
+        })
+        .unwrap_or(1.);
+    let can_harvest = match kind.harvest_tools() {
+        Some(tools) => tool.map_or(false, |tool| tools.contains(&tool)),
+        None => true,
+    };
+
+    speed / hardness / if can_harvest { 30. } else { 100. }
+}
+
+/// Returns the kind of the item in the player's main hand.
+pub fn held_item(game: &Game, player: Entity) -> anyhow::Result<Option<Item>> {
+    let window = game.ecs.get::<Window>(player)?;
+    let hotbar_slot = game.ecs.get::<HotbarSlot>(player)?.get();
+    let item = window.item(SLOT_HOTBAR_OFFSET + hotbar_slot)?.item_kind();
+    Ok(item)
+}
+
+/// Stops the player digging, removing the block
+/// break animation shown to nearby players.
+pub fn stop_digging(game: &mut Game, server: &Server, player: Entity) -> SysResult {
+    if let Ok(digging) = game.ecs.remove::<Digging>(player) {
+        let network_id = *game.ecs.get::<NetworkId>(player)?;
+        broadcast_stage(server, network_id, digging.position, None);
+    }
+    Ok(())
+}
+
+/// System to advance the progress of players digging blocks.
+pub(crate) fn advance_digging(game: &mut Game, server: &mut Server) -> SysResult {
+    for (player, (digging, &network_id)) in game.ecs.query::<(&mut Digging, &NetworkId)>().iter() {
+        let block = match game.block(digging.position) {
+            Some(block) => block,
+            None => continue,
+        };
+        digging.progress += dig_progress_per_tick(block, held_item(game, player)?);
+
+        let stage = digging.stage();
+        if digging.sent_stage != Some(stage) {
+            digging.sent_stage = Some(stage);
+            broadcast_stage(server, network_id, digging.position, Some(stage));
+        }
+    }
+    Ok(())
+}
+
+/// Sends the block break animation to players near the block,
+/// except the digger, whose client displays it already.
+fn broadcast_stage(
+    server: &Server,
+    network_id: NetworkId,
+    position: ValidBlockPosition,
+    stage: Option<u8>,
+) {
+    server.broadcast_nearby_with(position.position(), |client| {
+        if client.network_id() != Some(network_id) {
+            client.send_block_break_animation(network_id, position, stage);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tools_speed_up_digging() {
+        let stone = BlockId::stone();
+        let by_hand = dig_progress_per_tick(stone, None);
+        let with_pickaxe = dig_progress_per_tick(stone, Some(Item::IronPickaxe));
+        assert!(with_pickaxe > by_hand);
+        assert!(by_hand > 0.);
+    }
+
+    #[test]
+    fn instant_and_unbreakable_blocks() {
+        assert_eq!(dig_progress_per_tick(BlockId::tall_grass(), None), 1.);
+        assert_eq!(dig_progress_per_tick(BlockId::bedrock(), None), 0.);
+    }
+}
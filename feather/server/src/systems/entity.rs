@@ -5,16 +5,20 @@ use base::{
     metadata::{EntityBitMask, Pose, META_INDEX_ENTITY_BITMASK, META_INDEX_POSE},
     EntityMetadata, Position,
 };
-use common::Game;
-use ecs::{SysResult, SystemExecutor};
+use common::{
+    events::{DamageEvent, PassengersChangeEvent},
+    vehicle::Passengers,
+    Game, Velocity,
+};
+use ecs::{Entity, SysResult, SystemExecutor};
 use quill_common::{
-    components::{OnGround, Sprinting},
-    events::{SneakEvent, SprintEvent},
+    components::{Health, OnGround, Sprinting},
+    events::{EntityRemoveEvent, SneakEvent, SprintEvent},
 };
 
 use crate::{
-    entities::{PreviousOnGround, PreviousPosition},
-    NetworkId, Server,
+    entities::{PreviousOnGround, PreviousPosition, RelativeMovesSinceTeleport},
+    entity_status, NetworkId, Server,
 };
 
 mod spawn_packet;
@@ -25,33 +29,53 @@ pub fn register(game: &mut Game, systems: &mut SystemExecutor<Game>) {
         .group::<Server>()
         .add_system(send_entity_movement)
         .add_system(send_entity_sneak_metadata)
-        .add_system(send_entity_sprint_metadata);
+        .add_system(send_entity_sprint_metadata)
+        .add_system(send_knockback_velocity)
+        .add_system(send_damage_statuses)
+        .add_system(send_passenger_changes)
+        .add_system(free_network_ids);
 }
 
+/// Number of relative movement updates after which an absolute
+/// `EntityTeleport` is sent to correct any desync on clients.
+const MAX_RELATIVE_MOVES_BETWEEN_TELEPORTS: u32 = 400;
+
 /// Sends entity movement packets.
 fn send_entity_movement(game: &mut Game, server: &mut Server) -> SysResult {
-    for (_, (&position, prev_position, &on_ground, &network_id, prev_on_ground)) in game
-        .ecs
-        .query::<(
-            &Position,
-            &mut PreviousPosition,
-            &OnGround,
-            &NetworkId,
-            &mut PreviousOnGround,
-        )>()
-        .iter()
+    for (_, (&position, prev_position, &on_ground, &network_id, prev_on_ground, relative_moves)) in
+        game.ecs
+            .query::<(
+                &Position,
+                &mut PreviousPosition,
+                &OnGround,
+                &NetworkId,
+                &mut PreviousOnGround,
+                &mut RelativeMovesSinceTeleport,
+            )>()
+            .iter()
     {
-        if position != prev_position.0 {
-            server.broadcast_nearby_with(position, |client| {
+        // A change in on-ground state alone (e.g. landing) must
+        // also be broadcast, or remote players appear to float.
+        if position != prev_position.0 || on_ground != prev_on_ground.0 {
+            let force_teleport = relative_moves.0 >= MAX_RELATIVE_MOVES_BETWEEN_TELEPORTS;
+            // Congested clients miss relative moves; the periodic
+            // teleport puts the entity back in the right place.
+            server.broadcast_nearby_nonessential_with(position, |client| {
                 client.update_entity_position(
                     network_id,
                     position,
                     *prev_position,
                     on_ground,
                     *prev_on_ground,
+                    force_teleport,
                 );
             });
             prev_position.0 = position;
+            if force_teleport {
+                relative_moves.0 = 0;
+            } else {
+                relative_moves.0 += 1;
+            }
         }
         if on_ground != prev_on_ground.0 {
             prev_on_ground.0 = on_ground;
@@ -107,3 +131,115 @@ fn send_entity_sprint_metadata(game: &mut Game, server: &mut Server) -> SysResul
     }
     Ok(())
 }
+
+/// Sends the velocity of entities knocked back this tick.
+fn send_knockback_velocity(game: &mut Game, server: &mut Server) -> SysResult {
+    for (_, (_event, &velocity, &position, &network_id)) in game
+        .ecs
+        .query::<(&DamageEvent, &Velocity, &Position, &NetworkId)>()
+        .iter()
+    {
+        server.broadcast_velocity(position, network_id, velocity);
+    }
+    Ok(())
+}
+
+/// Plays the hurt animation of entities damaged this tick,
+/// or the death animation if they died.
+fn send_damage_statuses(game: &mut Game, server: &mut Server) -> SysResult {
+    for (_, (_event, &position, &network_id, health)) in game
+        .ecs
+        .query::<(&DamageEvent, &Position, &NetworkId, Option<&Health>)>()
+        .iter()
+    {
+        let status = match health {
+            Some(health) if health.0 <= 0.0 => entity_status::LIVING_DEATH,
+            _ => entity_status::LIVING_HURT,
+        };
+        server.broadcast_entity_status(position, network_id, status);
+    }
+    Ok(())
+}
+
+/// Sends the passengers of vehicles which were mounted or dismounted this tick.
+fn send_passenger_changes(game: &mut Game, server: &mut Server) -> SysResult {
+    for (vehicle, (_event, &position, &network_id)) in game
+        .ecs
+        .query::<(&PassengersChangeEvent, &Position, &NetworkId)>()
+        .iter()
+    {
+        let passengers = passenger_network_ids(game, vehicle);
+        server.broadcast_set_passengers(position, network_id, &passengers);
+    }
+    Ok(())
+}
+
+/// Returns the network IDs of the entities riding `vehicle`.
+pub fn passenger_network_ids(game: &Game, vehicle: Entity) -> Vec<NetworkId> {
+    match game.ecs.get::<Passengers>(vehicle) {
+        Ok(passengers) => passengers
+            .0
+            .iter()
+            .filter_map(|&passenger| game.ecs.get::<NetworkId>(passenger).ok().map(|id| *id))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Frees the network IDs of removed entities
+/// once they have been unloaded on clients.
+fn free_network_ids(game: &mut Game, server: &mut Server) -> SysResult {
+    let mut network_ids = server.network_ids.borrow_mut();
+    for (_, (_event, &network_id)) in game.ecs.query::<(&EntityRemoveEvent, &NetworkId)>().iter() {
+        network_ids.free(network_id);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::{
+        packets::client::{InteractEntity, InteractEntityKind},
+        ClientPlayPacket, ServerPlayPacket,
+    };
+
+    use super::*;
+    use crate::{
+        packet_handlers::handle_packet,
+        tests::{join_test_player, test_game, test_server},
+    };
+
+    #[test]
+    fn killed_players_play_the_death_animation_for_watchers() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let attacker = join_test_player(&mut game, &mut server, &new_players);
+        let target = join_test_player(&mut game, &mut server, &new_players).entity;
+        let network_id = *game.ecs.get::<NetworkId>(target).unwrap();
+        server.clients.get(attacker.client_id).unwrap().mark_ready();
+        *game.ecs.get_mut::<Health>(target).unwrap() = Health(1.0);
+        attacker.connection.sent_packets.try_iter().for_each(drop);
+
+        let attack = ClientPlayPacket::InteractEntity(InteractEntity {
+            entity_id: network_id.0,
+            kind: InteractEntityKind::Attack,
+            sneaking: false,
+        });
+        handle_packet(&mut game, &mut server, attacker.entity, attack).unwrap();
+        assert_eq!(game.ecs.get::<Health>(target).unwrap().0, 0.0);
+        send_damage_statuses(&mut game, &mut server).unwrap();
+
+        let statuses: Vec<_> = attacker
+            .connection
+            .sent_packets
+            .try_iter()
+            .filter_map(|packet| match packet {
+                ServerPlayPacket::EntityStatus(status) if status.entity_id == network_id.0 => {
+                    Some(status.status as u8)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(statuses, vec![entity_status::LIVING_DEATH]);
+    }
+}
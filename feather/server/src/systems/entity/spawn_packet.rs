@@ -1,80 +1,218 @@
+use std::mem;
+
 use ahash::AHashSet;
 use anyhow::Context;
-use base::Position;
-use common::{
-    events::{ChunkCrossEvent, ViewUpdateEvent},
-    Game,
-};
-use ecs::{SysResult, SystemExecutor};
+use base::{ChunkPosition, EntityKind, Gamemode, Position, CHUNK_WIDTH};
+use common::{events::ChunkCrossEvent, vehicle::Passengers, view::View, Game};
+use ecs::{Entity, SysResult, SystemExecutor};
 use quill_common::events::{EntityCreateEvent, EntityRemoveEvent};
 
-use crate::{entities::SpawnPacketSender, ClientId, NetworkId, Server};
+use crate::{
+    entities::SpawnPacketSender, options::EntityTrackingRanges, Client, ClientId, NetworkId, Server,
+};
 
 pub fn register(_game: &mut Game, systems: &mut SystemExecutor<Game>) {
     systems
         .group::<Server>()
-        .add_system(update_visible_entities)
-        .add_system(send_entities_when_created)
         .add_system(unload_entities_when_removed)
-        .add_system(update_entities_on_chunk_cross);
+        .add_system(update_tracked_entities);
+}
+
+/// The chunks in which entities may be within
+/// tracking range of a player.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct TrackingArea {
+    center: ChunkPosition,
+    view: View,
+    chunk_radius: i32,
+}
+
+impl TrackingArea {
+    fn contains(&self, chunk: ChunkPosition) -> bool {
+        (chunk.x - self.center.x).abs() <= self.chunk_radius
+            && (chunk.z - self.center.z).abs() <= self.chunk_radius
+            && self.view.contains(chunk)
+    }
+}
+
+/// Component storing the entities in the [`TrackingArea`] of a player.
+///
+/// The area is rescanned only when the player's chunk or view,
+/// or the tracking ranges, change. Otherwise entities are
+/// added and removed as they enter and leave its chunks.
+#[derive(Debug, Default)]
+struct TrackingCandidates {
+    area: Option<TrackingArea>,
+    entities: AHashSet<Entity>,
 }
 
-/// System to spawn entities on clients when they become visible,
-/// and despawn entities when they become invisible, based on the client's view.
-pub fn update_visible_entities(game: &mut Game, server: &mut Server) -> SysResult {
-    for (player, (event, &client_id)) in game.ecs.query::<(&ViewUpdateEvent, &ClientId)>().iter() {
+/// System to spawn entities on clients when they come within
+/// tracking range, and despawn entities when they leave it.
+///
+/// The tracking range depends on the kind of the entity
+/// (see [`EntityTrackingRanges`]).
+/// Entities in chunks outside the client's view are never tracked,
+/// and players in spectator mode are only visible to other spectators.
+pub fn update_tracked_entities(game: &mut Game, server: &mut Server) -> SysResult {
+    let ranges = server.options().entity_tracking.clone();
+    let max_range = ranges
+        .players
+        .max(ranges.animals)
+        .max(ranges.monsters)
+        .max(ranges.misc)
+        .max(ranges.other);
+    let chunk_radius = (max_range / CHUNK_WIDTH as f64).ceil() as i32;
+
+    let untracked: Vec<Entity> = game
+        .ecs
+        .query::<(&ClientId, Option<&TrackingCandidates>)>()
+        .iter()
+        .filter(|(_, (_, candidates))| candidates.is_none())
+        .map(|(player, _)| player)
+        .collect();
+    for player in untracked {
+        game.ecs.insert(player, TrackingCandidates::default())?;
+    }
+
+    // Entities which entered a chunk this tick
+    let mut moved: Vec<(Entity, ChunkPosition)> = game
+        .ecs
+        .query::<&ChunkCrossEvent>()
+        .iter()
+        .map(|(entity, event)| (entity, event.new_chunk))
+        .collect();
+    moved.extend(
+        game.ecs
+            .query::<(&EntityCreateEvent, &Position)>()
+            .iter()
+            .map(|(entity, (_, position))| (entity, position.chunk())),
+    );
+    let removed: Vec<Entity> = game
+        .ecs
+        .query::<&EntityRemoveEvent>()
+        .iter()
+        .map(|(entity, _)| entity)
+        .collect();
+
+    for (player, (&client_id, &position, &view, &gamemode, candidates)) in game
+        .ecs
+        .query::<(
+            &ClientId,
+            &Position,
+            &View,
+            &Gamemode,
+            &mut TrackingCandidates,
+        )>()
+        .iter()
+    {
         let client = match server.clients.get(client_id) {
             Some(client) => client,
             None => continue,
         };
 
-        // Send newly visible entities
-        for &new_chunk in &event.new_chunks {
-            for &entity_id in game.chunk_entities.entities_in_chunk(new_chunk) {
-                if entity_id != player {
-                    let entity_ref = game.ecs.entity(entity_id)?;
-                    if let Ok(spawn_packet) = entity_ref.get::<SpawnPacketSender>() {
-                        spawn_packet
-                            .send(&entity_ref, client)
-                            .context("failed to send spawn packet")?;
+        let area = TrackingArea {
+            center: position.chunk(),
+            view,
+            chunk_radius,
+        };
+        if candidates.area != Some(area) {
+            let previous = mem::take(&mut candidates.entities);
+            candidates.area = Some(area);
+            for x in area.center.x - chunk_radius..=area.center.x + chunk_radius {
+                for z in area.center.z - chunk_radius..=area.center.z + chunk_radius {
+                    let chunk = ChunkPosition::new(x, z);
+                    if area.contains(chunk) {
+                        candidates
+                            .entities
+                            .extend(game.chunk_entities.entities_in_chunk(chunk));
                     }
                 }
             }
-        }
-
-        // Unload entities no longer visible
-        for &old_chunk in &event.old_chunks {
-            for &entity_id in game.chunk_entities.entities_in_chunk(old_chunk) {
-                if entity_id != player {
-                    if let Ok(network_id) = game.ecs.get::<NetworkId>(entity_id) {
-                        client.unload_entity(*network_id);
-                    }
+            for &entity in previous.difference(&candidates.entities) {
+                unload_if_loaded(game, client, entity);
+            }
+        } else {
+            for &(entity, chunk) in &moved {
+                if area.contains(chunk) {
+                    candidates.entities.insert(entity);
+                } else if candidates.entities.remove(&entity) {
+                    unload_if_loaded(game, client, entity);
                 }
             }
+            for entity in &removed {
+                candidates.entities.remove(entity);
+            }
+        }
+        candidates.entities.remove(&player);
+
+        for &entity in &candidates.entities {
+            update_tracked_entity(game, client, &ranges, position, gamemode, entity)?;
         }
     }
 
     Ok(())
 }
 
-/// System to send an entity to clients when it is created.
-fn send_entities_when_created(game: &mut Game, server: &mut Server) -> SysResult {
-    for (entity, (_event, &position, spawn_packet)) in game
-        .ecs
-        .query::<(&EntityCreateEvent, &Position, &SpawnPacketSender)>()
-        .iter()
-    {
-        let entity_ref = game.ecs.entity(entity)?;
-        server.broadcast_nearby_with(position, |client| {
+/// Spawns `entity` on `client` if it is within tracking range
+/// of the player at `position`, or despawns it otherwise.
+fn update_tracked_entity(
+    game: &Game,
+    client: &Client,
+    ranges: &EntityTrackingRanges,
+    position: Position,
+    gamemode: Gamemode,
+    entity: Entity,
+) -> SysResult {
+    let entity_ref = match game.ecs.entity(entity) {
+        Ok(entity_ref) => entity_ref,
+        Err(_) => return Ok(()),
+    };
+    let (network_id, entity_position, kind) = match (
+        entity_ref.get::<NetworkId>(),
+        entity_ref.get::<Position>(),
+        entity_ref.get::<EntityKind>(),
+    ) {
+        (Ok(network_id), Ok(entity_position), Ok(kind)) => (*network_id, *entity_position, *kind),
+        _ => return Ok(()),
+    };
+
+    let hidden = entity_ref.get::<EntityRemoveEvent>().is_ok()
+        || (gamemode != Gamemode::Spectator
+            && entity_ref
+                .get::<Gamemode>()
+                .map_or(false, |g| *g == Gamemode::Spectator));
+    let range = ranges.range_for(kind);
+    let in_range = !hidden
+        && (entity_position.x - position.x).abs() <= range
+        && (entity_position.z - position.z).abs() <= range;
+
+    if !in_range {
+        if client.is_entity_loaded(network_id) {
+            client.unload_entity(network_id);
+        }
+    } else if !client.is_entity_loaded(network_id) {
+        if let Ok(spawn_packet) = entity_ref.get::<SpawnPacketSender>() {
             spawn_packet
                 .send(&entity_ref, client)
-                .expect("failed to create spawn packet")
-        });
+                .context("failed to send spawn packet")?;
+        }
+        if entity_ref.get::<Passengers>().is_ok() {
+            let passengers = super::passenger_network_ids(game, entity);
+            client.send_set_passengers(network_id, &passengers);
+        }
     }
-
     Ok(())
 }
 
+/// Despawns `entity` on `client` if it was spawned there.
+fn unload_if_loaded(game: &Game, client: &Client, entity: Entity) {
+    if let Ok(network_id) = game.ecs.get::<NetworkId>(entity) {
+        if client.is_entity_loaded(*network_id) {
+            client.unload_entity(*network_id);
+        }
+    }
+}
+
 /// System to unload an entity on clients when it is removed.
 fn unload_entities_when_removed(game: &mut Game, server: &mut Server) -> SysResult {
     for (_, (_event, &position, &network_id)) in game
@@ -82,45 +220,144 @@ fn unload_entities_when_removed(game: &mut Game, server: &mut Server) -> SysResu
         .query::<(&EntityRemoveEvent, &Position, &NetworkId)>()
         .iter()
     {
-        server.broadcast_nearby_with(position, |client| client.unload_entity(network_id));
+        server.broadcast_nearby_with(position, |client| {
+            if client.is_entity_loaded(network_id) {
+                client.unload_entity(network_id)
+            }
+        });
     }
 
     Ok(())
 }
 
-/// System to send/unsend entities on clients when the entity changes chunks.
-fn update_entities_on_chunk_cross(game: &mut Game, server: &mut Server) -> SysResult {
-    for (entity, (event, spawn_packet, &network_id)) in game
-        .ecs
-        .query::<(&ChunkCrossEvent, &SpawnPacketSender, &NetworkId)>()
-        .iter()
-    {
-        let old_clients: AHashSet<_> = server
-            .chunk_subscriptions
-            .subscriptions_for(event.old_chunk)
-            .iter()
-            .copied()
-            .collect();
-        let new_clients: AHashSet<_> = server
-            .chunk_subscriptions
-            .subscriptions_for(event.new_chunk)
-            .iter()
-            .copied()
-            .collect();
+#[cfg(test)]
+mod tests {
+    use base::position;
+    use protocol::ServerPlayPacket;
 
-        for left_client in old_clients.difference(&new_clients) {
-            if let Some(client) = server.clients.get(*left_client) {
-                client.unload_entity(network_id);
-            }
-        }
+    use super::*;
+    use crate::{
+        systems::{gamemode::gamemode_change, player_leave::remove_disconnected_clients},
+        tests::{join_test_player, test_game, test_server},
+    };
 
-        let entity_ref = game.ecs.entity(entity)?;
-        for send_client in new_clients.difference(&old_clients) {
-            if let Some(client) = server.clients.get(*send_client) {
-                spawn_packet.send(&entity_ref, client)?;
-            }
-        }
+    #[test]
+    fn entities_are_spawned_and_despawned_as_they_enter_and_leave_range() {
+        let (mut server, new_players) = test_server();
+        let mut options = (*server.options()).clone();
+        options.entity_tracking.players = 4.0;
+        server.reload_options(options);
+        let mut game = test_game(&server);
+        let watcher = join_test_player(&mut game, &mut server, &new_players);
+        let tracked = join_test_player(&mut game, &mut server, &new_players).entity;
+        let network_id = *game.ecs.get::<NetworkId>(tracked).unwrap();
+        *game.ecs.get_mut::<Position>(watcher.entity).unwrap() = position!(8.0, 64.0, 8.0);
+
+        let mut chunk_entities = SystemExecutor::new();
+        common::chunk::entities::register(&mut chunk_entities);
+        let mut move_to = |game: &mut Game, server: &mut Server, x| {
+            *game.ecs.get_mut::<Position>(tracked).unwrap() = position!(x, 64.0, 8.0);
+            chunk_entities.run(game);
+            update_tracked_entities(game, server).unwrap();
+            server
+                .clients
+                .get(watcher.client_id)
+                .unwrap()
+                .is_entity_loaded(network_id)
+        };
+
+        assert!(move_to(&mut game, &mut server, 10.0));
+        // Out of range within the same chunk
+        assert!(!move_to(&mut game, &mut server, 14.0));
+        assert!(!move_to(&mut game, &mut server, 100.0));
+        // Back into the watcher's chunk
+        assert!(move_to(&mut game, &mut server, 11.0));
     }
 
-    Ok(())
+    #[test]
+    fn spectators_are_only_visible_to_spectators() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let watcher = join_test_player(&mut game, &mut server, &new_players);
+        let spectator = join_test_player(&mut game, &mut server, &new_players).entity;
+        let network_id = *game.ecs.get::<NetworkId>(spectator).unwrap();
+        server.clients.get(watcher.client_id).unwrap().mark_ready();
+        let mut chunk_entities = SystemExecutor::new();
+        common::chunk::entities::register(&mut chunk_entities);
+        chunk_entities.run(&mut game);
+
+        let is_visible = |game: &mut Game, server: &mut Server| {
+            update_tracked_entities(game, server).unwrap();
+            server
+                .clients
+                .get(watcher.client_id)
+                .unwrap()
+                .is_entity_loaded(network_id)
+        };
+        let set_gamemode = |game: &mut Game, server: &mut Server, player, gamemode| {
+            game.set_gamemode(player, gamemode).unwrap();
+            gamemode_change(game, server).unwrap();
+        };
+        assert!(is_visible(&mut game, &mut server));
+        watcher.connection.sent_packets.try_iter().for_each(drop);
+
+        set_gamemode(&mut game, &mut server, spectator, Gamemode::Spectator);
+        assert!(!is_visible(&mut game, &mut server));
+        assert!(
+            watcher
+                .connection
+                .sent_packets
+                .try_iter()
+                .any(|packet| matches!(packet,
+                ServerPlayPacket::DestroyEntities(destroy)
+                    if destroy.entity_ids.iter().any(|id| id.0 == network_id.0))),
+            "spectator not despawned"
+        );
+
+        set_gamemode(&mut game, &mut server, watcher.entity, Gamemode::Spectator);
+        assert!(is_visible(&mut game, &mut server));
+        set_gamemode(&mut game, &mut server, watcher.entity, Gamemode::Survival);
+        assert!(!is_visible(&mut game, &mut server));
+
+        set_gamemode(&mut game, &mut server, spectator, Gamemode::Creative);
+        assert!(is_visible(&mut game, &mut server));
+    }
+
+    #[test]
+    fn cleared_players_are_destroyed_for_watchers_and_disconnected() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let watcher = join_test_player(&mut game, &mut server, &new_players);
+        let cleared = join_test_player(&mut game, &mut server, &new_players);
+        let network_id = *game.ecs.get::<NetworkId>(cleared.entity).unwrap();
+        server.clients.get(watcher.client_id).unwrap().mark_ready();
+        let mut chunk_entities = SystemExecutor::new();
+        common::chunk::entities::register(&mut chunk_entities);
+        chunk_entities.run(&mut game);
+        update_tracked_entities(&mut game, &mut server).unwrap();
+        watcher.connection.sent_packets.try_iter().for_each(drop);
+
+        assert_eq!(game.clear_entities(false), 2);
+        unload_entities_when_removed(&mut game, &mut server).unwrap();
+        assert!(
+            watcher
+                .connection
+                .sent_packets
+                .try_iter()
+                .any(|packet| matches!(packet,
+                ServerPlayPacket::DestroyEntities(destroy)
+                    if destroy.entity_ids.iter().any(|id| id.0 == network_id.0))),
+            "cleared player not destroyed"
+        );
+
+        remove_disconnected_clients(&mut game, &mut server).unwrap();
+        for player in &[watcher, cleared] {
+            assert!(server.clients.get(player.client_id).is_none());
+            assert!(player
+                .connection
+                .sent_packets
+                .try_iter()
+                .any(|packet| matches!(packet, ServerPlayPacket::Disconnect(_))));
+        }
+    }
 }
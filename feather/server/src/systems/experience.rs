@@ -0,0 +1,95 @@
+//! Collecting experience orbs.
+
+use ahash::AHashSet;
+use base::{ChunkPosition, Gamemode, Position};
+use common::{
+    events::ExperienceChangeEvent,
+    experience::{Experience, ExperienceOrb},
+    Game,
+};
+use ecs::{Entity, SysResult, SystemExecutor};
+use quill_common::{components::Health, events::EntityRemoveEvent};
+
+use super::item_pickup::is_in_pickup_range;
+use crate::{ClientId, NetworkId, Server};
+
+pub fn register(systems: &mut SystemExecutor<Game>) {
+    systems
+        .group::<Server>()
+        .add_system(collect_experience_orbs)
+        .add_system(send_experience_changes);
+}
+
+/// System for living players to collect nearby experience orbs.
+///
+/// Like item pickup, must run before entity removals are broadcast.
+fn collect_experience_orbs(game: &mut Game, server: &mut Server) -> SysResult {
+    let mut collected = AHashSet::new();
+    let mut changed = Vec::new();
+    for (player, (&position, &gamemode, experience, &collector_id, health)) in game
+        .ecs
+        .query::<(&Position, &Gamemode, &mut Experience, &NetworkId, &Health)>()
+        .iter()
+    {
+        if gamemode == Gamemode::Spectator || health.0 <= 0.0 {
+            continue;
+        }
+
+        let center = position.chunk();
+        for x in center.x - 1..=center.x + 1 {
+            for z in center.z - 1..=center.z + 1 {
+                for &orb in game
+                    .chunk_entities
+                    .entities_in_chunk(ChunkPosition::new(x, z))
+                {
+                    if collected.contains(&orb) || !is_orb_in_range(game, orb, position) {
+                        continue;
+                    }
+                    let amount = game.ecs.get::<ExperienceOrb>(orb)?.amount;
+                    experience.add(amount);
+                    collected.insert(orb);
+                    changed.push(player);
+
+                    let orb_position = *game.ecs.get::<Position>(orb)?;
+                    let orb_id = *game.ecs.get::<NetworkId>(orb)?;
+                    server.broadcast_collect_item(orb_position, orb_id, collector_id, 1);
+                }
+            }
+        }
+    }
+
+    for player in changed {
+        game.ecs
+            .insert_entity_event(player, ExperienceChangeEvent)?;
+    }
+    for orb in collected {
+        game.remove_entity(orb)?;
+    }
+    Ok(())
+}
+
+fn is_orb_in_range(game: &Game, entity: Entity, player_position: Position) -> bool {
+    if game.ecs.get::<ExperienceOrb>(entity).is_err()
+        || game.ecs.get::<EntityRemoveEvent>(entity).is_ok()
+    {
+        return false;
+    }
+    match game.ecs.get::<Position>(entity) {
+        Ok(position) => is_in_pickup_range(*position, player_position),
+        Err(_) => false,
+    }
+}
+
+/// Sends players their experience when it changes.
+fn send_experience_changes(game: &mut Game, server: &mut Server) -> SysResult {
+    for (_, (_event, &client_id, &experience)) in game
+        .ecs
+        .query::<(&ExperienceChangeEvent, &ClientId, &Experience)>()
+        .iter()
+    {
+        if let Some(client) = server.clients.get(client_id) {
+            client.send_experience(experience);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use common::{
+    events::{DamageEvent, HealthUpdateEvent},
+    hunger::Food,
+    Game, Window,
+};
+use ecs::{SysResult, SystemExecutor};
+use quill_common::components::Health;
+
+use crate::{ClientId, Server};
+
+pub fn register(systems: &mut SystemExecutor<Game>) {
+    systems
+        .group::<Server>()
+        .add_system(send_health_updates)
+        .add_system(send_inventories_on_death);
+}
+
+/// Sends players their health and food when they change.
+fn send_health_updates(game: &mut Game, server: &mut Server) -> SysResult {
+    for (_, (_event, &client_id, health, food)) in game
+        .ecs
+        .query::<(&HealthUpdateEvent, &ClientId, &Health, &Food)>()
+        .iter()
+    {
+        if let Some(client) = server.clients.get(client_id) {
+            client.send_update_health(health.0, food.level, food.saturation);
+        }
+    }
+    Ok(())
+}
+
+/// Sends players who died this tick their inventory,
+/// which is emptied unless the `keepInventory` game rule is set.
+fn send_inventories_on_death(game: &mut Game, server: &mut Server) -> SysResult {
+    for (_, (_event, &client_id, health, window)) in game
+        .ecs
+        .query::<(&DamageEvent, &ClientId, &Health, &Window)>()
+        .iter()
+    {
+        if health.0 > 0.0 {
+            continue;
+        }
+        if let Some(client) = server.clients.get(client_id) {
+            client.send_window_items(window);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,220 @@
+//! Picking up dropped items.
+//!
+//! A dropped item entity has an [`ItemStack`] component
+//! holding its contents.
+
+use ahash::AHashSet;
+use base::{
+    inventory::{HOTBAR_SIZE, SLOT_HOTBAR_OFFSET, SLOT_INVENTORY_OFFSET},
+    ChunkPosition, Gamemode, ItemStack, Position,
+};
+use common::{entities::item::PickupDelay, Game, Window};
+use ecs::{Entity, SysResult, SystemExecutor};
+use libcraft_items::InventorySlot;
+use quill_common::{components::Health, entities::Item, events::EntityRemoveEvent};
+
+use crate::{ClientId, NetworkId, Server};
+
+/// Horizontal distance from a player's position
+/// within which items are picked up.
+const PICKUP_RANGE_HORIZONTAL: f64 = 1.3;
+/// Vertical extent above a player's feet
+/// within which items are picked up.
+const PICKUP_RANGE_ABOVE: f64 = 2.3;
+/// Vertical extent below a player's feet
+/// within which items are picked up.
+const PICKUP_RANGE_BELOW: f64 = 0.5;
+
+pub fn register(systems: &mut SystemExecutor<Game>) {
+    systems.group::<Server>().add_system(pick_up_items);
+}
+
+/// System for living players to pick up nearby items
+/// whose pickup delay is over.
+///
+/// Must run before entity removals are broadcast so
+/// that the collect animation is shown before the item is destroyed.
+fn pick_up_items(game: &mut Game, server: &mut Server) -> SysResult {
+    let mut picked_up = AHashSet::new();
+    for (_, (&client_id, &position, &gamemode, window, &collector_id, health)) in game
+        .ecs
+        .query::<(
+            &ClientId,
+            &Position,
+            &Gamemode,
+            &Window,
+            &NetworkId,
+            &Health,
+        )>()
+        .iter()
+    {
+        if gamemode == Gamemode::Spectator || health.0 <= 0.0 {
+            continue;
+        }
+
+        let mut window_changed = false;
+        let center = position.chunk();
+        for x in center.x - 1..=center.x + 1 {
+            for z in center.z - 1..=center.z + 1 {
+                for &item in game
+                    .chunk_entities
+                    .entities_in_chunk(ChunkPosition::new(x, z))
+                {
+                    if picked_up.contains(&item) || !can_pick_up(game, item, position) {
+                        continue;
+                    }
+                    let mut stack = match game.ecs.get_mut::<ItemStack>(item) {
+                        Ok(stack) => stack,
+                        Err(_) => continue,
+                    };
+
+                    let original_count = stack.count();
+                    let remaining = give_item(window, stack.clone())?;
+                    let count = original_count - remaining.count();
+                    if count == 0 {
+                        continue;
+                    }
+                    window_changed = true;
+
+                    let item_position = *game.ecs.get::<Position>(item)?;
+                    let item_id = *game.ecs.get::<NetworkId>(item)?;
+                    server.broadcast_collect_item(item_position, item_id, collector_id, count);
+
+                    match remaining {
+                        InventorySlot::Filled(remaining) => *stack = remaining,
+                        InventorySlot::Empty => {
+                            picked_up.insert(item);
+                        }
+                    }
+                }
+            }
+        }
+
+        if window_changed {
+            if let Some(client) = server.clients.get(client_id) {
+                client.send_window_items(window);
+            }
+        }
+    }
+
+    for item in picked_up {
+        game.remove_entity(item)?;
+    }
+    Ok(())
+}
+
+fn can_pick_up(game: &Game, entity: Entity, player_position: Position) -> bool {
+    if game.ecs.get::<Item>(entity).is_err() || game.ecs.get::<EntityRemoveEvent>(entity).is_ok() {
+        return false;
+    }
+    if let Ok(delay) = game.ecs.get::<PickupDelay>(entity) {
+        if !delay.is_over() {
+            return false;
+        }
+    }
+    let position = match game.ecs.get::<Position>(entity) {
+        Ok(position) => *position,
+        Err(_) => return false,
+    };
+    is_in_pickup_range(position, player_position)
+}
+
+/// Determines whether an entity at `position` is close enough
+/// to a player at `player_position` to be picked up.
+pub(super) fn is_in_pickup_range(position: Position, player_position: Position) -> bool {
+    (position.x - player_position.x).abs() <= PICKUP_RANGE_HORIZONTAL
+        && (position.z - player_position.z).abs() <= PICKUP_RANGE_HORIZONTAL
+        && position.y >= player_position.y - PICKUP_RANGE_BELOW
+        && position.y <= player_position.y + PICKUP_RANGE_ABOVE
+}
+
+/// Adds a stack to the player's hotbar and main inventory,
+/// filling stacks of the same item before empty slots.
+///
+/// Returns the items which did not fit.
+fn give_item(window: &Window, stack: ItemStack) -> anyhow::Result<InventorySlot> {
+    let mut remaining = InventorySlot::Filled(stack);
+    // The hotbar is filled before the main inventory
+    let slots: Vec<usize> = (SLOT_HOTBAR_OFFSET..SLOT_HOTBAR_OFFSET + HOTBAR_SIZE)
+        .chain(SLOT_INVENTORY_OFFSET..SLOT_HOTBAR_OFFSET)
+        .collect();
+
+    for fill_empty in [false, true] {
+        for &index in &slots {
+            let mut slot = window.item(index)?;
+            if slot.is_empty() == fill_empty {
+                slot.merge(&mut remaining);
+            }
+            if remaining.is_empty() {
+                return Ok(remaining);
+            }
+        }
+    }
+    Ok(remaining)
+}
+
+#[cfg(test)]
+mod tests {
+    use base::Item as ItemKind;
+    use common::{events::DamageEvent, GameRules};
+
+    use super::*;
+    use crate::tests::{join_test_player, test_game, test_server};
+
+    fn diamonds(window: &Window) -> u32 {
+        window.item(SLOT_HOTBAR_OFFSET).unwrap().count()
+    }
+
+    #[test]
+    fn items_dropped_on_death_are_not_picked_up_by_the_dead() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        game.insert_resource(GameRules::default());
+        let dead = join_test_player(&mut game, &mut server, &new_players).entity;
+        let alive = join_test_player(&mut game, &mut server, &new_players).entity;
+        game.ecs
+            .get::<Window>(dead)
+            .unwrap()
+            .set_item(
+                SLOT_HOTBAR_OFFSET,
+                InventorySlot::Filled(ItemStack::new(ItemKind::Diamond, 3).unwrap()),
+            )
+            .unwrap();
+        *game.ecs.get_mut::<Health>(dead).unwrap() = Health(0.0);
+        game.ecs
+            .insert_entity_event(
+                dead,
+                DamageEvent {
+                    attacker: None,
+                    amount: 20.0,
+                },
+            )
+            .unwrap();
+
+        let mut systems = SystemExecutor::new();
+        common::death::register(&mut systems);
+        systems.run(&mut game);
+        let item = game
+            .ecs
+            .query::<&ItemStack>()
+            .iter()
+            .map(|(item, _)| item)
+            .next()
+            .unwrap();
+        game.chunk_entities
+            .add_entity(item, ChunkPosition::new(0, 0));
+
+        let mut pickup_delays = SystemExecutor::new();
+        common::entities::item::register(&mut pickup_delays);
+        for _ in 0..PickupDelay::DROPPED.0 {
+            pick_up_items(&mut game, &mut server).unwrap();
+            assert_eq!(diamonds(&game.ecs.get::<Window>(alive).unwrap()), 0);
+            pickup_delays.run(&mut game);
+        }
+
+        // Picked up by the living player next to the dead one
+        pick_up_items(&mut game, &mut server).unwrap();
+        assert_eq!(diamonds(&game.ecs.get::<Window>(dead).unwrap()), 0);
+        assert_eq!(diamonds(&game.ecs.get::<Window>(alive).unwrap()), 3);
+    }
+}
@@ -11,7 +11,7 @@ fn send_particle_packets(game: &mut Game, server: &mut Server) -> SysResult {
     let mut entities = Vec::new();
 
     for (entity, (&particle, &position)) in game.ecs.query::<(&Particle, &Position)>().iter() {
-        server.broadcast_nearby_with(position, |client| {
+        server.broadcast_nearby_nonessential_with(position, |client| {
             client.send_particle(&particle, &position);
         });
 
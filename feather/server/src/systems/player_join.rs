@@ -2,10 +2,13 @@ use libcraft_items::InventorySlot;
 use log::debug;
 
 use base::anvil::player::PlayerAbilities;
-use base::{Gamemode, Inventory, ItemStack, Position, Text};
+use base::{Gamemode, Inventory, ItemStack, Position};
 use common::{
     chat::{ChatKind, ChatPreference},
     entities::player::HotbarSlot,
+    events::HealthUpdateEvent,
+    experience::Experience,
+    hunger::Food,
     view::View,
     window::BackingWindow,
     ChatBox, Game, Window,
@@ -18,7 +21,7 @@ use quill_common::components::{
 use quill_common::events::GamemodeEvent;
 use quill_common::{components::Name, entity_init::EntityInit};
 
-use crate::{ClientId, NetworkId, Server};
+use crate::{client::JoinParameters, ClientId, NetworkId, Options, Server};
 
 pub fn register(systems: &mut SystemExecutor<Game>) {
     systems.group::<Server>().add_system(poll_new_players);
@@ -26,7 +29,7 @@ pub fn register(systems: &mut SystemExecutor<Game>) {
 
 /// Polls for new clients and sends them the necessary packets
 /// to join the game.
-fn poll_new_players(game: &mut Game, server: &mut Server) -> SysResult {
+pub(crate) fn poll_new_players(game: &mut Game, server: &mut Server) -> SysResult {
     for client_id in server.accept_new_players() {
         accept_new_player(game, server, client_id)?;
     }
@@ -54,21 +57,36 @@ fn accept_new_player(game: &mut Game, server: &mut Server, client_id: ClientId)
     if player_data.is_err() {
         debug!("{} is a new player", client.username())
     }
-    let gamemode = player_data
+    let saved_gamemode = player_data
         .as_ref()
-        .map(|data| Gamemode::from_id(data.gamemode as u8).expect("Unsupported gamemode"))
-        .unwrap_or(server.options.default_gamemode);
+        .ok()
+        .map(|data| Gamemode::from_id(data.gamemode as u8).expect("Unsupported gamemode"));
+    let gamemode = server.options().gamemode_on_join(saved_gamemode);
     let previous_gamemode = player_data
         .as_ref()
         .map(|data| PreviousGamemode::from_id(data.previous_gamemode as i8))
         .unwrap_or(PreviousGamemode(None));
 
-    client.send_join_game(gamemode, previous_gamemode);
+    let world_names = game.worlds().map(|world| world.name().to_owned()).collect();
+    client.send_join_game(
+        JoinParameters {
+            previous_gamemode,
+            world_names,
+            is_flat: game.world.is_flat(),
+            ..JoinParameters::new(gamemode, game.world.name())
+        },
+        server.dimensions(),
+    );
     client.send_brand();
+    client.send_command_graph(server.command_graph());
 
-    // Abilities
+    // Abilities. Saved abilities no longer apply if the gamemode was forced.
     let abilities = player_abilities_or_default(
-        player_data.as_ref().map(|data| data.abilities.clone()).ok(),
+        player_data
+            .as_ref()
+            .ok()
+            .filter(|_| saved_gamemode == Some(gamemode))
+            .map(|data| data.abilities.clone()),
         gamemode,
     );
     client.send_abilities(&abilities);
@@ -103,11 +121,20 @@ fn accept_new_player(game: &mut Game, server: &mut Server, client_id: ClientId)
 
     client.send_window_items(&window);
 
+    let food = player_data
+        .as_ref()
+        .map(|data| {
+            let mut food = Food::new(data.food_level, data.food_saturation);
+            food.exhaustion = data.food_exhaustion;
+            food
+        })
+        .unwrap_or_default();
+
     builder
         .add(client_id)
         .add(View::new(
             Position::default().chunk(),
-            server.options.view_distance,
+            server.options().view_distance,
         ))
         .add(gamemode)
         .add(previous_gamemode)
@@ -124,6 +151,15 @@ fn accept_new_player(game: &mut Game, server: &mut Server, client_id: ClientId)
                 .map(|data| data.animal.health)
                 .unwrap_or(20.0),
         ))
+        .add(food)
+        .add(
+            player_data
+                .as_ref()
+                .map(|data| Experience {
+                    total: data.xp_total.max(0) as u32,
+                })
+                .unwrap_or_default(),
+        )
         .add(abilities.walk_speed)
         .add(abilities.fly_speed)
         .add(abilities.is_flying)
@@ -134,16 +170,20 @@ fn accept_new_player(game: &mut Game, server: &mut Server, client_id: ClientId)
 
     builder.add(GamemodeEvent(gamemode));
 
-    game.spawn_entity(builder);
+    let player = game.spawn_entity(builder);
+    game.ecs.insert_entity_event(player, HealthUpdateEvent)?;
 
-    broadcast_player_join(game, client.username());
+    if !(client.replaced_previous() && server.options().suppress_reconnect_messages) {
+        broadcast_player_join(game, &server.options(), client.username());
+    }
 
     Ok(())
 }
 
-fn broadcast_player_join(game: &mut Game, username: &str) {
-    let message = Text::translate_with("multiplayer.player.joined", vec![username.to_owned()]);
-    game.broadcast_chat(ChatKind::System, message);
+fn broadcast_player_join(game: &mut Game, options: &Options, username: &str) {
+    if let Some(message) = options.join_message_for(username) {
+        game.broadcast_chat(ChatKind::System, message);
+    }
 }
 
 fn player_abilities_or_default(
@@ -160,3 +200,57 @@ fn player_abilities_or_default(
         invulnerable: Invulnerable(matches!(gamemode, Gamemode::Creative | Gamemode::Spectator)),
     })
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use base::{Text, TextComponentBuilder};
+    use ecs::Entity;
+
+    use super::*;
+    use crate::tests::{join_test_player, test_game, test_server};
+
+    /// Drains the chat messages sent to `player`, as JSON.
+    pub(crate) fn received_chat(game: &Game, player: Entity) -> Vec<String> {
+        game.ecs
+            .get_mut::<ChatBox>(player)
+            .unwrap()
+            .drain()
+            .map(|message| String::from(message.text().clone()))
+            .collect()
+    }
+
+    #[test]
+    fn vanilla_join_message_is_broadcast_by_default() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let watcher = join_test_player(&mut game, &mut server, &new_players).entity;
+        received_chat(&game, watcher);
+
+        join_test_player(&mut game, &mut server, &new_players);
+        let joined =
+            Text::translate_with("multiplayer.player.joined", vec!["test".to_owned()]).yellow();
+        assert_eq!(received_chat(&game, watcher), vec![String::from(joined)]);
+    }
+
+    #[test]
+    fn configured_join_message_replaces_the_vanilla_one() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let watcher = join_test_player(&mut game, &mut server, &new_players).entity;
+        received_chat(&game, watcher);
+
+        let mut options = (*server.options()).clone();
+        options.join_message = Some("{player} is here".to_owned());
+        server.reload_options(options.clone());
+        join_test_player(&mut game, &mut server, &new_players);
+        assert_eq!(
+            received_chat(&game, watcher),
+            vec![String::from(Text::from("test is here"))]
+        );
+
+        options.join_message = Some(String::new());
+        server.reload_options(options);
+        join_test_player(&mut game, &mut server, &new_players);
+        assert!(received_chat(&game, watcher).is_empty());
+    }
+}
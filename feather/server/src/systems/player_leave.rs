@@ -1,17 +1,10 @@
-use num_traits::cast::ToPrimitive;
-
-use base::anvil::entity::{AnimalData, BaseEntityData};
-use base::anvil::player::{InventorySlot, PlayerAbilities, PlayerData};
-use base::{Gamemode, Inventory, Position, Text};
-use common::entities::player::HotbarSlot;
 use common::{chat::ChatKind, Game};
 use ecs::{SysResult, SystemExecutor};
-use quill_common::components::{
-    CanBuild, CanCreativeFly, CreativeFlying, CreativeFlyingSpeed, Health, Instabreak,
-    Invulnerable, Name, PreviousGamemode, WalkSpeed,
-};
+use quill_common::{components::Name, events::EntityRemoveEvent};
+
+use crate::{ClientId, Options, Server};
 
-use crate::{ClientId, Server};
+use super::save::player_data;
 
 pub fn register(systems: &mut SystemExecutor<Game>) {
     systems
@@ -19,135 +12,108 @@ pub fn register(systems: &mut SystemExecutor<Game>) {
         .add_system(remove_disconnected_clients);
 }
 
-fn remove_disconnected_clients(game: &mut Game, server: &mut Server) -> SysResult {
+/// Removes the players whose clients disconnected, and
+/// disconnects the clients of players removed from the world,
+/// such as by [`Game::clear_entities`].
+pub(crate) fn remove_disconnected_clients(game: &mut Game, server: &mut Server) -> SysResult {
     let mut entities_to_remove = Vec::new();
-    for (
-        player,
-        (
-            &client_id,
-            name,
-            position,
-            gamemode,
-            previous_gamemode,
-            health,
-            walk_speed,
-            fly_speed,
-            can_fly,
-            is_flying,
-            can_build,
-            instabreak,
-            invulnerable,
-            hotbar_slot,
-            inventory,
-        ),
-    ) in game
-        .ecs
-        .query::<(
-            &ClientId,
-            &Name,
-            &Position,
-            &Gamemode,
-            &PreviousGamemode,
-            &Health,
-            &WalkSpeed,
-            &CreativeFlyingSpeed,
-            &CanCreativeFly,
-            &CreativeFlying,
-            &CanBuild,
-            &Instabreak,
-            &Invulnerable,
-            &HotbarSlot,
-            &Inventory,
-        )>()
-        .iter()
-    {
+    for (player, (&client_id, name)) in game.ecs.query::<(&ClientId, &Name)>().iter() {
         let client = server.clients.get(client_id).unwrap();
-        if client.is_disconnected() {
-            entities_to_remove.push(player);
-            broadcast_player_leave(game, name);
-            game.world
-                .save_player_data(
-                    client.uuid(),
-                    &create_player_data(
-                        *position,
-                        *gamemode,
-                        *previous_gamemode,
-                        *health,
-                        PlayerAbilities {
-                            walk_speed: *walk_speed,
-                            fly_speed: *fly_speed,
-                            may_fly: *can_fly,
-                            is_flying: *is_flying,
-                            may_build: *can_build,
-                            instabreak: *instabreak,
-                            invulnerable: *invulnerable,
-                        },
-                        *hotbar_slot,
-                        inventory,
-                    ),
-                )
-                .unwrap_or_else(|e| panic!("Couldn't save data for {}: {}", client.username(), e));
+        client.poll_connection();
+        let removed = game.ecs.get::<EntityRemoveEvent>(player).is_ok();
+        if removed {
+            client.disconnect("Removed from the world");
+        }
+        if let Some(reason) = client.disconnect_reason() {
+            log::debug!("{} disconnected: {}", client.username(), reason);
+            entities_to_remove.push((player, client.uuid(), removed));
+            if !(client.was_replaced() && server.options().suppress_reconnect_messages) {
+                broadcast_player_leave(game, &server.options(), name);
+            }
             server.remove_client(client_id);
         }
     }
 
-    for player in entities_to_remove {
-        game.remove_entity(player)?;
+    for (player, uuid, removed) in entities_to_remove {
+        let data = player_data(game, player)?;
+        game.world.queue_player_data_save(uuid, data);
+        if !removed {
+            game.remove_entity(player)?;
+        }
     }
 
     Ok(())
 }
 
-fn broadcast_player_leave(game: &Game, username: &Name) {
-    let message = Text::translate_with("multiplayer.player.left", vec![username.to_string()]);
-    game.broadcast_chat(ChatKind::System, message);
+fn broadcast_player_leave(game: &Game, options: &Options, username: &Name) {
+    if let Some(message) = options.leave_message_for(&username.to_string()) {
+        game.broadcast_chat(ChatKind::System, message);
+    }
 }
 
-fn create_player_data(
-    position: Position,
-    gamemode: Gamemode,
-    previous_gamemode: PreviousGamemode,
-    health: Health,
-    abilities: PlayerAbilities,
-    hotbar_slot: HotbarSlot,
-    inventory: &Inventory,
-) -> PlayerData {
-    PlayerData {
-        animal: AnimalData {
-            base: BaseEntityData {
-                position: [position.x, position.y, position.z].into(),
-                rotation: [position.yaw, position.pitch].into(),
-                velocity: [0.0, 0.0, 0.0].into(),
-            },
-            health: *health,
-        },
-        gamemode: gamemode.to_i32().unwrap(),
-        previous_gamemode: previous_gamemode.id() as i32,
-        inventory: inventory
-            .to_vec()
-            .iter()
-            .enumerate()
-            // Here we filter out all empty slots.
-            .filter_map(|(slot, item)| {
-                match item {
-                    libcraft_items::InventorySlot::Filled(item) => {
-                        let res = InventorySlot::from_network_index(slot, item);
-                        match res {
-                            Some(i) => Some(i),
-                            None => {
-                                log::error!("Failed to convert the slot into anvil format.");
-                                None
-                            }
-                        }
-                    }
-                    libcraft_items::InventorySlot::Empty => {
-                        // Empty items are filtered out.
-                        None
-                    }
-                }
-            })
-            .collect(),
-        held_item: hotbar_slot.get() as i32,
-        abilities,
+#[cfg(test)]
+mod tests {
+    use base::{Text, TextComponentBuilder};
+
+    use super::*;
+    use crate::{
+        systems::player_join::tests::received_chat,
+        tests::{join_test_player, test_game, test_server},
+    };
+
+    #[test]
+    fn vanilla_leave_message_is_broadcast_by_default() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let watcher = join_test_player(&mut game, &mut server, &new_players).entity;
+        let leaving = join_test_player(&mut game, &mut server, &new_players);
+        received_chat(&game, watcher);
+
+        drop(leaving.connection);
+        remove_disconnected_clients(&mut game, &mut server).unwrap();
+        let left =
+            Text::translate_with("multiplayer.player.left", vec!["test".to_owned()]).yellow();
+        assert_eq!(received_chat(&game, watcher), vec![String::from(left)]);
+        assert!(server.clients.get(leaving.client_id).is_none());
+    }
+
+    #[test]
+    fn clients_are_removed_once_their_worker_stops() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let errored = join_test_player(&mut game, &mut server, &new_players);
+        let closed = join_test_player(&mut game, &mut server, &new_players);
+        remove_disconnected_clients(&mut game, &mut server).unwrap();
+        assert!(server.clients.get(errored.client_id).is_some());
+        assert!(server.clients.get(closed.client_id).is_some());
+
+        // As done by the worker, which keeps its error channel
+        // until after the reader and writer stopped
+        errored
+            .connection
+            .worker_errors
+            .send("timed out".to_owned())
+            .unwrap();
+        drop(errored.connection);
+        drop(closed.connection.worker_errors);
+
+        let client = server.clients.get(errored.client_id).unwrap();
+        assert!(!client.is_disconnected());
+        client.poll_connection();
+        assert_eq!(client.disconnect_reason().as_deref(), Some("timed out"));
+        assert_eq!(
+            server
+                .clients
+                .get(closed.client_id)
+                .unwrap()
+                .disconnect_reason(),
+            None
+        );
+
+        remove_disconnected_clients(&mut game, &mut server).unwrap();
+        assert!(server.clients.get(errored.client_id).is_none());
+        assert!(server.clients.get(closed.client_id).is_none());
+        assert!(game.ecs.get::<EntityRemoveEvent>(errored.entity).is_ok());
+        assert!(game.ecs.get::<EntityRemoveEvent>(closed.entity).is_ok());
     }
 }
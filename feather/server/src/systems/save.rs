@@ -0,0 +1,187 @@
+//! Autosaving of player and world data.
+
+use std::time::Instant;
+
+use num_traits::cast::ToPrimitive;
+
+use base::anvil::entity::{AnimalData, BaseEntityData};
+use base::anvil::player::{InventorySlot, PlayerAbilities, PlayerData};
+use base::{Gamemode, Inventory, Position};
+use common::{entities::player::HotbarSlot, experience::Experience, hunger::Food, Game};
+use ecs::{Entity, SysResult, SystemExecutor};
+use quill_common::components::{
+    CanBuild, CanCreativeFly, CreativeFlying, CreativeFlyingSpeed, Health, Instabreak,
+    Invulnerable, PreviousGamemode, WalkSpeed,
+};
+use uuid::Uuid;
+
+use crate::{ClientId, Server};
+
+pub fn register(systems: &mut SystemExecutor<Game>) {
+    systems.group::<Server>().add_system(autosave);
+}
+
+/// System to save the game every
+/// [`autosave_interval`](crate::Options::autosave_interval).
+fn autosave(game: &mut Game, server: &mut Server) -> SysResult {
+    let interval = server.options().autosave_interval;
+    if interval.as_secs() == 0 || server.last_autosave_time.elapsed() < interval {
+        return Ok(());
+    }
+    server.last_autosave_time = Instant::now();
+
+    log::debug!("Autosaving");
+    queue_save(game)
+}
+
+/// Queues the data of all online players and all loaded chunks
+/// to be saved. The data is written asynchronously by the chunk worker,
+/// so this does not block on disk IO.
+pub fn queue_save(game: &mut Game) -> SysResult {
+    let players: Vec<(Entity, Uuid)> = game
+        .ecs
+        .query::<(&ClientId, &Uuid)>()
+        .iter()
+        .map(|(player, (_, &uuid))| (player, uuid))
+        .collect();
+    for (player, uuid) in players {
+        let data = player_data(game, player)?;
+        game.world.queue_player_data_save(uuid, data);
+    }
+
+    game.world.queue_loaded_chunks_save();
+    Ok(())
+}
+
+/// Saves the data of all online players and all loaded chunks,
+/// blocking until it has been written. Called on shutdown.
+pub fn save_all(game: &mut Game) -> SysResult {
+    queue_save(game)?;
+    game.world.flush()?;
+    log::info!("Saved the game");
+    Ok(())
+}
+
+/// Creates the data file contents for the given player.
+pub fn player_data(game: &Game, player: Entity) -> anyhow::Result<PlayerData> {
+    let player = game.ecs.entity(player)?;
+    let position = *player.get::<Position>()?;
+    let inventory = player.get::<Inventory>()?;
+    let food = *player.get::<Food>()?;
+    let experience = *player.get::<Experience>()?;
+    Ok(PlayerData {
+        animal: AnimalData {
+            base: BaseEntityData {
+                position: [position.x, position.y, position.z].into(),
+                rotation: [position.yaw, position.pitch].into(),
+                velocity: [0.0, 0.0, 0.0].into(),
+            },
+            health: **player.get::<Health>()?,
+        },
+        gamemode: player.get::<Gamemode>()?.to_i32().unwrap(),
+        previous_gamemode: player.get::<PreviousGamemode>()?.id() as i32,
+        inventory: inventory
+            .to_vec()
+            .iter()
+            .enumerate()
+            // Here we filter out all empty slots.
+            .filter_map(|(slot, item)| {
+                match item {
+                    libcraft_items::InventorySlot::Filled(item) => {
+                        let res = InventorySlot::from_network_index(slot, item);
+                        match res {
+                            Some(i) => Some(i),
+                            None => {
+                                log::error!("Failed to convert the slot into anvil format.");
+                                None
+                            }
+                        }
+                    }
+                    libcraft_items::InventorySlot::Empty => {
+                        // Empty items are filtered out.
+                        None
+                    }
+                }
+            })
+            .collect(),
+        held_item: player.get::<HotbarSlot>()?.get() as i32,
+        abilities: PlayerAbilities {
+            walk_speed: *player.get::<WalkSpeed>()?,
+            fly_speed: *player.get::<CreativeFlyingSpeed>()?,
+            may_fly: *player.get::<CanCreativeFly>()?,
+            is_flying: *player.get::<CreativeFlying>()?,
+            may_build: *player.get::<CanBuild>()?,
+            instabreak: *player.get::<Instabreak>()?,
+            invulnerable: *player.get::<Invulnerable>()?,
+        },
+        food_level: food.level,
+        food_saturation: food.saturation,
+        food_exhaustion: food.exhaustion,
+        xp_total: experience.total as i32,
+        xp_level: experience.level() as i32,
+        xp_progress: experience.progress(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use base::anvil::player::PlayerDataFormat;
+    use common::World;
+    use worldgen::VoidWorldGenerator;
+
+    use super::*;
+    use crate::{
+        client::tests::test_new_player,
+        systems::player_join::poll_new_players,
+        tests::{join_test_player, test_game, test_server},
+    };
+
+    #[test]
+    fn food_and_experience_survive_rejoining() {
+        for &format in &[PlayerDataFormat::Nbt, PlayerDataFormat::Json] {
+            let (mut server, new_players) = test_server();
+            let mut game = test_game(&server);
+            let world_dir = std::env::temp_dir().join(format!(
+                "feather-save-{}-{}",
+                std::process::id(),
+                format.extension()
+            ));
+            game.world = World::with_gen_and_path(Arc::new(VoidWorldGenerator), &world_dir);
+            game.world.set_player_data_format(format);
+
+            let player = join_test_player(&mut game, &mut server, &new_players);
+            let mut food = Food::new(7, 2.5);
+            food.exhaustion = 1.5;
+            *game.ecs.get_mut::<Food>(player.entity).unwrap() = food;
+            game.ecs
+                .get_mut::<Experience>(player.entity)
+                .unwrap()
+                .add(100);
+            let data = player_data(&game, player.entity).unwrap();
+            assert_eq!(data.xp_level, 7);
+
+            // Rejoin under another UUID with the saved data
+            let (rejoining, _connection) = test_new_player();
+            let uuid = rejoining.uuid;
+            game.world.save_player_data(uuid, &data).unwrap();
+            new_players.send(rejoining).unwrap();
+            poll_new_players(&mut game, &mut server).unwrap();
+            let rejoined = game
+                .ecs
+                .query::<&Uuid>()
+                .iter()
+                .find(|(_, player_uuid)| **player_uuid == uuid)
+                .map(|(entity, _)| entity)
+                .expect("player rejoined");
+
+            assert_eq!(*game.ecs.get::<Food>(rejoined).unwrap(), food);
+            assert_eq!(
+                *game.ecs.get::<Experience>(rejoined).unwrap(),
+                Experience { total: 100 }
+            );
+            std::fs::remove_dir_all(&world_dir).unwrap();
+        }
+    }
+}
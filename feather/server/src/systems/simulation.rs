@@ -0,0 +1,74 @@
+//! Pausing the simulation while the server is empty.
+
+use common::{simulation::SimulatedChunks, Game};
+
+use crate::Server;
+
+/// Pauses or resumes the simulation depending on whether
+/// the server is empty.
+///
+/// Called by the tick loop before running the systems.
+pub fn update_simulation_pause(game: &mut Game) {
+    let simulate = match game.resources.get::<Server>() {
+        Ok(server) => server.should_simulate(),
+        Err(_) => return,
+    };
+    if let Ok(mut simulated) = game.resources.get_mut::<SimulatedChunks>() {
+        if simulated.is_paused() == simulate {
+            if simulate {
+                log::debug!("Resuming the simulation");
+            } else {
+                log::debug!("Pausing the simulation until a player joins");
+            }
+        }
+        simulated.set_paused(!simulate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{test_game, test_server};
+
+    /// A game linked with an empty server.
+    fn empty_server_game(pause_when_empty: bool) -> Game {
+        let (server, _) = test_server();
+        let mut options = (*server.options()).clone();
+        options.pause_when_empty = pause_when_empty;
+        server.reload_options(options);
+        let mut game = test_game(&server);
+        game.insert_resource(SimulatedChunks::new(2));
+        game.insert_resource(server);
+        game
+    }
+
+    fn is_paused(game: &Game) -> bool {
+        game.resources.get::<SimulatedChunks>().unwrap().is_paused()
+    }
+
+    #[test]
+    fn empty_server_pauses_until_a_player_joins() {
+        let mut game = empty_server_game(true);
+        assert!(!game.resources.get::<Server>().unwrap().should_simulate());
+        update_simulation_pause(&mut game);
+        assert!(is_paused(&game));
+
+        // As done by the connection worker when a player connects
+        game.resources
+            .get::<Server>()
+            .unwrap()
+            .player_count
+            .try_add_player()
+            .unwrap();
+        assert!(game.resources.get::<Server>().unwrap().should_simulate());
+        update_simulation_pause(&mut game);
+        assert!(!is_paused(&game));
+    }
+
+    #[test]
+    fn empty_server_keeps_simulating_unless_configured_to_pause() {
+        let mut game = empty_server_game(false);
+        update_simulation_pause(&mut game);
+        assert!(!is_paused(&game));
+    }
+}
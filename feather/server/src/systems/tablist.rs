@@ -8,14 +8,15 @@ use ecs::{SysResult, SystemExecutor};
 use quill_common::events::{EntityRemoveEvent, GamemodeEvent, PlayerJoinEvent};
 use quill_common::{components::Name, entities::Player};
 
-use crate::{ClientId, Server};
+use crate::{entities::TablistDisplayName, ClientId, Server};
 
 pub fn register(systems: &mut SystemExecutor<Game>) {
     systems
         .group::<Server>()
         .add_system(remove_tablist_players)
         .add_system(add_tablist_players)
-        .add_system(change_tablist_player_gamemode);
+        .add_system(change_tablist_player_gamemode)
+        .add_system(update_tablist_pings);
 }
 
 fn remove_tablist_players(game: &mut Game, server: &mut Server) -> SysResult {
@@ -30,7 +31,7 @@ fn remove_tablist_players(game: &mut Game, server: &mut Server) -> SysResult {
 }
 
 fn add_tablist_players(game: &mut Game, server: &mut Server) -> SysResult {
-    for (player, (_, &client_id, &uuid, name, &gamemode, profile)) in game
+    for (player, (_, &client_id, &uuid, name, &gamemode, profile, display_name)) in game
         .ecs
         .query::<(
             &PlayerJoinEvent,
@@ -39,23 +40,47 @@ fn add_tablist_players(game: &mut Game, server: &mut Server) -> SysResult {
             &Name,
             &Gamemode,
             &Vec<ProfileProperty>,
+            Option<&TablistDisplayName>,
         )>()
         .iter()
     {
+        let display_name = display_name.map(|name| &name.0);
+        let ping = client_ping(server, client_id);
         // Add this player to other players' tablists
         server.broadcast_with(|client| {
-            client.add_tablist_player(uuid, name.to_string(), profile, gamemode)
+            client.add_tablist_player(
+                uuid,
+                name.to_string(),
+                profile,
+                gamemode,
+                ping,
+                display_name,
+            )
         });
 
         // Add other players to this player's tablist
-        for (other_player, (&uuid, name, &gamemode, profile)) in game
+        for (other_player, (&uuid, name, &gamemode, profile, display_name, other_client_id)) in game
             .ecs
-            .query::<(&Uuid, &Name, &Gamemode, &Vec<ProfileProperty>)>()
+            .query::<(
+                &Uuid,
+                &Name,
+                &Gamemode,
+                &Vec<ProfileProperty>,
+                Option<&TablistDisplayName>,
+                Option<&ClientId>,
+            )>()
             .iter()
         {
             if let Some(client) = server.clients.get(client_id) {
                 if other_player != player {
-                    client.add_tablist_player(uuid, name.to_string(), profile, gamemode);
+                    client.add_tablist_player(
+                        uuid,
+                        name.to_string(),
+                        profile,
+                        gamemode,
+                        other_client_id.map_or(0, |&id| client_ping(server, id)),
+                        display_name.map(|name| &name.0),
+                    );
                 }
             }
         }
@@ -70,3 +95,129 @@ fn change_tablist_player_gamemode(game: &mut Game, server: &mut Server) -> SysRe
     }
     Ok(())
 }
+
+/// Sends the pings of players whose latency changed,
+/// i.e. who answered a keepalive since the last tick.
+fn update_tablist_pings(game: &mut Game, server: &mut Server) -> SysResult {
+    let mut changed = Vec::new();
+    for (_, (&client_id, &uuid, _)) in game.ecs.query::<(&ClientId, &Uuid, &Player)>().iter() {
+        if let Some(ping) = server
+            .clients
+            .get(client_id)
+            .and_then(|client| client.take_tablist_ping_change())
+        {
+            changed.push((uuid, ping));
+        }
+    }
+    if !changed.is_empty() {
+        server.broadcast_with(|client| client.change_player_tablist_pings(&changed));
+    }
+    Ok(())
+}
+
+fn client_ping(server: &Server, client_id: ClientId) -> i32 {
+    server
+        .clients
+        .get(client_id)
+        .map_or(0, |client| client.ping())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use protocol::{
+        packets::{client::KeepAlive, server::PlayerInfo},
+        ClientPlayPacket, ServerPlayPacket,
+    };
+
+    use super::*;
+    use crate::{
+        packet_handlers::handle_packet,
+        tests::{join_test_player, test_game, test_server, TestPlayer},
+    };
+
+    /// Answers the keepalive sent to `player` after `delay`.
+    fn answer_keepalive(
+        game: &mut Game,
+        server: &mut Server,
+        player: &TestPlayer,
+        delay: Duration,
+    ) {
+        server
+            .clients
+            .get(player.client_id)
+            .unwrap()
+            .send_keepalive();
+        let id = player
+            .connection
+            .sent_packets
+            .try_iter()
+            .find_map(|packet| match packet {
+                ServerPlayPacket::KeepAlive(keepalive) => Some(keepalive.id),
+                _ => None,
+            })
+            .expect("keepalive sent");
+        thread::sleep(delay);
+        handle_packet(
+            game,
+            server,
+            player.entity,
+            ClientPlayPacket::KeepAlive(KeepAlive { id }),
+        )
+        .unwrap();
+    }
+
+    fn player_infos(player: &TestPlayer) -> Vec<PlayerInfo> {
+        player
+            .connection
+            .sent_packets
+            .try_iter()
+            .filter_map(|packet| match packet {
+                ServerPlayPacket::PlayerInfo(info) => Some(info),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pings_reach_other_players_once_measured() {
+        let (mut server, new_players) = test_server();
+        let mut game = test_game(&server);
+        let watcher = join_test_player(&mut game, &mut server, &new_players);
+        let pinged = join_test_player(&mut game, &mut server, &new_players);
+        let uuid = server.clients.get(pinged.client_id).unwrap().uuid();
+        server.clients.get(watcher.client_id).unwrap().mark_ready();
+        update_tablist_pings(&mut game, &mut server).unwrap();
+        watcher.connection.sent_packets.try_iter().for_each(drop);
+
+        answer_keepalive(&mut game, &mut server, &pinged, Duration::from_millis(20));
+        let ping = server.clients.get(pinged.client_id).unwrap().ping();
+        assert!(ping >= 20, "ping {} shorter than the delay", ping);
+        update_tablist_pings(&mut game, &mut server).unwrap();
+        let updated = player_infos(&watcher).into_iter().any(
+            |info| matches!(info, PlayerInfo::UpdatePings(pings) if pings == vec![(uuid, ping)]),
+        );
+        assert!(updated, "ping not sent to the other player");
+
+        // Unchanged pings are not resent
+        update_tablist_pings(&mut game, &mut server).unwrap();
+        assert!(player_infos(&watcher).is_empty());
+
+        let late = join_test_player(&mut game, &mut server, &new_players);
+        server.clients.get(late.client_id).unwrap().mark_ready();
+        add_tablist_players(&mut game, &mut server).unwrap();
+        let added_pings: Vec<i32> = player_infos(&late)
+            .into_iter()
+            .filter_map(|info| match info {
+                PlayerInfo::AddPlayers(added) => Some(added),
+                _ => None,
+            })
+            .flatten()
+            .filter(|added| added.uuid == uuid)
+            .map(|added| added.ping)
+            .collect();
+        assert!(!added_pings.is_empty(), "pinged player not added");
+        assert!(added_pings.iter().all(|&added| added == ping));
+    }
+}
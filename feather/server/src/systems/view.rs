@@ -66,8 +66,12 @@ fn update_chunks(
     position: Position,
     waiting_chunks: &mut WaitingChunks,
 ) -> SysResult {
-    // Send chunks that are in the new view but not the old view.
+    // Send chunks that are in the new view but not the old view,
+    // unless the client kept them because they were within the margin.
     for &pos in &event.new_chunks {
+        if client.knows_chunk(pos) {
+            continue;
+        }
         if let Some(chunk) = game.world.chunk_map().chunk_handle_at(pos) {
             client.send_chunk(&chunk);
         } else {
@@ -75,10 +79,8 @@ fn update_chunks(
         }
     }
 
-    // Unsend the chunks that are in the old view but not the new view.
-    for &pos in &event.old_chunks {
-        client.unload_chunk(pos);
-    }
+    // Chunks that left the view are unloaded
+    // when the client unsubscribes from them.
 
     spawn_client_if_needed(client, position);
 
@@ -104,7 +106,7 @@ fn send_loaded_chunks(game: &mut Game, server: &mut Server) -> SysResult {
     Ok(())
 }
 
-fn spawn_client_if_needed(client: &Client, pos: Position) {
+pub(crate) fn spawn_client_if_needed(client: &Client, pos: Position) {
     if !client.knows_own_position() && client.known_chunks() >= 9 * 9 {
         log::debug!("Sent all chunks to {}; now spawning", client.username());
         client.update_own_position(pos);
@@ -0,0 +1,54 @@
+use std::{collections::VecDeque, time::Duration};
+
+/// Records the durations of the most recent ticks.
+#[derive(Debug, Default)]
+pub struct TickTimes {
+    durations: VecDeque<Duration>,
+}
+
+impl TickTimes {
+    /// Records the duration of a tick, keeping at most
+    /// `window` durations.
+    pub fn record(&mut self, duration: Duration, window: usize) {
+        self.durations.push_back(duration);
+        while self.durations.len() > window.max(1) {
+            self.durations.pop_front();
+        }
+    }
+
+    /// Returns the average tick duration in milliseconds,
+    /// or 0 if no ticks have been recorded.
+    pub fn average_millis(&self) -> f32 {
+        if self.durations.is_empty() {
+            return 0.;
+        }
+        let total: Duration = self.durations.iter().sum();
+        total.as_secs_f32() * 1000. / self.durations.len() as f32
+    }
+
+    /// Returns the longest tick duration in milliseconds,
+    /// or 0 if no ticks have been recorded.
+    pub fn max_millis(&self) -> f32 {
+        self.durations
+            .iter()
+            .max()
+            .map_or(0., |max| max.as_secs_f32() * 1000.)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_over_window() {
+        let mut times = TickTimes::default();
+        assert_eq!(times.average_millis(), 0.);
+
+        times.record(Duration::from_millis(100), 2);
+        times.record(Duration::from_millis(10), 2);
+        times.record(Duration::from_millis(20), 2);
+        assert!((times.average_millis() - 15.).abs() < 0.01);
+        assert!((times.max_millis() - 20.).abs() < 0.01);
+    }
+}